@@ -0,0 +1,237 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Compares serving connections through io_uring with serving them through
+//! epoll. Two embedded proxies, one with `io_uring = true`, each serve gets
+//! from many connections at once, and the throughput, latency, and CPU time of
+//! the two are reported side by side. The gets are served from the proxies'
+//! memory caches, so that the cost of the connection I/O isn't hidden behind
+//! the round trip to Momento, but a Momento cache is still needed to fill
+//! them: the key in `MOMENTO_API_KEY` is used with the cache in
+//! `MOMENTO_CACHE_NAME` (default `proxy-bench`).
+//!
+//! `IO_URING_CONNECTIONS` sets the number of connections (default 64), and
+//! `IO_URING_OPS` the gets made on each of them (default 10000).
+//!
+//! Run with `cargo bench --features io-uring --bench io_uring`.
+
+use std::net::TcpListener;
+use std::time::{Duration, Instant};
+
+use momento_proxy::{MomentoProxyConfig, ProxyBuilder, ProxyHandle};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const KEY: &str = "io_uring_bench";
+const VALUE: [u8; 64] = [b'x'; 64];
+const WARMUP: usize = 1_000;
+
+/// The throughput, latency, and CPU time of a run of gets.
+struct Stats {
+    ops_per_sec: f64,
+    p50: Duration,
+    p99: Duration,
+    // process CPU time per request, if it can be measured
+    cpu: Option<Duration>,
+}
+
+// the user and system CPU time of the process so far
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+    // SAFETY: rusage is plain data, for which all zeroes is valid
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    // SAFETY: `rusage` is a valid rusage for getrusage to write to
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut rusage) } != 0 {
+        return None;
+    }
+
+    let time = |t: libc::timeval| {
+        Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+    };
+    Some(time(rusage.ru_utime) + time(rusage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("failed to find a free port")
+}
+
+// starts a proxy with a single memcache listener which holds values in its
+// memory cache, returning it with the port of the listener
+fn start_proxy(cache_name: &str, io_uring: bool) -> (ProxyHandle, u16) {
+    let port = free_port();
+    let config = format!(
+        r#"
+[admin]
+host = "127.0.0.1"
+port = "{}"
+
+[proxy]
+io_uring = {io_uring}
+
+[debug]
+log_level = "error"
+
+[[cache]]
+host = "127.0.0.1"
+port = "{port}"
+cache_name = "{cache_name}"
+default_ttl = 600
+memory_cache_bytes = 1048576
+"#,
+        free_port()
+    );
+    let path = std::env::temp_dir().join(format!("io_uring_bench_{port}.toml"));
+    std::fs::write(&path, config).expect("failed to write the proxy config");
+    let config = MomentoProxyConfig::load(path.to_str().unwrap()).expect("invalid proxy config");
+    let _ = std::fs::remove_file(&path);
+
+    let proxy = ProxyBuilder::new(config)
+        .start()
+        .expect("failed to start the proxy");
+    (proxy, port)
+}
+
+async fn connect(proxy: &ProxyHandle, port: u16) -> TcpStream {
+    for _ in 0..100 {
+        if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)).await {
+            stream.set_nodelay(true).expect("failed to set nodelay");
+            return stream;
+        }
+        assert!(proxy.is_running(), "the proxy failed to start");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("could not connect to the proxy");
+}
+
+// sends a request and reads until the response ends with `end`
+async fn request(stream: &mut TcpStream, buf: &mut Vec<u8>, request: &[u8], end: &[u8]) {
+    stream.write_all(request).await.expect("write failed");
+
+    buf.clear();
+    while !buf.ends_with(end) {
+        let mut chunk = [0; 4096];
+        let n = stream.read(&mut chunk).await.expect("read failed");
+        assert!(n > 0, "proxy closed the connection");
+        buf.extend_from_slice(&chunk[..n]);
+        assert!(
+            !buf.starts_with(b"SERVER_ERROR") && !buf.starts_with(b"CLIENT_ERROR"),
+            "request failed: {}",
+            String::from_utf8_lossy(buf)
+        );
+    }
+}
+
+// makes `ops` gets on each of `connections` connections at once, returning
+// the latency of each get
+async fn measure(proxy: &ProxyHandle, port: u16, connections: usize, ops: usize) -> Stats {
+    let get = format!("get {KEY}\r\n").into_bytes();
+
+    // the value is set once, after which every get is a memory cache hit
+    let mut set = format!("set {KEY} 0 600 {}\r\n", VALUE.len()).into_bytes();
+    set.extend_from_slice(&VALUE);
+    set.extend_from_slice(b"\r\n");
+    let mut stream = connect(proxy, port).await;
+    request(&mut stream, &mut Vec::new(), &set, b"STORED\r\n").await;
+
+    let mut streams = Vec::with_capacity(connections);
+    for _ in 0..connections {
+        let mut stream = connect(proxy, port).await;
+        let mut buf = Vec::new();
+        for _ in 0..WARMUP {
+            request(&mut stream, &mut buf, &get, b"END\r\n").await;
+        }
+        streams.push(stream);
+    }
+
+    let cpu_start = cpu_time();
+    let start = Instant::now();
+    let tasks: Vec<_> = streams
+        .into_iter()
+        .map(|mut stream| {
+            let get = get.clone();
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut latencies = Vec::with_capacity(ops);
+                for _ in 0..ops {
+                    let start = Instant::now();
+                    request(&mut stream, &mut buf, &get, b"END\r\n").await;
+                    latencies.push(start.elapsed());
+                }
+                latencies
+            })
+        })
+        .collect();
+
+    let mut latencies = Vec::with_capacity(connections * ops);
+    for task in tasks {
+        latencies.extend(task.await.expect("a connection failed"));
+    }
+    let elapsed = start.elapsed();
+    let cpu = cpu_time()
+        .zip(cpu_start)
+        .map(|(end, start)| end.saturating_sub(start));
+
+    latencies.sort();
+    let count = latencies.len().max(1);
+    let percentile = |p: usize| latencies[(latencies.len() * p / 100).min(count - 1)];
+
+    Stats {
+        ops_per_sec: count as f64 / elapsed.as_secs_f64(),
+        p50: percentile(50),
+        p99: percentile(99),
+        cpu: cpu.map(|cpu| cpu / count as u32),
+    }
+}
+
+fn main() {
+    // the proxies read the key from the environment
+    if std::env::var("MOMENTO_API_KEY").is_err() {
+        eprintln!("MOMENTO_API_KEY is not set, skipping");
+        return;
+    }
+
+    let cache_name =
+        std::env::var("MOMENTO_CACHE_NAME").unwrap_or_else(|_| "proxy-bench".to_string());
+    let connections: usize = std::env::var("IO_URING_CONNECTIONS")
+        .ok()
+        .and_then(|connections| connections.parse().ok())
+        .unwrap_or(64);
+    let ops: usize = std::env::var("IO_URING_OPS")
+        .ok()
+        .and_then(|ops| ops.parse().ok())
+        .unwrap_or(10_000);
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build the runtime");
+
+    println!(
+        "{:>8} {:>12} {:>10} {:>10} {:>10}",
+        "path", "ops/s", "p50", "p99", "cpu/op"
+    );
+    for (path, io_uring) in [("epoll", false), ("io_uring", true)] {
+        let (proxy, port) = start_proxy(&cache_name, io_uring);
+        let stats = runtime.block_on(measure(&proxy, port, connections, ops));
+        let _ = proxy.stop();
+
+        println!(
+            "{:>8} {:>12.0} {:>10?} {:>10?} {:>10}",
+            path,
+            stats.ops_per_sec,
+            stats.p50,
+            stats.p99,
+            stats.cpu.map_or("-".to_string(), |cpu| format!("{cpu:?}")),
+        );
+    }
+}
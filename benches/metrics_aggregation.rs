@@ -0,0 +1,92 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Compares the cost of the counter updates made by each request when they
+//! go straight to the shared counters and when they are aggregated by each
+//! thread, with every thread updating the same counters at once. Each request
+//! updates a few dozen counters, as a request through the proxy does.
+//!
+//! Run with `cargo bench --bench metrics_aggregation`.
+
+use std::sync::Barrier;
+use std::time::{Duration, Instant};
+
+use metriken::Counter;
+use momento_proxy::batch;
+
+// the updates made by one request: a read and a write, with their bytes
+static RECV: Counter = Counter::new();
+static RECV_BYTE: Counter = Counter::new();
+static SEND: Counter = Counter::new();
+static SEND_BYTE: Counter = Counter::new();
+
+// the rest: the counts of the command, its keys, its backend calls and their
+// outcomes
+static COMMAND: [Counter; 20] = [const { Counter::new() }; 20];
+
+const REQUESTS: u64 = 500_000;
+
+fn request() {
+    batch::increment(&RECV);
+    batch::add(&RECV_BYTE, 64);
+    batch::increment(&SEND);
+    batch::add(&SEND_BYTE, 512);
+    for counter in &COMMAND {
+        batch::increment(counter);
+    }
+}
+
+// runs the requests on every thread at once, returning the mean time taken
+// by each request
+fn run(threads: usize) -> Duration {
+    let barrier = Barrier::new(threads);
+
+    let elapsed: Duration = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    barrier.wait();
+                    let start = Instant::now();
+                    for _ in 0..REQUESTS {
+                        request();
+                    }
+                    batch::flush();
+                    start.elapsed()
+                })
+            })
+            .collect();
+
+        handles.into_iter().map(|h| h.join().unwrap()).sum()
+    });
+
+    elapsed / (threads as u32 * REQUESTS as u32)
+}
+
+fn main() {
+    let max = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let mut threads: Vec<usize> = [1, 2, 4, 8, 16, 32]
+        .into_iter()
+        .filter(|n| *n <= max)
+        .collect();
+    if !threads.contains(&max) {
+        threads.push(max);
+    }
+
+    println!("{:>8} {:>12} {:>14}", "threads", "global", "thread_local");
+    for threads in threads {
+        let sent = SEND.value();
+
+        batch::set_thread_local(false);
+        let global = run(threads);
+
+        batch::set_thread_local(true);
+        let thread_local = run(threads);
+
+        println!("{threads:>8} {global:>12?} {thread_local:>14?}");
+
+        // every update reaches the shared counters either way
+        assert_eq!(SEND.value() - sent, 2 * threads as u64 * REQUESTS);
+        assert_eq!(COMMAND[0].value(), SEND.value());
+    }
+}
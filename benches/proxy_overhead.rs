@@ -0,0 +1,374 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Measures what the proxy adds to each request: the latency and CPU time of
+//! gets and sets sent through the memcache listener of an embedded proxy,
+//! against the same requests made directly with a `CacheClient`. The SDK only
+//! connects over TLS to the endpoint in the API key, so rather than a mock
+//! server, both run against the cache in `MOMENTO_CACHE_NAME` (default
+//! `proxy-bench`) with the key in `MOMENTO_API_KEY`, and the overhead is the
+//! difference between them. The two paths take turns in blocks of requests,
+//! so that both see the same network conditions, but the round trips still
+//! vary with them. The benchmark is meant to be run by hand for each release,
+//! from a host in the cache's region, rather than in CI.
+//!
+//! With `PROXY_OVERHEAD_RESULTS`, the overhead is appended to that file as a
+//! JSON line tagged with the crate version, so it can be tracked per release.
+//! With `PROXY_OVERHEAD_BASELINE`, the overhead is compared against the last
+//! results in that file, and the benchmark fails if the median latency or the
+//! CPU time of an operation grew by more than 20%.
+//!
+//! Run with `cargo bench --bench proxy_overhead`.
+
+use std::io::Write;
+use std::net::TcpListener;
+use std::time::{Duration, Instant};
+
+use momento::cache::configurations;
+use momento::{CacheClient, CredentialProvider};
+use momento_proxy::{MomentoProxyConfig, ProxyBuilder};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const WARMUP: usize = 1_000;
+
+// the requests made on one path before taking turns with the other
+const BLOCK: usize = 100;
+const VALUE: [u8; 64] = [b'x'; 64];
+
+// how much the overhead may grow over the baseline, as a ratio and in
+// microseconds, before the benchmark fails
+const REGRESSION_RATIO: f64 = 1.2;
+const REGRESSION_SLACK_US: f64 = 10.0;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Get,
+    Set,
+}
+
+impl Op {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Op::Get => "get",
+            Op::Set => "set",
+        }
+    }
+}
+
+/// The latency and CPU time of a run of requests.
+struct Stats {
+    p50: Duration,
+    p99: Duration,
+    mean: Duration,
+    // process CPU time per request, if it can be measured
+    cpu: Option<Duration>,
+}
+
+impl Stats {
+    fn new(mut latencies: Vec<Duration>, cpu: Option<Duration>) -> Self {
+        latencies.sort();
+        let count = latencies.len().max(1);
+        let percentile = |p: usize| latencies[(latencies.len() * p / 100).min(count - 1)];
+
+        Self {
+            p50: percentile(50),
+            p99: percentile(99),
+            mean: latencies.iter().sum::<Duration>() / count as u32,
+            cpu: cpu.map(|cpu| cpu / count as u32),
+        }
+    }
+}
+
+// the user and system CPU time of the process so far
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+    // SAFETY: rusage is plain data, for which all zeroes is valid
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    // SAFETY: `rusage` is a valid rusage for getrusage to write to
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut rusage) } != 0 {
+        return None;
+    }
+
+    let time = |t: libc::timeval| {
+        Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+    };
+    Some(time(rusage.ru_utime) + time(rusage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("failed to find a free port")
+}
+
+/// Where the requests are sent.
+enum Target {
+    Direct {
+        client: CacheClient,
+        cache_name: String,
+    },
+    Proxied {
+        stream: TcpStream,
+        buf: Vec<u8>,
+    },
+}
+
+impl Target {
+    async fn request(&mut self, op: Op, key: String) {
+        match self {
+            Target::Direct { client, cache_name } => match op {
+                Op::Get => {
+                    client
+                        .get(cache_name.as_str(), key)
+                        .await
+                        .expect("direct get failed");
+                }
+                Op::Set => {
+                    client
+                        .set(cache_name.as_str(), key, VALUE.to_vec())
+                        .await
+                        .expect("direct set failed");
+                }
+            },
+            Target::Proxied { stream, buf } => {
+                let (request, end): (Vec<u8>, &[u8]) = match op {
+                    Op::Get => (format!("get {key}\r\n").into_bytes(), b"END\r\n"),
+                    Op::Set => {
+                        let mut request =
+                            format!("set {key} 0 60 {}\r\n", VALUE.len()).into_bytes();
+                        request.extend_from_slice(&VALUE);
+                        request.extend_from_slice(b"\r\n");
+                        (request, b"STORED\r\n")
+                    }
+                };
+                stream
+                    .write_all(&request)
+                    .await
+                    .expect("proxied write failed");
+
+                buf.clear();
+                while !buf.ends_with(end) {
+                    let mut chunk = [0; 4096];
+                    let n = stream.read(&mut chunk).await.expect("proxied read failed");
+                    assert!(n > 0, "proxy closed the connection");
+                    buf.extend_from_slice(&chunk[..n]);
+                    assert!(
+                        !buf.starts_with(b"SERVER_ERROR") && !buf.starts_with(b"CLIENT_ERROR"),
+                        "proxied {} failed: {}",
+                        op.as_str(),
+                        String::from_utf8_lossy(buf)
+                    );
+                }
+            }
+        }
+    }
+}
+
+// the key of the `i`th request. The keys repeat, so that gets after the sets hit
+fn key(i: usize) -> String {
+    format!("proxy_overhead_{}", i % 1_000)
+}
+
+// times `ops` requests made one after another on each path, after a warmup.
+// The paths take turns in blocks, and the CPU time of each is summed over its
+// blocks
+async fn measure(direct: &mut Target, proxied: &mut Target, op: Op, ops: usize) -> [Stats; 2] {
+    for target in [&mut *direct, &mut *proxied] {
+        for i in 0..WARMUP {
+            target.request(op, key(i)).await;
+        }
+    }
+
+    let mut latencies = [Vec::with_capacity(ops), Vec::with_capacity(ops)];
+    let mut cpu = [Some(Duration::ZERO); 2];
+    for block in (0..ops).step_by(BLOCK) {
+        for (path, target) in [&mut *direct, &mut *proxied].into_iter().enumerate() {
+            let cpu_start = cpu_time();
+            for i in block..(block + BLOCK).min(ops) {
+                let start = Instant::now();
+                target.request(op, key(i)).await;
+                latencies[path].push(start.elapsed());
+            }
+            cpu[path] = cpu[path]
+                .zip(cpu_time().zip(cpu_start))
+                .map(|(total, (end, start))| total + end.saturating_sub(start));
+        }
+    }
+
+    let [direct_latencies, proxied_latencies] = latencies;
+    [
+        Stats::new(direct_latencies, cpu[0]),
+        Stats::new(proxied_latencies, cpu[1]),
+    ]
+}
+
+fn us(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000_000.0
+}
+
+// the last overhead recorded for `op` in a results file
+fn baseline(path: &str, op: Op) -> Option<serde_json::Value> {
+    std::fs::read_to_string(path)
+        .expect("failed to read the baseline")
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .rfind(|result| result["op"] == op.as_str())
+}
+
+fn main() {
+    let Ok(api_key) = std::env::var("MOMENTO_API_KEY") else {
+        eprintln!("MOMENTO_API_KEY is not set, skipping");
+        return;
+    };
+    let cache_name =
+        std::env::var("MOMENTO_CACHE_NAME").unwrap_or_else(|_| "proxy-bench".to_string());
+    let ops: usize = std::env::var("PROXY_OVERHEAD_OPS")
+        .ok()
+        .and_then(|ops| ops.parse().ok())
+        .unwrap_or(10_000);
+
+    // a proxy with a single memcache listener, on ports which are free
+    let port = free_port();
+    let config = format!(
+        r#"
+[admin]
+host = "127.0.0.1"
+port = "{}"
+
+[debug]
+log_level = "error"
+
+[[cache]]
+host = "127.0.0.1"
+port = "{port}"
+cache_name = "{cache_name}"
+default_ttl = 60
+"#,
+        free_port()
+    );
+    let path = std::env::temp_dir().join(format!("proxy_overhead_{port}.toml"));
+    std::fs::write(&path, config).expect("failed to write the proxy config");
+    let config = MomentoProxyConfig::load(path.to_str().unwrap()).expect("invalid proxy config");
+    let _ = std::fs::remove_file(&path);
+
+    let proxy = ProxyBuilder::new(config)
+        .start()
+        .expect("failed to start the proxy");
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build the runtime");
+
+    let results = runtime.block_on(async {
+        let client = CacheClient::builder()
+            .default_ttl(Duration::from_secs(60))
+            .configuration(configurations::Laptop::latest())
+            .credential_provider(
+                CredentialProvider::from_string(api_key).expect("invalid MOMENTO_API_KEY"),
+            )
+            .build()
+            .expect("failed to build the cache client");
+
+        let mut stream = None;
+        for _ in 0..100 {
+            if let Ok(connected) = TcpStream::connect(("127.0.0.1", port)).await {
+                stream = Some(connected);
+                break;
+            }
+            assert!(proxy.is_running(), "the proxy failed to start");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let stream = stream.expect("could not connect to the proxy");
+        stream.set_nodelay(true).expect("failed to set nodelay");
+
+        let mut direct = Target::Direct { client, cache_name };
+        let mut proxied = Target::Proxied {
+            stream,
+            buf: Vec::new(),
+        };
+
+        let mut results = Vec::new();
+        // sets first, so that the gets hit
+        for op in [Op::Set, Op::Get] {
+            let [direct, proxied] = measure(&mut direct, &mut proxied, op, ops).await;
+            results.push((op, direct, proxied));
+        }
+        results
+    });
+
+    let _ = proxy.stop();
+
+    println!(
+        "{:>4} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "op", "path", "p50", "p99", "mean", "cpu/op"
+    );
+    let mut regressed = false;
+    for (op, direct, proxied) in &results {
+        for (path, stats) in [("direct", direct), ("proxied", proxied)] {
+            println!(
+                "{:>4} {:>8} {:>10?} {:>10?} {:>10?} {:>10}",
+                op.as_str(),
+                path,
+                stats.p50,
+                stats.p99,
+                stats.mean,
+                stats.cpu.map_or("-".to_string(), |cpu| format!("{cpu:?}")),
+            );
+        }
+
+        let overhead = serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "op": op.as_str(),
+            "ops": ops,
+            "p50_us": us(proxied.p50.saturating_sub(direct.p50)),
+            "p99_us": us(proxied.p99.saturating_sub(direct.p99)),
+            "cpu_us": proxied.cpu.zip(direct.cpu).map(|(p, d)| us(p.saturating_sub(d))),
+        });
+        println!("{:>4} overhead {overhead}", op.as_str());
+
+        if let Ok(path) = std::env::var("PROXY_OVERHEAD_BASELINE") {
+            if let Some(baseline) = baseline(&path, *op) {
+                for field in ["p50_us", "cpu_us"] {
+                    let (Some(current), Some(previous)) =
+                        (overhead[field].as_f64(), baseline[field].as_f64())
+                    else {
+                        continue;
+                    };
+                    if current > previous * REGRESSION_RATIO
+                        && current - previous > REGRESSION_SLACK_US
+                    {
+                        eprintln!(
+                            "{} {field} regressed: {current:.1} against {previous:.1} in {}",
+                            op.as_str(),
+                            baseline["version"]
+                        );
+                        regressed = true;
+                    }
+                }
+            }
+        }
+
+        if let Ok(path) = std::env::var("PROXY_OVERHEAD_RESULTS") {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .expect("failed to open the results file");
+            writeln!(file, "{overhead}").expect("failed to write the results");
+        }
+    }
+
+    if regressed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,25 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Feeds arbitrary RESP streams, including inline commands, through the RESP
+//! frontend's connection handler.
+//!
+//! Run with `cargo +nightly fuzz run resp` from the repository root.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use momento_proxy::fuzz;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    stream: Vec<u8>,
+    // the size of each read, less one
+    reads: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    fuzz::resp(&input.stream, &input.reads);
+});
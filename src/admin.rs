@@ -3,7 +3,32 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use crate::*;
+use core::sync::atomic::AtomicU64;
 use session::Buf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+// how long a runtime logging change lasts when no duration is given
+const DEFAULT_OVERRIDE_SECONDS: u64 = 300;
+
+// how long a client has to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// the longest first line accepted from a client which must authenticate
+const MAX_AUTH_LINE: usize = 1024;
+
+// incremented on each runtime logging change so that a pending revert does not
+// undo a more recent change
+static LOG_LEVEL_GENERATION: AtomicU64 = AtomicU64::new(0);
+static KLOG_SAMPLE_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// the settings to restore once the temporary changes expire
+static LOG_LEVEL_BASE: Mutex<Option<log::LevelFilter>> = Mutex::new(None);
+static KLOG_SAMPLE_BASE: Mutex<Option<u64>> = Mutex::new(None);
 
 #[metric(name = "admin_conn_curr")]
 pub static ADMIN_CONN_CURR: Gauge = Gauge::new();
@@ -14,59 +39,281 @@ pub static ADMIN_CONN_ACCEPT: Counter = Counter::new();
 #[metric(name = "admin_conn_close")]
 pub static ADMIN_CONN_CLOSE: Counter = Counter::new();
 
-pub(crate) async fn admin(admin_listener: TcpListener) {
-    loop {
-        // accept a new client
-        if let Ok(Ok((socket, _))) =
-            timeout(Duration::from_millis(1), admin_listener.accept()).await
-        {
-            ADMIN_CONN_CURR.increment();
-            ADMIN_CONN_ACCEPT.increment();
-            tokio::spawn(async move {
-                admin::handle_admin_client(socket).await;
-                ADMIN_CONN_CLOSE.increment();
-                ADMIN_CONN_CURR.decrement();
-            });
+#[metric(name = "admin_auth_fail")]
+pub static ADMIN_AUTH_FAIL: Counter = Counter::new();
+
+#[metric(name = "admin_tls_handshake_ex")]
+pub static ADMIN_TLS_HANDSHAKE_EX: Counter = Counter::new();
+
+/// The admin listener, bound on startup and served from the admin runtime so
+/// that it stays responsive while the workers are busy.
+pub(crate) struct Admin {
+    listener: AdminListener,
+    tls: Option<TlsAcceptor>,
+    token: Option<Arc<str>>,
+}
+
+enum AdminListener {
+    Tcp(std::net::TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+impl Admin {
+    /// Binds the admin listener, on the unix socket if one is configured and
+    /// the `[admin]` address otherwise, and loads its TLS identity.
+    pub(crate) fn bind(config: &MomentoProxyConfig) -> Result<Self, String> {
+        let listener = match config.admin_unix_socket() {
+            Some(path) => bind_unix(path)?,
+            None => {
+                let addr = config
+                    .admin()
+                    .socket_addr()
+                    .map_err(|e| format!("listen address is not valid: {e}"))?;
+                let listener = std::net::TcpListener::bind(addr)
+                    .map_err(|e| format!("could not bind on address `{addr}`: {e}"))?;
+                AdminListener::Tcp(listener)
+            }
         };
 
-        // SAFETY: libc::rusage is a POD struct; zeroing it is equivalent to C's {0} initializer.
-        // Needed for portability across both GNU libc and musl targets.
-        let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
-
-        if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut rusage) } == 0 {
-            RU_UTIME.set(rusage.ru_utime.tv_sec as u64 * S + rusage.ru_utime.tv_usec as u64 * US);
-            RU_STIME.set(rusage.ru_stime.tv_sec as u64 * S + rusage.ru_stime.tv_usec as u64 * US);
-            RU_MAXRSS.set(rusage.ru_maxrss * KB as i64);
-            RU_IXRSS.set(rusage.ru_ixrss * KB as i64);
-            RU_IDRSS.set(rusage.ru_idrss * KB as i64);
-            RU_ISRSS.set(rusage.ru_isrss * KB as i64);
-            RU_MINFLT.set(rusage.ru_minflt as u64);
-            RU_MAJFLT.set(rusage.ru_majflt as u64);
-            RU_NSWAP.set(rusage.ru_nswap as u64);
-            RU_INBLOCK.set(rusage.ru_inblock as u64);
-            RU_OUBLOCK.set(rusage.ru_oublock as u64);
-            RU_MSGSND.set(rusage.ru_msgsnd as u64);
-            RU_MSGRCV.set(rusage.ru_msgrcv as u64);
-            RU_NSIGNALS.set(rusage.ru_nsignals as u64);
-            RU_NVCSW.set(rusage.ru_nvcsw as u64);
-            RU_NIVCSW.set(rusage.ru_nivcsw as u64);
+        let tls = config
+            .admin_tls_identity()
+            .map(|(cert, key)| tls_acceptor(cert, key))
+            .transpose()?;
+
+        Ok(Self {
+            listener,
+            tls,
+            token: config.admin_auth_token().map(Arc::from),
+        })
+    }
+
+    /// Where the admin listener is bound, for logging.
+    pub(crate) fn local_addr(&self) -> String {
+        match &self.listener {
+            AdminListener::Tcp(listener) => listener
+                .local_addr()
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|e| e.to_string()),
+            #[cfg(unix)]
+            AdminListener::Unix(listener) => listener
+                .local_addr()
+                .ok()
+                .and_then(|addr| addr.as_pathname().map(|path| path.display().to_string()))
+                .unwrap_or_else(|| "unnamed unix socket".to_string()),
+        }
+    }
+
+    /// Serves admin clients. Must be run on the admin runtime, which the
+    /// listener and its connections are registered with.
+    pub(crate) async fn serve(self) {
+        let Self {
+            listener,
+            tls,
+            token,
+        } = self;
+
+        let listener = match listener.into_tokio() {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("could not start the admin listener: {e}");
+                return;
+            }
+        };
+
+        loop {
+            // accept a new client
+            match &listener {
+                TokioListener::Tcp(listener) => {
+                    if let Ok(Ok((socket, _))) =
+                        timeout(Duration::from_millis(1), listener.accept()).await
+                    {
+                        spawn_client(socket, tls.clone(), token.clone());
+                    }
+                }
+                #[cfg(unix)]
+                TokioListener::Unix(listener) => {
+                    if let Ok(Ok((socket, _))) =
+                        timeout(Duration::from_millis(1), listener.accept()).await
+                    {
+                        spawn_client(socket, tls.clone(), token.clone());
+                    }
+                }
+            }
+
+            update_rusage();
+
+            tokio::time::sleep(core::time::Duration::from_millis(100)).await;
         }
+    }
+}
+
+enum TokioListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl AdminListener {
+    fn into_tokio(self) -> std::io::Result<TokioListener> {
+        match self {
+            Self::Tcp(listener) => {
+                listener.set_nonblocking(true)?;
+                Ok(TokioListener::Tcp(TcpListener::from_std(listener)?))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener) => {
+                listener.set_nonblocking(true)?;
+                Ok(TokioListener::Unix(tokio::net::UnixListener::from_std(
+                    listener,
+                )?))
+            }
+        }
+    }
+}
 
-        tokio::time::sleep(core::time::Duration::from_millis(100)).await;
+// binds the unix socket, replacing a socket left behind by an earlier run,
+// and restricts it to the user the proxy runs as
+#[cfg(unix)]
+fn bind_unix(path: &str) -> Result<AdminListener, String> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!(
+                "admin unix socket `{path}` exists and is not a socket"
+            ));
+        }
+        std::fs::remove_file(path)
+            .map_err(|e| format!("could not remove stale admin unix socket `{path}`: {e}"))?;
     }
+
+    let listener = std::os::unix::net::UnixListener::bind(path)
+        .map_err(|e| format!("could not bind admin unix socket `{path}`: {e}"))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("could not set permissions of admin unix socket `{path}`: {e}"))?;
+
+    Ok(AdminListener::Unix(listener))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(path: &str) -> Result<AdminListener, String> {
+    Err(format!(
+        "admin unix socket `{path}` is not supported on this platform"
+    ))
 }
 
-async fn handle_admin_client(mut socket: tokio::net::TcpStream) {
+fn tls_acceptor(cert: &str, key: &str) -> Result<TlsAcceptor, String> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("could not read admin certificate `{cert}`: {e}"))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| format!("could not read admin key `{key}`: {e}"))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("invalid admin TLS config: {e}"))?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn spawn_client<S>(socket: S, tls: Option<TlsAcceptor>, token: Option<Arc<str>>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    ADMIN_CONN_CURR.increment();
+    ADMIN_CONN_ACCEPT.increment();
+    tokio::spawn(async move {
+        match tls {
+            Some(tls) => match timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(socket)).await {
+                Ok(Ok(socket)) => handle_admin_client(socket, token).await,
+                _ => {
+                    ADMIN_TLS_HANDSHAKE_EX.increment();
+                }
+            },
+            None => handle_admin_client(socket, token).await,
+        }
+        ADMIN_CONN_CLOSE.increment();
+        ADMIN_CONN_CURR.decrement();
+    });
+}
+
+// resource usage is only available on unix platforms
+#[cfg(unix)]
+fn update_rusage() {
+    // SAFETY: libc::rusage is a POD struct; zeroing it is equivalent to C's {0} initializer.
+    // Needed for portability across both GNU libc and musl targets.
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut rusage) } == 0 {
+        RU_UTIME.set(rusage.ru_utime.tv_sec as u64 * S + rusage.ru_utime.tv_usec as u64 * US);
+        RU_STIME.set(rusage.ru_stime.tv_sec as u64 * S + rusage.ru_stime.tv_usec as u64 * US);
+        // linux reports the max rss in kilobytes but macOS reports it in bytes
+        #[cfg(target_os = "macos")]
+        RU_MAXRSS.set(rusage.ru_maxrss);
+        #[cfg(not(target_os = "macos"))]
+        RU_MAXRSS.set(rusage.ru_maxrss * KB as i64);
+        RU_IXRSS.set(rusage.ru_ixrss * KB as i64);
+        RU_IDRSS.set(rusage.ru_idrss * KB as i64);
+        RU_ISRSS.set(rusage.ru_isrss * KB as i64);
+        RU_MINFLT.set(rusage.ru_minflt as u64);
+        RU_MAJFLT.set(rusage.ru_majflt as u64);
+        RU_NSWAP.set(rusage.ru_nswap as u64);
+        RU_INBLOCK.set(rusage.ru_inblock as u64);
+        RU_OUBLOCK.set(rusage.ru_oublock as u64);
+        RU_MSGSND.set(rusage.ru_msgsnd as u64);
+        RU_MSGRCV.set(rusage.ru_msgrcv as u64);
+        RU_NSIGNALS.set(rusage.ru_nsignals as u64);
+        RU_NVCSW.set(rusage.ru_nvcsw as u64);
+        RU_NIVCSW.set(rusage.ru_nivcsw as u64);
+    }
+}
+
+#[cfg(not(unix))]
+fn update_rusage() {}
+
+async fn handle_admin_client<S>(mut socket: S, token: Option<Arc<str>>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
     // initialize a buffer for incoming bytes from the client
     let mut buf = Buffer::new(INITIAL_BUFFER_SIZE);
 
+    // without a token, clients are trusted from the start
+    let mut authenticated = token.is_none();
+
     // initialize the request parser
     let parser = AdminProtocol::default();
     loop {
-        if do_read(&mut socket, &mut buf).await.is_err() {
+        if do_read(&mut socket, &mut buf, INITIAL_BUFFER_SIZE)
+            .await
+            .is_err()
+        {
             break;
         }
 
+        if let Some(token) = token.as_deref().filter(|_| !authenticated) {
+            match authenticate(buf.borrow(), token) {
+                Auth::Incomplete => continue,
+                Auth::Accepted(consumed) => {
+                    authenticated = true;
+                    buf.advance(consumed);
+                    if socket.write_all(b"OK\r\n").await.is_err() {
+                        break;
+                    }
+                    if buf.remaining() == 0 {
+                        continue;
+                    }
+                }
+                Auth::Rejected => {
+                    ADMIN_AUTH_FAIL.increment();
+                    let _ = socket.write_all(b"CLIENT_ERROR unauthorized\r\n").await;
+                    break;
+                }
+            }
+        }
+
         match parser.parse_request(buf.borrow()) {
             Ok(request) => {
                 ADMIN_REQUEST_PARSE.increment();
@@ -91,6 +338,19 @@ async fn handle_admin_client(mut socket: tokio::net::TcpStream) {
             Err(e) => match e.kind() {
                 ErrorKind::WouldBlock => {}
                 _ => {
+                    // commands which are specific to the proxy are not known
+                    // to the admin protocol parser
+                    if let Some((consumed, response)) = proxy_command(buf.borrow()) {
+                        ADMIN_REQUEST_PARSE.increment();
+                        ADMIN_RESPONSE_COMPOSE.increment();
+
+                        buf.advance(consumed);
+                        if socket.write_all(response.as_bytes()).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+
                     // invalid request
                     let _ = socket.write_all(b"CLIENT_ERROR\r\n").await;
                     break;
@@ -100,7 +360,175 @@ async fn handle_admin_client(mut socket: tokio::net::TcpStream) {
     }
 }
 
-async fn stats_response(socket: &mut tokio::net::TcpStream) -> Result<(), Error> {
-    let message = protocol_admin::memcache_stats();
+async fn stats_response(socket: &mut (impl AsyncWrite + Unpin)) -> Result<(), Error> {
+    let mut message = protocol_admin::memcache_stats();
+
+    // identify the instance just before the terminating `END`
+    if let Some(id) = instance::id() {
+        if let Some(end) = message.rfind("END\r\n") {
+            message.insert_str(end, &format!("STAT instance_id {id}\r\n"));
+        }
+    }
+
     socket.write_all(message.as_bytes()).await
 }
+
+enum Auth {
+    Incomplete,
+    Accepted(usize),
+    Rejected,
+}
+
+// checks that the first line is `auth <token>`, returning the bytes consumed
+// once it is accepted. Anything else closes the connection
+fn authenticate(buf: &[u8], token: &str) -> Auth {
+    let Some(end) = buf.windows(2).position(|w| w == b"\r\n") else {
+        if buf.len() > MAX_AUTH_LINE {
+            return Auth::Rejected;
+        }
+        return Auth::Incomplete;
+    };
+
+    match buf[..end].strip_prefix(b"auth ") {
+        Some(given) if constant_time_eq(given, token.as_bytes()) => Auth::Accepted(end + 2),
+        _ => Auth::Rejected,
+    }
+}
+
+// compares without returning early, so the time taken doesn't reveal how much
+// of the token was guessed correctly
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Parses and serves a line based command which is specific to the proxy.
+/// Returns the number of bytes consumed and the response, or `None` if the
+/// buffer does not start with such a command.
+fn proxy_command(buf: &[u8]) -> Option<(usize, String)> {
+    let end = buf.windows(2).position(|w| w == b"\r\n")?;
+    let line = std::str::from_utf8(&buf[..end]).ok()?;
+    let mut tokens = line.split_whitespace();
+
+    let response = match (tokens.next()?, tokens.next(), tokens.next()) {
+        // lists the open client connections
+        ("connections", None, _) => format!("{}END\r\n", connections::list()),
+        // closes all connections from the address
+        ("kill", Some(addr), None) => {
+            if connections::kill_addr(addr) > 0 {
+                "OK\r\n".to_string()
+            } else {
+                "NOT_FOUND\r\n".to_string()
+            }
+        }
+        // stops serving new client connections, existing ones are unaffected
+        ("drain", None, _) => {
+            listener::drain();
+            info!("draining, new client connections will be closed");
+            "OK\r\n".to_string()
+        }
+        // serves new client connections again
+        ("resume", None, _) => {
+            listener::resume();
+            info!("resumed accepting client connections");
+            "OK\r\n".to_string()
+        }
+        // health check which reports not ready while draining, or before the
+        // listeners have warmed up their clients
+        ("ready", None, _) => {
+            if listener::is_draining() {
+                "DRAINING\r\n".to_string()
+            } else if listener::is_warming_up() {
+                "WARMING\r\n".to_string()
+            } else {
+                "READY\r\n".to_string()
+            }
+        }
+        // shows or temporarily changes the log level
+        ("log_level", None, _) => format!("LOG_LEVEL {}\r\n", log::max_level()),
+        ("log_level", Some(level), seconds) => match (level.parse(), parse_seconds(seconds)) {
+            (Ok(level), Some(seconds)) => {
+                set_log_level(level, seconds);
+                "OK\r\n".to_string()
+            }
+            _ => "CLIENT_ERROR bad log level or duration\r\n".to_string(),
+        },
+        // shows or temporarily changes the klog sampling
+        ("klog_sample", None, _) => format!("KLOG_SAMPLE {}\r\n", klog::sample()),
+        ("klog_sample", Some(n), seconds) => match (n.parse(), parse_seconds(seconds)) {
+            (Ok(n), Some(seconds)) => {
+                set_klog_sample(n, seconds);
+                "OK\r\n".to_string()
+            }
+            _ => "CLIENT_ERROR bad sample rate or duration\r\n".to_string(),
+        },
+        _ => return None,
+    };
+
+    Some((end + 2, response))
+}
+
+// a missing duration means the default, and zero makes the change permanent
+fn parse_seconds(seconds: Option<&str>) -> Option<u64> {
+    match seconds {
+        Some(seconds) => seconds.parse().ok(),
+        None => Some(DEFAULT_OVERRIDE_SECONDS),
+    }
+}
+
+/// Changes the log level, reverting to the previous level after `seconds`
+/// unless it is zero.
+fn set_log_level(level: log::LevelFilter, seconds: u64) {
+    let generation = LOG_LEVEL_GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+
+    let mut base = LOG_LEVEL_BASE.lock().unwrap();
+    if seconds == 0 {
+        *base = None;
+    } else if base.is_none() {
+        *base = Some(log::max_level());
+    }
+    drop(base);
+
+    info!("log level changed to {level} for {seconds} seconds");
+    log::set_max_level(level);
+
+    if seconds > 0 {
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(seconds)).await;
+            if LOG_LEVEL_GENERATION.load(Ordering::Relaxed) == generation {
+                if let Some(level) = LOG_LEVEL_BASE.lock().unwrap().take() {
+                    log::set_max_level(level);
+                    info!("log level reverted to {level}");
+                }
+            }
+        });
+    }
+}
+
+/// Changes the klog sampling, reverting to the previous sampling after
+/// `seconds` unless it is zero.
+fn set_klog_sample(n: u64, seconds: u64) {
+    let generation = KLOG_SAMPLE_GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+
+    let mut base = KLOG_SAMPLE_BASE.lock().unwrap();
+    if seconds == 0 {
+        *base = None;
+    } else if base.is_none() {
+        *base = Some(klog::sample());
+    }
+    drop(base);
+
+    info!("klog sampling changed to 1 in {n} for {seconds} seconds");
+    klog::set_sample(n);
+
+    if seconds > 0 {
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(seconds)).await;
+            if KLOG_SAMPLE_GENERATION.load(Ordering::Relaxed) == generation {
+                if let Some(n) = KLOG_SAMPLE_BASE.lock().unwrap().take() {
+                    klog::set_sample(n);
+                    info!("klog sampling reverted to 1 in {n}");
+                }
+            }
+        });
+    }
+}
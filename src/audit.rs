@@ -0,0 +1,271 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! An audit log of the writes and deletes of keys with the configured
+//! prefixes, from either protocol. Unlike the command log, it is never sampled
+//! and is written to its own file. Requests are audited as they are
+//! dispatched, so writes the proxy refuses, such as `FLUSHALL`, are recorded
+//! along with their result.
+//!
+//! Each line is the time in milliseconds since the epoch, the client address
+//! and name, the command and key, and the klog status, followed by the
+//! SHA-256, as hex, of the hash of the line before it, a space, and the rest
+//! of the line. The first line of a file follows a hash of zeros. Editing,
+//! removing, or reordering lines breaks the chain from that line on, which
+//! `momento-proxy audit-verify` reports.
+//!
+//! The lines are chained and written by a thread of their own, so requests
+//! never wait on the file.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::connections::Connection;
+use crate::klog::Status;
+use crate::*;
+
+#[metric(name = "audit_log_entry")]
+pub static AUDIT_LOG_ENTRY: Counter = Counter::new();
+
+#[metric(name = "audit_log_ex")]
+pub static AUDIT_LOG_EX: Counter = Counter::new();
+
+// the commands of either protocol which change or remove keys, whether or not
+// the proxy serves them, in lowercase and sorted
+const MUTATING: &[&str] = &[
+    "add",
+    "append",
+    "bitop",
+    "cas",
+    "decr",
+    "decrby",
+    "del",
+    "delete",
+    "expire",
+    "expireat",
+    "flush_all",
+    "flushall",
+    "flushdb",
+    "getdel",
+    "getex",
+    "getset",
+    "hdel",
+    "hincrby",
+    "hincrbyfloat",
+    "hmset",
+    "hset",
+    "hsetnx",
+    "incr",
+    "incrby",
+    "incrbyfloat",
+    "linsert",
+    "lmpop",
+    "lpop",
+    "lpush",
+    "lrem",
+    "lset",
+    "ltrim",
+    "mset",
+    "msetnx",
+    "persist",
+    "pexpire",
+    "pexpireat",
+    "pfadd",
+    "pfmerge",
+    "prepend",
+    "psetex",
+    "rename",
+    "replace",
+    "restore",
+    "rpop",
+    "rpush",
+    "sadd",
+    "set",
+    "setbit",
+    "setex",
+    "setnx",
+    "setrange",
+    "smove",
+    "spop",
+    "srem",
+    "touch",
+    "unlink",
+    "zadd",
+    "zincrby",
+    "zpopmax",
+    "zpopmin",
+    "zrem",
+    "zunionstore",
+];
+
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+static AUDIT: OnceLock<AuditLog> = OnceLock::new();
+
+struct AuditLog {
+    prefixes: Vec<Vec<u8>>,
+    // hands entries to the writer thread
+    entries: mpsc::Sender<String>,
+}
+
+/// Opens the audit log, continuing the chain of an existing file, and starts
+/// the thread which writes to it. Without a file, nothing is audited. With no
+/// prefixes, every key is audited.
+pub(crate) fn init(path: Option<String>, prefixes: Vec<String>) -> Result<(), String> {
+    let Some(path) = path else {
+        return Ok(());
+    };
+
+    let last = match File::open(&path) {
+        Ok(file) => BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .last()
+            .and_then(|line| line.rsplit_once(' ').map(|(_, hash)| hash.to_string())),
+        Err(_) => None,
+    };
+
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("could not open audit log `{path}`: {e}"))?;
+
+    let (entries, receiver) = mpsc::channel();
+    let previous = last.unwrap_or_else(|| GENESIS.to_string());
+    std::thread::Builder::new()
+        .name("pelikan_audit".to_string())
+        .spawn(move || writer(file, previous, receiver))
+        .map_err(|e| format!("could not start the audit log writer: {e}"))?;
+
+    let _ = AUDIT.set(AuditLog {
+        prefixes: prefixes.into_iter().map(String::into_bytes).collect(),
+        entries,
+    });
+
+    Ok(())
+}
+
+/// Whether there is an audit log.
+pub(crate) fn is_enabled() -> bool {
+    AUDIT.get().is_some()
+}
+
+/// Whether a command of either protocol changes or removes keys, and so is
+/// audited.
+pub(crate) fn is_audited(command: &str) -> bool {
+    MUTATING
+        .binary_search(&command.to_ascii_lowercase().as_str())
+        .is_ok()
+}
+
+/// Records a command which changes or removes keys, with the client which sent
+/// it and its result. There is an entry for each of its keys with one of the
+/// prefixes, or a single entry for a command without keys, such as
+/// `FLUSHALL`, which affects every key.
+pub(crate) fn record(connection: &Connection, command: &str, keys: &[&[u8]], status: Status) {
+    let Some(audit) = AUDIT.get() else {
+        return;
+    };
+
+    let command = command.to_ascii_lowercase();
+    if !is_audited(&command) {
+        return;
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let name = connection.name();
+    let client = format!(
+        "{now} {} {}",
+        connection.addr(),
+        name.as_deref().unwrap_or("-")
+    );
+    let status = status as u8;
+
+    let entries: Vec<String> = if keys.is_empty() {
+        vec![format!("{client} \"{command}\" {status}")]
+    } else {
+        keys.iter()
+            .filter(|key| {
+                audit.prefixes.is_empty() || audit.prefixes.iter().any(|p| key.starts_with(p))
+            })
+            .map(|key| format!("{client} \"{command} {}\" {status}", key.escape_ascii()))
+            .collect()
+    };
+
+    for entry in entries {
+        if audit.entries.send(entry).is_err() {
+            AUDIT_LOG_EX.increment();
+            error!("could not write to the audit log: the writer has stopped");
+        }
+    }
+}
+
+// writes the entries in the order they were recorded, each chained to the
+// line before it
+fn writer(mut file: File, mut previous: String, entries: mpsc::Receiver<String>) {
+    for entry in entries {
+        let hash = chain(&previous, &entry);
+
+        match writeln!(file, "{entry} {hash}") {
+            Ok(()) => {
+                AUDIT_LOG_ENTRY.increment();
+                previous = hash;
+            }
+            Err(e) => {
+                AUDIT_LOG_EX.increment();
+                error!("could not write to the audit log: {e}");
+            }
+        }
+    }
+}
+
+// the hash of an entry, chained to the hash of the entry before it
+fn chain(previous: &str, entry: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(previous.as_bytes());
+    hasher.update(b" ");
+    hasher.update(entry.as_bytes());
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Checks the chain of hashes of an audit log, returning the number of lines,
+/// or the first line which does not follow from the ones before it.
+pub fn verify_audit_log(path: &str) -> Result<usize, String> {
+    let file = File::open(path).map_err(|e| format!("could not open `{path}`: {e}"))?;
+
+    let mut previous = GENESIS.to_string();
+    let mut count = 0;
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("could not read `{path}`: {e}"))?;
+        let number = number + 1;
+
+        let (entry, hash) = line
+            .rsplit_once(' ')
+            .ok_or_else(|| format!("line {number} has no hash"))?;
+        if chain(&previous, entry) != hash {
+            return Err(format!(
+                "line {number} does not follow from the lines before it"
+            ));
+        }
+
+        previous = hash.to_string();
+        count = number;
+    }
+
+    Ok(count)
+}
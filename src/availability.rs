@@ -0,0 +1,198 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Probes the Momento client of each cache with a read of a key which doesn't
+//! exist, and reports the state of its channel as gauges. The client doesn't
+//! expose the state of its gRPC channels, so it is inferred from the probes: a
+//! probe which times out or finds the service unreachable means the network
+//! between the proxy and Momento is failing, while any other error is an
+//! answer from the service, so the channel is still ready. This separates a
+//! network flap from Momento service errors during an incident.
+
+use crate::*;
+use goodmetrics::{default_gauge_factory, GaugeDimensions, StatisticSetHandle};
+use momento::cache::GetResponse;
+use std::collections::HashSet;
+use std::sync::{Mutex, OnceLock};
+
+#[metric(name = "backend_probe")]
+pub static BACKEND_PROBE: Counter = Counter::new();
+
+#[metric(name = "backend_probe_transport_error")]
+pub static BACKEND_PROBE_TRANSPORT_ERROR: Counter = Counter::new();
+
+#[metric(name = "backend_probe_service_error")]
+pub static BACKEND_PROBE_SERVICE_ERROR: Counter = Counter::new();
+
+#[metric(name = "backend_channel_transition")]
+pub static BACKEND_CHANNEL_TRANSITION: Counter = Counter::new();
+
+// the key read by probes, whether or not it exists
+const PROBE_KEY: &str = "momento-proxy-backend-probe";
+
+// the longest a probe may take, so that a connection which hangs is reported
+// as failing within a probe interval
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The state of the channel between the proxy and Momento, named after the
+/// gRPC connectivity states.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ChannelState {
+    /// no probe has completed yet
+    Connecting,
+    /// the last probe was answered by Momento
+    Ready,
+    /// the last probe timed out or couldn't reach Momento
+    TransientFailure,
+}
+
+const STATES: [ChannelState; 3] = [
+    ChannelState::Connecting,
+    ChannelState::Ready,
+    ChannelState::TransientFailure,
+];
+
+impl ChannelState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ChannelState::Connecting => "connecting",
+            ChannelState::Ready => "ready",
+            ChannelState::TransientFailure => "transient_failure",
+        }
+    }
+}
+
+// a gauge for each state of one cache's channel, set to 1 for the current
+// state and 0 for the others, in both metriken and goodmetrics
+struct ChannelGauges {
+    metriken: Vec<DynBoxedMetric<Gauge>>,
+    exported: Vec<StatisticSetHandle>,
+}
+
+impl ChannelGauges {
+    fn new(cache_name: &str) -> Self {
+        let g = default_gauge_factory();
+
+        Self {
+            metriken: STATES
+                .iter()
+                .map(|state| {
+                    MetricBuilder::new(format!("cache/{cache_name}/backend_{}", state.as_str()))
+                        .description(format!(
+                            "1 while the momento channel for cache {cache_name} is {}",
+                            state.as_str()
+                        ))
+                        .metadata("cache_name", cache_name)
+                        .build(Gauge::new())
+                })
+                .collect(),
+            exported: STATES
+                .iter()
+                .map(|state| {
+                    g.dimensioned_gauge_statistic_set(
+                        "momento_proxy",
+                        "backend_channel_state",
+                        GaugeDimensions::new([
+                            ("cache_name".to_string(), cache_name.to_string()),
+                            ("state".to_string(), state.as_str().to_string()),
+                        ]),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn set(&self, current: ChannelState) {
+        for (i, state) in STATES.iter().enumerate() {
+            let value = (*state == current) as i64;
+            self.metriken[i].set(value);
+            self.exported[i].observe(value);
+        }
+    }
+}
+
+// claims a cache for probing, so that listeners which share a cache don't
+// report its state twice
+fn claim(cache_name: &str) -> bool {
+    static PROBED: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+    PROBED
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(cache_name.to_string())
+}
+
+// whether an error means Momento couldn't be reached, rather than that it
+// answered with an error
+fn is_transport_error(error: &MomentoError) -> bool {
+    matches!(
+        error.error_code,
+        MomentoErrorCode::TimeoutError | MomentoErrorCode::ServerUnavailable
+    )
+}
+
+/// Every `interval`, probes Momento through `client` and reports the state of
+/// the channel for `cache_name`, logging each change of state. A cache which
+/// another listener already probes is left to that listener.
+pub(crate) async fn prober(client: CacheClient, cache_name: String, interval: Duration) {
+    if !claim(&cache_name) {
+        return;
+    }
+
+    let gauges = ChannelGauges::new(&cache_name);
+    let mut state = ChannelState::Connecting;
+
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let probe_timeout = PROBE_TIMEOUT.min(interval.period());
+
+    loop {
+        interval.tick().await;
+
+        BACKEND_PROBE.increment();
+        let start = std::time::Instant::now();
+        let next = match timeout(probe_timeout, client.get(&cache_name, PROBE_KEY)).await {
+            Ok(Ok(GetResponse::Hit { .. } | GetResponse::Miss)) => ChannelState::Ready,
+            Ok(Err(e)) if !is_transport_error(&e) => {
+                BACKEND_PROBE_SERVICE_ERROR.increment();
+                debug!("backend probe for cache `{cache_name}` got a service error: {e}");
+                ChannelState::Ready
+            }
+            Ok(Err(e)) => {
+                BACKEND_PROBE_TRANSPORT_ERROR.increment();
+                debug!("backend probe for cache `{cache_name}` failed: {e}");
+                ChannelState::TransientFailure
+            }
+            Err(_) => {
+                BACKEND_PROBE_TRANSPORT_ERROR.increment();
+                debug!("backend probe for cache `{cache_name}` timed out");
+                ChannelState::TransientFailure
+            }
+        };
+
+        if next != state {
+            BACKEND_CHANNEL_TRANSITION.increment();
+            match next {
+                ChannelState::TransientFailure => warn!(
+                    "momento channel for cache `{cache_name}` is {} after {:?}, was {}",
+                    next.as_str(),
+                    start.elapsed(),
+                    state.as_str()
+                ),
+                _ => info!(
+                    "momento channel for cache `{cache_name}` is {} in {:?}, was {}",
+                    next.as_str(),
+                    start.elapsed(),
+                    state.as_str()
+                ),
+            }
+            state = next;
+        }
+
+        // observed every probe, so that the exported gauges have a value for
+        // each export interval
+        gauges.set(state);
+    }
+}
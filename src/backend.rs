@@ -0,0 +1,622 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! The operations the RESP handlers for hashes, lists, sets, and sorted sets
+//! make on the backend, with a trait for each data type. The handlers take any
+//! implementation rather than a `CacheClient`, so that they can be served by a
+//! mock in tests or by a backend other than Momento. Responses are Momento's
+//! types, which another backend builds from its own.
+//!
+//! The handlers for strings, and those such as DUMP and RESTORE which read and
+//! write values of any type, still take the `CacheClient`.
+
+use std::future::Future;
+#[cfg(feature = "preview-leaderboards")]
+use std::ops::Range;
+
+use momento::cache::{
+    CollectionTtl, DictionaryFetchResponse, DictionaryGetFieldResponse,
+    DictionaryGetFieldsResponse, DictionaryIncrementResponse, DictionaryLengthResponse,
+    DictionaryRemoveFieldsResponse, DictionarySetFieldsRequest, DictionarySetFieldsResponse,
+    ListConcatenateBackResponse, ListConcatenateFrontResponse, ListFetchResponse,
+    ListLengthResponse, ListPopBackResponse, ListPopFrontResponse, ScoreBound,
+    SetAddElementsResponse, SetFetchResponse, SetRemoveElementsResponse,
+    SortedSetAggregateFunction, SortedSetElement, SortedSetFetchByScoreRequest,
+    SortedSetFetchResponse, SortedSetGetRankRequest, SortedSetGetRankResponse,
+    SortedSetGetScoreResponse, SortedSetGetScoresResponse, SortedSetIncrementScoreResponse,
+    SortedSetLengthByScoreRequest, SortedSetLengthByScoreResponse, SortedSetLengthResponse,
+    SortedSetOrder, SortedSetPutElementsResponse, SortedSetRemoveElementsResponse,
+    SortedSetUnionStoreRequest, SortedSetUnionStoreResponse,
+};
+#[cfg(feature = "preview-leaderboards")]
+use momento::leaderboard::messages::data::fetch::FetchResponse;
+#[cfg(feature = "preview-leaderboards")]
+use momento::leaderboard::{
+    Element, FetchByRankRequest, GetRankRequest, LeaderboardClient, Order, RankedElement,
+};
+use momento::{CacheClient, MomentoResult};
+
+#[cfg(test)]
+pub(crate) mod mock;
+
+/// Hashes, stored as dictionaries.
+pub(crate) trait DictionaryBackend: Send + Sync {
+    fn dictionary_fetch(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+    ) -> impl Future<Output = MomentoResult<DictionaryFetchResponse>> + Send;
+
+    fn dictionary_get_field(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        field: &[u8],
+    ) -> impl Future<Output = MomentoResult<DictionaryGetFieldResponse>> + Send;
+
+    fn dictionary_get_fields<'a>(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        fields: Vec<&'a [u8]>,
+    ) -> impl Future<Output = MomentoResult<DictionaryGetFieldsResponse<Vec<&'a [u8]>>>> + Send;
+
+    fn dictionary_increment(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        field: &[u8],
+        amount: i64,
+    ) -> impl Future<Output = MomentoResult<DictionaryIncrementResponse>> + Send;
+
+    fn dictionary_length(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+    ) -> impl Future<Output = MomentoResult<DictionaryLengthResponse>> + Send;
+
+    fn dictionary_remove_fields(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        fields: Vec<&[u8]>,
+    ) -> impl Future<Output = MomentoResult<DictionaryRemoveFieldsResponse>> + Send;
+
+    fn dictionary_set_fields(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        fields: Vec<(Vec<u8>, Vec<u8>)>,
+        ttl: CollectionTtl,
+    ) -> impl Future<Output = MomentoResult<DictionarySetFieldsResponse>> + Send;
+}
+
+/// Lists.
+pub(crate) trait ListBackend: Send + Sync {
+    fn list_fetch(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+    ) -> impl Future<Output = MomentoResult<ListFetchResponse>> + Send;
+
+    fn list_length(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+    ) -> impl Future<Output = MomentoResult<ListLengthResponse>> + Send;
+
+    fn list_pop_front(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+    ) -> impl Future<Output = MomentoResult<ListPopFrontResponse>> + Send;
+
+    fn list_pop_back(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+    ) -> impl Future<Output = MomentoResult<ListPopBackResponse>> + Send;
+
+    fn list_concatenate_front(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+        values: Vec<&[u8]>,
+    ) -> impl Future<Output = MomentoResult<ListConcatenateFrontResponse>> + Send;
+
+    fn list_concatenate_back(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+        values: Vec<&[u8]>,
+    ) -> impl Future<Output = MomentoResult<ListConcatenateBackResponse>> + Send;
+}
+
+/// Sets.
+pub(crate) trait SetBackend: Send + Sync {
+    fn set_fetch(
+        &self,
+        cache_name: &str,
+        set: &[u8],
+    ) -> impl Future<Output = MomentoResult<SetFetchResponse>> + Send;
+
+    fn set_add_elements(
+        &self,
+        cache_name: &str,
+        set: &[u8],
+        elements: Vec<&[u8]>,
+    ) -> impl Future<Output = MomentoResult<SetAddElementsResponse>> + Send;
+
+    fn set_remove_elements(
+        &self,
+        cache_name: &str,
+        set: &[u8],
+        elements: Vec<&[u8]>,
+    ) -> impl Future<Output = MomentoResult<SetRemoveElementsResponse>> + Send;
+}
+
+/// Sorted sets.
+pub(crate) trait SortedSetBackend: Send + Sync {
+    fn sorted_set_fetch_by_rank(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        order: SortedSetOrder,
+        start: Option<i32>,
+        stop: Option<i32>,
+    ) -> impl Future<Output = MomentoResult<SortedSetFetchResponse>> + Send;
+
+    #[allow(clippy::too_many_arguments)]
+    fn sorted_set_fetch_by_score(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        order: SortedSetOrder,
+        min_score: Option<ScoreBound>,
+        max_score: Option<ScoreBound>,
+        offset: Option<u32>,
+        count: Option<i32>,
+    ) -> impl Future<Output = MomentoResult<SortedSetFetchResponse>> + Send;
+
+    fn sorted_set_get_rank(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        member: &[u8],
+        order: SortedSetOrder,
+    ) -> impl Future<Output = MomentoResult<SortedSetGetRankResponse>> + Send;
+
+    fn sorted_set_get_score(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        member: &[u8],
+    ) -> impl Future<Output = MomentoResult<SortedSetGetScoreResponse>> + Send;
+
+    fn sorted_set_get_scores<'a>(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        members: Vec<&'a [u8]>,
+    ) -> impl Future<Output = MomentoResult<SortedSetGetScoresResponse<Vec<&'a [u8]>>>> + Send;
+
+    fn sorted_set_increment_score(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        member: &[u8],
+        amount: f64,
+    ) -> impl Future<Output = MomentoResult<SortedSetIncrementScoreResponse>> + Send;
+
+    fn sorted_set_length(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+    ) -> impl Future<Output = MomentoResult<SortedSetLengthResponse>> + Send;
+
+    fn sorted_set_length_by_score(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        min_score: Option<ScoreBound>,
+        max_score: Option<ScoreBound>,
+    ) -> impl Future<Output = MomentoResult<SortedSetLengthByScoreResponse>> + Send;
+
+    fn sorted_set_put_elements(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        elements: Vec<SortedSetElement<Vec<u8>>>,
+    ) -> impl Future<Output = MomentoResult<SortedSetPutElementsResponse>> + Send;
+
+    fn sorted_set_remove_elements(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        members: Vec<&[u8]>,
+    ) -> impl Future<Output = MomentoResult<SortedSetRemoveElementsResponse>> + Send;
+
+    fn sorted_set_union_store(
+        &self,
+        cache_name: &str,
+        destination: &[u8],
+        sources: Vec<(Vec<u8>, f32)>,
+        aggregate: SortedSetAggregateFunction,
+    ) -> impl Future<Output = MomentoResult<SortedSetUnionStoreResponse>> + Send;
+}
+
+/// Sorted sets stored as leaderboards. Momento's leaderboard responses can't
+/// be built outside of its SDK, so lengths and elements are returned as they
+/// are.
+#[cfg(feature = "preview-leaderboards")]
+pub(crate) trait LeaderboardBackend: Send + Sync {
+    fn leaderboard_length(
+        &self,
+        cache_name: &str,
+        leaderboard: &str,
+    ) -> impl Future<Output = MomentoResult<u32>> + Send;
+
+    fn leaderboard_fetch_by_rank(
+        &self,
+        cache_name: &str,
+        leaderboard: &str,
+        ranks: Range<u32>,
+        order: Order,
+    ) -> impl Future<Output = MomentoResult<Vec<RankedElement>>> + Send;
+
+    fn leaderboard_get_rank(
+        &self,
+        cache_name: &str,
+        leaderboard: &str,
+        ids: Vec<u32>,
+        order: Order,
+    ) -> impl Future<Output = MomentoResult<Vec<RankedElement>>> + Send;
+
+    fn leaderboard_upsert(
+        &self,
+        cache_name: &str,
+        leaderboard: &str,
+        elements: Vec<Element>,
+    ) -> impl Future<Output = MomentoResult<()>> + Send;
+
+    fn leaderboard_remove_elements(
+        &self,
+        cache_name: &str,
+        leaderboard: &str,
+        ids: Vec<u32>,
+    ) -> impl Future<Output = MomentoResult<()>> + Send;
+}
+
+// the inherent methods of the same names take precedence over the trait
+// methods, so each of these sends the request to Momento
+
+impl DictionaryBackend for CacheClient {
+    async fn dictionary_fetch(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+    ) -> MomentoResult<DictionaryFetchResponse> {
+        self.dictionary_fetch(cache_name, dictionary).await
+    }
+
+    async fn dictionary_get_field(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        field: &[u8],
+    ) -> MomentoResult<DictionaryGetFieldResponse> {
+        self.dictionary_get_field(cache_name, dictionary, field)
+            .await
+    }
+
+    async fn dictionary_get_fields<'a>(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        fields: Vec<&'a [u8]>,
+    ) -> MomentoResult<DictionaryGetFieldsResponse<Vec<&'a [u8]>>> {
+        self.dictionary_get_fields(cache_name, dictionary, fields)
+            .await
+    }
+
+    async fn dictionary_increment(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        field: &[u8],
+        amount: i64,
+    ) -> MomentoResult<DictionaryIncrementResponse> {
+        self.dictionary_increment(cache_name, dictionary, field, amount)
+            .await
+    }
+
+    async fn dictionary_length(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+    ) -> MomentoResult<DictionaryLengthResponse> {
+        self.dictionary_length(cache_name, dictionary).await
+    }
+
+    async fn dictionary_remove_fields(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        fields: Vec<&[u8]>,
+    ) -> MomentoResult<DictionaryRemoveFieldsResponse> {
+        self.dictionary_remove_fields(cache_name, dictionary, fields)
+            .await
+    }
+
+    async fn dictionary_set_fields(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        fields: Vec<(Vec<u8>, Vec<u8>)>,
+        ttl: CollectionTtl,
+    ) -> MomentoResult<DictionarySetFieldsResponse> {
+        self.send_request(DictionarySetFieldsRequest::new(cache_name, dictionary, fields).ttl(ttl))
+            .await
+    }
+}
+
+impl ListBackend for CacheClient {
+    async fn list_fetch(&self, cache_name: &str, list: &[u8]) -> MomentoResult<ListFetchResponse> {
+        self.list_fetch(cache_name, list).await
+    }
+
+    async fn list_length(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+    ) -> MomentoResult<ListLengthResponse> {
+        self.list_length(cache_name, list).await
+    }
+
+    async fn list_pop_front(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+    ) -> MomentoResult<ListPopFrontResponse> {
+        self.list_pop_front(cache_name, list).await
+    }
+
+    async fn list_pop_back(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+    ) -> MomentoResult<ListPopBackResponse> {
+        self.list_pop_back(cache_name, list).await
+    }
+
+    async fn list_concatenate_front(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+        values: Vec<&[u8]>,
+    ) -> MomentoResult<ListConcatenateFrontResponse> {
+        self.list_concatenate_front(cache_name, list, values).await
+    }
+
+    async fn list_concatenate_back(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+        values: Vec<&[u8]>,
+    ) -> MomentoResult<ListConcatenateBackResponse> {
+        self.list_concatenate_back(cache_name, list, values).await
+    }
+}
+
+impl SetBackend for CacheClient {
+    async fn set_fetch(&self, cache_name: &str, set: &[u8]) -> MomentoResult<SetFetchResponse> {
+        self.set_fetch(cache_name, set).await
+    }
+
+    async fn set_add_elements(
+        &self,
+        cache_name: &str,
+        set: &[u8],
+        elements: Vec<&[u8]>,
+    ) -> MomentoResult<SetAddElementsResponse> {
+        self.set_add_elements(cache_name, set, elements).await
+    }
+
+    async fn set_remove_elements(
+        &self,
+        cache_name: &str,
+        set: &[u8],
+        elements: Vec<&[u8]>,
+    ) -> MomentoResult<SetRemoveElementsResponse> {
+        self.set_remove_elements(cache_name, set, elements).await
+    }
+}
+
+impl SortedSetBackend for CacheClient {
+    async fn sorted_set_fetch_by_rank(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        order: SortedSetOrder,
+        start: Option<i32>,
+        stop: Option<i32>,
+    ) -> MomentoResult<SortedSetFetchResponse> {
+        self.sorted_set_fetch_by_rank(cache_name, sorted_set, order, start, stop)
+            .await
+    }
+
+    async fn sorted_set_fetch_by_score(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        order: SortedSetOrder,
+        min_score: Option<ScoreBound>,
+        max_score: Option<ScoreBound>,
+        offset: Option<u32>,
+        count: Option<i32>,
+    ) -> MomentoResult<SortedSetFetchResponse> {
+        let request = SortedSetFetchByScoreRequest::new(cache_name, sorted_set)
+            .order(order)
+            .min_score(min_score)
+            .max_score(max_score)
+            .offset(offset)
+            .count(count);
+        self.send_request(request).await
+    }
+
+    async fn sorted_set_get_rank(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        member: &[u8],
+        order: SortedSetOrder,
+    ) -> MomentoResult<SortedSetGetRankResponse> {
+        let request = SortedSetGetRankRequest::new(cache_name, sorted_set, member).order(order);
+        self.send_request(request).await
+    }
+
+    async fn sorted_set_get_score(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        member: &[u8],
+    ) -> MomentoResult<SortedSetGetScoreResponse> {
+        self.sorted_set_get_score(cache_name, sorted_set, member)
+            .await
+    }
+
+    async fn sorted_set_get_scores<'a>(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        members: Vec<&'a [u8]>,
+    ) -> MomentoResult<SortedSetGetScoresResponse<Vec<&'a [u8]>>> {
+        self.sorted_set_get_scores(cache_name, sorted_set, members)
+            .await
+    }
+
+    async fn sorted_set_increment_score(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        member: &[u8],
+        amount: f64,
+    ) -> MomentoResult<SortedSetIncrementScoreResponse> {
+        self.sorted_set_increment_score(cache_name, sorted_set, member, amount)
+            .await
+    }
+
+    async fn sorted_set_length(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+    ) -> MomentoResult<SortedSetLengthResponse> {
+        self.sorted_set_length(cache_name, sorted_set).await
+    }
+
+    async fn sorted_set_length_by_score(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        min_score: Option<ScoreBound>,
+        max_score: Option<ScoreBound>,
+    ) -> MomentoResult<SortedSetLengthByScoreResponse> {
+        let request = SortedSetLengthByScoreRequest::new(cache_name, sorted_set)
+            .min_score(min_score)
+            .max_score(max_score);
+        self.send_request(request).await
+    }
+
+    async fn sorted_set_put_elements(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        elements: Vec<SortedSetElement<Vec<u8>>>,
+    ) -> MomentoResult<SortedSetPutElementsResponse> {
+        self.sorted_set_put_elements(cache_name, sorted_set, elements)
+            .await
+    }
+
+    async fn sorted_set_remove_elements(
+        &self,
+        cache_name: &str,
+        sorted_set: &[u8],
+        members: Vec<&[u8]>,
+    ) -> MomentoResult<SortedSetRemoveElementsResponse> {
+        self.sorted_set_remove_elements(cache_name, sorted_set, members)
+            .await
+    }
+
+    async fn sorted_set_union_store(
+        &self,
+        cache_name: &str,
+        destination: &[u8],
+        sources: Vec<(Vec<u8>, f32)>,
+        aggregate: SortedSetAggregateFunction,
+    ) -> MomentoResult<SortedSetUnionStoreResponse> {
+        let request =
+            SortedSetUnionStoreRequest::new(cache_name, destination, sources).aggregate(aggregate);
+        self.send_request(request).await
+    }
+}
+
+#[cfg(feature = "preview-leaderboards")]
+impl LeaderboardBackend for LeaderboardClient {
+    async fn leaderboard_length(&self, cache_name: &str, leaderboard: &str) -> MomentoResult<u32> {
+        let response = self.leaderboard(cache_name, leaderboard).len().await?;
+        Ok(response.length())
+    }
+
+    async fn leaderboard_fetch_by_rank(
+        &self,
+        cache_name: &str,
+        leaderboard: &str,
+        ranks: Range<u32>,
+        order: Order,
+    ) -> MomentoResult<Vec<RankedElement>> {
+        let request = FetchByRankRequest::new(ranks).order(order);
+        self.leaderboard(cache_name, leaderboard)
+            .send_request(request)
+            .await
+            .map(FetchResponse::into_elements)
+    }
+
+    async fn leaderboard_get_rank(
+        &self,
+        cache_name: &str,
+        leaderboard: &str,
+        ids: Vec<u32>,
+        order: Order,
+    ) -> MomentoResult<Vec<RankedElement>> {
+        let request = GetRankRequest::new(ids).order(order);
+        self.leaderboard(cache_name, leaderboard)
+            .send_request(request)
+            .await
+            .map(FetchResponse::into_elements)
+    }
+
+    async fn leaderboard_upsert(
+        &self,
+        cache_name: &str,
+        leaderboard: &str,
+        elements: Vec<Element>,
+    ) -> MomentoResult<()> {
+        self.leaderboard(cache_name, leaderboard)
+            .upsert(elements)
+            .await?;
+        Ok(())
+    }
+
+    async fn leaderboard_remove_elements(
+        &self,
+        cache_name: &str,
+        leaderboard: &str,
+        ids: Vec<u32>,
+    ) -> MomentoResult<()> {
+        self.leaderboard(cache_name, leaderboard)
+            .remove_elements(ids)
+            .await?;
+        Ok(())
+    }
+}
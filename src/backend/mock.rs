@@ -0,0 +1,447 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! An in-memory backend for tests, which holds hashes, lists, sets, and
+//! leaderboards in maps instead of sending them to Momento. As with Momento, a
+//! collection is removed once its last element is. Collection TTLs are
+//! ignored, and a collection is instead expired by the test.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+
+use momento::cache::messages::data::dictionary::{dictionary_fetch, dictionary_get_field};
+use momento::cache::messages::data::list::{list_fetch, list_pop_back, list_pop_front};
+use momento::cache::messages::data::set::set_fetch;
+use momento::cache::{
+    CollectionTtl, DictionaryFetchResponse, DictionaryGetFieldResponse,
+    DictionaryGetFieldsResponse, DictionaryIncrementResponse, DictionaryLengthResponse,
+    DictionaryRemoveFieldsResponse, DictionarySetFieldsResponse, ListConcatenateBackResponse,
+    ListConcatenateFrontResponse, ListFetchResponse, ListLengthResponse, ListPopBackResponse,
+    ListPopFrontResponse, SetAddElementsResponse, SetFetchResponse, SetRemoveElementsResponse,
+};
+#[cfg(feature = "preview-leaderboards")]
+use momento::leaderboard::{Element, Order, RankedElement};
+use momento::{MomentoError, MomentoErrorCode, MomentoResult};
+use protocol_resp::{Request, RequestParser};
+
+#[cfg(feature = "preview-leaderboards")]
+use super::LeaderboardBackend;
+use super::{DictionaryBackend, ListBackend, SetBackend};
+use crate::*;
+
+// collections are named by their cache and key
+type Name = (String, Vec<u8>);
+
+type Fields = HashMap<Vec<u8>, Vec<u8>>;
+
+#[derive(Default)]
+pub(crate) struct MockBackend {
+    dictionaries: Mutex<HashMap<Name, Fields>>,
+    lists: Mutex<HashMap<Name, VecDeque<Vec<u8>>>>,
+    sets: Mutex<HashMap<Name, HashSet<Vec<u8>>>>,
+    // the score of each id, by cache and leaderboard name
+    #[cfg(feature = "preview-leaderboards")]
+    leaderboards: Mutex<HashMap<(String, String), HashMap<u32, f64>>>,
+}
+
+/// Parses the RESP request for a command with these arguments.
+pub(crate) fn request(args: &[&str]) -> Request {
+    let mut buf = format!("*{}\r\n", args.len());
+    for arg in args {
+        buf.push_str(&format!("${}\r\n{arg}\r\n", arg.len()));
+    }
+
+    RequestParser::new()
+        .parse(buf.as_bytes())
+        .expect("invalid request")
+        .into_inner()
+}
+
+fn name(cache_name: &str, key: &[u8]) -> Name {
+    (cache_name.to_string(), key.to_vec())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl DictionaryBackend for MockBackend {
+    async fn dictionary_fetch(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+    ) -> MomentoResult<DictionaryFetchResponse> {
+        Ok(
+            match lock(&self.dictionaries).get(&name(cache_name, dictionary)) {
+                Some(fields) => DictionaryFetchResponse::Hit {
+                    value: dictionary_fetch::Value::new(fields.clone()),
+                },
+                None => DictionaryFetchResponse::Miss,
+            },
+        )
+    }
+
+    async fn dictionary_get_field(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        field: &[u8],
+    ) -> MomentoResult<DictionaryGetFieldResponse> {
+        let dictionaries = lock(&self.dictionaries);
+        Ok(
+            match dictionaries
+                .get(&name(cache_name, dictionary))
+                .and_then(|fields| fields.get(field))
+            {
+                Some(value) => DictionaryGetFieldResponse::Hit {
+                    value: dictionary_get_field::Value::new(value.clone()),
+                },
+                None => DictionaryGetFieldResponse::Miss,
+            },
+        )
+    }
+
+    async fn dictionary_get_fields<'a>(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        fields: Vec<&'a [u8]>,
+    ) -> MomentoResult<DictionaryGetFieldsResponse<Vec<&'a [u8]>>> {
+        let dictionaries = lock(&self.dictionaries);
+        let Some(stored) = dictionaries.get(&name(cache_name, dictionary)) else {
+            return Ok(DictionaryGetFieldsResponse::Miss);
+        };
+
+        let responses = fields
+            .iter()
+            .map(|field| match stored.get(*field) {
+                Some(value) => DictionaryGetFieldResponse::Hit {
+                    value: dictionary_get_field::Value::new(value.clone()),
+                },
+                None => DictionaryGetFieldResponse::Miss,
+            })
+            .collect();
+        Ok(DictionaryGetFieldsResponse::Hit { fields, responses })
+    }
+
+    async fn dictionary_increment(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        field: &[u8],
+        amount: i64,
+    ) -> MomentoResult<DictionaryIncrementResponse> {
+        let mut dictionaries = lock(&self.dictionaries);
+        let value = dictionaries
+            .entry(name(cache_name, dictionary))
+            .or_default()
+            .entry(field.to_vec())
+            .or_insert_with(|| b"0".to_vec());
+
+        let current = std::str::from_utf8(value)
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .ok_or_else(|| MomentoError {
+                message: "the field is not an integer".to_string(),
+                error_code: MomentoErrorCode::FailedPreconditionError,
+                inner_error: None,
+                details: None,
+            })?;
+
+        let incremented = current.wrapping_add(amount);
+        *value = incremented.to_string().into_bytes();
+        Ok(DictionaryIncrementResponse { value: incremented })
+    }
+
+    async fn dictionary_length(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+    ) -> MomentoResult<DictionaryLengthResponse> {
+        Ok(
+            match lock(&self.dictionaries).get(&name(cache_name, dictionary)) {
+                Some(fields) => DictionaryLengthResponse::Hit {
+                    length: fields.len() as u32,
+                },
+                None => DictionaryLengthResponse::Miss,
+            },
+        )
+    }
+
+    async fn dictionary_remove_fields(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        fields: Vec<&[u8]>,
+    ) -> MomentoResult<DictionaryRemoveFieldsResponse> {
+        let mut dictionaries = lock(&self.dictionaries);
+        let name = name(cache_name, dictionary);
+        if let Some(stored) = dictionaries.get_mut(&name) {
+            for field in fields {
+                stored.remove(field);
+            }
+            if stored.is_empty() {
+                dictionaries.remove(&name);
+            }
+        }
+        Ok(DictionaryRemoveFieldsResponse {})
+    }
+
+    async fn dictionary_set_fields(
+        &self,
+        cache_name: &str,
+        dictionary: &[u8],
+        fields: Vec<(Vec<u8>, Vec<u8>)>,
+        _ttl: CollectionTtl,
+    ) -> MomentoResult<DictionarySetFieldsResponse> {
+        lock(&self.dictionaries)
+            .entry(name(cache_name, dictionary))
+            .or_default()
+            .extend(fields);
+        Ok(DictionarySetFieldsResponse {})
+    }
+}
+
+impl ListBackend for MockBackend {
+    async fn list_fetch(&self, cache_name: &str, list: &[u8]) -> MomentoResult<ListFetchResponse> {
+        Ok(match lock(&self.lists).get(&name(cache_name, list)) {
+            Some(values) => ListFetchResponse::Hit {
+                values: list_fetch::Value::new(values.iter().cloned().collect()),
+            },
+            None => ListFetchResponse::Miss,
+        })
+    }
+
+    async fn list_length(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+    ) -> MomentoResult<ListLengthResponse> {
+        Ok(match lock(&self.lists).get(&name(cache_name, list)) {
+            Some(values) => ListLengthResponse::Hit {
+                length: values.len() as u32,
+            },
+            None => ListLengthResponse::Miss,
+        })
+    }
+
+    async fn list_pop_front(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+    ) -> MomentoResult<ListPopFrontResponse> {
+        Ok(match self.pop(cache_name, list, VecDeque::pop_front) {
+            Some(value) => ListPopFrontResponse::Hit {
+                value: list_pop_front::Value::new(value),
+            },
+            None => ListPopFrontResponse::Miss,
+        })
+    }
+
+    async fn list_pop_back(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+    ) -> MomentoResult<ListPopBackResponse> {
+        Ok(match self.pop(cache_name, list, VecDeque::pop_back) {
+            Some(value) => ListPopBackResponse::Hit {
+                value: list_pop_back::Value::new(value),
+            },
+            None => ListPopBackResponse::Miss,
+        })
+    }
+
+    async fn list_concatenate_front(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+        values: Vec<&[u8]>,
+    ) -> MomentoResult<ListConcatenateFrontResponse> {
+        let mut lists = lock(&self.lists);
+        let stored = lists.entry(name(cache_name, list)).or_default();
+        for value in values.into_iter().rev() {
+            stored.push_front(value.to_vec());
+        }
+        Ok(ListConcatenateFrontResponse {})
+    }
+
+    async fn list_concatenate_back(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+        values: Vec<&[u8]>,
+    ) -> MomentoResult<ListConcatenateBackResponse> {
+        lock(&self.lists)
+            .entry(name(cache_name, list))
+            .or_default()
+            .extend(values.into_iter().map(|value| value.to_vec()));
+        Ok(ListConcatenateBackResponse {})
+    }
+}
+
+impl SetBackend for MockBackend {
+    async fn set_fetch(&self, cache_name: &str, set: &[u8]) -> MomentoResult<SetFetchResponse> {
+        Ok(match lock(&self.sets).get(&name(cache_name, set)) {
+            Some(elements) => SetFetchResponse::Hit {
+                values: set_fetch::Value::new(elements.iter().cloned().collect()),
+            },
+            None => SetFetchResponse::Miss,
+        })
+    }
+
+    async fn set_add_elements(
+        &self,
+        cache_name: &str,
+        set: &[u8],
+        elements: Vec<&[u8]>,
+    ) -> MomentoResult<SetAddElementsResponse> {
+        lock(&self.sets)
+            .entry(name(cache_name, set))
+            .or_default()
+            .extend(elements.into_iter().map(|element| element.to_vec()));
+        Ok(SetAddElementsResponse {})
+    }
+
+    async fn set_remove_elements(
+        &self,
+        cache_name: &str,
+        set: &[u8],
+        elements: Vec<&[u8]>,
+    ) -> MomentoResult<SetRemoveElementsResponse> {
+        let mut sets = lock(&self.sets);
+        let name = name(cache_name, set);
+        if let Some(stored) = sets.get_mut(&name) {
+            for element in elements {
+                stored.remove(element);
+            }
+            if stored.is_empty() {
+                sets.remove(&name);
+            }
+        }
+        Ok(SetRemoveElementsResponse {})
+    }
+}
+
+#[cfg(feature = "preview-leaderboards")]
+impl LeaderboardBackend for MockBackend {
+    async fn leaderboard_length(&self, cache_name: &str, leaderboard: &str) -> MomentoResult<u32> {
+        Ok(self.ranked(cache_name, leaderboard, Order::Ascending).len() as u32)
+    }
+
+    async fn leaderboard_fetch_by_rank(
+        &self,
+        cache_name: &str,
+        leaderboard: &str,
+        ranks: std::ops::Range<u32>,
+        order: Order,
+    ) -> MomentoResult<Vec<RankedElement>> {
+        Ok(self
+            .ranked(cache_name, leaderboard, order)
+            .into_iter()
+            .filter(|element| ranks.contains(&element.rank))
+            .collect())
+    }
+
+    async fn leaderboard_get_rank(
+        &self,
+        cache_name: &str,
+        leaderboard: &str,
+        ids: Vec<u32>,
+        order: Order,
+    ) -> MomentoResult<Vec<RankedElement>> {
+        Ok(self
+            .ranked(cache_name, leaderboard, order)
+            .into_iter()
+            .filter(|element| ids.contains(&element.id))
+            .collect())
+    }
+
+    async fn leaderboard_upsert(
+        &self,
+        cache_name: &str,
+        leaderboard: &str,
+        elements: Vec<Element>,
+    ) -> MomentoResult<()> {
+        lock(&self.leaderboards)
+            .entry((cache_name.to_string(), leaderboard.to_string()))
+            .or_default()
+            .extend(
+                elements
+                    .into_iter()
+                    .map(|element| (element.id, element.score)),
+            );
+        Ok(())
+    }
+
+    async fn leaderboard_remove_elements(
+        &self,
+        cache_name: &str,
+        leaderboard: &str,
+        ids: Vec<u32>,
+    ) -> MomentoResult<()> {
+        let mut leaderboards = lock(&self.leaderboards);
+        let name = (cache_name.to_string(), leaderboard.to_string());
+        if let Some(scores) = leaderboards.get_mut(&name) {
+            for id in ids {
+                scores.remove(&id);
+            }
+            if scores.is_empty() {
+                leaderboards.remove(&name);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MockBackend {
+    /// Removes the collection at a key, as if its TTL had run out.
+    pub(crate) fn expire(&self, cache_name: &str, key: &[u8]) {
+        let name = name(cache_name, key);
+        lock(&self.dictionaries).remove(&name);
+        lock(&self.lists).remove(&name);
+        lock(&self.sets).remove(&name);
+    }
+
+    // the elements of a leaderboard in rank order, with ties ranked by id
+    #[cfg(feature = "preview-leaderboards")]
+    fn ranked(&self, cache_name: &str, leaderboard: &str, order: Order) -> Vec<RankedElement> {
+        let leaderboards = lock(&self.leaderboards);
+        let mut elements: Vec<(u32, f64)> = leaderboards
+            .get(&(cache_name.to_string(), leaderboard.to_string()))
+            .map(|scores| scores.iter().map(|(id, score)| (*id, *score)).collect())
+            .unwrap_or_default();
+
+        elements.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        if matches!(order, Order::Descending) {
+            elements.reverse();
+        }
+
+        elements
+            .into_iter()
+            .enumerate()
+            .map(|(rank, (id, score))| RankedElement {
+                id,
+                rank: rank as u32,
+                score,
+            })
+            .collect()
+    }
+
+    // pops a value from one end of a list, removing the list once it is empty
+    fn pop(
+        &self,
+        cache_name: &str,
+        list: &[u8],
+        pop: fn(&mut VecDeque<Vec<u8>>) -> Option<Vec<u8>>,
+    ) -> Option<Vec<u8>> {
+        let mut lists = lock(&self.lists);
+        let name = name(cache_name, list);
+        let stored = lists.get_mut(&name)?;
+        let value = pop(stored);
+        if stored.is_empty() {
+            lists.remove(&name);
+        }
+        value
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Accounting for the memory held by connection buffers. Buffers grow to fit
+//! large requests and responses, so they are returned to their configured size
+//! once they are empty again rather than pinning the memory for the life of
+//! the connection.
+
+use crate::*;
+
+#[metric(name = "buffer_bytes")]
+pub static BUFFER_BYTES: Gauge = Gauge::new();
+
+#[metric(name = "buffer_shrink")]
+pub static BUFFER_SHRINK: Counter = Counter::new();
+
+/// Tracks one connection buffer in `buffer_bytes`.
+pub(crate) struct BufferGauge {
+    size: usize,
+    bytes: usize,
+}
+
+impl BufferGauge {
+    /// Starts tracking `buffer`, which was created with `size`.
+    pub fn new(buffer: &Buffer, size: usize) -> Self {
+        let bytes = footprint(buffer);
+        BUFFER_BYTES.add(bytes as _);
+
+        Self { size, bytes }
+    }
+
+    /// Records any growth of the buffer.
+    pub fn update(&mut self, buffer: &Buffer) {
+        let bytes = footprint(buffer);
+        if bytes != self.bytes {
+            BUFFER_BYTES.add(bytes as i64 - self.bytes as i64);
+            self.bytes = bytes;
+        }
+    }
+
+    /// Whether the buffer is empty and has grown well past its configured
+    /// size.
+    pub fn is_oversized(&self, buffer: &Buffer) -> bool {
+        buffer.remaining() == 0 && footprint(buffer) > 2 * self.size
+    }
+
+    /// Replaces an empty, oversized buffer with one of the configured size.
+    pub fn shrink(&mut self, buffer: &mut Buffer) {
+        if !self.is_oversized(buffer) {
+            return;
+        }
+
+        *buffer = Buffer::new(self.size);
+        BUFFER_SHRINK.increment();
+        self.update(buffer);
+    }
+}
+
+impl Drop for BufferGauge {
+    fn drop(&mut self) {
+        BUFFER_BYTES.sub(self.bytes as _);
+    }
+}
+
+// the bytes the buffer holds, this excludes space already consumed at the front
+// of the buffer so it is an approximation of the allocation
+fn footprint(buffer: &Buffer) -> usize {
+    buffer.remaining() + buffer.remaining_mut()
+}
@@ -1,23 +1,172 @@
 use std::{
     borrow::Borrow,
+    collections::hash_map::RandomState,
+    fs::File,
+    hash::BuildHasher,
+    io::{BufReader, BufWriter, Read, Write},
     mem::size_of,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Arc,
+    },
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use moka::{sync::Cache, Expiry};
+use metriken::{metric, Counter};
+use moka::notification::RemovalCause;
+use moka::ops::compute::{CompResult, Op};
+use moka::{policy::EvictionPolicy, sync::Cache, Expiry};
+use tokio::sync::broadcast;
+
+use crate::klog::{klog_2, Status};
+use crate::momento_proxy::MemoryCacheEviction;
+use crate::policy::KeyPolicy;
+
+#[metric(name = "memory_cache_reject_size")]
+pub static MEMORY_CACHE_REJECT_SIZE: Counter = Counter::new();
+
+#[metric(name = "memory_cache_reject_prefix")]
+pub static MEMORY_CACHE_REJECT_PREFIX: Counter = Counter::new();
+
+#[metric(name = "memory_cache_reject_frequency")]
+pub static MEMORY_CACHE_REJECT_FREQUENCY: Counter = Counter::new();
+
+#[metric(name = "memory_cache_set_fail")]
+pub static MEMORY_CACHE_SET_FAIL: Counter = Counter::new();
+
+#[metric(name = "memory_cache_set_invalidate")]
+pub static MEMORY_CACHE_SET_INVALIDATE: Counter = Counter::new();
+
+#[metric(name = "memory_cache_evict_size")]
+pub static MEMORY_CACHE_EVICT_SIZE: Counter = Counter::new();
+
+#[metric(name = "memory_cache_evict_expired")]
+pub static MEMORY_CACHE_EVICT_EXPIRED: Counter = Counter::new();
+
+#[metric(name = "memory_cache_evict_explicit")]
+pub static MEMORY_CACHE_EVICT_EXPLICIT: Counter = Counter::new();
+
+#[metric(name = "memory_cache_evict_replaced")]
+pub static MEMORY_CACHE_EVICT_REPLACED: Counter = Counter::new();
+
+#[metric(name = "memory_cache_evict_event_drop")]
+pub static MEMORY_CACHE_EVICT_EVENT_DROP: Counter = Counter::new();
+
+// how many eviction events a slow subscriber may fall behind by before it
+// misses some
+const EVICTION_EVENTS: usize = 1024;
+
+/// The outcome of a write to the memory cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheWrite {
+    Stored,
+    /// the admission policy turned the item away
+    Rejected,
+    /// the item can't be held, as it is larger than the whole cache
+    Failed,
+}
+
+/// Why an entry left the memory cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionCause {
+    /// evicted to make room for other entries
+    Size,
+    /// its TTL, and any stale window, passed
+    Expired,
+    /// deleted or invalidated by the proxy
+    Explicit,
+    /// overwritten by a newer value for the key
+    Replaced,
+}
+
+impl EvictionCause {
+    fn counter(self) -> &'static Counter {
+        match self {
+            Self::Size => &MEMORY_CACHE_EVICT_SIZE,
+            Self::Expired => &MEMORY_CACHE_EVICT_EXPIRED,
+            Self::Explicit => &MEMORY_CACHE_EVICT_EXPLICIT,
+            Self::Replaced => &MEMORY_CACHE_EVICT_REPLACED,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Size => "size",
+            Self::Expired => "expired",
+            Self::Explicit => "explicit",
+            Self::Replaced => "replaced",
+        }
+    }
+}
+
+impl From<RemovalCause> for EvictionCause {
+    fn from(cause: RemovalCause) -> Self {
+        match cause {
+            RemovalCause::Size => Self::Size,
+            RemovalCause::Expired => Self::Expired,
+            RemovalCause::Explicit => Self::Explicit,
+            RemovalCause::Replaced => Self::Replaced,
+        }
+    }
+}
+
+/// A key which left the memory cache, as sent to the subscribers of
+/// `MCache::subscribe_evictions`.
+#[derive(Debug, Clone)]
+pub struct Eviction {
+    pub key: Arc<KeyType>,
+    pub cause: EvictionCause,
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CacheValue {
     Memcached { value: protocol_memcache::Value },
 }
 
+/// An entry of the memory cache. The memcache flags are held by the value,
+/// while the entry records when it was stored and a CAS token which changes
+/// with every write of the key.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CacheEntry {
     value: CacheValue,
     expire_at: Instant,
+    stored_at: Instant,
+    cas: u64,
 }
 
+// the CAS token of the next write, shared by every memory cache so that a
+// token is never reused for a key
+static NEXT_CAS: AtomicU64 = AtomicU64::new(1);
+
 impl CacheEntry {
+    fn new(value: CacheValue, expire_at: Instant, stored_at: Instant) -> Self {
+        Self {
+            value,
+            expire_at,
+            stored_at,
+            cas: NEXT_CAS.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// The memcache flags stored with the value
+    pub fn flags(&self) -> u32 {
+        let CacheValue::Memcached { value } = &self.value;
+        value.flags()
+    }
+
+    /// The token of the write which stored this entry, which is greater for
+    /// each later write
+    pub fn cas(&self) -> u64 {
+        self.cas
+    }
+
+    /// How long ago the entry was stored. Entries restored from a snapshot
+    /// count from when they were restored.
+    pub fn age(&self) -> Duration {
+        self.stored_at.elapsed()
+    }
+
     pub fn _expiry_epoch_seconds(&self) -> i64 {
         match SystemTime::now().duration_since(UNIX_EPOCH) {
             Ok(n) => {
@@ -35,7 +184,80 @@ impl CacheEntry {
 #[derive(Clone)]
 pub struct MCache {
     cache: Cache<KeyType, CacheEntry>,
-    ttl: Duration,
+    // in milliseconds, shared by the clones so it can be changed at runtime
+    ttl: Arc<AtomicU64>,
+    stale: Duration,
+    admission: Arc<AdmissionPolicy>,
+    sketch: Option<Arc<FrequencySketch>>,
+    evictions: broadcast::Sender<Eviction>,
+}
+
+/// Controls which items are admitted into the memory cache.
+#[derive(Clone, Debug, Default)]
+pub struct AdmissionPolicy {
+    /// largest value which will be admitted, 0 for no limit
+    pub max_value_bytes: usize,
+    /// if non-empty, only keys with one of these prefixes are admitted
+    pub include_prefixes: Vec<Vec<u8>>,
+    /// keys with any of these prefixes are never admitted
+    pub exclude_prefixes: Vec<Vec<u8>>,
+    /// only admit keys which have been seen recently
+    pub frequency_filter: bool,
+    /// key rules which admit or refuse keys regardless of the prefixes
+    pub key_policy: KeyPolicy,
+}
+
+/// A count-min sketch of recent key frequencies, used as a TinyLFU-style
+/// doorkeeper so that keys seen only once don't displace hot entries.
+struct FrequencySketch {
+    counters: Vec<AtomicU8>,
+    mask: usize,
+    hasher: RandomState,
+    additions: AtomicU64,
+    sample_size: u64,
+}
+
+impl FrequencySketch {
+    // number of independent counter rows
+    const DEPTH: usize = 4;
+
+    fn new(width: usize) -> Self {
+        let width = width.clamp(1024, 1 << 20).next_power_of_two();
+
+        Self {
+            counters: (0..width * Self::DEPTH).map(|_| AtomicU8::new(0)).collect(),
+            mask: width - 1,
+            hasher: RandomState::new(),
+            additions: AtomicU64::new(0),
+            sample_size: 10 * width as u64,
+        }
+    }
+
+    /// Records an occurrence of the key and returns its estimated frequency,
+    /// including this occurrence.
+    fn increment(&self, key: &[u8]) -> u8 {
+        let hash = self.hasher.hash_one(key);
+        let mut estimate = u8::MAX;
+
+        for row in 0..Self::DEPTH {
+            let index =
+                row * (self.mask + 1) + (hash.rotate_left(16 * row as u32) as usize & self.mask);
+            let previous = self.counters[index]
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| v.checked_add(1))
+                .unwrap_or(u8::MAX);
+            estimate = estimate.min(previous.saturating_add(1));
+        }
+
+        // periodically age the counters so the sketch tracks recent activity
+        if self.additions.fetch_add(1, Ordering::Relaxed) + 1 >= self.sample_size {
+            self.additions.store(0, Ordering::Relaxed);
+            for counter in self.counters.iter() {
+                let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| Some(v / 2));
+            }
+        }
+
+        estimate
+    }
 }
 
 fn weigh(key: &KeyType, value: &CacheEntry) -> u32 {
@@ -46,7 +268,18 @@ fn weigh(key: &KeyType, value: &CacheEntry) -> u32 {
         + size_of::<protocol_memcache::Value>()) as u32
 }
 
-struct MCacheExpiry;
+/// Keeps entries around for the stale window after they expire so they can
+/// still be served when the backend is failing.
+struct MCacheExpiry {
+    stale: Duration,
+}
+
+/// The longest TTL and stale window, which keeps the expiry instants from
+/// overflowing. Items with this TTL effectively never expire.
+pub const MAX_TTL: Duration = Duration::from_secs(5 * 365 * 24 * 3600);
+
+// identifies a memory cache snapshot file and its format version
+const SNAPSHOT_MAGIC: &[u8; 8] = b"MPCACHE1";
 
 type KeyType = Vec<u8>;
 
@@ -59,7 +292,7 @@ impl Expiry<KeyType, CacheEntry> for MCacheExpiry {
         value: &CacheEntry,
         current_time: Instant,
     ) -> Option<Duration> {
-        Some(value.expire_at.saturating_duration_since(current_time))
+        Some((value.expire_at + self.stale).saturating_duration_since(current_time))
     }
 
     fn expire_after_update(
@@ -69,24 +302,133 @@ impl Expiry<KeyType, CacheEntry> for MCacheExpiry {
         updated_at: Instant,
         _duration_until_expiry: Option<Duration>,
     ) -> Option<Duration> {
-        Some(value.expire_at.saturating_duration_since(updated_at))
+        Some((value.expire_at + self.stale).saturating_duration_since(updated_at))
     }
 }
 
 impl MCache {
     pub fn new(max_bytes: usize, ttl: Duration) -> Self {
+        Self::with_stale(max_bytes, ttl, Duration::ZERO, Default::default())
+    }
+
+    /// Creates a cache which keeps entries for `stale` past their expiry. Such
+    /// entries are only returned by `get_stale`.
+    pub fn with_stale(
+        max_bytes: usize,
+        ttl: Duration,
+        stale: Duration,
+        eviction: MemoryCacheEviction,
+    ) -> Self {
+        // bound both so the expiry instants can't overflow
+        let stale = std::cmp::min(stale, MAX_TTL);
+
+        let eviction = match eviction {
+            MemoryCacheEviction::TinyLfu => EvictionPolicy::tiny_lfu(),
+            MemoryCacheEviction::Lru => EvictionPolicy::lru(),
+        };
+
+        // every eviction is counted by its cause, and is only sent on when
+        // something is listening for them
+        let (evictions, _) = broadcast::channel(EVICTION_EVENTS);
+        let sender = evictions.clone();
+        let listener = move |key: Arc<KeyType>, _: CacheEntry, cause: RemovalCause| {
+            let cause = EvictionCause::from(cause);
+            cause.counter().increment();
+            if sender.receiver_count() > 0 {
+                let _ = sender.send(Eviction { key, cause });
+            }
+        };
+
         let cache = Cache::builder()
             .max_capacity(max_bytes as u64)
             .weigher(weigh)
-            .expire_after(MCacheExpiry)
+            .eviction_policy(eviction)
+            .expire_after(MCacheExpiry { stale })
+            .eviction_listener(listener)
             .build();
         Self {
             cache,
-            ttl: std::cmp::min(ttl, Duration::from_secs(5 * 365 * 24 * 3600)),
+            ttl: Arc::new(AtomicU64::new(
+                std::cmp::min(ttl, MAX_TTL).as_millis() as u64
+            )),
+            stale,
+            admission: Default::default(),
+            sketch: None,
+            evictions,
+        }
+    }
+
+    /// Receives the keys which leave the memory cache from now on. A receiver
+    /// which falls too far behind misses the oldest events.
+    pub fn subscribe_evictions(&self) -> broadcast::Receiver<Eviction> {
+        self.evictions.subscribe()
+    }
+
+    /// Applies an admission policy to future insertions.
+    pub fn with_admission(mut self, admission: AdmissionPolicy) -> Self {
+        if admission.frequency_filter {
+            let width = (self.cache.policy().max_capacity().unwrap_or_default() / 1024) as usize;
+            self.sketch = Some(Arc::new(FrequencySketch::new(width)));
+        }
+        self.admission = Arc::new(admission);
+        self
+    }
+
+    fn admit(&self, key: &[u8], value: &CacheValue) -> bool {
+        let admission = &self.admission;
+
+        // a key rule decides in place of the prefixes
+        let ruled = admission
+            .key_policy
+            .rule(key)
+            .and_then(|rule| rule.memory_cache());
+        let refused_by_prefix = || {
+            admission
+                .exclude_prefixes
+                .iter()
+                .any(|prefix| key.starts_with(prefix))
+                || (!admission.include_prefixes.is_empty()
+                    && !admission
+                        .include_prefixes
+                        .iter()
+                        .any(|prefix| key.starts_with(prefix)))
+        };
+        if ruled == Some(false) || (ruled.is_none() && refused_by_prefix()) {
+            MEMORY_CACHE_REJECT_PREFIX.increment();
+            return false;
+        }
+
+        let CacheValue::Memcached { value } = value;
+        if admission.max_value_bytes > 0
+            && value.len().unwrap_or_default() > admission.max_value_bytes
+        {
+            MEMORY_CACHE_REJECT_SIZE.increment();
+            return false;
+        }
+
+        if let Some(sketch) = &self.sketch {
+            if sketch.increment(key) < 2 && !self.cache.contains_key(key) {
+                MEMORY_CACHE_REJECT_FREQUENCY.increment();
+                return false;
+            }
         }
+
+        true
     }
 
     pub fn get<Q>(&self, key: &Q) -> Option<CacheEntry>
+    where
+        KeyType: Borrow<Q>,
+        Q: std::hash::Hash + Eq + ?Sized,
+    {
+        self.cache
+            .get(&key)
+            .filter(|entry| self.stale.is_zero() || entry.expire_at > Instant::now())
+    }
+
+    /// Like `get`, but also returns entries which have expired within the
+    /// stale window.
+    pub fn get_stale<Q>(&self, key: &Q) -> Option<CacheEntry>
     where
         KeyType: Borrow<Q>,
         Q: std::hash::Hash + Eq + ?Sized,
@@ -94,14 +436,53 @@ impl MCache {
         self.cache.get(&key)
     }
 
-    pub fn set(&self, key: KeyType, value: impl Into<CacheValue>) {
-        self.cache.insert(
-            key,
-            CacheEntry {
-                value: value.into(),
-                expire_at: Instant::now() + self.ttl,
-            },
-        )
+    pub fn set(&self, key: KeyType, value: impl Into<CacheValue>) -> CacheWrite {
+        self.set_with_ttl(key, value, self.ttl())
+    }
+
+    /// The longest an item is kept before it expires.
+    pub fn ttl(&self) -> Duration {
+        Duration::from_millis(self.ttl.load(Ordering::Relaxed))
+    }
+
+    /// Changes the TTL of items inserted from now on.
+    pub fn set_ttl(&self, ttl: Duration) {
+        self.ttl.store(
+            std::cmp::min(ttl, MAX_TTL).as_millis() as u64,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Inserts an item which expires after the lesser of the provided TTL and
+    /// the memory cache TTL, so that items are never served locally past the
+    /// expiry the client requested.
+    pub fn set_with_ttl(
+        &self,
+        key: KeyType,
+        value: impl Into<CacheValue>,
+        ttl: Duration,
+    ) -> CacheWrite {
+        let value = value.into();
+
+        if !self.admit(&key, &value) {
+            // make sure a rejected write doesn't leave a stale entry behind
+            self.cache.invalidate(&key);
+            return CacheWrite::Rejected;
+        }
+
+        let now = Instant::now();
+        let entry = CacheEntry::new(value, now + std::cmp::min(ttl, self.ttl()), now);
+
+        // an entry heavier than the cache would be evicted right away, while
+        // the previous value for the key stays behind
+        if weigh(&key, &entry) as u64 > self.cache.policy().max_capacity().unwrap_or(u64::MAX) {
+            MEMORY_CACHE_SET_FAIL.increment();
+            self.cache.invalidate(&key);
+            return CacheWrite::Failed;
+        }
+
+        self.cache.insert(key, entry);
+        CacheWrite::Stored
     }
 
     pub fn delete<Q>(&self, key: &Q) -> Option<CacheValue>
@@ -111,4 +492,181 @@ impl MCache {
     {
         self.cache.remove(key).map(|e| e.value)
     }
+
+    /// Removes the entry for `key` only if it is still the one with the `cas`
+    /// token, so that a write since it was read is kept. Returns whether the
+    /// entry was removed.
+    pub fn delete_if_cas(&self, key: &[u8], cas: u64) -> bool {
+        let result = self
+            .cache
+            .entry(key.to_vec())
+            .and_compute_with(|entry| match entry {
+                Some(entry) if entry.value().cas == cas => Op::Remove,
+                _ => Op::Nop,
+            });
+
+        matches!(result, CompResult::Removed(_))
+    }
+
+    /// Picks up to `count` live entries at random.
+    pub fn sample(&self, count: usize) -> Vec<(KeyType, CacheEntry)> {
+        let now = Instant::now();
+        let hasher = RandomState::new();
+        let mut sample = Vec::with_capacity(count);
+
+        // reservoir sampling, so each entry is equally likely to be picked
+        for (seen, (key, entry)) in self
+            .cache
+            .iter()
+            .filter(|(_, entry)| entry.expire_at > now)
+            .enumerate()
+        {
+            if sample.len() < count {
+                sample.push((key.as_ref().clone(), entry));
+            } else {
+                let index = (hasher.hash_one(seen) % (seen as u64 + 1)) as usize;
+                if index < count {
+                    sample[index] = (key.as_ref().clone(), entry);
+                }
+            }
+        }
+
+        sample
+    }
+
+    /// Writes all live entries to the file at `path`, returning the number of
+    /// entries written. Entries are stored with their remaining TTL so that a
+    /// restored entry expires no later than the original would have.
+    pub fn snapshot(&self, path: &Path) -> std::io::Result<usize> {
+        let tmp = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        let now = Instant::now();
+        let mut count = 0;
+
+        writer.write_all(SNAPSHOT_MAGIC)?;
+
+        for (key, entry) in self.cache.iter() {
+            let remaining = entry.expire_at.saturating_duration_since(now);
+            if remaining.is_zero() {
+                continue;
+            }
+
+            let CacheValue::Memcached { value } = &entry.value;
+            let data = value.value().unwrap_or_default();
+
+            writer.write_all(&(key.len() as u32).to_be_bytes())?;
+            writer.write_all(&key)?;
+            writer.write_all(&entry.flags().to_be_bytes())?;
+            writer.write_all(&(remaining.as_millis() as u64).to_be_bytes())?;
+            writer.write_all(&(data.len() as u32).to_be_bytes())?;
+            writer.write_all(data)?;
+
+            count += 1;
+        }
+
+        writer.flush()?;
+        drop(writer);
+
+        // rename into place so a crash mid-write never leaves a truncated
+        // snapshot behind
+        std::fs::rename(&tmp, path)?;
+
+        Ok(count)
+    }
+
+    /// Loads entries from a snapshot previously written by `snapshot`,
+    /// returning the number of entries restored.
+    pub fn restore(&self, path: &Path) -> std::io::Result<usize> {
+        let file = File::open(path)?;
+        // the bytes left in the file, which bound the lengths read from it so
+        // that a corrupt snapshot can't make us allocate more than its size
+        let mut left = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let now = Instant::now();
+        let mut count = 0;
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not a memory cache snapshot",
+            ));
+        }
+        left = left.saturating_sub(magic.len() as u64);
+
+        let mut take = |len: [u8; 4], header: u64| -> std::io::Result<Vec<u8>> {
+            let len = u32::from_be_bytes(len) as u64;
+            if len + header > left {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "memory cache snapshot is truncated",
+                ));
+            }
+            left -= len + header;
+            Ok(vec![0; len as usize])
+        };
+
+        loop {
+            let mut len = [0; 4];
+            match reader.read_exact(&mut len) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            // the key, and its length, flags, and remaining ttl
+            let mut key = take(len, 4 + 4 + 8)?;
+            reader.read_exact(&mut key)?;
+
+            let mut flags = [0; 4];
+            reader.read_exact(&mut flags)?;
+
+            let mut remaining = [0; 8];
+            reader.read_exact(&mut remaining)?;
+
+            reader.read_exact(&mut len)?;
+            let mut data = take(len, 4)?;
+            reader.read_exact(&mut data)?;
+
+            let remaining = std::cmp::min(
+                Duration::from_millis(u64::from_be_bytes(remaining)),
+                self.ttl(),
+            );
+
+            let value = protocol_memcache::Value::new(&key, u32::from_be_bytes(flags), None, &data);
+            self.cache.insert(
+                key,
+                CacheEntry::new(CacheValue::Memcached { value }, now + remaining, now),
+            );
+
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+/// Writes each key which leaves the memory cache to the command log as an
+/// `evict` of the key, with the cause in place of a field. Replaced entries
+/// are left out, as the write which replaced them is logged already.
+pub(crate) async fn log_evictions(mut evictions: broadcast::Receiver<Eviction>) {
+    loop {
+        match evictions.recv().await {
+            Ok(Eviction {
+                cause: EvictionCause::Replaced,
+                ..
+            }) => {}
+            Ok(eviction) => klog_2(
+                &"evict",
+                eviction.key.as_ref(),
+                &eviction.cause.as_str(),
+                Status::Deleted,
+                0,
+            ),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                MEMORY_CACHE_EVICT_EVENT_DROP.add(missed);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
 }
@@ -0,0 +1,299 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Preflight self-test for the `check` subcommand. Each step of bringing up
+//! the proxy is exercised without serving traffic and the outcome is printed
+//! as a JSON report.
+
+use crate::*;
+use momento::cache::{CreateCacheResponse, GetResponse, SetRequest};
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// upper bound on each call to Momento made during the check
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// round trip test items expire on their own if the delete fails
+const CHECK_ITEM_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Default)]
+struct Report {
+    ok: bool,
+    config: Step,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    admin: Option<Bind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    credentials: Option<Step>,
+    caches: Vec<CacheReport>,
+    // problems with the config which don't stop the proxy from starting
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+#[derive(Serialize, Default, Clone)]
+struct Step {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Step {
+    fn ok() -> Self {
+        Self {
+            ok: true,
+            error: None,
+        }
+    }
+
+    fn error(error: impl ToString) -> Self {
+        Self {
+            ok: false,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+struct Bind {
+    addr: String,
+    #[serde(flatten)]
+    step: Step,
+}
+
+#[derive(Serialize)]
+struct CacheReport {
+    name: String,
+    listen: Bind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exists: Option<Step>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    round_trip: Option<Step>,
+}
+
+/// Runs the self-test against the config at `file`, prints the JSON report to
+/// stdout, and returns the process exit code.
+pub fn check(file: &str) -> i32 {
+    let runtime = Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to launch tokio runtime");
+
+    let report = runtime.block_on(run(file));
+
+    match serde_json::to_string_pretty(&report) {
+        Ok(json) => println!("{json}"),
+        Err(e) => eprintln!("could not serialize check report: {e}"),
+    }
+
+    if report.ok {
+        0
+    } else {
+        1
+    }
+}
+
+async fn run(file: &str) -> Report {
+    let mut report = Report::default();
+
+    let config = match MomentoProxyConfig::load(file) {
+        Ok(c) => c,
+        Err(e) => {
+            report.config = Step::error(e);
+            return report;
+        }
+    };
+
+    report.config = if config.caches().is_empty() {
+        Step::error("no caches specified in the config")
+    } else {
+        Step::ok()
+    };
+    report.warnings = config.value_format_conflicts();
+
+    // a unix socket isn't bound here, which would replace the socket of a
+    // proxy which is already running
+    report.admin = Some(match config.admin_unix_socket() {
+        Some(path) => Bind {
+            addr: path.to_string(),
+            step: match std::path::Path::new(path).parent() {
+                Some(dir) if !dir.as_os_str().is_empty() && !dir.is_dir() => {
+                    Step::error(format!("directory `{}` does not exist", dir.display()))
+                }
+                _ => Step::ok(),
+            },
+        },
+        None => bind(
+            config
+                .admin()
+                .socket_addr()
+                .map(|addr| vec![addr])
+                .map_err(|e| e.to_string()),
+        ),
+    });
+
+    let client = match client() {
+        Ok(client) => {
+            report.credentials = Some(Step::ok());
+            Some(client)
+        }
+        Err(e) => {
+            report.credentials = Some(Step::error(e));
+            None
+        }
+    };
+
+    // listing the caches is the first authenticated call, so a failure here
+    // most likely means the credentials are bad
+    let existing = match &client {
+        Some(client) => match timeout(CHECK_TIMEOUT, client.list_caches()).await {
+            Ok(Ok(response)) => Some(
+                response
+                    .caches
+                    .into_iter()
+                    .map(|cache| cache.name)
+                    .collect::<Vec<_>>(),
+            ),
+            Ok(Err(e)) => {
+                report.credentials = Some(Step::error(e));
+                None
+            }
+            Err(_) => {
+                report.credentials = Some(Step::error("timed out listing caches"));
+                None
+            }
+        },
+        None => None,
+    };
+
+    for cache in config.caches() {
+        let listen = bind(cache.socket_addrs());
+
+        // a sharded listener is reported once for each of its caches
+        for name in cache.shards() {
+            let mut cache_report = CacheReport {
+                name: name.clone(),
+                listen: listen.clone(),
+                created: None,
+                exists: None,
+                round_trip: None,
+            };
+
+            if let (Some(client), Some(existing)) = (&client, &existing) {
+                let exists = if existing.contains(&name) {
+                    cache_report.created = Some(false);
+                    Step::ok()
+                } else {
+                    match timeout(CHECK_TIMEOUT, client.create_cache(name.clone())).await {
+                        Ok(Ok(CreateCacheResponse::Created)) => {
+                            cache_report.created = Some(true);
+                            Step::ok()
+                        }
+                        Ok(Ok(CreateCacheResponse::AlreadyExists)) => {
+                            cache_report.created = Some(false);
+                            Step::ok()
+                        }
+                        Ok(Err(e)) => Step::error(e),
+                        Err(_) => Step::error("timed out creating cache"),
+                    }
+                };
+
+                if exists.ok {
+                    cache_report.round_trip = Some(round_trip(client, &name).await);
+                }
+                cache_report.exists = Some(exists);
+            }
+
+            report.caches.push(cache_report);
+        }
+    }
+
+    report.ok = report.config.ok
+        && report.admin.as_ref().map(|b| b.step.ok).unwrap_or(false)
+        && report.credentials.as_ref().map(|s| s.ok).unwrap_or(false)
+        && report.caches.iter().all(|cache| {
+            cache.listen.step.ok
+                && cache.exists.as_ref().map(|s| s.ok).unwrap_or(false)
+                && cache.round_trip.as_ref().map(|s| s.ok).unwrap_or(false)
+        });
+
+    report
+}
+
+// checks that every address is available, reporting the first which is not
+fn bind(addrs: Result<Vec<std::net::SocketAddr>, String>) -> Bind {
+    match addrs {
+        Ok(addrs) => Bind {
+            addr: addrs
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            // the listeners are dropped right away, we only care that the
+            // addresses are available
+            step: match addrs
+                .iter()
+                .map(std::net::TcpListener::bind)
+                .find_map(Result::err)
+            {
+                None => Step::ok(),
+                Some(e) => Step::error(e),
+            },
+        },
+        Err(e) => Bind {
+            addr: String::new(),
+            step: Step::error(e),
+        },
+    }
+}
+
+fn client() -> Result<CacheClient, String> {
+    let api_key = std::env::var("MOMENTO_API_KEY")
+        .map_err(|_| "environment variable `MOMENTO_API_KEY` is not set".to_string())?;
+    let credential_provider = CredentialProvider::from_string(api_key)
+        .map_err(|e| format!("failed to initialize credential provider: {e}"))?;
+
+    CacheClient::builder()
+        .default_ttl(CHECK_ITEM_TTL)
+        .configuration(configurations::Laptop::latest())
+        .credential_provider(credential_provider)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// Sets, reads back, and deletes a uniquely named item in the cache.
+async fn round_trip(client: &CacheClient, cache_name: &str) -> Step {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let key = format!("momento-proxy-check-{}-{nanos}", std::process::id());
+    let value = key.as_bytes().to_vec();
+
+    let request = SetRequest::new(cache_name, key.clone(), value.clone()).ttl(CHECK_ITEM_TTL);
+    match timeout(CHECK_TIMEOUT, client.send_request(request)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => return Step::error(format!("set failed: {e}")),
+        Err(_) => return Step::error("set timed out"),
+    }
+
+    match timeout(CHECK_TIMEOUT, client.get(cache_name, key.clone())).await {
+        Ok(Ok(GetResponse::Hit { value: found })) => {
+            let found: Vec<u8> = found.into();
+            if found != value {
+                return Step::error("get returned a different value than was set");
+            }
+        }
+        Ok(Ok(GetResponse::Miss)) => return Step::error("get missed after set"),
+        Ok(Err(e)) => return Step::error(format!("get failed: {e}")),
+        Err(_) => return Step::error("get timed out"),
+    }
+
+    match timeout(CHECK_TIMEOUT, client.delete(cache_name, key)).await {
+        Ok(Ok(_)) => Step::ok(),
+        Ok(Err(e)) => Step::error(format!("delete failed: {e}")),
+        Err(_) => Step::error("delete timed out"),
+    }
+}
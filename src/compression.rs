@@ -0,0 +1,468 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Compresses memcache values with zstd dictionaries trained on the values
+//! passing through the proxy. Small values don't compress well on their own,
+//! but do against a dictionary of what similar values look like.
+//!
+//! Each cache samples the values it stores, and periodically trains a new
+//! dictionary from them. A dictionary is identified by a hash of its content,
+//! which is written ahead of each value compressed with it. Dictionaries are
+//! stored in Momento under reserved keys, along with the id of the current
+//! one, so that every proxy sharing the cache can read what the others wrote
+//! and converges on the same dictionary.
+
+use crate::*;
+use core::sync::atomic::AtomicBool;
+use momento::cache::{GetResponse, SetRequest};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+#[metric(name = "compression_compressed")]
+pub static COMPRESSION_COMPRESSED: Counter = Counter::new();
+
+#[metric(name = "compression_skipped")]
+pub static COMPRESSION_SKIPPED: Counter = Counter::new();
+
+#[metric(name = "compression_bytes_in")]
+pub static COMPRESSION_BYTES_IN: Counter = Counter::new();
+
+#[metric(name = "compression_bytes_out")]
+pub static COMPRESSION_BYTES_OUT: Counter = Counter::new();
+
+#[metric(name = "compression_ex")]
+pub static COMPRESSION_EX: Counter = Counter::new();
+
+#[metric(name = "compression_dictionary_trained")]
+pub static COMPRESSION_DICTIONARY_TRAINED: Counter = Counter::new();
+
+#[metric(name = "compression_dictionary_loaded")]
+pub static COMPRESSION_DICTIONARY_LOADED: Counter = Counter::new();
+
+#[metric(name = "compression_dictionary_missing")]
+pub static COMPRESSION_DICTIONARY_MISSING: Counter = Counter::new();
+
+// marks a compressed value. A value which starts with it is always compressed,
+// without a dictionary if there is none yet, so that it can't be mistaken for
+// a compressed value when it is read
+const MAGIC: [u8; 4] = [0xfd, b'm', b'p', b'z'];
+
+// the magic, the dictionary id, and the uncompressed length
+const HEADER_LEN: usize = 12;
+
+// the id written for a value compressed without a dictionary
+const NO_DICTIONARY: u32 = 0;
+
+/// The largest value decompressed for a listener without a `max_item_size`.
+/// The length is read from the stored value, so it must be bounded before it
+/// is allocated.
+pub(crate) const MAX_VALUE_LEN: usize = 64 * MB;
+
+const LEVEL: i32 = 3;
+
+// values are only sampled for training up to this size, as larger values
+// compress well without a dictionary
+const SAMPLE_MAX_BYTES: usize = 1024;
+
+// the most values kept for training between dictionaries
+const SAMPLE_CAPACITY: usize = 4096;
+
+// the fewest values a dictionary is trained from
+const MIN_SAMPLES: usize = 256;
+
+// how often the current dictionary is checked in momento, and dictionaries
+// which values were found compressed with are loaded
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+const DICTIONARY_KEY_PREFIX: &str = "__momento_proxy_zstd_dictionary/";
+const CURRENT_DICTIONARY_KEY: &str = "__momento_proxy_zstd_dictionary/current";
+
+/// The dictionaries of a cache, shared by the listeners which compress values
+/// for it.
+pub(crate) struct Dictionaries {
+    cache_name: String,
+    // the dictionary new values are compressed with, and its id
+    current: RwLock<Option<(u32, Arc<EncoderDictionary<'static>>)>>,
+    decoders: RwLock<HashMap<u32, Arc<DecoderDictionary<'static>>>>,
+    // dictionaries which values were found compressed with, but which aren't
+    // loaded yet
+    missing: Mutex<HashSet<u32>>,
+    samples: Mutex<Samples>,
+    // whether a listener already runs the trainer for the cache
+    trained: AtomicBool,
+}
+
+// a uniform sample of the values stored since the last dictionary
+#[derive(Default)]
+struct Samples {
+    values: Vec<Vec<u8>>,
+    seen: u64,
+    random: RandomState,
+}
+
+impl std::fmt::Debug for Dictionaries {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dictionaries")
+            .field("cache_name", &self.cache_name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The dictionaries of `cache_name`, which are created on first use and live
+/// for the rest of the process.
+pub(crate) fn dictionaries(cache_name: &str) -> &'static Dictionaries {
+    static CACHES: OnceLock<Mutex<HashMap<String, &'static Dictionaries>>> = OnceLock::new();
+
+    *CACHES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(cache_name.to_string())
+        .or_insert_with(|| Box::leak(Box::new(Dictionaries::new(cache_name))))
+}
+
+// the id of a dictionary, from its content, so that proxies which train the
+// same dictionary agree on its id and different dictionaries don't collide
+fn dictionary_id(dictionary: &[u8]) -> u32 {
+    let digest = Sha256::digest(dictionary);
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]).max(1)
+}
+
+fn dictionary_key(id: u32) -> String {
+    format!("{DICTIONARY_KEY_PREFIX}{id}")
+}
+
+impl Dictionaries {
+    fn new(cache_name: &str) -> Self {
+        Self {
+            cache_name: cache_name.to_string(),
+            current: Default::default(),
+            decoders: Default::default(),
+            missing: Default::default(),
+            samples: Default::default(),
+            trained: Default::default(),
+        }
+    }
+
+    fn current(&self) -> Option<(u32, Arc<EncoderDictionary<'static>>)> {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn current_id(&self) -> Option<u32> {
+        self.current().map(|(id, _)| id)
+    }
+
+    fn decoder(&self, id: u32) -> Option<Arc<DecoderDictionary<'static>>> {
+        self.decoders
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&id)
+            .cloned()
+    }
+
+    // makes a dictionary available for reads, and with `current` also for
+    // writes
+    fn install(&self, id: u32, dictionary: &[u8], current: bool) {
+        self.decoders
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(id)
+            .or_insert_with(|| Arc::new(DecoderDictionary::copy(dictionary)));
+        self.missing
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&id);
+
+        if current {
+            *self.current.write().unwrap_or_else(|e| e.into_inner()) =
+                Some((id, Arc::new(EncoderDictionary::copy(dictionary, LEVEL))));
+        }
+    }
+
+    fn sample(&self, value: &[u8]) {
+        if value.is_empty() || value.len() > SAMPLE_MAX_BYTES {
+            return;
+        }
+
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        samples.seen += 1;
+        if samples.values.len() < SAMPLE_CAPACITY {
+            samples.values.push(value.to_vec());
+        } else {
+            // replaces a sample with a chance of capacity in seen, which
+            // keeps every value seen equally likely to be in the sample
+            let slot = (samples.random.hash_one(samples.seen) % samples.seen) as usize;
+            if slot < SAMPLE_CAPACITY {
+                samples.values[slot] = value.to_vec();
+            }
+        }
+    }
+
+    fn take_samples(&self) -> Vec<Vec<u8>> {
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        samples.seen = 0;
+        std::mem::take(&mut samples.values)
+    }
+
+    fn sample_count(&self) -> usize {
+        self.samples
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values
+            .len()
+    }
+
+    /// Appends a value to `stored` in the form it is kept in Momento: with the
+    /// current dictionary if that makes it smaller, and otherwise as is.
+    pub(crate) fn encode_into(&self, value: &[u8], stored: &mut Vec<u8>) {
+        self.sample(value);
+
+        let current = self.current();
+        let marked = value.starts_with(&MAGIC);
+        if current.is_none() && !marked {
+            stored.extend_from_slice(value);
+            return;
+        }
+
+        let compressed = match &current {
+            Some((_, dictionary)) => zstd::bulk::Compressor::with_prepared_dictionary(dictionary)
+                .and_then(|mut compressor| compressor.compress(value)),
+            None => zstd::bulk::compress(value, LEVEL),
+        };
+
+        match compressed {
+            Ok(compressed) if marked || HEADER_LEN + compressed.len() < value.len() => {
+                let id = current.map_or(NO_DICTIONARY, |(id, _)| id);
+                COMPRESSION_COMPRESSED.increment();
+                COMPRESSION_BYTES_IN.add(value.len() as u64);
+                COMPRESSION_BYTES_OUT.add((HEADER_LEN + compressed.len()) as u64);
+
+                stored.extend_from_slice(&MAGIC);
+                stored.extend_from_slice(&id.to_be_bytes());
+                stored.extend_from_slice(&(value.len() as u32).to_be_bytes());
+                stored.extend_from_slice(&compressed);
+            }
+            Ok(_) => {
+                COMPRESSION_SKIPPED.increment();
+                stored.extend_from_slice(value);
+            }
+            Err(e) => {
+                COMPRESSION_EX.increment();
+                debug!("could not compress a value for `{}`: {e}", self.cache_name);
+                stored.extend_from_slice(value);
+            }
+        }
+    }
+
+    /// The value stored as `stored`, decompressed if it was compressed.
+    /// Returns `None` if it can't be decompressed, including when it claims
+    /// to be longer than `max_len` or was compressed with a dictionary which
+    /// isn't loaded yet. That dictionary is then loaded in the background, so
+    /// that later reads find it.
+    pub(crate) fn decode<'a>(&self, stored: &'a [u8], max_len: usize) -> Option<Cow<'a, [u8]>> {
+        if stored.len() < HEADER_LEN || !stored.starts_with(&MAGIC) {
+            return Some(Cow::Borrowed(stored));
+        }
+
+        let id = u32::from_be_bytes([stored[4], stored[5], stored[6], stored[7]]);
+        let len = u32::from_be_bytes([stored[8], stored[9], stored[10], stored[11]]) as usize;
+        let frame = &stored[HEADER_LEN..];
+
+        // the length is the capacity the value is decompressed into
+        if len > max_len {
+            COMPRESSION_EX.increment();
+            return None;
+        }
+
+        let value = if id == NO_DICTIONARY {
+            zstd::bulk::decompress(frame, len)
+        } else {
+            let Some(dictionary) = self.decoder(id) else {
+                COMPRESSION_DICTIONARY_MISSING.increment();
+                self.missing
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(id);
+                return None;
+            };
+            zstd::bulk::Decompressor::with_prepared_dictionary(&dictionary)
+                .and_then(|mut decompressor| decompressor.decompress(frame, len))
+        };
+
+        match value {
+            Ok(value) if value.len() == len => Some(Cow::Owned(value)),
+            _ => {
+                COMPRESSION_EX.increment();
+                None
+            }
+        }
+    }
+
+    // reads a dictionary from momento
+    async fn fetch(&self, client: &CacheClient, key: String) -> Option<Vec<u8>> {
+        match timeout(
+            backend_timeout(),
+            client.get(&self.cache_name, key.as_str()),
+        )
+        .await
+        {
+            Ok(Ok(GetResponse::Hit { value })) => Some(value.into()),
+            Ok(Ok(GetResponse::Miss)) => None,
+            Ok(Err(e)) => {
+                warn!("could not read `{key}` from `{}`: {e}", self.cache_name);
+                None
+            }
+            Err(_) => {
+                warn!("timed out reading `{key}` from `{}`", self.cache_name);
+                None
+            }
+        }
+    }
+
+    async fn store(&self, client: &CacheClient, key: String, value: Vec<u8>, ttl: Duration) {
+        let request = SetRequest::new(&self.cache_name, key.as_str(), value).ttl(Some(ttl));
+        match timeout(backend_timeout(), client.send_request(request)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => warn!("could not write `{key}` to `{}`: {e}", self.cache_name),
+            Err(_) => warn!("timed out writing `{key}` to `{}`", self.cache_name),
+        }
+    }
+
+    // loads a dictionary by id, returning whether it was found
+    async fn load(&self, client: &CacheClient, id: u32, current: bool) -> bool {
+        let Some(dictionary) = self.fetch(client, dictionary_key(id)).await else {
+            return false;
+        };
+        if dictionary_id(&dictionary) != id {
+            warn!(
+                "dictionary {id} of `{}` doesn't match its content, ignoring it",
+                self.cache_name
+            );
+            return false;
+        }
+
+        COMPRESSION_DICTIONARY_LOADED.increment();
+        self.install(id, &dictionary, current);
+        true
+    }
+
+    // follows the current dictionary in momento, which may have been trained
+    // by another proxy
+    async fn refresh(&self, client: &CacheClient) {
+        let current = self
+            .fetch(client, CURRENT_DICTIONARY_KEY.to_string())
+            .await
+            .and_then(|id| std::str::from_utf8(&id).ok()?.parse::<u32>().ok());
+
+        if let Some(id) = current.filter(|id| Some(*id) != self.current_id()) {
+            if self.load(client, id, true).await {
+                info!(
+                    "compressing values of `{}` with dictionary {id}",
+                    self.cache_name
+                );
+            }
+        }
+
+        let missing: Vec<u32> = self
+            .missing
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .copied()
+            .collect();
+        for id in missing {
+            if !self.load(client, id, false).await {
+                debug!("dictionary {id} of `{}` isn't in momento", self.cache_name);
+            }
+        }
+    }
+
+    // trains a dictionary from the sampled values and makes it current, here
+    // and in momento
+    async fn train(&self, client: &CacheClient, dictionary_bytes: usize, ttl: Duration) {
+        let samples = self.take_samples();
+        let count = samples.len();
+
+        let dictionary = match tokio::task::spawn_blocking(move || {
+            zstd::dict::from_samples(&samples, dictionary_bytes)
+        })
+        .await
+        {
+            Ok(Ok(dictionary)) => dictionary,
+            Ok(Err(e)) => {
+                warn!(
+                    "could not train a dictionary for `{}`: {e}",
+                    self.cache_name
+                );
+                return;
+            }
+            Err(e) => {
+                error!("dictionary training for `{}` failed: {e}", self.cache_name);
+                return;
+            }
+        };
+
+        let id = dictionary_id(&dictionary);
+        COMPRESSION_DICTIONARY_TRAINED.increment();
+        info!(
+            "trained dictionary {id} of {} bytes for `{}` from {count} values",
+            dictionary.len(),
+            self.cache_name
+        );
+
+        // the dictionary is stored before it is made current, so that another
+        // proxy which follows the current id can always read it
+        self.store(client, dictionary_key(id), dictionary.clone(), ttl)
+            .await;
+        self.store(
+            client,
+            CURRENT_DICTIONARY_KEY.to_string(),
+            id.to_string().into_bytes(),
+            ttl,
+        )
+        .await;
+        self.install(id, &dictionary, true);
+    }
+}
+
+/// Keeps the dictionaries of a cache up to date: follows the current
+/// dictionary in Momento, loads dictionaries which values were found
+/// compressed with, and every `interval` trains a new dictionary of up to
+/// `dictionary_bytes` from the sampled values. Dictionaries are kept in
+/// Momento for `ttl`. A cache which another listener already trains is left
+/// to that listener.
+pub(crate) async fn trainer(
+    client: CacheClient,
+    dictionaries: &'static Dictionaries,
+    interval: Duration,
+    dictionary_bytes: usize,
+    ttl: Duration,
+) {
+    if dictionaries.trained.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+    refresh.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut last_trained = std::time::Instant::now();
+
+    loop {
+        refresh.tick().await;
+
+        dictionaries.refresh(&client).await;
+
+        if last_trained.elapsed() >= interval && dictionaries.sample_count() >= MIN_SAMPLES {
+            dictionaries.train(&client, dictionary_bytes, ttl).await;
+            last_trained = std::time::Instant::now();
+        }
+    }
+}
@@ -0,0 +1,413 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! A registry of the open client connections across all listeners. Each
+//! frontend records activity on its connection so operators can inspect and
+//! terminate clients through `CLIENT LIST`/`CLIENT KILL` or the admin port.
+//! Each connection also tracks what it is doing, which is logged at trace level
+//! as it changes and sampled into a gauge per state, so that a connection which
+//! appears stuck can be diagnosed from the metrics alone. When a connection
+//! closes, a summary of everything it did is logged, so that an abusive or
+//! broken client can be identified after the fact.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use metriken::{metric, Gauge};
+use tokio::sync::Notify;
+
+#[metric(name = "connections_reading")]
+pub static CONNECTIONS_READING: Gauge = Gauge::new();
+
+#[metric(name = "connections_parsing")]
+pub static CONNECTIONS_PARSING: Gauge = Gauge::new();
+
+#[metric(name = "connections_dispatching")]
+pub static CONNECTIONS_DISPATCHING: Gauge = Gauge::new();
+
+#[metric(name = "connections_awaiting_backend")]
+pub static CONNECTIONS_AWAITING_BACKEND: Gauge = Gauge::new();
+
+#[metric(name = "connections_writing")]
+pub static CONNECTIONS_WRITING: Gauge = Gauge::new();
+
+#[metric(name = "connections_backpressured")]
+pub static CONNECTIONS_BACKPRESSURED: Gauge = Gauge::new();
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+static CONNECTIONS: Mutex<BTreeMap<u64, Arc<Connection>>> = Mutex::new(BTreeMap::new());
+
+/// What a connection is doing. The reader and the writer of a pipelined
+/// connection run concurrently, so this is the most recent change made by
+/// either of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum State {
+    /// waiting for a request, with none in flight
+    Reading,
+    /// parsing the requests which were read
+    Parsing,
+    /// starting the requests which were parsed
+    Dispatching,
+    /// waiting for more requests while some are in flight
+    AwaitingBackend,
+    /// writing responses to the socket
+    Writing,
+    /// not reading, as the pipeline or response queue is full
+    Backpressured,
+}
+
+impl State {
+    const ALL: [State; 6] = [
+        State::Reading,
+        State::Parsing,
+        State::Dispatching,
+        State::AwaitingBackend,
+        State::Writing,
+        State::Backpressured,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Reading => "reading",
+            State::Parsing => "parsing",
+            State::Dispatching => "dispatching",
+            State::AwaitingBackend => "awaiting_backend",
+            State::Writing => "writing",
+            State::Backpressured => "backpressured",
+        }
+    }
+
+    fn gauge(&self) -> &'static Gauge {
+        match self {
+            State::Reading => &CONNECTIONS_READING,
+            State::Parsing => &CONNECTIONS_PARSING,
+            State::Dispatching => &CONNECTIONS_DISPATCHING,
+            State::AwaitingBackend => &CONNECTIONS_AWAITING_BACKEND,
+            State::Writing => &CONNECTIONS_WRITING,
+            State::Backpressured => &CONNECTIONS_BACKPRESSURED,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        Self::ALL
+            .get(value as usize)
+            .copied()
+            .unwrap_or(State::Reading)
+    }
+}
+
+/// The accounting for a single client connection.
+pub(crate) struct Connection {
+    id: u64,
+    addr: SocketAddr,
+    cache_name: String,
+    // set by the client with `CLIENT SETNAME` or `version <name>`
+    name: Mutex<Option<Arc<str>>>,
+    created: Instant,
+    // milliseconds since `created` of the most recent request
+    last_active: AtomicU64,
+    last_command: Mutex<&'static str>,
+    // the requests received for each command
+    requests: Mutex<BTreeMap<&'static str, u64>>,
+    pending: AtomicUsize,
+    // the most requests which were awaiting a response at once
+    max_pending: AtomicUsize,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    errors: AtomicU64,
+    // the resp database chosen with `SELECT`
+    database: AtomicUsize,
+    // whether the summary is logged at info rather than debug level
+    log_summary: bool,
+    // a `State` as its index in `State::ALL`
+    state: AtomicU8,
+    killed: AtomicBool,
+    kill: Notify,
+}
+
+impl Connection {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The name the client identified itself with, if any.
+    pub fn name(&self) -> Option<Arc<str>> {
+        self.name.lock().unwrap().clone()
+    }
+
+    /// Names the connection, or clears the name if it is empty. Returns false
+    /// if the name is not valid, as names are used as metric dimensions they
+    /// are limited to 64 letters, digits, and `-_.:`.
+    pub fn set_name(&self, name: &str) -> bool {
+        if name.len() > 64
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c))
+        {
+            return false;
+        }
+
+        *self.name.lock().unwrap() = (!name.is_empty()).then(|| name.into());
+        true
+    }
+
+    /// Records that a request for `command` was received.
+    pub fn request(&self, command: &'static str) {
+        self.last_active
+            .store(self.created.elapsed().as_millis() as u64, Ordering::Relaxed);
+        *self.last_command.lock().unwrap() = command;
+        *self.requests.lock().unwrap().entry(command).or_default() += 1;
+        let pending = self.pending.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_pending.fetch_max(pending, Ordering::Relaxed);
+    }
+
+    /// Records that the response for a request was sent.
+    pub fn response(&self) {
+        let _ = self
+            .pending
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| v.checked_sub(1));
+    }
+
+    /// Records bytes read from the client.
+    pub fn received(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records bytes written to the client.
+    pub fn sent(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records that the client was sent an error.
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The resp database the connection has selected.
+    pub fn database(&self) -> usize {
+        self.database.load(Ordering::Relaxed)
+    }
+
+    /// Selects the resp database which serves the connection's requests.
+    pub fn select(&self, database: usize) {
+        self.database.store(database, Ordering::Relaxed);
+    }
+
+    /// What the connection is doing.
+    pub fn state(&self) -> State {
+        State::from_u8(self.state.load(Ordering::Relaxed))
+    }
+
+    /// Records what the connection is doing, logging the transition.
+    pub fn set_state(&self, state: State) {
+        let previous = State::from_u8(self.state.swap(state as u8, Ordering::Relaxed));
+        if previous != state {
+            trace!(
+                "connection {} state {} -> {}",
+                self.id,
+                previous.as_str(),
+                state.as_str()
+            );
+        }
+    }
+
+    /// Records that the connection is waiting for its next request, which is
+    /// awaiting the backend if any request is still in flight.
+    pub fn set_idle(&self) {
+        self.set_state(if self.pending.load(Ordering::Relaxed) > 0 {
+            State::AwaitingBackend
+        } else {
+            State::Reading
+        });
+    }
+
+    /// Asks the frontend serving this connection to close it.
+    pub fn kill(&self) {
+        self.killed.store(true, Ordering::Relaxed);
+        self.kill.notify_waiters();
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::Relaxed)
+    }
+
+    /// Completes once the connection has been killed.
+    pub async fn killed(&self) {
+        let notified = self.kill.notified();
+        if self.is_killed() {
+            return;
+        }
+        notified.await;
+    }
+
+    // how long since the most recent request, in milliseconds
+    fn idle_ms(&self) -> u64 {
+        let last_active = self.last_active.load(Ordering::Relaxed);
+        (self.created.elapsed().as_millis() as u64).saturating_sub(last_active)
+    }
+
+    /// Formats the connection in the style of a `CLIENT LIST` line.
+    pub fn describe(&self) -> String {
+        let age = self.created.elapsed();
+        let idle = self.idle_ms() / 1000;
+
+        format!(
+            "id={} addr={} name={} cache={} db={} age={} idle={} cmd={} pipeline={} state={}",
+            self.id,
+            self.addr,
+            self.name().as_deref().unwrap_or(""),
+            self.cache_name,
+            self.database(),
+            age.as_secs(),
+            idle,
+            self.last_command.lock().unwrap(),
+            self.pending.load(Ordering::Relaxed),
+            self.state().as_str(),
+        )
+    }
+
+    /// Summarizes everything the connection did, for logging once it closes.
+    pub fn summary(&self) -> String {
+        let mut requests = String::new();
+        for (command, count) in self.requests.lock().unwrap().iter() {
+            if !requests.is_empty() {
+                requests.push(',');
+            }
+            let _ = write!(requests, "{command}:{count}");
+        }
+
+        format!(
+            "connection closed: id={} addr={} name={} cache={} duration_ms={} requests={} bytes_in={} bytes_out={} errors={} max_pipeline={}",
+            self.id,
+            self.addr,
+            self.name().as_deref().unwrap_or(""),
+            self.cache_name,
+            self.created.elapsed().as_millis(),
+            requests,
+            self.bytes_in.load(Ordering::Relaxed),
+            self.bytes_out.load(Ordering::Relaxed),
+            self.errors.load(Ordering::Relaxed),
+            self.max_pending.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Removes the connection from the registry when dropped.
+pub(crate) struct ConnectionGuard {
+    connection: Arc<Connection>,
+}
+
+impl ConnectionGuard {
+    pub fn connection(&self) -> Arc<Connection> {
+        self.connection.clone()
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        CONNECTIONS.lock().unwrap().remove(&self.connection.id);
+
+        if self.connection.log_summary {
+            info!("{}", self.connection.summary());
+        } else {
+            debug!("{}", self.connection.summary());
+        }
+    }
+}
+
+/// Adds a newly accepted connection to the registry. With `log_summary`, the
+/// summary logged when it closes is logged at info rather than debug level.
+pub(crate) fn register(addr: SocketAddr, cache_name: String, log_summary: bool) -> ConnectionGuard {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    let connection = Arc::new(Connection {
+        id,
+        addr,
+        cache_name,
+        name: Mutex::new(None),
+        created: Instant::now(),
+        last_active: AtomicU64::new(0),
+        last_command: Mutex::new("NULL"),
+        requests: Mutex::new(BTreeMap::new()),
+        pending: AtomicUsize::new(0),
+        max_pending: AtomicUsize::new(0),
+        bytes_in: AtomicU64::new(0),
+        bytes_out: AtomicU64::new(0),
+        errors: AtomicU64::new(0),
+        database: AtomicUsize::new(0),
+        log_summary,
+        state: AtomicU8::new(State::Reading as u8),
+        killed: AtomicBool::new(false),
+        kill: Notify::new(),
+    });
+
+    CONNECTIONS.lock().unwrap().insert(id, connection.clone());
+
+    ConnectionGuard { connection }
+}
+
+/// Describes every open connection, one per line.
+pub(crate) fn list() -> String {
+    let connections = CONNECTIONS.lock().unwrap();
+
+    let mut out = String::new();
+    for connection in connections.values() {
+        let _ = writeln!(out, "{}", connection.describe());
+    }
+    out
+}
+
+/// Kills every connection from `addr`, returning how many were found.
+pub(crate) fn kill_addr(addr: &str) -> usize {
+    let connections = CONNECTIONS.lock().unwrap();
+
+    let mut killed = 0;
+    for connection in connections.values() {
+        if connection.addr.to_string() == addr {
+            connection.kill();
+            killed += 1;
+        }
+    }
+    killed
+}
+
+/// Kills the connection which has been idle the longest, without a request in
+/// flight, returning its address.
+pub(crate) fn kill_idlest() -> Option<SocketAddr> {
+    let connections = CONNECTIONS.lock().unwrap();
+
+    let idlest = connections
+        .values()
+        .filter(|connection| {
+            !connection.is_killed() && connection.pending.load(Ordering::Relaxed) == 0
+        })
+        .max_by_key(|connection| connection.idle_ms())?;
+    idlest.kill();
+    Some(idlest.addr)
+}
+
+/// Periodically counts the open connections in each state into the gauges.
+pub(crate) async fn sample_states(interval: Duration) {
+    loop {
+        let mut counts = [0; State::ALL.len()];
+        for connection in CONNECTIONS.lock().unwrap().values() {
+            counts[connection.state() as usize] += 1;
+        }
+
+        for (state, count) in State::ALL.iter().zip(counts) {
+            state.gauge().set(count);
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
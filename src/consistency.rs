@@ -0,0 +1,118 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Periodically compares a random sample of memory cache entries against
+//! Momento, so that a memory cache which has drifted from the backend shows up
+//! in the metrics rather than only as stale reads.
+
+use crate::cache::CacheValue;
+use crate::keys::KeyTransform;
+use crate::protocol::memcache::ValueCodec;
+use crate::shards::Shards;
+use crate::*;
+use momento::cache::GetResponse;
+use std::sync::Arc;
+
+#[metric(name = "memory_cache_consistency_sampled")]
+pub static MEMORY_CACHE_CONSISTENCY_SAMPLED: Counter = Counter::new();
+
+#[metric(name = "memory_cache_consistency_mismatch")]
+pub static MEMORY_CACHE_CONSISTENCY_MISMATCH: Counter = Counter::new();
+
+#[metric(name = "memory_cache_consistency_missing")]
+pub static MEMORY_CACHE_CONSISTENCY_MISSING: Counter = Counter::new();
+
+#[metric(name = "memory_cache_consistency_ex")]
+pub static MEMORY_CACHE_CONSISTENCY_EX: Counter = Counter::new();
+
+/// Every `interval`, looks up `count` memory cache entries in Momento. Entries
+/// which differ from Momento, or which Momento no longer holds, are removed
+/// from the memory cache.
+pub(crate) async fn sampler(
+    client: CacheClient,
+    shards: Arc<Shards>,
+    key_transform: KeyTransform,
+    codec: ValueCodec,
+    interval: Duration,
+    count: usize,
+    memory_cache: MCache,
+) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    // the first tick completes immediately, skip it so the memory cache has a
+    // chance to fill
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        for (key, entry) in memory_cache.sample(count) {
+            let cas = entry.cas();
+            check_key(
+                &client,
+                shards.cache_for(&key),
+                &key,
+                &key_transform,
+                entry.into_value(),
+                cas,
+                codec,
+                &memory_cache,
+            )
+            .await;
+        }
+    }
+}
+
+async fn check_key(
+    client: &CacheClient,
+    cache_name: &str,
+    key: &[u8],
+    key_transform: &KeyTransform,
+    local: CacheValue,
+    cas: u64,
+    codec: ValueCodec,
+    memory_cache: &MCache,
+) {
+    let result = timeout(
+        Duration::from_millis(200),
+        client.get(cache_name, key_transform.apply(key).into_owned()),
+    )
+    .await;
+
+    MEMORY_CACHE_CONSISTENCY_SAMPLED.increment();
+
+    let remote = match result {
+        Ok(Ok(GetResponse::Hit { value })) => {
+            let value: Vec<u8> = value.into();
+            codec.decode(key, &value)
+        }
+        Ok(Ok(GetResponse::Miss)) => None,
+        Ok(Err(e)) => {
+            debug!("backend error during consistency check: {e}");
+            MEMORY_CACHE_CONSISTENCY_EX.increment();
+            return;
+        }
+        Err(_) => {
+            MEMORY_CACHE_CONSISTENCY_EX.increment();
+            return;
+        }
+    };
+
+    match remote {
+        Some(value) if (CacheValue::Memcached { value }) == local => {}
+        Some(_) => {
+            MEMORY_CACHE_CONSISTENCY_MISMATCH.increment();
+            debug!(
+                "memory cache entry for `{}` differs from cache `{cache_name}`",
+                String::from_utf8_lossy(key)
+            );
+            memory_cache.delete_if_cas(key, cas);
+        }
+        None => {
+            MEMORY_CACHE_CONSISTENCY_MISSING.increment();
+            memory_cache.delete_if_cas(key, cas);
+        }
+    }
+}
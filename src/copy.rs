@@ -0,0 +1,624 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Keyspace copy for the `copy` subcommand. Items are streamed from a source
+//! backend into a Momento cache, so that the cache can be filled before
+//! traffic is moved to the proxy. The sources are a memcache text dump, a
+//! running Redis server which is walked with `SCAN`, or another Momento cache
+//! whose keys are read from a key list, as Momento can't enumerate them.
+
+use crate::protocol::memcache::Expiry;
+use crate::*;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use momento::cache::{GetResponse, ItemGetTtlResponse, SetRequest};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpStream;
+
+// limits the number of outstanding writes to the target cache
+const COPY_CONCURRENCY: usize = 16;
+
+// upper bound on each call made to a backend
+const COPY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// the number of keys requested by each redis SCAN
+const SCAN_COUNT: &str = "1000";
+
+// only the first few failures are printed, the rest are counted
+const MAX_REPORTED_ERRORS: u64 = 10;
+
+#[metric(name = "copy_read")]
+pub static COPY_READ: Counter = Counter::new();
+
+#[metric(name = "copy_written")]
+pub static COPY_WRITTEN: Counter = Counter::new();
+
+#[metric(name = "copy_skipped")]
+pub static COPY_SKIPPED: Counter = Counter::new();
+
+#[metric(name = "copy_error")]
+pub static COPY_ERROR: Counter = Counter::new();
+
+/// Where the copied items are read from.
+pub enum CopySource {
+    /// A memcache text protocol dump of `set` or `add` commands, such as the
+    /// output of `memcached-tool dump`.
+    File(String),
+    /// A Redis server, walked with `SCAN`. Only string keys are copied.
+    Redis {
+        addr: String,
+        pattern: Option<String>,
+    },
+    /// Another Momento cache, reading the keys listed one per line in a file.
+    Momento { cache_name: String, keys: String },
+}
+
+/// How the TTL of a copied item is chosen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TtlPolicy {
+    /// The remaining TTL of the source item, or the default TTL for items
+    /// which don't expire.
+    Preserve,
+    /// The default TTL for every item.
+    Default,
+    /// A fixed TTL for every item.
+    Fixed(Duration),
+}
+
+impl std::str::FromStr for TtlPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(TtlPolicy::Preserve),
+            "default" => Ok(TtlPolicy::Default),
+            seconds => match seconds.parse::<u64>() {
+                Ok(seconds) if seconds > 0 => Ok(TtlPolicy::Fixed(Duration::from_secs(seconds))),
+                _ => Err(format!(
+                    "ttl policy must be `preserve`, `default`, or a number of seconds, not `{s}`"
+                )),
+            },
+        }
+    }
+}
+
+/// The options of the `copy` subcommand.
+pub struct CopyOptions {
+    pub source: CopySource,
+    /// The name of the target Momento cache.
+    pub cache_name: String,
+    pub ttl: TtlPolicy,
+    /// The TTL of items which are written without one.
+    pub default_ttl: Duration,
+    /// The most items to write per second, zero for no limit.
+    pub rate: u64,
+    /// Whether memcache flags are stored with values, as with the `flags`
+    /// cache option. Items copied from Momento are written unchanged.
+    pub flags: bool,
+    /// How often progress is printed.
+    pub progress_interval: Duration,
+}
+
+struct Item {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    // `None` if the source item doesn't expire
+    ttl: Option<Duration>,
+}
+
+enum Read {
+    Item(Item),
+    // the key is gone, expired, or has a type which isn't copied
+    Skip,
+    // the key could not be read, which doesn't stop the copy
+    Failed(String),
+    End,
+}
+
+/// Runs the copy, printing progress to stderr, and returns the process exit
+/// code, which is non-zero if the copy stopped early or any item failed.
+pub fn copy(options: CopyOptions) -> i32 {
+    let runtime = Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to launch tokio runtime");
+
+    match runtime.block_on(run(options)) {
+        Ok(()) if COPY_ERROR.value() == 0 => 0,
+        Ok(()) => 1,
+        Err(e) => {
+            eprintln!("copy failed: {e}");
+            1
+        }
+    }
+}
+
+async fn run(options: CopyOptions) -> Result<(), String> {
+    let client = client("MOMENTO_API_KEY", options.default_ttl)?;
+    let mut reader = Reader::open(&options).await?;
+    let fetch_ttl = options.ttl == TtlPolicy::Preserve;
+
+    let mut interval = if options.rate > 0 {
+        let mut interval =
+            tokio::time::interval(Duration::from_nanos((1_000_000_000 / options.rate).max(1)));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        Some(interval)
+    } else {
+        None
+    };
+
+    let start = Instant::now();
+    let mut reported = start;
+    let mut tasks = FuturesUnordered::new();
+
+    loop {
+        let read = reader.next(fetch_ttl, options.flags).await?;
+        if !matches!(read, Read::End) {
+            COPY_READ.increment();
+        }
+
+        if reported.elapsed() >= options.progress_interval {
+            progress(start, "copying");
+            reported = Instant::now();
+        }
+
+        let item = match read {
+            Read::Item(item) => item,
+            Read::Skip => {
+                COPY_SKIPPED.increment();
+                continue;
+            }
+            Read::Failed(e) => {
+                failed(&e);
+                continue;
+            }
+            Read::End => break,
+        };
+
+        if let Some(interval) = interval.as_mut() {
+            interval.tick().await;
+        }
+
+        if tasks.len() >= COPY_CONCURRENCY {
+            tasks.next().await;
+        }
+
+        tasks.push(write(&client, &options.cache_name, item, options.ttl));
+    }
+
+    while tasks.next().await.is_some() {}
+
+    progress(start, "done");
+    Ok(())
+}
+
+fn client(variable: &str, default_ttl: Duration) -> Result<CacheClient, String> {
+    let api_key = std::env::var(variable)
+        .map_err(|_| format!("environment variable `{variable}` is not set"))?;
+    let credential_provider = CredentialProvider::from_string(api_key)
+        .map_err(|e| format!("failed to initialize credential provider: {e}"))?;
+
+    CacheClient::builder()
+        .default_ttl(default_ttl)
+        .configuration(configurations::Laptop::latest())
+        .credential_provider(credential_provider)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+fn progress(start: Instant, state: &str) {
+    let written = COPY_WRITTEN.value();
+    let rate = written as f64 / start.elapsed().as_secs_f64().max(0.001);
+
+    eprintln!(
+        "{state}: read {} written {written} skipped {} errors {} ({rate:.0} items/s)",
+        COPY_READ.value(),
+        COPY_SKIPPED.value(),
+        COPY_ERROR.value(),
+    );
+}
+
+fn failed(error: &str) {
+    if COPY_ERROR.value() < MAX_REPORTED_ERRORS {
+        eprintln!("error: {error}");
+    }
+    COPY_ERROR.increment();
+}
+
+async fn write(client: &CacheClient, cache_name: &str, item: Item, policy: TtlPolicy) {
+    let ttl = match policy {
+        TtlPolicy::Preserve => item.ttl,
+        TtlPolicy::Default => None,
+        TtlPolicy::Fixed(ttl) => Some(ttl),
+    };
+
+    let key = String::from_utf8_lossy(&item.key).into_owned();
+    let mut request = SetRequest::new(cache_name, item.key, item.value);
+    if let Some(ttl) = ttl {
+        request = request.ttl(ttl);
+    }
+
+    match timeout(COPY_TIMEOUT, client.send_request(request)).await {
+        Ok(Ok(_)) => COPY_WRITTEN.increment(),
+        Ok(Err(e)) => failed(&format!("could not write `{key}`: {e}")),
+        Err(_) => failed(&format!("timed out writing `{key}`")),
+    };
+}
+
+enum Reader {
+    File(BufReader<tokio::fs::File>),
+    Redis(Redis),
+    Momento {
+        client: CacheClient,
+        cache_name: String,
+        keys: std::vec::IntoIter<String>,
+    },
+}
+
+impl Reader {
+    async fn open(options: &CopyOptions) -> Result<Self, String> {
+        match &options.source {
+            CopySource::File(path) => tokio::fs::File::open(path)
+                .await
+                .map(|file| Reader::File(BufReader::new(file)))
+                .map_err(|e| format!("could not open dump file `{path}`: {e}")),
+            CopySource::Redis { addr, pattern } => Redis::connect(addr, pattern.clone())
+                .await
+                .map(Reader::Redis),
+            CopySource::Momento { cache_name, keys } => {
+                let keys = tokio::fs::read_to_string(keys)
+                    .await
+                    .map_err(|e| format!("could not read key file `{keys}`: {e}"))?;
+
+                // the source may be in another account or region
+                let variable = if std::env::var("MOMENTO_SOURCE_API_KEY").is_ok() {
+                    "MOMENTO_SOURCE_API_KEY"
+                } else {
+                    "MOMENTO_API_KEY"
+                };
+
+                Ok(Reader::Momento {
+                    client: client(variable, options.default_ttl)?,
+                    cache_name: cache_name.clone(),
+                    keys: keys
+                        .lines()
+                        .map(|key| key.trim())
+                        .filter(|key| !key.is_empty())
+                        .map(|key| key.to_string())
+                        .collect::<Vec<_>>()
+                        .into_iter(),
+                })
+            }
+        }
+    }
+
+    async fn next(&mut self, fetch_ttl: bool, flags: bool) -> Result<Read, String> {
+        match self {
+            Reader::File(reader) => read_dump(reader, flags).await,
+            Reader::Redis(redis) => redis.next(fetch_ttl, flags).await,
+            Reader::Momento {
+                client,
+                cache_name,
+                keys,
+            } => {
+                let Some(key) = keys.next() else {
+                    return Ok(Read::End);
+                };
+                Ok(read_momento(client, cache_name, key, fetch_ttl).await)
+            }
+        }
+    }
+}
+
+// reads the next `set` or `add` command of a memcache text dump
+async fn read_dump(reader: &mut BufReader<tokio::fs::File>, flags: bool) -> Result<Read, String> {
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .await
+            .map_err(|e| format!("could not read dump file: {e}"))?;
+        if read == 0 {
+            return Ok(Read::End);
+        }
+        if !line.trim_ascii().is_empty() {
+            break;
+        }
+    }
+
+    let line = String::from_utf8_lossy(&line);
+    let bad_line = || format!("bad line in dump file: `{}`", line.trim_end());
+
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let [command, key, item_flags, exptime, bytes] = tokens[..] else {
+        return Err(bad_line());
+    };
+    if command != "set" && command != "add" {
+        return Err(bad_line());
+    }
+
+    let item_flags: u32 = item_flags.parse().map_err(|_| bad_line())?;
+    let exptime: i32 = exptime.parse().map_err(|_| bad_line())?;
+    let bytes: usize = bytes.parse().map_err(|_| bad_line())?;
+
+    let mut data = vec![0; bytes + 2];
+    reader
+        .read_exact(&mut data)
+        .await
+        .map_err(|e| format!("could not read the value of `{key}` from dump file: {e}"))?;
+    if !data.ends_with(b"\r\n") {
+        return Err(format!(
+            "value of `{key}` in dump file has the wrong length"
+        ));
+    }
+    data.truncate(bytes);
+
+    // in a dump, an exptime of zero means the item doesn't expire
+    let ttl = match exptime {
+        0 => None,
+        exptime => match Expiry::from_exptime_now(Some(exptime)) {
+            Expiry::After(ttl) => Some(ttl),
+            Expiry::Expired => return Ok(Read::Skip),
+            Expiry::Default => None,
+        },
+    };
+
+    let value = if flags {
+        let mut value = item_flags.to_be_bytes().to_vec();
+        value.extend_from_slice(&data);
+        value
+    } else {
+        data
+    };
+
+    Ok(Read::Item(Item {
+        key: key.as_bytes().to_vec(),
+        value,
+        ttl,
+    }))
+}
+
+async fn read_momento(
+    client: &CacheClient,
+    cache_name: &str,
+    key: String,
+    fetch_ttl: bool,
+) -> Read {
+    let value: Vec<u8> = match timeout(COPY_TIMEOUT, client.get(cache_name, key.clone())).await {
+        Ok(Ok(GetResponse::Hit { value })) => value.into(),
+        Ok(Ok(GetResponse::Miss)) => return Read::Skip,
+        Ok(Err(e)) => return Read::Failed(format!("could not read `{key}`: {e}")),
+        Err(_) => return Read::Failed(format!("timed out reading `{key}`")),
+    };
+
+    let ttl = if fetch_ttl {
+        match timeout(COPY_TIMEOUT, client.item_get_ttl(cache_name, key.clone())).await {
+            Ok(Ok(ItemGetTtlResponse::Hit { remaining_ttl })) => Some(remaining_ttl),
+            Ok(Ok(ItemGetTtlResponse::Miss)) => return Read::Skip,
+            Ok(Err(e)) => return Read::Failed(format!("could not read the ttl of `{key}`: {e}")),
+            Err(_) => return Read::Failed(format!("timed out reading the ttl of `{key}`")),
+        }
+    } else {
+        None
+    };
+
+    Read::Item(Item {
+        key: key.into_bytes(),
+        value,
+        ttl,
+    })
+}
+
+/// A reply from a redis server.
+enum Reply {
+    Simple,
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+/// A minimal redis client which walks the keyspace with `SCAN`.
+struct Redis {
+    stream: BufReader<TcpStream>,
+    pattern: Option<String>,
+    cursor: String,
+    done: bool,
+    keys: VecDeque<Vec<u8>>,
+}
+
+impl Redis {
+    async fn connect(addr: &str, pattern: Option<String>) -> Result<Self, String> {
+        let stream = timeout(COPY_TIMEOUT, TcpStream::connect(addr))
+            .await
+            .map_err(|_| format!("timed out connecting to redis at `{addr}`"))?
+            .map_err(|e| format!("could not connect to redis at `{addr}`: {e}"))?;
+
+        let mut redis = Self {
+            stream: BufReader::new(stream),
+            pattern,
+            cursor: "0".to_string(),
+            done: false,
+            keys: VecDeque::new(),
+        };
+
+        // the same variable as redis-cli
+        if let Ok(password) = std::env::var("REDISCLI_AUTH") {
+            redis.send(&[&[b"AUTH", password.as_bytes()]]).await?;
+            if let Reply::Error(e) = redis.read().await? {
+                return Err(format!("redis authentication failed: {e}"));
+            }
+        }
+
+        Ok(redis)
+    }
+
+    async fn next(&mut self, fetch_ttl: bool, flags: bool) -> Result<Read, String> {
+        while self.keys.is_empty() {
+            if self.done {
+                return Ok(Read::End);
+            }
+            self.scan().await?;
+        }
+        let key = self.keys.pop_front().expect("keys is not empty");
+
+        // the ttl is read along with the value, so both replies are read
+        // before deciding what to do with the key
+        if fetch_ttl {
+            self.send(&[&[b"GET", &key], &[b"PTTL", &key]]).await?;
+        } else {
+            self.send(&[&[b"GET", &key]]).await?;
+        }
+        let value = self.read().await?;
+        let ttl = if fetch_ttl {
+            Some(self.read().await?)
+        } else {
+            None
+        };
+
+        let data = match value {
+            Reply::Bulk(Some(data)) => data,
+            Reply::Bulk(None) => return Ok(Read::Skip),
+            Reply::Error(e) if e.starts_with("WRONGTYPE") => return Ok(Read::Skip),
+            Reply::Error(e) => {
+                return Ok(Read::Failed(format!(
+                    "could not read `{}`: {e}",
+                    String::from_utf8_lossy(&key)
+                )))
+            }
+            _ => return Err("unexpected reply to GET".to_string()),
+        };
+
+        // a pttl of -1 means the key doesn't expire and -2 that it's gone
+        let ttl = match ttl {
+            None | Some(Reply::Integer(-1)) => None,
+            Some(Reply::Integer(ms)) if ms > 0 => Some(Duration::from_millis(ms as u64)),
+            Some(Reply::Integer(_)) => return Ok(Read::Skip),
+            Some(_) => return Err("unexpected reply to PTTL".to_string()),
+        };
+
+        let value = if flags {
+            let mut value = 0_u32.to_be_bytes().to_vec();
+            value.extend_from_slice(&data);
+            value
+        } else {
+            data
+        };
+
+        Ok(Read::Item(Item { key, value, ttl }))
+    }
+
+    async fn scan(&mut self) -> Result<(), String> {
+        let cursor = self.cursor.clone();
+        let mut args: Vec<&[u8]> =
+            vec![b"SCAN", cursor.as_bytes(), b"COUNT", SCAN_COUNT.as_bytes()];
+        let pattern = self.pattern.clone();
+        if let Some(pattern) = &pattern {
+            args.extend_from_slice(&[b"MATCH", pattern.as_bytes()]);
+        }
+        self.send(&[&args]).await?;
+
+        let Reply::Array(Some(reply)) = self.read().await? else {
+            return Err("unexpected reply to SCAN".to_string());
+        };
+        let [Reply::Bulk(Some(cursor)), Reply::Array(Some(keys))] = &reply[..] else {
+            return Err("unexpected reply to SCAN".to_string());
+        };
+
+        self.cursor = String::from_utf8_lossy(cursor).into_owned();
+        self.done = self.cursor == "0";
+
+        for key in keys {
+            if let Reply::Bulk(Some(key)) = key {
+                self.keys.push_back(key.clone());
+            }
+        }
+        Ok(())
+    }
+
+    // writes the commands in a single pipelined request
+    async fn send(&mut self, commands: &[&[&[u8]]]) -> Result<(), String> {
+        let mut buf = Vec::new();
+        for args in commands {
+            buf.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+            for arg in args.iter() {
+                buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+                buf.extend_from_slice(arg);
+                buf.extend_from_slice(b"\r\n");
+            }
+        }
+
+        self.stream
+            .get_mut()
+            .write_all(&buf)
+            .await
+            .map_err(|e| format!("could not write to redis: {e}"))
+    }
+
+    async fn read(&mut self) -> Result<Reply, String> {
+        timeout(COPY_TIMEOUT, read_reply(&mut self.stream))
+            .await
+            .map_err(|_| "timed out reading from redis".to_string())?
+    }
+}
+
+// arrays hold nested replies, so the future is boxed to allow recursion
+fn read_reply(
+    stream: &mut BufReader<TcpStream>,
+) -> Pin<Box<dyn Future<Output = Result<Reply, String>> + Send + '_>> {
+    Box::pin(async move {
+        let mut line = Vec::new();
+        stream
+            .read_until(b'\n', &mut line)
+            .await
+            .map_err(|e| format!("could not read from redis: {e}"))?;
+        if !line.ends_with(b"\r\n") {
+            return Err("connection to redis closed".to_string());
+        }
+
+        let header = String::from_utf8_lossy(&line[1..line.len() - 2]).into_owned();
+        let length = || {
+            header
+                .parse::<i64>()
+                .map_err(|_| format!("bad reply from redis: `{header}`"))
+        };
+
+        match line[0] {
+            b'+' => Ok(Reply::Simple),
+            b'-' => Ok(Reply::Error(header.clone())),
+            b':' => Ok(Reply::Integer(length()?)),
+            b'$' => match length()? {
+                len if len < 0 => Ok(Reply::Bulk(None)),
+                len => {
+                    let mut data = vec![0; len as usize + 2];
+                    stream
+                        .read_exact(&mut data)
+                        .await
+                        .map_err(|e| format!("could not read from redis: {e}"))?;
+                    data.truncate(len as usize);
+                    Ok(Reply::Bulk(Some(data)))
+                }
+            },
+            b'*' => match length()? {
+                len if len < 0 => Ok(Reply::Array(None)),
+                len => {
+                    let mut replies = Vec::with_capacity(len as usize);
+                    for _ in 0..len {
+                        replies.push(read_reply(stream).await?);
+                    }
+                    Ok(Reply::Array(Some(replies)))
+                }
+            },
+            _ => Err(format!(
+                "bad reply from redis: `{}`",
+                String::from_utf8_lossy(&line).trim_end()
+            )),
+        }
+    })
+}
@@ -0,0 +1,54 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! The timeout for backend calls made on behalf of a request. Each request
+//! runs with the timeout of its listener or of its class of command, or the
+//! timeout hint sent with the request, and the handlers read it rather than
+//! taking it as a parameter.
+
+use crate::momento_proxy::CommandClass;
+use crate::*;
+use std::future::Future;
+
+// used outside of a request, such as for warmup
+const DEFAULT_BACKEND_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[metric(name = "timeout_hint")]
+pub static TIMEOUT_HINT: Counter = Counter::new();
+
+#[metric(name = "timeout_hint_clamped")]
+pub static TIMEOUT_HINT_CLAMPED: Counter = Counter::new();
+
+tokio::task_local! {
+    // set while polling a request
+    static BACKEND_TIMEOUT: Duration;
+}
+
+/// The timeout for a backend call made by the current request.
+pub(crate) fn backend_timeout() -> Duration {
+    BACKEND_TIMEOUT
+        .try_with(|timeout| *timeout)
+        .unwrap_or(DEFAULT_BACKEND_TIMEOUT)
+}
+
+/// Polls the request future with `timeout` for its backend calls.
+pub(crate) async fn with_backend_timeout<F: Future>(timeout: Duration, future: F) -> F::Output {
+    BACKEND_TIMEOUT.scope(timeout, future).await
+}
+
+/// The class of a command, by its name in either protocol, for its backend
+/// timeout. Commands which are not writes or range reads are reads.
+pub(crate) fn command_class(command: &str) -> CommandClass {
+    match command.to_ascii_lowercase().as_str() {
+        "set" | "del" | "delete" | "hset" | "hdel" | "hincrby" | "lpush" | "rpush" | "lpop"
+        | "rpop" | "lmpop" | "sadd" | "srem" | "zadd" | "zincrby" | "zrem" | "pfadd"
+        | "pfmerge" | "setbit" | "setrange" | "restore" | "bitop" | "zunionstore" => {
+            CommandClass::Write
+        }
+        "hgetall" | "hkeys" | "hvals" | "hmget" | "lrange" | "lpos" | "smembers" | "sdiff"
+        | "sunion" | "sinter" | "zrange" | "zmscore" | "zcount" | "pfcount" | "bitcount"
+        | "dump" | "memory" => CommandClass::Range,
+        _ => CommandClass::Read,
+    }
+}
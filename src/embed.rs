@@ -0,0 +1,235 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Runs the proxy from within another program. The binary is a thin command
+//! line wrapper around `ProxyBuilder::run`, while tests and sidecars can use
+//! `ProxyBuilder::start` to run the proxy in the background and stop it again.
+//! Its config is read with `MomentoProxyConfig::load`, or parsed from a string
+//! with `str::parse`, which both migrate a config written for an older schema.
+//!
+//! Logging and metrics are process wide, so a process should only run one
+//! proxy at a time. Failures are returned rather than ending the process, and
+//! the panic hook which ends it on a panic outside a connection is only
+//! installed by the binary, with `set_panic_hook`.
+
+use crate::momento_proxy::MetricsAggregation;
+use crate::*;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::thread::JoinHandle;
+use tokio::sync::oneshot;
+
+/// Configures and runs a proxy.
+pub struct ProxyBuilder {
+    config: MomentoProxyConfig,
+    systemd: bool,
+}
+
+impl ProxyBuilder {
+    pub fn new(config: MomentoProxyConfig) -> Self {
+        Self {
+            config,
+            systemd: false,
+        }
+    }
+
+    /// Notify systemd once the proxy is ready, send watchdog keep-alives, and
+    /// notify it when stopping.
+    pub fn systemd(mut self, enabled: bool) -> Self {
+        self.systemd = enabled;
+        self
+    }
+
+    /// Runs the proxy on the calling thread until `shutdown` completes or the
+    /// admin listener exits.
+    pub fn run(self, shutdown: impl Future<Output = ()>) -> Result<(), Box<dyn std::error::Error>> {
+        let config = self.config;
+
+        panic::set_abort_on_panic(config.abort_on_panic());
+        enforce::set_mode(config.policy_mode());
+
+        if self.systemd {
+            systemd::enable();
+        }
+        instance::init(config.instance_id(), config.trace_key_prefix());
+        instance::init_server(config.server_name(), config.server_version());
+
+        // initialize logging
+        let mut log = configure_logging(&config);
+
+        info!("starting momento-proxy v{}", env!("CARGO_PKG_VERSION"));
+
+        validate(&config)?;
+
+        audit::init(config.audit_log_file(), config.audit_key_prefixes())?;
+
+        // initialize metrics
+        common::metrics::init();
+
+        metrics::batch::set_thread_local(
+            config.metrics().aggregation() == MetricsAggregation::ThreadLocal,
+        );
+
+        // initialize async runtime
+        let admin_runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .worker_threads(1)
+            .thread_name("pelikan_admin")
+            .on_thread_park(metrics::batch::flush)
+            .build()?;
+
+        let mut runtime = Builder::new_multi_thread();
+
+        runtime.thread_name_fn(|| {
+            static ATOMIC_ID: AtomicUsize = AtomicUsize::new(0);
+            let id = ATOMIC_ID.fetch_add(1, Ordering::SeqCst);
+            format!("pelikan_wrk_{id}")
+        });
+
+        // with pinned workers, the connections are served from their own
+        // threads, which leaves only the startup and background tasks here
+        if config.pinned_workers() {
+            runtime.worker_threads(1);
+        } else if let Some(threads) = config.threads() {
+            runtime.worker_threads(threads);
+        }
+
+        // an idle worker adds the metrics it batched to the shared counters
+        runtime.on_thread_park(metrics::batch::flush);
+
+        // spawn logging thread
+        admin_runtime.spawn(async move {
+            loop {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                let _ = log.flush();
+            }
+        });
+
+        let runtime = runtime.enable_all().build()?;
+
+        // spawn the proxy metrics
+        let proxy_metrics = runtime.block_on(async {
+            ProxyMetricsBuilder::new()
+                .instance_id(config.instance_id())
+                .klog_sample(config.klog_otlp_sample())
+                .config(config.metrics())
+                .build()
+                .await
+        })?;
+
+        // keep-alives come from the admin runtime, which stays responsive while
+        // the workers are busy
+        if let Some(interval) = systemd::watchdog_interval() {
+            admin_runtime.spawn(systemd::watchdog(interval));
+        }
+
+        // sample the health of both runtimes from the admin runtime
+        admin_runtime.spawn(monitor_runtime(
+            "admin",
+            admin_runtime.handle().clone(),
+            &ADMIN_RUNTIME,
+            Duration::from_secs(1),
+        ));
+        admin_runtime.spawn(monitor_runtime(
+            "worker",
+            runtime.handle().clone(),
+            &WORKER_RUNTIME,
+            Duration::from_secs(1),
+        ));
+
+        // count the connections in each state for diagnosing stuck clients
+        admin_runtime.spawn(connections::sample_states(Duration::from_secs(1)));
+
+        // watch for running out of file descriptors
+        admin_runtime.spawn(fds::monitor(
+            config.fd_high_water_percent(),
+            Duration::from_secs(1),
+        ));
+
+        runtime.block_on(spawn(
+            config,
+            proxy_metrics,
+            admin_runtime.handle().clone(),
+            shutdown,
+        ))
+    }
+
+    /// Starts the proxy on a background thread. The proxy runs until the
+    /// handle is stopped or dropped.
+    pub fn start(self) -> std::io::Result<ProxyHandle> {
+        let (shutdown, receiver) = oneshot::channel();
+
+        let thread = std::thread::Builder::new()
+            .name("momento_proxy".to_string())
+            .spawn(move || {
+                self.run(async {
+                    let _ = receiver.await;
+                })
+                .map_err(|e| e.to_string())
+            })?;
+
+        Ok(ProxyHandle {
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Controls a proxy started with `ProxyBuilder::start`.
+pub struct ProxyHandle {
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl ProxyHandle {
+    /// Whether the proxy is still running. A proxy which failed to start, or
+    /// whose admin listener exited, is no longer running.
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+
+    /// The current value of every counter and gauge, by name.
+    pub fn metrics(&self) -> BTreeMap<String, i64> {
+        let mut values = BTreeMap::new();
+
+        for metric in &metriken::metrics() {
+            let Some(any) = metric.as_any() else {
+                continue;
+            };
+
+            if let Some(counter) = any.downcast_ref::<Counter>() {
+                values.insert(metric.name().to_string(), counter.value() as i64);
+            } else if let Some(gauge) = any.downcast_ref::<Gauge>() {
+                values.insert(metric.name().to_string(), gauge.value());
+            }
+        }
+
+        values
+    }
+
+    /// Stops the proxy and waits for it to exit. Returns the error the proxy
+    /// failed with, if any.
+    pub fn stop(mut self) -> Result<(), String> {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .unwrap_or_else(|_| Err("proxy thread panicked".to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for ProxyHandle {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
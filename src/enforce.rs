@@ -0,0 +1,101 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Whether the policies which reject requests enforce them, or only observe
+//! them. With `policy_mode = "observe"`, a request which a policy would have
+//! rejected is counted and logged, and then served as if the policy allowed
+//! it, so that a new limit can be checked against real traffic before it is
+//! enforced.
+
+use crate::momento_proxy::PolicyMode;
+use crate::*;
+use core::sync::atomic::{AtomicBool, AtomicU64};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[metric(name = "policy_observed_acl")]
+pub static POLICY_OBSERVED_ACL: Counter = Counter::new();
+
+#[metric(name = "policy_observed_rate_limit")]
+pub static POLICY_OBSERVED_RATE_LIMIT: Counter = Counter::new();
+
+#[metric(name = "policy_observed_item_size")]
+pub static POLICY_OBSERVED_ITEM_SIZE: Counter = Counter::new();
+
+#[metric(name = "policy_observed_quota")]
+pub static POLICY_OBSERVED_QUOTA: Counter = Counter::new();
+
+static OBSERVE: AtomicBool = AtomicBool::new(false);
+
+/// A policy which rejects requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Policy {
+    /// `denied_commands`
+    Acl,
+    /// `rate_limit`
+    RateLimit,
+    /// `max_item_size`
+    ItemSize,
+    /// `quotas`
+    Quota,
+}
+
+impl Policy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Policy::Acl => "acl",
+            Policy::RateLimit => "rate_limit",
+            Policy::ItemSize => "item_size",
+            Policy::Quota => "quota",
+        }
+    }
+
+    fn counter(&self) -> &'static Counter {
+        match self {
+            Policy::Acl => &POLICY_OBSERVED_ACL,
+            Policy::RateLimit => &POLICY_OBSERVED_RATE_LIMIT,
+            Policy::ItemSize => &POLICY_OBSERVED_ITEM_SIZE,
+            Policy::Quota => &POLICY_OBSERVED_QUOTA,
+        }
+    }
+}
+
+// the second each policy last logged a violation, so that a policy which
+// would reject a flood of requests logs one line a second
+static LAST_LOGGED: [AtomicU64; 4] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Sets whether policies are enforced or only observed.
+pub(crate) fn set_mode(mode: PolicyMode) {
+    OBSERVE.store(mode == PolicyMode::Observe, Ordering::Relaxed);
+}
+
+/// Whether a request which violates `policy` is rejected. When the policy is
+/// only observed, the violation is counted, logged at most once a second for
+/// each policy, and the request is served.
+pub(crate) fn enforce(policy: Policy, violation: impl FnOnce() -> String) -> bool {
+    if !OBSERVE.load(Ordering::Relaxed) {
+        return true;
+    }
+
+    policy.counter().increment();
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    let last = &LAST_LOGGED[policy as usize];
+    if last.swap(now, Ordering::Relaxed) != now {
+        info!(
+            "policy {} observed, not enforced: {}",
+            policy.as_str(),
+            violation()
+        );
+    }
+
+    false
+}
@@ -0,0 +1,88 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Watches the number of open file descriptors against the soft limit on
+//! them. Past the configured high-water mark, the listeners close new
+//! connections as soon as they are accepted, so that the descriptors left are
+//! kept for the connections to Momento and the open client connections,
+//! rather than failing somewhere less obvious once they run out.
+
+use crate::*;
+use std::sync::atomic::AtomicBool;
+
+#[metric(name = "fd_open")]
+pub static FD_OPEN: Gauge = Gauge::new();
+
+#[metric(name = "fd_limit")]
+pub static FD_LIMIT: Gauge = Gauge::new();
+
+#[metric(name = "fd_reject")]
+pub static FD_REJECT: Counter = Counter::new();
+
+// set while more descriptors are open than the high-water mark allows
+static OVER_HIGH_WATER: AtomicBool = AtomicBool::new(false);
+
+/// Whether new connections are being rejected to save file descriptors.
+pub(crate) fn is_over_high_water() -> bool {
+    OVER_HIGH_WATER.load(Ordering::Relaxed)
+}
+
+/// Samples the open descriptors and the limit into the gauges every
+/// `interval`. With a `high_water_percent` of the limit, new connections are
+/// rejected while more than that are open.
+pub(crate) async fn monitor(high_water_percent: u8, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+
+        let (Some(open), Some(limit)) = (open_count(), soft_limit()) else {
+            continue;
+        };
+        FD_OPEN.set(open as _);
+        FD_LIMIT.set(limit as _);
+
+        if high_water_percent == 0 {
+            continue;
+        }
+
+        let high_water = limit.saturating_mul(high_water_percent as u64) / 100;
+        let over = open > high_water;
+        if over != OVER_HIGH_WATER.swap(over, Ordering::Relaxed) {
+            if over {
+                warn!("{open} of {limit} file descriptors are open, rejecting new connections");
+            } else {
+                info!("{open} of {limit} file descriptors are open, accepting new connections");
+            }
+        }
+    }
+}
+
+// the number of open descriptors, not counting the one used to list them
+#[cfg(target_os = "linux")]
+fn open_count() -> Option<u64> {
+    let count = std::fs::read_dir("/proc/self/fd").ok()?.count() as u64;
+    Some(count.saturating_sub(1))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_count() -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+fn soft_limit() -> Option<u64> {
+    // SAFETY: rlimit is plain data, for which all zeroes is valid
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+
+    // SAFETY: `limit` is a valid rlimit for getrlimit to write to
+    let result = unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) };
+
+    (result == 0 && limit.rlim_cur != libc::RLIM_INFINITY).then_some(limit.rlim_cur as u64)
+}
+
+#[cfg(not(unix))]
+fn soft_limit() -> Option<u64> {
+    None
+}
@@ -2,25 +2,183 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
+use crate::buffers::BufferGauge;
+use crate::connections::{Connection, State};
+use crate::deadline::*;
+use crate::keys::KeyTransform;
+use crate::momento_proxy::{UnsupportedCommandBehavior, ZsetBackend};
+use crate::pressure::RESP_OOM;
+use crate::preview::Leaderboards;
 use crate::protocol::*;
+use crate::quota::{self, Quotas};
+use crate::shards::{Router, Shards, SHARD_CROSSSLOT};
+use crate::tuning::Tuning;
 use crate::*;
 use pelikan_net::TCP_SEND_BYTE;
 use protocol_memcache::Protocol;
-use session::Buf;
+use session::{Buf, BufMut};
 use std::collections::BTreeMap;
-use std::sync::atomic::AtomicBool;
+use std::future::Future;
 use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, Semaphore};
+
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
+
+#[metric(name = "pipeline_depth_limited")]
+pub static PIPELINE_DEPTH_LIMITED: Counter = Counter::new();
+
+#[metric(name = "response_queue_full")]
+pub static RESPONSE_QUEUE_FULL: Counter = Counter::new();
+
+#[metric(name = "response_queue_overflow")]
+pub static RESPONSE_QUEUE_OVERFLOW: Counter = Counter::new();
+
+#[metric(name = "request_too_large")]
+pub static REQUEST_TOO_LARGE: Counter = Counter::new();
+
+#[metric(name = "connection_idle_reaped")]
+pub static CONNECTION_IDLE_REAPED: Counter = Counter::new();
+
+#[metric(name = "connection_writer_stalled")]
+pub static CONNECTION_WRITER_STALLED: Counter = Counter::new();
+
+// the reply to a refused write, as sent by redis when it is out of memory
+const OOM_RESPONSE: &[u8] = b"-OOM command not allowed when used memory > 'maxmemory'.\r\n";
+
+// how long a closing connection waits for the responses still in flight to be
+// written before the writer is aborted
+const WRITER_DRAIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+// completes once the connection has gone `idle_timeout` without a read, never
+// if there is no idle timeout
+async fn idle_expired(last_read: tokio::time::Instant, idle_timeout: Option<Duration>) {
+    match idle_timeout {
+        Some(idle_timeout) => tokio::time::sleep_until(last_read + idle_timeout).await,
+        None => std::future::pending().await,
+    }
+}
+
+// records a resp response in the connection summary, where responses which
+// start with `-` are errors
+fn resp_response_sent(connection: &Connection, audited: Option<&resp::Command>, response: &[u8]) {
+    connection.sent(response.len());
+    if response.first() == Some(&b'-') {
+        connection.error();
+    }
+    if let Some(command) = audited {
+        audit::record(
+            connection,
+            &command.name(),
+            &command.keys(),
+            resp_status(response),
+        );
+    }
+}
+
+// the klog status of a response, for the audit log. A write which succeeds is
+// recorded as stored, whatever its reply
+fn resp_status(response: &[u8]) -> klog::Status {
+    match response {
+        b"-ERR backend timeout\r\n" => klog::Status::Timeout,
+        [b'-', ..] => klog::Status::ServerError,
+        _ => klog::Status::Stored,
+    }
+}
+
+/// The metrics of a connection, which are those of its listener until the
+/// client names itself, and then those of the named client.
+struct ClientMetrics<M> {
+    listener: M,
+    current: M,
+    name: Option<Arc<str>>,
+    // counts the connection in the named client's connection metrics
+    _connection: Option<ConnectionGuard>,
+}
+
+impl<M: ConnectionMetrics> ClientMetrics<M> {
+    fn new(listener: M) -> Self {
+        Self {
+            current: listener.clone(),
+            listener,
+            name: None,
+            _connection: None,
+        }
+    }
+
+    /// Switches to the metrics for the name of the connection, if it has
+    /// changed.
+    fn update(&mut self, connection: &Connection) {
+        let name = connection.name();
+        if name == self.name {
+            return;
+        }
+
+        (self.current, self._connection) = match &name {
+            Some(name) => {
+                let metrics = self.listener.with_client_name(name);
+                let guard = metrics.begin_connection();
+                (metrics, Some(guard))
+            }
+            None => (self.listener.clone(), None),
+        };
+        self.name = name;
+    }
+
+    fn metrics(&self) -> &M {
+        &self.current
+    }
+
+    fn name(&self) -> Option<Arc<str>> {
+        self.name.clone()
+    }
+}
+
+/// A client connection, which is a TCP stream unless the connection is served
+/// through io_uring.
+pub(crate) trait ClientSocket: AsyncRead + AsyncWrite + Send + Unpin + 'static {
+    type ReadHalf: AsyncRead + Send + Unpin + 'static;
+    type WriteHalf: AsyncWrite + Send + Unpin + 'static;
+
+    /// Reads into `buf` without consuming the bytes read.
+    fn peek<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+    ) -> impl Future<Output = std::io::Result<usize>> + Send + 'a;
+
+    /// Splits the connection so that it can be read and written by separate
+    /// tasks.
+    fn into_split(self) -> (Self::ReadHalf, Self::WriteHalf);
+}
+
+impl ClientSocket for tokio::net::TcpStream {
+    type ReadHalf = tokio::net::tcp::OwnedReadHalf;
+    type WriteHalf = tokio::net::tcp::OwnedWriteHalf;
+
+    fn peek<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+    ) -> impl Future<Output = std::io::Result<usize>> + Send + 'a {
+        tokio::net::TcpStream::peek(self, buf)
+    }
+
+    fn into_split(self) -> (Self::ReadHalf, Self::WriteHalf) {
+        tokio::net::TcpStream::into_split(self)
+    }
+}
 
 pub(crate) async fn handle_memcache_client(
-    socket: tokio::net::TcpStream,
+    mut socket: impl ClientSocket,
     client: CacheClient,
-    cache_name: String,
-    flags: bool,
+    config: Arc<momento_proxy::Cache>,
+    shards: Arc<Shards>,
     proxy_metrics: impl ProxyMetrics,
     memory_cache: Option<MCache>,
-    buffer_size: usize,
+    tuning: Arc<Tuning>,
+    quotas: Arc<Quotas>,
+    connection: Arc<Connection>,
 ) {
     debug!("accepted memcache client, waiting for first byte to detect text or binary");
 
@@ -39,12 +197,14 @@ pub(crate) async fn handle_memcache_client(
                     handle_memcache_client_concrete(
                         socket,
                         client,
-                        cache_name,
+                        config,
+                        shards,
                         protocol_memcache::BinaryProtocol::default(),
-                        flags,
                         proxy_metrics,
                         memory_cache,
-                        buffer_size,
+                        tuning,
+                        quotas,
+                        connection,
                     )
                     .await;
                     return;
@@ -53,12 +213,14 @@ pub(crate) async fn handle_memcache_client(
                     handle_memcache_client_concrete(
                         socket,
                         client,
-                        cache_name,
+                        config,
+                        shards,
                         protocol_memcache::TextProtocol::default(),
-                        flags,
                         proxy_metrics,
                         memory_cache,
-                        buffer_size,
+                        tuning,
+                        quotas,
+                        connection,
                     )
                     .await;
                     return;
@@ -78,115 +240,173 @@ pub(crate) async fn handle_memcache_client(
 }
 
 pub(crate) async fn handle_memcache_client_concrete(
-    socket: tokio::net::TcpStream,
+    mut socket: impl ClientSocket,
     client: CacheClient,
-    cache_name: String,
+    config: Arc<momento_proxy::Cache>,
+    shards: Arc<Shards>,
     protocol: impl Protocol<protocol_memcache::Request, protocol_memcache::Response>
         + Clone
         + Send
         + 'static,
-    flags: bool,
     proxy_metrics: impl ProxyMetrics,
     memory_cache: Option<MCache>,
-    buffer_size: usize,
+    tuning: Arc<Tuning>,
+    quotas: Arc<Quotas>,
+    connection: Arc<Connection>,
 ) {
     // initialize a buffer for incoming bytes from the client
-    let mut read_buffer = Buffer::new(buffer_size);
-    let mut write_buffer = Buffer::new(buffer_size);
+    let mut read_buffer = Buffer::new(config.buffer_size());
+    let mut write_buffer = Buffer::new(config.buffer_size());
+    let mut read_gauge = BufferGauge::new(&read_buffer, config.buffer_size());
+    let mut write_gauge = BufferGauge::new(&write_buffer, config.buffer_size());
+    let shrink_idle = config.buffer_shrink_idle();
+    let key_transform = Arc::new(
+        config
+            .key_transform()
+            .expect("key transform is validated on startup"),
+    );
 
     // initialize the protocol
     let protocol2 = protocol.clone();
 
-    // queue for response passing back from tasks
-    let (sender, mut receiver) = mpsc::channel::<
-        std::io::Result<(u64, protocol_memcache::Request, protocol_memcache::Response)>,
-    >(1024);
+    // queue for response passing back from tasks. the pipeline keeps the
+    // responses in flight within the limit, so sends never wait, and there is
+    // room for the error sent when the queue overflows
+    let pipeline = Pipeline::new(&config);
+    let (sender, mut receiver) =
+        mpsc::channel::<std::io::Result<(u64, PendingResponse)>>(config.response_queue_limit() + 1);
+
+    // the protocol was detected from the first byte, which is still unread
+    let mut first = [0];
+    let binary = matches!(socket.peek(&mut first).await, Ok(1)) && first[0] == 0x80;
 
     let (mut read_half, mut write_half) = socket.into_split();
 
     let sequence = Arc::new(AtomicU64::new(0));
-    let sequence2 = sequence.clone();
 
-    let read_alive = Arc::new(AtomicBool::new(true));
-    let read_alive2 = read_alive.clone();
+    let connection2 = connection.clone();
 
-    let write_alive = Arc::new(AtomicBool::new(true));
-    let write_alive2 = write_alive.clone();
+    let pipeline2 = pipeline.permits.clone();
+    let pipeline3 = pipeline.permits.clone();
 
-    tokio::spawn(async move {
-        let mut next_sequence: u64 = 0;
-        let mut backlog = BTreeMap::new();
-
-        while write_alive2.load(Ordering::Relaxed) {
-            if !read_alive2.load(Ordering::Relaxed)
-                && next_sequence == sequence2.load(Ordering::Relaxed)
-            {
-                write_alive2.store(false, Ordering::Relaxed);
-                return;
-            }
+    // the writer runs until every sender is dropped, which happens once the
+    // reader has stopped and every request task has sent its response, or
+    // until it fails. a panic here drops the write half, which closes the
+    // connection
+    let writer = crate::panic::isolate(async move {
+        let mut order = ResponseOrder::new();
 
+        while let Some(result) = receiver.recv().await {
             debug!("writer loop");
-            if let Some(result) = receiver.recv().await {
-                match result {
-                    Ok((sequence, request, response)) => {
-                        if sequence == next_sequence {
-                            debug!("sending next: {next_sequence}");
-                            next_sequence += 1;
-                            if protocol2
-                                .compose_response(&request, &response, &mut write_buffer)
-                                .is_err()
-                            {
-                                read_alive2.store(false, Ordering::Relaxed);
-                                write_alive2.store(false, Ordering::Relaxed);
-                                return;
-                            }
-
-                            'backlog: while !backlog.is_empty() {
-                                if let Some((request, response)) = backlog.remove(&next_sequence) {
-                                    debug!("sending next: {next_sequence}");
-                                    next_sequence += 1;
-                                    if protocol2
-                                        .compose_response(&request, &response, &mut write_buffer)
-                                        .is_err()
-                                    {
-                                        read_alive2.store(false, Ordering::Relaxed);
-                                        write_alive2.store(false, Ordering::Relaxed);
-                                        return;
-                                    }
-                                } else {
-                                    break 'backlog;
-                                }
-                            }
-                        } else {
-                            debug!("queueing seq: {sequence}");
-                            backlog.insert(sequence, (request, response));
+            connection2.set_state(State::Writing);
+            match result {
+                // written right away, after the responses already composed,
+                // and then the connection is closed
+                Ok((_, PendingResponse::Close(bytes))) => {
+                    write_buffer.put_slice(&bytes);
+                    while write_buffer.remaining() > 0 {
+                        match do_write2(&mut write_half, &mut write_buffer).await {
+                            Ok(n) => connection2.sent(n.get()),
+                            Err(_) => break,
                         }
                     }
-                    Err(_e) => {
-                        read_alive2.store(false, Ordering::Relaxed);
-                        write_alive2.store(false, Ordering::Relaxed);
-                        return;
+                    return;
+                }
+                Ok((sequence, response)) => {
+                    let mut next = order.push(sequence, response);
+                    while let Some(response) = next {
+                        connection2.response();
+                        pipeline2.add_permits(1);
+                        if compose_memcache_response(
+                            &protocol2,
+                            response,
+                            &mut write_buffer,
+                            &connection2,
+                        )
+                        .is_err()
+                        {
+                            return;
+                        }
+                        next = order.pop();
                     }
                 }
+                Err(_e) => {
+                    return;
+                }
             }
 
             while write_buffer.remaining() > 0 {
                 debug!("non-blocking write");
-                if do_write2(&mut write_half, &mut write_buffer).await.is_err() {
-                    read_alive2.store(false, Ordering::Relaxed);
-                    write_alive2.store(false, Ordering::Relaxed);
-                    return;
+                match do_write2(&mut write_half, &mut write_buffer).await {
+                    Ok(n) => connection2.sent(n.get()),
+                    Err(_) => return,
                 }
             }
+
+            connection2.set_idle();
+
+            // everything has been written, so a buffer grown by a large
+            // response can shrink
+            write_gauge.update(&write_buffer);
+            write_gauge.shrink(&mut write_buffer);
         }
     });
 
+    let mut writer = tokio::spawn(async move {
+        writer.await;
+
+        // wake the reader if it is waiting for responses which won't be written
+        pipeline3.close();
+    });
+
+    let idle_timeout = config.idle_timeout();
+    let mut last_read = tokio::time::Instant::now();
+
+    let mut client_metrics = ClientMetrics::new(proxy_metrics);
+
+    // set when responses stopped draining while the response queue was full
+    let mut overflowed = false;
+
     // loop to handle the connection
-    while read_alive.load(Ordering::Relaxed) {
+    'connection: loop {
+        connection.set_idle();
+        read_gauge.update(&read_buffer);
+        if shrink_idle.is_zero() {
+            read_gauge.shrink(&mut read_buffer);
+        }
+
         // read data from the tcp stream into the buffer
-        if do_read2(&mut read_half, &mut read_buffer).await.is_err() {
-            // any read errors result in hangup
-            read_alive.store(false, Ordering::Relaxed);
+        tokio::select! {
+            result = do_read2(&mut read_half, &mut read_buffer, config.buffer_size()) => {
+                match result {
+                    Ok(n) => connection.received(n.get()),
+                    // any read errors result in hangup
+                    Err(_) => break 'connection,
+                }
+                last_read = tokio::time::Instant::now();
+                connection.set_state(State::Parsing);
+            }
+            // a client which went away without closing, such as behind a NAT
+            // which dropped the connection, never sends another byte
+            _ = idle_expired(last_read, idle_timeout) => {
+                debug!("connection {} idle, closing", connection.addr());
+                CONNECTION_IDLE_REAPED.increment();
+                break 'connection;
+            }
+            _ = tokio::time::sleep(shrink_idle), if read_gauge.is_oversized(&read_buffer) => {
+                read_gauge.shrink(&mut read_buffer);
+                continue;
+            }
+            _ = connection.killed() => {
+                debug!("connection {} killed", connection.addr());
+                // responses still in flight are not written
+                writer.abort();
+                return;
+            }
+            // the writer has failed, so no more responses can be written
+            _ = sender.closed() => {
+                break 'connection;
+            }
         }
 
         // dispatch all complete requests in the socket buffer as async tasks
@@ -207,26 +427,78 @@ pub(crate) async fn handle_memcache_client_concrete(
 
                     read_buffer.advance(consumed);
 
+                    if pipeline.wait(&connection).await.is_err() {
+                        overflowed = true;
+                        break 'connection;
+                    }
+                    connection.request(memcache_command_name(&request));
+
+                    // a rejected request is answered in order with the
+                    // others, and a throttled one waits in its own task, so
+                    // that it doesn't hold back the requests already in flight
+                    let mut rejected = None;
+                    let mut throttle = None;
+                    match memcache_admission(&quotas, &request) {
+                        quota::Admission::Admit => {}
+                        quota::Admission::Throttle(delay) => throttle = Some(delay),
+                        quota::Admission::Reject(error) => rejected = Some(error),
+                    }
+
                     let sender = sender.clone();
                     let client = client.clone();
-                    let cache_name = cache_name.clone();
+                    let config = config.clone();
+                    let shards = shards.clone();
+                    let key_transform = key_transform.clone();
 
                     let sequence = sequence.fetch_add(1, Ordering::Relaxed);
 
-                    let proxy_metrics = proxy_metrics.clone();
+                    let tuning = tuning.clone();
+                    let proxy_metrics = client_metrics.metrics().clone();
+                    let client_name = client_metrics.name();
+                    let connection = connection.clone();
                     let memory_cache = memory_cache.clone();
                     tokio::spawn(async move {
-                        handle_memcache_request(
-                            sender,
-                            client,
-                            cache_name,
-                            sequence,
-                            request,
-                            flags,
-                            proxy_metrics,
-                            memory_cache,
-                        )
+                        if let Some(delay) = throttle {
+                            tokio::time::sleep(delay).await;
+                        }
+
+                        let panic_sender = sender.clone();
+                        let backend_timeout =
+                            tuning.backend_timeout(command_class(memcache_command_name(&request)));
+                        let result = crate::panic::isolate(klog::with_listener_sample(
+                            tuning.klog_sample(),
+                            klog::with_client_name(
+                                client_name,
+                                with_backend_timeout(
+                                    backend_timeout,
+                                    handle_memcache_request(
+                                        sender,
+                                        client,
+                                        config,
+                                        shards,
+                                        key_transform,
+                                        sequence,
+                                        request,
+                                        rejected,
+                                        proxy_metrics,
+                                        memory_cache,
+                                        connection,
+                                    ),
+                                ),
+                            ),
+                        ))
                         .await;
+
+                        // the response for this request will never be sent,
+                        // so the connection must be closed
+                        if result.is_none() {
+                            let _ = panic_sender
+                                .send(Err(Error::new(
+                                    ErrorKind::Other,
+                                    "request handler panicked",
+                                )))
+                                .await;
+                        }
                     });
                 }
                 Err(e) => match e.kind() {
@@ -236,27 +508,572 @@ pub(crate) async fn handle_memcache_client_concrete(
                         break 'requests;
                     }
                     _ => {
-                        // invalid request
-                        trace!("malformed request: {:?}", borrowed_buf);
-                        read_alive.store(false, Ordering::Relaxed);
-                        return;
+                        // `version` is served by the proxy itself so clients
+                        // can tell which instance they are connected to, and
+                        // `version <name>` also names the connection
+                        if let Some((consumed, response)) =
+                            memcache_version_request(borrowed_buf, &connection)
+                        {
+                            read_buffer.advance(consumed);
+                            client_metrics.update(&connection);
+
+                            if pipeline.wait(&connection).await.is_err() {
+                                overflowed = true;
+                                break 'connection;
+                            }
+                            connection.request("version");
+
+                            let sequence = sequence.fetch_add(1, Ordering::Relaxed);
+                            let _ = sender
+                                .send(Ok((sequence, PendingResponse::Raw(response))))
+                                .await;
+                            continue 'requests;
+                        }
+
+                        // meta get is served from momento, so that clients can
+                        // read the remaining ttl of an item
+                        if let Some((consumed, request)) = memcache::MetaGet::parse(borrowed_buf) {
+                            read_buffer.advance(consumed);
+
+                            if pipeline.wait(&connection).await.is_err() {
+                                overflowed = true;
+                                break 'connection;
+                            }
+                            connection.request("mg");
+
+                            let sequence = sequence.fetch_add(1, Ordering::Relaxed);
+                            let request = match request {
+                                Ok(request) => request,
+                                Err(error) => {
+                                    connection.error();
+                                    let response = format!("CLIENT_ERROR {error}\r\n");
+                                    let _ = sender
+                                        .send(Ok((
+                                            sequence,
+                                            PendingResponse::Raw(response.into_bytes()),
+                                        )))
+                                        .await;
+                                    continue 'requests;
+                                }
+                            };
+
+                            let sender = sender.clone();
+                            let client = client.clone();
+                            let config = config.clone();
+                            let shards = shards.clone();
+                            let key_transform = key_transform.clone();
+                            let tuning = tuning.clone();
+                            let proxy_metrics = client_metrics.metrics().clone();
+                            let client_name = client_metrics.name();
+                            let connection = connection.clone();
+                            tokio::spawn(async move {
+                                let backend_timeout = tuning.backend_timeout(command_class("mg"));
+                                let result = crate::panic::isolate(klog::with_listener_sample(
+                                    tuning.klog_sample(),
+                                    klog::with_client_name(
+                                        client_name,
+                                        with_backend_timeout(
+                                            backend_timeout,
+                                            with_rpc_call_guard(
+                                                proxy_metrics.begin_memcached_get(),
+                                                memcache::meta_get(
+                                                    &client,
+                                                    &shards,
+                                                    &request,
+                                                    &key_transform,
+                                                    memcache::ValueCodec::new(&config),
+                                                ),
+                                            ),
+                                        ),
+                                    ),
+                                ))
+                                .await;
+
+                                let message = match result {
+                                    Some(Ok(response)) => {
+                                        Ok((sequence, PendingResponse::Raw(response)))
+                                    }
+                                    Some(Err(e)) => {
+                                        debug!("backend error for mg: {e}");
+                                        connection.error();
+                                        Ok((
+                                            sequence,
+                                            PendingResponse::Raw(
+                                                b"SERVER_ERROR backend error\r\n".to_vec(),
+                                            ),
+                                        ))
+                                    }
+                                    // the response for this request will
+                                    // never be sent, so the connection must
+                                    // be closed
+                                    None => Err(Error::new(
+                                        ErrorKind::Other,
+                                        "request handler panicked",
+                                    )),
+                                };
+                                let _ = sender.send(message).await;
+                            });
+                            continue 'requests;
+                        }
+
+                        // runtime tuning, when the listener allows it
+                        if let Some((consumed, response)) = config
+                            .proxy_config_commands()
+                            .then(|| tuning.command(borrowed_buf))
+                            .flatten()
+                        {
+                            read_buffer.advance(consumed);
+
+                            if pipeline.wait(&connection).await.is_err() {
+                                overflowed = true;
+                                break 'connection;
+                            }
+                            connection.request("proxy_config");
+
+                            let sequence = sequence.fetch_add(1, Ordering::Relaxed);
+                            let _ = sender
+                                .send(Ok((sequence, PendingResponse::Raw(response))))
+                                .await;
+                            continue 'requests;
+                        }
+
+                        if config.unsupported_command_behavior()
+                            == UnsupportedCommandBehavior::Hangup
+                        {
+                            // invalid request
+                            trace!("malformed request: {:?}", borrowed_buf);
+                            break 'connection;
+                        }
+
+                        // reply with an error and skip past the request so the
+                        // rest of the pipeline can still be served
+                        let (consumed, response) = match unsupported_memcache_request(borrowed_buf)
+                        {
+                            Unsupported::Skip(consumed, response) => (consumed, response),
+                            Unsupported::Incomplete => break 'requests,
+                            Unsupported::Malformed => {
+                                trace!("malformed request: {:?}", borrowed_buf);
+                                break 'connection;
+                            }
+                        };
+
+                        trace!("unsupported request: {:?}", &borrowed_buf[..consumed]);
+                        if let Some((command, key)) = audit::is_enabled()
+                            .then(|| memcache_refused_command(&borrowed_buf[..consumed]))
+                            .flatten()
+                        {
+                            // `flush_all` takes a delay rather than a key
+                            let keys: Vec<&[u8]> =
+                                key.filter(|_| command != "flush_all").into_iter().collect();
+                            audit::record(&connection, command, &keys, klog::Status::ClientError);
+                        }
+                        read_buffer.advance(consumed);
+
+                        if pipeline.wait(&connection).await.is_err() {
+                            overflowed = true;
+                            break 'connection;
+                        }
+                        connection.request("unsupported");
+                        connection.error();
+
+                        client_metrics
+                            .metrics()
+                            .begin_memcached_unimplemented()
+                            .complete_error();
+
+                        let sequence = sequence.fetch_add(1, Ordering::Relaxed);
+                        let _ = sender
+                            .send(Ok((sequence, PendingResponse::Raw(response))))
+                            .await;
                     }
                 },
             }
         }
+
+        // the incomplete request left in the buffer is already over the limit,
+        // so it is rejected instead of buffering the rest of it
+        if read_buffer.remaining() > config.max_request_size() {
+            debug!("request from {} is too large", connection.addr());
+            REQUEST_TOO_LARGE.increment();
+
+            if pipeline.wait(&connection).await.is_err() {
+                overflowed = true;
+                break 'connection;
+            }
+            connection.request("too_large");
+            connection.error();
+
+            let response = memcache_too_large_response(read_buffer.borrow());
+            let sequence = sequence.fetch_add(1, Ordering::Relaxed);
+            let _ = sender
+                .send(Ok((sequence, PendingResponse::Raw(response))))
+                .await;
+
+            break 'connection;
+        }
+    }
+
+    // the client is told why the connection is closed, instead of the
+    // responses which are still queued
+    if overflowed {
+        debug!("connection {} response queue overflowed", connection.addr());
+        RESPONSE_QUEUE_OVERFLOW.increment();
+        connection.error();
+
+        let response = memcache_overflow_response(binary);
+        let _ = sender
+            .send(Ok((u64::MAX, PendingResponse::Close(response))))
+            .await;
+    }
+
+    // the writer exits once the responses still in flight are written, which
+    // closes the connection. a writer which can't make progress, such as for a
+    // client which stopped reading, is aborted instead of being left behind
+    drop(sender);
+    if timeout(WRITER_DRAIN_TIMEOUT, &mut writer).await.is_err() {
+        debug!("connection {} writer stalled, closing", connection.addr());
+        CONNECTION_WRITER_STALLED.increment();
+        writer.abort();
+    }
+}
+
+/// A response produced by a request task, written to the client in request
+/// order by the connection's writer task.
+enum PendingResponse {
+    /// A response which is composed by the memcache protocol.
+    Memcache(protocol_memcache::Request, protocol_memcache::Response),
+    /// Pre-composed bytes which are written as-is.
+    Raw(Vec<u8>),
+    /// Nothing is written to the client, as for `noreply` requests.
+    Silent,
+    /// Pre-composed bytes which are written ahead of any responses which are
+    /// still queued, after which the connection is closed.
+    Close(Vec<u8>),
+}
+
+fn compose_memcache_response(
+    protocol: &impl Protocol<protocol_memcache::Request, protocol_memcache::Response>,
+    response: PendingResponse,
+    buffer: &mut Buffer,
+    connection: &Connection,
+) -> std::io::Result<()> {
+    match response {
+        PendingResponse::Memcache(request, response) => {
+            if response.is_error() {
+                connection.error();
+            }
+            protocol
+                .compose_response(&request, &response, buffer)
+                .map(|_| ())
+        }
+        PendingResponse::Raw(bytes) | PendingResponse::Close(bytes) => {
+            buffer.put_slice(&bytes);
+            Ok(())
+        }
+        PendingResponse::Silent => Ok(()),
+    }
+}
+
+/// Puts the responses of a pipelined connection back in the order of their
+/// requests, as the request tasks may complete in any order.
+pub(crate) struct ResponseOrder<T> {
+    next_sequence: u64,
+    backlog: BTreeMap<u64, T>,
+}
+
+impl<T> ResponseOrder<T> {
+    pub fn new() -> Self {
+        Self {
+            next_sequence: 0,
+            backlog: BTreeMap::new(),
+        }
     }
 
-    for _ in 0..60 {
-        if write_alive.load(Ordering::Relaxed) {
-            // time delay for write half to complete
-            tokio::time::sleep(Duration::from_secs(1)).await;
+    /// Takes the response to request `sequence`, returning it if it is the
+    /// next to be written. Otherwise it is held until the responses before it
+    /// have been written.
+    pub fn push(&mut self, sequence: u64, response: T) -> Option<T> {
+        if sequence == self.next_sequence {
+            debug!("sending next: {sequence}");
+            self.next_sequence += 1;
+            Some(response)
         } else {
-            break;
+            debug!("queueing seq: {sequence}");
+            self.backlog.insert(sequence, response);
+            None
+        }
+    }
+
+    /// Returns the next response to be written, if it has been held.
+    pub fn pop(&mut self) -> Option<T> {
+        let response = self.backlog.remove(&self.next_sequence)?;
+        debug!("sending next: {}", self.next_sequence);
+        self.next_sequence += 1;
+        Some(response)
+    }
+}
+
+/// Bounds the requests of a connection which are awaiting a response, by the
+/// pipeline depth or the response queue limit, whichever is lower. A permit is
+/// taken for each request and returned once its response is written.
+struct Pipeline {
+    permits: Arc<Semaphore>,
+    // whether the bound is the pipeline depth rather than the queue limit
+    depth_limited: bool,
+    overflow_timeout: Duration,
+}
+
+// the reader gave up waiting for the response queue to drain
+struct Overflow;
+
+impl Pipeline {
+    fn new(config: &momento_proxy::Cache) -> Self {
+        let limit = config.response_queue_limit();
+        let depth = config.max_pipeline_depth().unwrap_or(limit);
+
+        Self {
+            permits: Arc::new(Semaphore::new(depth.min(limit))),
+            depth_limited: depth < limit,
+            overflow_timeout: config.response_queue_timeout(),
+        }
+    }
+
+    /// Waits until the connection has room for another request in flight,
+    /// which stops reading from the socket while the pipeline is full. Fails
+    /// if no response is written within the overflow timeout.
+    async fn wait(&self, connection: &Connection) -> Result<(), Overflow> {
+        let permit = match self.permits.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                connection.set_state(State::Backpressured);
+                if self.depth_limited {
+                    PIPELINE_DEPTH_LIMITED.increment();
+                } else {
+                    RESPONSE_QUEUE_FULL.increment();
+                }
+
+                match timeout(self.overflow_timeout, self.permits.acquire()).await {
+                    Ok(Ok(permit)) => permit,
+                    // closed once the writer has exited
+                    Ok(Err(_)) => return Ok(()),
+                    Err(_) => return Err(Overflow),
+                }
+            }
+        };
+
+        // returned by the writer with `add_permits` once the response is written
+        permit.forget();
+        connection.set_state(State::Dispatching);
+        Ok(())
+    }
+}
+
+// counts the request against the quotas of its keys, with the bytes of keys
+// and values it sends
+fn memcache_admission(quotas: &Quotas, request: &protocol_memcache::Request) -> quota::Admission {
+    match request {
+        memcache::Request::Delete(r) => quotas.admit([&r.key()[..]], r.key().len()),
+        memcache::Request::Get(r) => quotas.admit(
+            r.keys().iter().map(|key| &key[..]),
+            r.keys().iter().map(|key| key.len()).sum(),
+        ),
+        memcache::Request::Set(r) => quotas.admit([&r.key()[..]], r.key().len() + r.value().len()),
+        _ => quota::Admission::Admit,
+    }
+}
+
+// the command name recorded in the connection registry
+fn memcache_command_name(request: &protocol_memcache::Request) -> &'static str {
+    match request {
+        memcache::Request::Delete(_) => "delete",
+        memcache::Request::Get(_) => "get",
+        memcache::Request::Set(_) => "set",
+        _ => "unsupported",
+    }
+}
+
+/// Composes the response to a complete `version` request, including the
+/// instance id if there is one. A text `version <name>` request also names the
+/// connection. Returns `None` for any other request.
+fn memcache_version_request(buf: &[u8], connection: &Connection) -> Option<(usize, Vec<u8>)> {
+    let version = match instance::id() {
+        Some(id) => format!("{} {id}", instance::server_version()),
+        None => instance::server_version().to_string(),
+    };
+
+    if buf.first() == Some(&0x80) {
+        // binary protocol, the version opcode has no extras, key, or value
+        if buf.len() < 24 || buf[1] != 0x0b {
+            return None;
         }
+
+        let body_len = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]) as usize;
+        if body_len != 0 {
+            return None;
+        }
+
+        let mut response = vec![0; 24];
+        response[0] = 0x81;
+        response[1] = 0x0b;
+        response[8..12].copy_from_slice(&(version.len() as u32).to_be_bytes());
+        // opaque
+        response[12..16].copy_from_slice(&buf[12..16]);
+        response.extend_from_slice(version.as_bytes());
+
+        Some((24, response))
+    } else {
+        let end = buf.iter().position(|b| *b == b'\n')?;
+        let line = std::str::from_utf8(&buf[..end]).ok()?;
+        let mut tokens = line.trim_end_matches('\r').split_whitespace();
+        if tokens.next() != Some("version") {
+            return None;
+        }
+
+        match (tokens.next(), tokens.next()) {
+            (None, _) => {}
+            (Some(name), None) => {
+                if !connection.set_name(name) {
+                    return Some((end + 1, b"CLIENT_ERROR bad client name\r\n".to_vec()));
+                }
+            }
+            _ => return None,
+        }
+
+        Some((end + 1, format!("VERSION {version}\r\n").into_bytes()))
+    }
+}
+
+/// Composes the error response for a request which exceeds the maximum request
+/// size.
+fn memcache_too_large_response(buf: &[u8]) -> Vec<u8> {
+    if buf.first() == Some(&0x80) && buf.len() >= 24 {
+        let mut response = vec![0; 24];
+        response[0] = 0x81;
+        response[1] = buf[1];
+        // status: value too large
+        response[7] = 0x03;
+        // opaque
+        response[12..16].copy_from_slice(&buf[12..16]);
+        response
+    } else {
+        b"SERVER_ERROR object too large for cache\r\n".to_vec()
     }
+}
+
+/// Composes the error sent before closing a connection whose response queue
+/// overflowed.
+fn memcache_overflow_response(binary: bool) -> Vec<u8> {
+    if binary {
+        // there is no request to answer, so this is an unsolicited response
+        // with a status of internal error
+        let mut response = vec![0; 24];
+        response[0] = 0x81;
+        response[7] = 0x84;
+        response
+    } else {
+        b"SERVER_ERROR response queue overflow\r\n".to_vec()
+    }
+}
 
-    // shutdown write half
-    write_alive.store(false, Ordering::Relaxed);
+/// The extent of a request the memcache parser rejected.
+enum Unsupported {
+    /// the request isn't complete yet
+    Incomplete,
+    /// the request is this many bytes, and is answered with the response
+    Skip(usize, Vec<u8>),
+    /// the extent of the request can't be found, so the connection must be
+    /// closed rather than parse what follows as new requests
+    Malformed,
+}
+
+/// Finds the extent of a request the memcache parser rejected and composes
+/// the error response for it.
+fn unsupported_memcache_request(buf: &[u8]) -> Unsupported {
+    if buf.first() == Some(&0x80) {
+        // binary protocol, the header holds the length of the rest of the
+        // request
+        if buf.len() < 24 {
+            return Unsupported::Incomplete;
+        }
+
+        let body_len = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]) as usize;
+        if buf.len() < 24 + body_len {
+            return Unsupported::Incomplete;
+        }
+
+        let mut response = vec![0; 24];
+        response[0] = 0x81;
+        response[1] = buf[1];
+        // status: unknown command
+        response[7] = 0x81;
+        // opaque
+        response[12..16].copy_from_slice(&buf[12..16]);
+
+        Unsupported::Skip(24 + body_len, response)
+    } else {
+        let Some(end) = buf.iter().position(|b| *b == b'\n') else {
+            return Unsupported::Incomplete;
+        };
+
+        // a storage command is followed by its data block, which must be
+        // skipped too so that a value isn't parsed as commands
+        let mut tokens = buf[..end]
+            .split(|b| *b == b' ')
+            .map(|token| token.strip_suffix(b"\r").unwrap_or(token))
+            .filter(|token| !token.is_empty());
+        let consumed = match tokens.next() {
+            Some(b"set" | b"add" | b"replace" | b"append" | b"prepend" | b"cas") => {
+                let Some(bytes) = tokens
+                    .nth(3)
+                    .and_then(|bytes| std::str::from_utf8(bytes).ok())
+                    .and_then(|bytes| bytes.parse::<usize>().ok())
+                else {
+                    return Unsupported::Malformed;
+                };
+                match (end + 1)
+                    .checked_add(bytes)
+                    .and_then(|len| len.checked_add(2))
+                {
+                    Some(consumed) => consumed,
+                    None => return Unsupported::Malformed,
+                }
+            }
+            _ => end + 1,
+        };
+        if buf.len() < consumed {
+            return Unsupported::Incomplete;
+        }
+
+        Unsupported::Skip(consumed, b"ERROR\r\n".to_vec())
+    }
+}
+
+// the klog status of a response, for the audit log
+fn memcache_status(response: &protocol_memcache::Response) -> klog::Status {
+    match response {
+        protocol_memcache::Response::Stored(_) => klog::Status::Stored,
+        protocol_memcache::Response::NotStored(_) => klog::Status::NotStored,
+        protocol_memcache::Response::Exists(_) => klog::Status::Exists,
+        protocol_memcache::Response::Deleted(_) => klog::Status::Deleted,
+        protocol_memcache::Response::NotFound(_) => klog::Status::NotFound,
+        protocol_memcache::Response::ClientError(_) | protocol_memcache::Response::Error(_) => {
+            klog::Status::ClientError
+        }
+        _ => klog::Status::ServerError,
+    }
+}
+
+// the command and key of a text request the proxy refused, so that writes it
+// doesn't serve, such as `flush_all`, are still audited
+fn memcache_refused_command(buf: &[u8]) -> Option<(&str, Option<&[u8]>)> {
+    let end = buf.iter().position(|b| *b == b'\n')?;
+    let mut tokens = buf[..end]
+        .split(|b| *b == b' ')
+        .map(|token| token.strip_suffix(b"\r").unwrap_or(token))
+        .filter(|token| !token.is_empty());
+
+    let command = std::str::from_utf8(tokens.next()?).ok()?;
+    Some((command, tokens.next()))
 }
 
 // The memcached protocol expects us to return a reponse corresponding to
@@ -273,113 +1090,589 @@ impl ResponseWrappingError for protocol_memcache::Response {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_memcache_request(
-    channel: mpsc::Sender<
-        std::result::Result<
-            (u64, protocol_memcache::Request, protocol_memcache::Response),
-            std::io::Error,
-        >,
-    >,
+    channel: mpsc::Sender<std::io::Result<(u64, PendingResponse)>>,
     mut client: CacheClient,
-    cache_name: String,
+    config: Arc<momento_proxy::Cache>,
+    shards: Arc<Shards>,
+    key_transform: Arc<KeyTransform>,
     sequence: u64,
     request: protocol_memcache::Request,
-    flags: bool,
+    rejected: Option<String>,
     proxy_metrics: impl ProxyMetrics,
     memory_cache: Option<MCache>,
+    connection: Arc<Connection>,
 ) {
-    let result = match request {
-        memcache::Request::Delete(ref r) => {
-            if let Some(memory_cache) = memory_cache {
-                memory_cache.delete(r.key());
-            }
-            with_wrapped_error_response_rpc_call_guard(
-                proxy_metrics.begin_memcached_delete(),
-                memcache::delete(&mut client, &cache_name, r),
-            )
-            .await
-        }
-        memcache::Request::Get(ref r) => {
-            let recorder = proxy_metrics.begin_memcached_get();
-            with_wrapped_error_response_rpc_call_guard(
-                recorder.clone(),
-                memcache::get(&mut client, &cache_name, r, flags, memory_cache, &recorder),
-            )
-            .await
-        }
-        memcache::Request::Set(ref r) => {
-            with_wrapped_error_response_rpc_call_guard(
-                proxy_metrics.begin_memcached_set(),
-                memcache::set(&mut client, &cache_name, r, flags, memory_cache),
-            )
-            .await
-        }
-        _ => {
-            debug!("unsupported command: {}", request);
-            with_rpc_call_guard(proxy_metrics.begin_memcached_unimplemented(), async {
-                Err(Error::new(ErrorKind::Other, "unsupported"))
-            })
-            .await
-        }
+    let codec = memcache::ValueCodec::new(&config);
+
+    // Clients don't read responses for `noreply` requests, so writing anything
+    // back (including errors) would desync the connection. Metrics and klog
+    // have already been recorded by the request handlers.
+    let noreply = match &request {
+        memcache::Request::Delete(r) => r.noreply(),
+        memcache::Request::Set(r) => r.noreply(),
+        _ => false,
     };
 
-    match result {
-        Ok(response) => {
-            let _ = channel.send(Ok((sequence, request, response))).await;
+    let handle = async {
+        if let Some(error) = rejected {
+            return Ok(protocol_memcache::Response::server_error(error));
         }
-        Err(e) => {
-            let _ = channel.send(Err(e)).await;
+
+        match request {
+            memcache::Request::Delete(ref r) => {
+                if let Some(memory_cache) = memory_cache {
+                    memory_cache.delete(r.key());
+                }
+                let recorder = proxy_metrics.begin_memcached_delete();
+                with_wrapped_error_response_rpc_call_guard(
+                    recorder.clone(),
+                    memcache::delete(
+                        &mut client,
+                        shards.target_for(r.key()),
+                        r,
+                        &key_transform,
+                        config.strict_delete(),
+                        &recorder,
+                    ),
+                )
+                .await
+            }
+            memcache::Request::Get(ref r) => {
+                let recorder = proxy_metrics.begin_memcached_get();
+                with_wrapped_error_response_rpc_call_guard(
+                    recorder.clone(),
+                    memcache::get(
+                        &mut client,
+                        &shards,
+                        r,
+                        &key_transform,
+                        codec,
+                        config.multi_get_failure_policy(),
+                        config.multi_get_concurrency(),
+                        config.multi_get_max_keys(),
+                        memory_cache,
+                        config.memory_cache_preserve_ttl(),
+                        &recorder,
+                    ),
+                )
+                .await
+            }
+            memcache::Request::Set(ref r) => {
+                let recorder = proxy_metrics.begin_memcached_set();
+                with_wrapped_error_response_rpc_call_guard(
+                    recorder.clone(),
+                    memcache::set(
+                        &mut client,
+                        shards.target_for(r.key()),
+                        r,
+                        &key_transform,
+                        codec,
+                        config.default_ttl(),
+                        config.max_item_size(),
+                        memory_cache,
+                        config.memory_cache_write_failure_policy(),
+                        &recorder,
+                    ),
+                )
+                .await
+            }
+            _ => {
+                debug!("unsupported command: {}", request);
+                match config.unsupported_command_behavior() {
+                    UnsupportedCommandBehavior::ClientError => {
+                        with_wrapped_error_response_rpc_call_guard(
+                            proxy_metrics.begin_memcached_unimplemented(),
+                            async { Ok(protocol_memcache::Response::error()) },
+                        )
+                        .await
+                    }
+                    UnsupportedCommandBehavior::Hangup => {
+                        with_rpc_call_guard(proxy_metrics.begin_memcached_unimplemented(), async {
+                            Err(Error::new(ErrorKind::Other, "unsupported"))
+                        })
+                        .await
+                    }
+                }
+            }
         }
+    };
+
+    let result = if noreply && config.noreply_metrics_batching() {
+        batch::batched(handle).await
+    } else {
+        handle.await
+    };
+
+    let audited = match &request {
+        memcache::Request::Delete(r) => Some(("delete", &r.key()[..])),
+        memcache::Request::Set(r) => Some(("set", &r.key()[..])),
+        _ => None,
+    };
+    if let Some((command, key)) = audited.filter(|_| audit::is_enabled()) {
+        let status = match &result {
+            Ok(response) => memcache_status(response),
+            Err(e) if e.kind() == ErrorKind::TimedOut => klog::Status::Timeout,
+            Err(_) => klog::Status::ServerError,
+        };
+        audit::record(&connection, command, &[key], status);
     }
+
+    let message = match result {
+        Ok(_) if noreply => Ok((sequence, PendingResponse::Silent)),
+        Ok(response) => Ok((sequence, PendingResponse::Memcache(request, response))),
+        Err(e) if noreply => {
+            debug!("ignoring error for noreply request: {e}");
+            Ok((sequence, PendingResponse::Silent))
+        }
+        Err(e) => Err(e),
+    };
+
+    let _ = channel.send(message).await;
 }
 
 pub(crate) async fn handle_resp_client(
-    mut socket: tokio::net::TcpStream,
+    mut socket: impl ClientSocket,
     mut client: CacheClient,
-    cache_name: String,
-    proxy_metrics: impl RespMetrics,
-    buffer_size: usize,
+    config: Arc<momento_proxy::Cache>,
+    shards: Arc<Shards>,
+    proxy_metrics: impl ProxyMetrics,
+    leaderboards: Option<Leaderboards>,
+    middleware: Arc<middleware::Chain>,
+    connection: Arc<Connection>,
 ) {
     debug!("accepted resp client");
 
     // initialize a buffer for incoming bytes from the client
-    let mut buf = Buffer::new(buffer_size);
+    let mut buf = Buffer::new(config.buffer_size());
+    let mut gauge = BufferGauge::new(&buf, config.buffer_size());
+    let shrink_idle = config.buffer_shrink_idle();
 
     // initialize the request parser
     let parser = resp::RequestParser::new();
+    let key_transform = config
+        .key_transform()
+        .expect("key transform is validated on startup");
+
+    let idle_timeout = config.idle_timeout();
+    let mut last_read = tokio::time::Instant::now();
+
+    let mut client_metrics = ClientMetrics::new(proxy_metrics);
+
+    // set once the buffer holds no complete request
+    let mut read_more = true;
 
     // handle incoming data from the client
     loop {
-        if do_read(&mut socket, &mut buf).await.is_err() {
-            break;
+        connection.set_state(State::Reading);
+        gauge.update(&buf);
+        if shrink_idle.is_zero() {
+            gauge.shrink(&mut buf);
         }
 
+        // requests left in the buffer by a pipelining client are served
+        // before reading more, which may never come
+        if read_more || buf.remaining() == 0 {
+            tokio::select! {
+                result = do_read(&mut socket, &mut buf, config.buffer_size()) => {
+                    match result {
+                        Ok(n) => connection.received(n.get()),
+                        Err(_) => break,
+                    }
+                    last_read = tokio::time::Instant::now();
+                    connection.set_state(State::Parsing);
+                }
+                _ = idle_expired(last_read, idle_timeout) => {
+                    debug!("connection {} idle, closing", connection.addr());
+                    CONNECTION_IDLE_REAPED.increment();
+                    break;
+                }
+                _ = tokio::time::sleep(shrink_idle), if gauge.is_oversized(&buf) => {
+                    gauge.shrink(&mut buf);
+                    continue;
+                }
+                _ = connection.killed() => {
+                    debug!("connection {} killed", connection.addr());
+                    break;
+                }
+            }
+        }
+        read_more = false;
+
         let borrowed_buf = buf.borrow();
 
-        let request = match parser.parse(borrowed_buf) {
+        // inline commands, as typed over telnet, are rewritten as an array for
+        // the parser
+        let mut inline = None;
+        if borrowed_buf.first().is_some_and(|b| *b != b'*') {
+            match resp::Command::parse_inline(borrowed_buf) {
+                Some(command) if command.is_empty() => {
+                    // blank lines are ignored
+                    buf.advance(command.consumed());
+                    continue;
+                }
+                Some(command) => inline = Some(command),
+                None if borrowed_buf.len() > config.max_request_size() => {
+                    debug!("request from {} is too large", connection.addr());
+                    REQUEST_TOO_LARGE.increment();
+                    connection.error();
+                    let _ = socket.write_all(b"-ERR request too large\r\n").await;
+                    break;
+                }
+                None => {
+                    read_more = true;
+                    continue;
+                }
+            }
+        }
+        let encoded = inline.as_ref().map(|command| command.to_resp());
+        let parse_buf = encoded.as_deref().unwrap_or(borrowed_buf);
+
+        // the command as the client sent it, for the audit log
+        let audited = audit::is_enabled()
+            .then(|| resp::Command::parse(parse_buf).ok().flatten())
+            .flatten()
+            .filter(|command| audit::is_audited(&command.name()));
+
+        // the bytes to consume from the buffer, for an inline command this is
+        // the line rather than the array it was rewritten as
+        let mut original_consumed = inline.as_ref().map(|command| command.consumed());
+
+        // with middleware, timeout hints or a key transform, the command is
+        // rewritten by the middleware, without the hint and with the keys
+        // for the backend before it is parsed
+        let mut rewritten = None;
+        let mut timeout_hint = None;
+        let mut rejected = None;
+        // the keys the client sent, by the keys they were rewritten to
+        let mut originals = Vec::new();
+        if !middleware.is_empty() || config.timeout_hints() || key_transform.is_enabled() {
+            if let Ok(Some(mut command)) = resp::Command::parse(parse_buf) {
+                original_consumed = original_consumed.or(Some(command.consumed()));
+                if config.timeout_hints() {
+                    timeout_hint = command.take_timeout_hint();
+                }
+                match middleware.on_request(&connection, &mut command) {
+                    middleware::Decision::Continue => {}
+                    middleware::Decision::Throttle(delay) => {
+                        // holding back the connection holds back the client,
+                        // as a pipelined request after this one waits too
+                        tokio::time::sleep(delay).await;
+                    }
+                    middleware::Decision::Reject(error) => rejected = Some(error),
+                }
+                if rejected.is_none() {
+                    if key_transform.is_enabled() {
+                        originals = command.transform_keys(&key_transform);
+                    }
+                    rewritten = Some(command.to_resp());
+                }
+            }
+        }
+
+        if let (Some(error), Some(consumed)) = (rejected, original_consumed) {
+            let response = format!("-{error}\r\n");
+            batch::increment(&SESSION_SEND);
+            batch::add(&SESSION_SEND_BYTE, response.len() as _);
+            batch::add(&TCP_SEND_BYTE, response.len() as _);
+            resp_response_sent(&connection, audited.as_ref(), response.as_bytes());
+
+            if socket.write_all(response.as_bytes()).await.is_err() {
+                SESSION_SEND_EX.increment();
+                break;
+            }
+
+            buf.advance(consumed);
+            continue;
+        }
+        let parse_buf = rewritten.as_deref().unwrap_or(parse_buf);
+
+        // the caches of the database the connection selected, which is
+        // always one the listener has, routed by the keys the client sent
+        let router = Router::new(
+            shards
+                .database(connection.database())
+                .unwrap_or(shards.as_ref()),
+            &originals,
+        );
+
+        let request = match parser.parse(parse_buf) {
             Ok(request) => request,
             Err(e) => match e.kind() {
-                ErrorKind::WouldBlock => continue,
+                ErrorKind::WouldBlock => {
+                    // reject an incomplete request which is already over the
+                    // limit instead of buffering the rest of it
+                    if borrowed_buf.len() > config.max_request_size() {
+                        debug!("request from {} is too large", connection.addr());
+                        REQUEST_TOO_LARGE.increment();
+                        connection.error();
+                        let _ = socket.write_all(b"-ERR request too large\r\n").await;
+                        break;
+                    }
+                    read_more = true;
+                    continue;
+                }
                 _ => {
+                    // commands which the proxy serves itself are not known to
+                    // the resp parser
+                    if let Ok(Some(command)) = resp::Command::parse(parse_buf) {
+                        let mut response_buf = Vec::new();
+                        let class = command_class(&command.name());
+                        let mut served =
+                            resp::proxy_command(&command, &connection, &config, &mut response_buf);
+                        if served.is_none() && config.resp_dump_restore() {
+                            connection.set_state(State::AwaitingBackend);
+                            served = klog::with_client_name(
+                                client_metrics.name(),
+                                with_backend_timeout(
+                                    config.command_timeout(class, timeout_hint),
+                                    resp::dump_restore(
+                                        &mut client,
+                                        &router,
+                                        &command,
+                                        client_metrics.metrics(),
+                                        &mut response_buf,
+                                    ),
+                                ),
+                            )
+                            .await;
+                        }
+                        if served.is_none() && config.resp_hyperloglog() {
+                            connection.set_state(State::AwaitingBackend);
+                            served = klog::with_client_name(
+                                client_metrics.name(),
+                                with_backend_timeout(
+                                    config.command_timeout(class, timeout_hint),
+                                    resp::hyperloglog(
+                                        &mut client,
+                                        &router,
+                                        &command,
+                                        config.hyperloglog_precision(),
+                                        client_metrics.metrics(),
+                                        &mut response_buf,
+                                    ),
+                                ),
+                            )
+                            .await;
+                        }
+                        if served.is_none() && config.resp_bit_operations() {
+                            connection.set_state(State::AwaitingBackend);
+                            served = klog::with_client_name(
+                                client_metrics.name(),
+                                with_backend_timeout(
+                                    config.command_timeout(class, timeout_hint),
+                                    resp::bit_operation(
+                                        &mut client,
+                                        &router,
+                                        &command,
+                                        config.resp_memcache_flags(),
+                                        config.bit_operations_max_bytes(),
+                                        client_metrics.metrics(),
+                                        &mut response_buf,
+                                    ),
+                                ),
+                            )
+                            .await;
+                        }
+                        if served.is_none() && config.resp_string_range() {
+                            connection.set_state(State::AwaitingBackend);
+                            served = klog::with_client_name(
+                                client_metrics.name(),
+                                with_backend_timeout(
+                                    config.command_timeout(class, timeout_hint),
+                                    resp::string_range(
+                                        &mut client,
+                                        &router,
+                                        &command,
+                                        config.resp_memcache_flags(),
+                                        config.setrange_max_bytes(),
+                                        client_metrics.metrics(),
+                                        &mut response_buf,
+                                    ),
+                                ),
+                            )
+                            .await;
+                        }
+                        if served.is_none() && config.resp_list_commands() {
+                            connection.set_state(State::AwaitingBackend);
+                            served = klog::with_client_name(
+                                client_metrics.name(),
+                                with_backend_timeout(
+                                    config.command_timeout(class, timeout_hint),
+                                    resp::list_command(
+                                        &mut client,
+                                        &router,
+                                        &command,
+                                        client_metrics.metrics(),
+                                        &mut response_buf,
+                                    ),
+                                ),
+                            )
+                            .await;
+                        }
+                        if served.is_none() && config.resp_introspection() {
+                            connection.set_state(State::AwaitingBackend);
+                            served = klog::with_client_name(
+                                client_metrics.name(),
+                                with_backend_timeout(
+                                    config.command_timeout(class, timeout_hint),
+                                    resp::introspection(
+                                        &mut client,
+                                        &router,
+                                        &command,
+                                        client_metrics.metrics(),
+                                        &mut response_buf,
+                                    ),
+                                ),
+                            )
+                            .await;
+                        }
+                        if served.is_none() && config.resp_ttl() {
+                            connection.set_state(State::AwaitingBackend);
+                            served = klog::with_client_name(
+                                client_metrics.name(),
+                                with_backend_timeout(
+                                    config.command_timeout(class, timeout_hint),
+                                    resp::ttl(
+                                        &mut client,
+                                        &router,
+                                        &command,
+                                        client_metrics.metrics(),
+                                        &mut response_buf,
+                                    ),
+                                ),
+                            )
+                            .await;
+                        }
+
+                        if let Some(name) = served {
+                            let consumed = original_consumed.unwrap_or(command.consumed());
+                            connection.request(name);
+
+                            batch::increment(&SESSION_SEND);
+                            batch::add(&SESSION_SEND_BYTE, response_buf.len() as _);
+                            batch::add(&TCP_SEND_BYTE, response_buf.len() as _);
+                            resp_response_sent(&connection, audited.as_ref(), &response_buf);
+
+                            connection.response();
+                            if socket.write_all(&response_buf).await.is_err() {
+                                SESSION_SEND_EX.increment();
+                                break;
+                            }
+
+                            buf.advance(consumed);
+
+                            // the client may have just named itself
+                            client_metrics.update(&connection);
+
+                            // the connection may have just killed itself
+                            if connection.is_killed() {
+                                break;
+                            }
+                            continue;
+                        }
+                    }
+
                     trace!("malformed request: {:?}", borrowed_buf);
+                    connection.error();
+                    if let Some(command) = &audited {
+                        audit::record(
+                            &connection,
+                            &command.name(),
+                            &command.keys(),
+                            klog::Status::ClientError,
+                        );
+                    }
                     let _ = socket.write_all(b"-ERR malformed request\r\n").await;
                     break;
                 }
             },
         };
 
-        let consumed = request.consumed();
+        let consumed = original_consumed.unwrap_or(request.consumed());
         let request = request.into_inner();
         let command = request.command();
 
+        connection.request(command);
+
+        // every key of a multi-key request must be on the same shard
+        let Some(cache_name) = router.cache_for_all(resp::request_keys(&request)) else {
+            debug!("keys for {command} span multiple shards");
+            SHARD_CROSSSLOT.increment();
+
+            let response = b"-CROSSSLOT Keys in request don't hash to the same shard\r\n";
+            batch::increment(&SESSION_SEND);
+            batch::add(&SESSION_SEND_BYTE, response.len() as _);
+            batch::add(&TCP_SEND_BYTE, response.len() as _);
+            resp_response_sent(&connection, audited.as_ref(), response);
+
+            connection.response();
+            if socket.write_all(response).await.is_err() {
+                SESSION_SEND_EX.increment();
+                break;
+            }
+
+            buf.advance(consumed);
+            continue;
+        };
+
+        // while momento is throttling the proxy, writes are refused before
+        // they add to the load
+        let oom_cooldown = config.resp_oom_cooldown();
+        let denyoom = oom_cooldown.is_some() && resp::is_denyoom(&request);
+        if denyoom && pressure::is_throttled() {
+            RESP_OOM.increment();
+            batch::increment(&SESSION_SEND);
+            batch::add(&SESSION_SEND_BYTE, OOM_RESPONSE.len() as _);
+            batch::add(&TCP_SEND_BYTE, OOM_RESPONSE.len() as _);
+            resp_response_sent(&connection, audited.as_ref(), OOM_RESPONSE);
+
+            connection.response();
+            if socket.write_all(OOM_RESPONSE).await.is_err() {
+                SESSION_SEND_EX.increment();
+                break;
+            }
+
+            buf.advance(consumed);
+            continue;
+        }
+
         let mut response_buf = Vec::<u8>::new();
 
-        let result: ProxyResult = async {
+        let backend_timeout = config.command_timeout(command_class(command), timeout_hint);
+        if let Some(hint) = timeout_hint {
+            TIMEOUT_HINT.increment();
+            if hint != backend_timeout {
+                TIMEOUT_HINT_CLAMPED.increment();
+            }
+        }
+
+        let proxy_metrics = client_metrics.metrics();
+        let client_name = client_metrics.name();
+
+        connection.set_state(State::AwaitingBackend);
+        let future = with_backend_timeout(backend_timeout, async {
+            if config.zset_backend() == ZsetBackend::Leaderboard {
+                if let Some(result) = preview::leaderboard_command(
+                    leaderboards.as_ref(),
+                    &mut client,
+                    cache_name,
+                    &request,
+                    proxy_metrics,
+                    &mut response_buf,
+                )
+                .await
+                {
+                    return result;
+                }
+            }
+
             match &request {
                 resp::Request::Del(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_del(),
-                        resp::del(&mut client, &cache_name, &mut response_buf, r),
+                        resp::del(&mut client, &router, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -387,7 +1680,13 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::Get(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_get(),
-                        resp::get(&mut client, &cache_name, &mut response_buf, r.key()),
+                        resp::get(
+                            &mut client,
+                            cache_name,
+                            &mut response_buf,
+                            r.key(),
+                            config.resp_memcache_flags(),
+                        ),
                     )
                     .await?
                 }
@@ -395,7 +1694,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::HashDelete(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_hdel(),
-                        resp::hdel(&mut client, &cache_name, &mut response_buf, r),
+                        resp::hdel(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -403,7 +1702,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::HashExists(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_hexists(),
-                        resp::hexists(&mut client, &cache_name, &mut response_buf, r),
+                        resp::hexists(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -411,7 +1710,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::HashGet(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_hget(),
-                        resp::hget(&mut client, &cache_name, &mut response_buf, r),
+                        resp::hget(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -419,7 +1718,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::HashGetAll(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_hgetall(),
-                        resp::hgetall(&mut client, &cache_name, &mut response_buf, r),
+                        resp::hgetall(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -427,7 +1726,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::HashIncrBy(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_hincrby(),
-                        resp::hincrby(&mut client, &cache_name, &mut response_buf, r),
+                        resp::hincrby(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -435,7 +1734,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::HashKeys(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_hkeys(),
-                        resp::hkeys(&mut client, &cache_name, &mut response_buf, r),
+                        resp::hkeys(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -443,7 +1742,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::HashLength(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_hlen(),
-                        resp::hlen(&mut client, &cache_name, &mut response_buf, r),
+                        resp::hlen(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -451,7 +1750,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::HashMultiGet(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_hmget(),
-                        resp::hmget(&mut client, &cache_name, &mut response_buf, r),
+                        resp::hmget(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -459,7 +1758,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::HashSet(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_hset(),
-                        resp::hset(&mut client, &cache_name, &mut response_buf, r),
+                        resp::hset(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -467,7 +1766,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::HashValues(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_hvals(),
-                        resp::hvals(&mut client, &cache_name, &mut response_buf, r),
+                        resp::hvals(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -475,7 +1774,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::ListIndex(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_lindex(),
-                        resp::lindex(&mut client, &cache_name, &mut response_buf, r),
+                        resp::lindex(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -483,7 +1782,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::ListLen(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_llen(),
-                        resp::llen(&mut client, &cache_name, &mut response_buf, r),
+                        resp::llen(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -491,7 +1790,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::ListPop(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_lpop(),
-                        resp::lpop(&mut client, &cache_name, &mut response_buf, r),
+                        resp::lpop(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -499,7 +1798,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::ListRange(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_lrange(),
-                        resp::lrange(&mut client, &cache_name, &mut response_buf, r),
+                        resp::lrange(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -507,7 +1806,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::ListPush(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_lpush(),
-                        resp::lpush(&mut client, &cache_name, &mut response_buf, r),
+                        resp::lpush(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -515,7 +1814,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::ListPushBack(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_rpush(),
-                        resp::rpush(&mut client, &cache_name, &mut response_buf, r),
+                        resp::rpush(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -523,7 +1822,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::ListPopBack(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_rpop(),
-                        resp::rpop(&mut client, &cache_name, &mut response_buf, r),
+                        resp::rpop(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -531,7 +1830,15 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::Set(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_set(),
-                        resp::set(&mut client, &cache_name, &mut response_buf, r),
+                        resp::set(
+                            &mut client,
+                            cache_name,
+                            &mut response_buf,
+                            r,
+                            config.resp_memcache_flags(),
+                            router.ttl_for(r.key()),
+                            config.max_item_size(),
+                        ),
                     )
                     .await?
                 }
@@ -539,7 +1846,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::SetAdd(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_sadd(),
-                        resp::sadd(&mut client, &cache_name, &mut response_buf, r),
+                        resp::sadd(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -547,7 +1854,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::SetRem(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_srem(),
-                        resp::srem(&mut client, &cache_name, &mut response_buf, r),
+                        resp::srem(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -555,7 +1862,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::SetDiff(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_sdiff(),
-                        resp::sdiff(&mut client, &cache_name, &mut response_buf, r),
+                        resp::sdiff(&mut client, &router, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -563,7 +1870,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::SetUnion(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_sunion(),
-                        resp::sunion(&mut client, &cache_name, &mut response_buf, r),
+                        resp::sunion(&mut client, &router, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -571,7 +1878,7 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::SetIntersect(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_sinter(),
-                        resp::sinter(&mut client, &cache_name, &mut response_buf, r),
+                        resp::sinter(&mut client, &router, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -579,91 +1886,103 @@ pub(crate) async fn handle_resp_client(
                 resp::Request::SetMembers(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_smembers(),
-                        resp::smembers(&mut client, &cache_name, &mut response_buf, r),
+                        resp::smembers(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
                 resp::Request::SetIsMember(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_sismember(),
-                        resp::sismember(&mut client, &cache_name, &mut response_buf, r),
+                        resp::sismember(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
                 resp::Request::SortedSetCardinality(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_zcard(),
-                        resp::zcard(&mut client, &cache_name, &mut response_buf, r),
+                        resp::zcard(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
                 resp::Request::SortedSetIncrement(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_zincrby(),
-                        resp::zincrby(&mut client, &cache_name, &mut response_buf, r),
+                        resp::zincrby(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
                 resp::Request::SortedSetScore(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_zscore(),
-                        resp::zscore(&mut client, &cache_name, &mut response_buf, r),
+                        resp::zscore(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
                 resp::Request::SortedSetMultiScore(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_zmscore(),
-                        resp::zmscore(&mut client, &cache_name, &mut response_buf, r),
+                        resp::zmscore(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
                 resp::Request::SortedSetRemove(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_zrem(),
-                        resp::zrem(&mut client, &cache_name, &mut response_buf, r),
+                        resp::zrem(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
                 resp::Request::SortedSetRank(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_zrank(),
-                        resp::zrank(&mut client, &cache_name, &mut response_buf, r),
+                        resp::zrank(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
                 resp::Request::SortedSetRange(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_zrange(),
-                        resp::zrange(&mut client, &cache_name, &mut response_buf, r),
+                        resp::zrange(
+                            &mut client,
+                            cache_name,
+                            &mut response_buf,
+                            r,
+                            config.zrange_bylex_max_set_size(),
+                        ),
                     )
                     .await?
                 }
                 resp::Request::SortedSetAdd(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_zadd(),
-                        resp::zadd(&mut client, &cache_name, &mut response_buf, r),
+                        resp::zadd(
+                            &mut client,
+                            cache_name,
+                            &mut response_buf,
+                            r,
+                            config.zadd_conditional_emulation(),
+                        ),
                     )
                     .await?
                 }
                 resp::Request::SortedSetReverseRank(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_zrevrank(),
-                        resp::zrevrank(&mut client, &cache_name, &mut response_buf, r),
+                        resp::zrevrank(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
                 resp::Request::SortedSetCount(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_zcount(),
-                        resp::zcount(&mut client, &cache_name, &mut response_buf, r),
+                        resp::zcount(&mut client, cache_name, &mut response_buf, r),
                     )
                     .await?
                 }
                 resp::Request::SortedSetUnionStore(r) => {
                     with_rpc_call_guard(
                         proxy_metrics.begin_resp_zunionstore(),
-                        resp::zunionstore(&mut client, &cache_name, &mut response_buf, r),
+                        resp::zunionstore(&mut client, &router, &mut response_buf, r),
                     )
                     .await?
                 }
@@ -677,8 +1996,8 @@ pub(crate) async fn handle_resp_client(
             }
 
             Ok(())
-        }
-        .await;
+        });
+        let result: ProxyResult = klog::with_client_name(client_name, future).await;
 
         let fatal = match result {
             Ok(()) => false,
@@ -686,6 +2005,26 @@ pub(crate) async fn handle_resp_client(
                 response_buf.clear();
 
                 match e {
+                    ProxyError::Momento(error) if pressure::is_throttled_error(&error) => {
+                        SESSION_SEND.increment();
+                        BACKEND_EX.increment();
+                        BACKEND_EX_RATE_LIMITED.increment();
+
+                        if let Some(cooldown) = oom_cooldown {
+                            pressure::throttled(cooldown);
+                        }
+
+                        if denyoom {
+                            RESP_OOM.increment();
+                            response_buf.extend_from_slice(OOM_RESPONSE);
+                        } else {
+                            response_buf.extend_from_slice(
+                                format!("-ERR backend error: {error}\r\n").as_bytes(),
+                            );
+                        }
+
+                        false
+                    }
                     ProxyError::Momento(error) => {
                         SESSION_SEND.increment();
                         crate::protocol::resp::momento_error_to_resp_error(
@@ -732,13 +2071,16 @@ pub(crate) async fn handle_resp_client(
         // handled in this function. As an intermediate, we increment only if the request
         // method put data into response_buf.
         if !response_buf.is_empty() {
-            BACKEND_REQUEST.increment();
-            SESSION_SEND.increment();
+            batch::increment(&BACKEND_REQUEST);
+            batch::increment(&SESSION_SEND);
         }
 
-        SESSION_SEND_BYTE.add(response_buf.len() as _);
-        TCP_SEND_BYTE.add(response_buf.len() as _);
+        batch::add(&SESSION_SEND_BYTE, response_buf.len() as _);
+        batch::add(&TCP_SEND_BYTE, response_buf.len() as _);
+        resp_response_sent(&connection, audited.as_ref(), &response_buf);
 
+        connection.response();
+        connection.set_state(State::Writing);
         if socket.write_all(&response_buf).await.is_err() {
             SESSION_SEND_EX.increment();
             break;
@@ -0,0 +1,850 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Entry points for the fuzz targets in `fuzz/`, which write arbitrary byte
+//! streams into the memcache and RESP frontends, and state machine tests of
+//! the same frontends. A stream is served by the real connection handler over
+//! an in-memory socket, on a runtime of its own with a paused clock, so each
+//! input is replayed deterministically without a network or Momento.
+//!
+//! The backend is a Momento client for an endpoint it never connects to, so
+//! each backend request waits until it times out, which takes no time on the
+//! paused clock. Requests the proxy serves itself, or from the memory cache,
+//! complete before the backend requests sent ahead of them, so a pipelined
+//! connection has to put its responses back in order.
+//!
+//! Every input is checked for:
+//! * no panics
+//! * the same responses however the stream is split into reads, so the
+//!   connection never desyncs
+//!
+//! The state machine tests also check that each request is answered as it
+//! should be, in the order it was sent, and that a request over the maximum
+//! request size is rejected before the rest of it is buffered.
+
+use super::*;
+use crate::cache::CacheValue;
+use crate::connections;
+use goodmetrics::default_gauge_factory;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use tokio::io::{DuplexStream, ReadBuf, ReadHalf, WriteHalf};
+use tokio::runtime::Runtime;
+
+// the room in each direction of an in-memory socket
+const SOCKET_BUFFER: usize = 64 * KB;
+
+// a credential for an endpoint which doesn't resolve, the base64 of
+// `{"endpoint":"unreachable.invalid","api_key":"fuzz"}`
+const UNREACHABLE: &str = "eyJlbmRwb2ludCI6InVucmVhY2hhYmxlLmludmFsaWQiLCJhcGlfa2V5IjoiZnV6eiJ9";
+
+// small enough that the fuzzer reaches the too large path
+const LIMITED_REQUEST_SIZE: usize = 1024;
+
+// the keys held by the memory cache of a memcache listener, with values which
+// look like the responses they are framed in
+const HITS: [(&str, &[u8]); 3] = [
+    ("hit0", b"value"),
+    ("hit1", b"END\r\n"),
+    ("hit2", b"\r\nVALUE hit2 0 1\r\n"),
+];
+
+/// Writes a memcache stream, text or binary as chosen by its first byte, into
+/// a connection in reads of the sizes in `reads`.
+pub fn memcache(stream: &[u8], reads: &[u8]) {
+    check(&Listener::memcache(MAX_REQUEST_SIZE), stream, reads);
+}
+
+/// Writes a RESP stream, including inline commands, into a connection in reads
+/// of the sizes in `reads`.
+pub fn resp(stream: &[u8], reads: &[u8]) {
+    check(&Listener::resp(MAX_REQUEST_SIZE), stream, reads);
+}
+
+fn check(listener: &Listener, stream: &[u8], reads: &[u8]) {
+    let whole = exchange(listener, stream, &[]);
+    let split = exchange(listener, stream, reads);
+    assert_eq!(
+        whole.escape_ascii().to_string(),
+        split.escape_ascii().to_string(),
+        "the responses depend on how the stream was read"
+    );
+
+    // a request is rejected as soon as it is over the limit, which depends on
+    // how it was read, so only the absence of panics is checked
+    exchange(&listener.limited(), stream, reads);
+}
+
+/// What a listener gives each connection it serves.
+#[derive(Clone)]
+struct Listener {
+    client: CacheClient,
+    config: Arc<momento_proxy::Cache>,
+    shards: Arc<Shards>,
+    memory_cache: Option<MCache>,
+    tuning: Arc<Tuning>,
+    quotas: Arc<Quotas>,
+    middleware: Arc<middleware::Chain>,
+}
+
+impl Listener {
+    // a memcache listener whose memory cache holds the `HITS`
+    fn memcache(max_request_size: usize) -> Self {
+        let memory_cache = MCache::new(MB, Duration::from_secs(3600));
+        for (key, value) in HITS {
+            let value = protocol_memcache::Value::new(key.as_bytes(), 0, None, value);
+            memory_cache.set(key.as_bytes().to_vec(), CacheValue::Memcached { value });
+        }
+
+        Self::new("memcache", max_request_size, Some(memory_cache))
+    }
+
+    fn resp(max_request_size: usize) -> Self {
+        Self::new("resp", max_request_size, None)
+    }
+
+    fn new(protocol: &str, max_request_size: usize, memory_cache: Option<MCache>) -> Self {
+        let config: momento_proxy::Cache = toml::from_str(&format!(
+            r#"
+            host = "127.0.0.1"
+            port = "0"
+            cache_name = "fuzz"
+            default_ttl = 60
+            protocol = "{protocol}"
+            max_request_size = {max_request_size}
+            "#
+        ))
+        .expect("invalid listener config");
+        let config = Arc::new(config);
+        let quotas = Arc::new(Quotas::new(&config));
+
+        Self {
+            client: backend(),
+            shards: Arc::new(Shards::new(config.shards())),
+            tuning: Arc::new(Tuning::new(config.clone(), memory_cache.clone())),
+            middleware: Arc::new(middleware::Chain::new(&config, quotas.clone())),
+            quotas,
+            memory_cache,
+            config,
+        }
+    }
+
+    // the same listener, with a maximum request size the fuzzer can reach
+    fn limited(&self) -> Self {
+        match self.config.protocol() {
+            momento_proxy::Protocol::Memcache => Self::memcache(LIMITED_REQUEST_SIZE),
+            momento_proxy::Protocol::Resp => Self::resp(LIMITED_REQUEST_SIZE),
+        }
+    }
+
+    // serves a connection until it is closed
+    async fn serve(self, socket: Socket) {
+        let proxy_metrics = Arc::new(DefaultProxyMetrics::new(
+            default_gauge_factory(),
+            Duration::from_secs(1),
+            &[],
+            false,
+        ));
+        let registration = connections::register(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            self.config.cache_name(),
+            false,
+        );
+
+        match self.config.protocol() {
+            momento_proxy::Protocol::Memcache => {
+                handle_memcache_client(
+                    socket,
+                    self.client,
+                    self.config,
+                    self.shards,
+                    proxy_metrics,
+                    self.memory_cache,
+                    self.tuning,
+                    self.quotas,
+                    registration.connection(),
+                )
+                .await;
+            }
+            momento_proxy::Protocol::Resp => {
+                handle_resp_client(
+                    socket,
+                    self.client,
+                    self.config,
+                    self.shards,
+                    proxy_metrics,
+                    None,
+                    self.middleware,
+                    registration.connection(),
+                )
+                .await;
+            }
+        }
+    }
+}
+
+// a client of an endpoint which is never connected to. Its connections are
+// driven by a runtime which never runs, so each backend request waits until it
+// times out
+fn backend() -> CacheClient {
+    static BACKEND: OnceLock<(Runtime, CacheClient)> = OnceLock::new();
+
+    let (_, client) = BACKEND.get_or_init(|| {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to create the backend runtime");
+        let client = {
+            let _guard = runtime.enter();
+            CacheClient::builder()
+                .default_ttl(Duration::from_secs(60))
+                .configuration(configurations::Laptop::latest())
+                .credential_provider(
+                    CredentialProvider::from_string(UNREACHABLE).expect("invalid credential"),
+                )
+                .build()
+                .expect("failed to create the backend client")
+        };
+        (runtime, client)
+    });
+
+    client.clone()
+}
+
+// a runtime for one connection. Its clock only moves when every task is
+// waiting on it, so a backend request times out as soon as nothing else can
+// make progress
+fn runtime() -> Runtime {
+    Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()
+        .expect("failed to create the connection runtime")
+}
+
+// writes the stream to a connection in reads of the given sizes, and returns
+// everything written back before the connection was closed
+fn exchange(listener: &Listener, stream: &[u8], reads: &[u8]) -> Vec<u8> {
+    let mut client = Client::new(listener);
+    for chunk in chunks(stream, reads) {
+        client.send(&[chunk]);
+    }
+    client.close()
+}
+
+/// The client end of a connection, and the runtime which serves it.
+struct Client {
+    runtime: Runtime,
+    server: tokio::task::JoinHandle<()>,
+    reader: ReadHalf<DuplexStream>,
+    writer: WriteHalf<DuplexStream>,
+    received: Vec<u8>,
+}
+
+impl Client {
+    fn new(listener: &Listener) -> Self {
+        let runtime = runtime();
+        let (client, server) = tokio::io::duplex(SOCKET_BUFFER);
+        let server = runtime.spawn(listener.clone().serve(Socket::new(server)));
+        let (reader, writer) = tokio::io::split(client);
+
+        Self {
+            runtime,
+            server,
+            reader,
+            writer,
+            received: Vec::new(),
+        }
+    }
+
+    // writes each chunk, letting the connection read it before the next. The
+    // responses are read meanwhile, so the connection is never held back
+    fn send(&mut self, chunks: &[&[u8]]) {
+        let Self {
+            runtime,
+            reader,
+            writer,
+            received,
+            ..
+        } = self;
+
+        runtime.block_on(async {
+            let write = async {
+                for chunk in chunks {
+                    // the connection is closed once it rejects a request
+                    if writer.write_all(chunk).await.is_err() {
+                        return;
+                    }
+                    tokio::task::yield_now().await;
+                }
+            };
+            tokio::pin!(write);
+
+            let mut buf = [0; 4096];
+            loop {
+                tokio::select! {
+                    biased;
+                    _ = &mut write => return,
+                    result = reader.read(&mut buf) => match result {
+                        Ok(n) if n > 0 => received.extend_from_slice(&buf[..n]),
+                        // the connection is closed, so the rest is dropped
+                        _ => return write.await,
+                    },
+                }
+            }
+        });
+    }
+
+    // reads until at least `len` bytes have been received, or the connection
+    // is closed. A connection which stops responding fails the test
+    #[cfg(test)]
+    fn receive(&mut self, len: usize) -> &[u8] {
+        let Self {
+            runtime,
+            reader,
+            received,
+            ..
+        } = self;
+
+        runtime.block_on(async {
+            let read = async {
+                let mut buf = [0; 4096];
+                while received.len() < len {
+                    match reader.read(&mut buf).await {
+                        Ok(n) if n > 0 => received.extend_from_slice(&buf[..n]),
+                        _ => return,
+                    }
+                }
+            };
+            timeout(Duration::from_secs(60), read)
+                .await
+                .expect("the connection stopped responding");
+        });
+
+        received
+    }
+
+    // closes the connection, and returns everything received once the proxy
+    // has closed its end too
+    fn close(mut self) -> Vec<u8> {
+        self.runtime.block_on(async {
+            let _ = self.writer.shutdown().await;
+            let _ = self.reader.read_to_end(&mut self.received).await;
+            assert!(
+                (&mut self.server).await.is_ok(),
+                "the connection handler panicked"
+            );
+        });
+
+        self.received
+    }
+}
+
+/// The proxy end of an in-memory connection. A duplex stream can't be peeked,
+/// so a peek reads ahead into a buffer which is read before the stream.
+struct Socket {
+    stream: DuplexStream,
+    peeked: Vec<u8>,
+}
+
+impl Socket {
+    fn new(stream: DuplexStream) -> Self {
+        Self {
+            stream,
+            peeked: Vec::new(),
+        }
+    }
+}
+
+impl AsyncRead for Socket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.peeked.is_empty() {
+            return Pin::new(&mut self.stream).poll_read(cx, buf);
+        }
+
+        let n = buf.remaining().min(self.peeked.len());
+        buf.put_slice(&self.peeked[..n]);
+        self.peeked.drain(..n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Socket {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+impl ClientSocket for Socket {
+    type ReadHalf = ReadHalf<Socket>;
+    type WriteHalf = WriteHalf<Socket>;
+
+    async fn peek(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.peeked.is_empty() {
+            let mut read = vec![0; buf.len()];
+            let n = self.stream.read(&mut read).await?;
+            self.peeked.extend_from_slice(&read[..n]);
+        }
+
+        let n = buf.len().min(self.peeked.len());
+        buf[..n].copy_from_slice(&self.peeked[..n]);
+        Ok(n)
+    }
+
+    fn into_split(self) -> (Self::ReadHalf, Self::WriteHalf) {
+        tokio::io::split(self)
+    }
+}
+
+// splits the stream into reads of the given sizes, repeating them as needed,
+// or a single read without any
+fn chunks<'a>(stream: &'a [u8], reads: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+    let mut rest = stream;
+    let mut sizes = reads.iter().cycle();
+
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let size = sizes
+            .next()
+            .map_or(rest.len(), |size| (*size as usize + 1).min(rest.len()));
+        let (chunk, remainder) = rest.split_at(size);
+        rest = remainder;
+        Some(chunk)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+    use proptest_state_machine::{prop_state_machine, ReferenceStateMachine, StateMachineTest};
+
+    // the maximum request size of the listeners under test
+    const REQUEST_LIMIT: usize = 1024;
+
+    /// A step of a state machine test.
+    #[derive(Clone, Debug)]
+    enum Transition<R> {
+        /// writes a request, split into two writes at the index
+        Send(R, Index),
+        /// reads the responses to the requests sent so far
+        Receive,
+    }
+
+    /// What the client expects of the connection.
+    #[derive(Clone, Debug, Default)]
+    struct Model {
+        /// the responses to every request sent, in order
+        responses: Vec<u8>,
+        /// how much of the responses the client has read
+        read: usize,
+        /// whether the proxy has closed the connection
+        closed: bool,
+        /// the name the client gave the connection
+        name: Option<u8>,
+    }
+
+    /// A request to a memcache listener.
+    #[derive(Clone, Debug)]
+    enum MemcacheRequest {
+        Version,
+        Get(Vec<Key>),
+        Set {
+            key: u8,
+            value: Vec<u8>,
+            noreply: bool,
+        },
+        Unsupported,
+        TooLarge,
+    }
+
+    /// A key of a memcache get.
+    #[derive(Clone, Copy, Debug)]
+    enum Key {
+        /// one of the `HITS`, held by the memory cache
+        Hit(usize),
+        /// a key which is fetched from the backend
+        Miss(u8),
+    }
+
+    impl MemcacheRequest {
+        fn encode(&self) -> Vec<u8> {
+            match self {
+                Self::Version => b"version\r\n".to_vec(),
+                Self::Get(keys) => {
+                    let keys: Vec<String> = keys
+                        .iter()
+                        .map(|key| match key {
+                            Key::Hit(i) => HITS[*i].0.to_string(),
+                            Key::Miss(i) => format!("miss{i}"),
+                        })
+                        .collect();
+                    format!("get {}\r\n", keys.join(" ")).into_bytes()
+                }
+                // the keys set are never read, as the memory cache holds them
+                // until the set times out
+                Self::Set {
+                    key,
+                    value,
+                    noreply,
+                } => {
+                    let noreply = if *noreply { " noreply" } else { "" };
+                    let mut bytes =
+                        format!("set set{key} 0 0 {}{noreply}\r\n", value.len()).into_bytes();
+                    bytes.extend_from_slice(value);
+                    bytes.extend_from_slice(b"\r\n");
+                    bytes
+                }
+                Self::Unsupported => b"frobnicate\r\n".to_vec(),
+                // the rest of the value is never sent
+                Self::TooLarge => {
+                    let mut bytes = format!("set large 0 0 {}\r\n", 4 * REQUEST_LIMIT).into_bytes();
+                    bytes.resize(bytes.len() + 2 * REQUEST_LIMIT, b'x');
+                    bytes
+                }
+            }
+        }
+
+        fn response(&self) -> Vec<u8> {
+            match self {
+                Self::Version => format!("VERSION {}\r\n", instance::server_version()).into_bytes(),
+                Self::Get(keys) if keys.iter().all(|key| matches!(key, Key::Hit(_))) => {
+                    let mut bytes = Vec::new();
+                    for key in keys {
+                        let Key::Hit(i) = key else { unreachable!() };
+                        let (key, value) = HITS[*i];
+                        bytes.extend_from_slice(
+                            format!("VALUE {key} 0 {}\r\n", value.len()).as_bytes(),
+                        );
+                        bytes.extend_from_slice(value);
+                        bytes.extend_from_slice(b"\r\n");
+                    }
+                    bytes.extend_from_slice(b"END\r\n");
+                    bytes
+                }
+                // any key fetched from the backend times out, which fails the
+                // whole get
+                Self::Get(_) => b"SERVER_ERROR backend timeout\r\n".to_vec(),
+                Self::Set { noreply: true, .. } => Vec::new(),
+                Self::Set { .. } => b"SERVER_ERROR backend timeout\r\n".to_vec(),
+                Self::Unsupported => b"ERROR\r\n".to_vec(),
+                Self::TooLarge => b"SERVER_ERROR object too large for cache\r\n".to_vec(),
+            }
+        }
+    }
+
+    fn memcache_request() -> impl Strategy<Value = MemcacheRequest> {
+        let key = prop_oneof![
+            (0..HITS.len()).prop_map(Key::Hit),
+            (0..4u8).prop_map(Key::Miss),
+        ];
+
+        prop_oneof![
+            Just(MemcacheRequest::Version),
+            prop::collection::vec(key, 1..4).prop_map(MemcacheRequest::Get),
+            (
+                0..4u8,
+                prop::collection::vec(any::<u8>(), 0..64),
+                any::<bool>()
+            )
+                .prop_map(|(key, value, noreply)| MemcacheRequest::Set {
+                    key,
+                    value,
+                    noreply
+                }),
+            Just(MemcacheRequest::Unsupported),
+            Just(MemcacheRequest::TooLarge),
+        ]
+    }
+
+    /// A request to a RESP listener.
+    #[derive(Clone, Debug)]
+    enum RespRequest {
+        /// a command, sent as an array or as an inline command
+        Command(RespCommand, bool),
+        /// an empty inline command, which is skipped
+        Blank,
+        TooLarge,
+    }
+
+    #[derive(Clone, Debug)]
+    enum RespCommand {
+        Get(u8),
+        Set(u8, String),
+        SetName(u8),
+        GetName,
+        Wait,
+    }
+
+    impl RespCommand {
+        fn args(&self) -> Vec<String> {
+            match self {
+                Self::Get(key) => vec!["GET".into(), format!("key{key}")],
+                Self::Set(key, value) => vec!["SET".into(), format!("key{key}"), value.clone()],
+                Self::SetName(name) => {
+                    vec!["CLIENT".into(), "SETNAME".into(), format!("name{name}")]
+                }
+                Self::GetName => vec!["CLIENT".into(), "GETNAME".into()],
+                Self::Wait => vec!["WAIT".into(), "0".into(), "0".into()],
+            }
+        }
+    }
+
+    impl RespRequest {
+        fn encode(&self) -> Vec<u8> {
+            match self {
+                Self::Command(command, false) => {
+                    let args = command.args();
+                    let mut bytes = format!("*{}\r\n", args.len());
+                    for arg in args {
+                        bytes.push_str(&format!("${}\r\n{arg}\r\n", arg.len()));
+                    }
+                    bytes.into_bytes()
+                }
+                Self::Command(command, true) => {
+                    format!("{}\r\n", command.args().join(" ")).into_bytes()
+                }
+                Self::Blank => b"\r\n".to_vec(),
+                // the rest of the value is never sent
+                Self::TooLarge => {
+                    let mut bytes = format!(
+                        "*3\r\n$3\r\nSET\r\n$5\r\nlarge\r\n${}\r\n",
+                        4 * REQUEST_LIMIT
+                    )
+                    .into_bytes();
+                    bytes.resize(bytes.len() + 2 * REQUEST_LIMIT, b'x');
+                    bytes
+                }
+            }
+        }
+
+        // the response, given the name of the connection once the request
+        // was served
+        fn response(&self, name: Option<u8>) -> Vec<u8> {
+            match self {
+                Self::Command(RespCommand::Get(_) | RespCommand::Set(..), _) => {
+                    b"-ERR backend timeout\r\n".to_vec()
+                }
+                Self::Command(RespCommand::SetName(_), _) => b"+OK\r\n".to_vec(),
+                Self::Command(RespCommand::GetName, _) => match name {
+                    Some(name) => {
+                        let name = format!("name{name}");
+                        format!("${}\r\n{name}\r\n", name.len()).into_bytes()
+                    }
+                    None => b"$-1\r\n".to_vec(),
+                },
+                Self::Command(RespCommand::Wait, _) => b":0\r\n".to_vec(),
+                Self::Blank => Vec::new(),
+                Self::TooLarge => b"-ERR request too large\r\n".to_vec(),
+            }
+        }
+    }
+
+    fn resp_request() -> impl Strategy<Value = RespRequest> {
+        let command = prop_oneof![
+            (0..4u8).prop_map(RespCommand::Get),
+            (0..4u8, "[a-z0-9]{1,16}").prop_map(|(key, value)| RespCommand::Set(key, value)),
+            (0..4u8).prop_map(RespCommand::SetName),
+            Just(RespCommand::GetName),
+            Just(RespCommand::Wait),
+        ];
+
+        prop_oneof![
+            8 => (command, any::<bool>()).prop_map(|(command, inline)| RespRequest::Command(command, inline)),
+            1 => Just(RespRequest::Blank),
+            1 => Just(RespRequest::TooLarge),
+        ]
+    }
+
+    // a request, and where it is split into two writes
+    fn transitions<R: Clone + std::fmt::Debug + 'static>(
+        model: &Model,
+        request: impl Strategy<Value = R> + 'static,
+    ) -> BoxedStrategy<Transition<R>> {
+        // nothing more is sent once the proxy has closed the connection
+        if model.closed {
+            return Just(Transition::Receive).boxed();
+        }
+
+        prop_oneof![
+            3 => (request, any::<Index>()).prop_map(|(request, split)| Transition::Send(request, split)),
+            1 => Just(Transition::Receive),
+        ]
+        .boxed()
+    }
+
+    struct MemcacheModel;
+
+    impl ReferenceStateMachine for MemcacheModel {
+        type State = Model;
+        type Transition = Transition<MemcacheRequest>;
+
+        fn init_state() -> BoxedStrategy<Model> {
+            Just(Model::default()).boxed()
+        }
+
+        fn transitions(model: &Model) -> BoxedStrategy<Self::Transition> {
+            transitions(model, memcache_request())
+        }
+
+        fn apply(mut model: Model, transition: &Self::Transition) -> Model {
+            match transition {
+                Transition::Send(request, _) => {
+                    model.responses.extend(request.response());
+                    model.closed = matches!(request, MemcacheRequest::TooLarge);
+                }
+                Transition::Receive => model.read = model.responses.len(),
+            }
+            model
+        }
+    }
+
+    struct RespModel;
+
+    impl ReferenceStateMachine for RespModel {
+        type State = Model;
+        type Transition = Transition<RespRequest>;
+
+        fn init_state() -> BoxedStrategy<Model> {
+            Just(Model::default()).boxed()
+        }
+
+        fn transitions(model: &Model) -> BoxedStrategy<Self::Transition> {
+            transitions(model, resp_request())
+        }
+
+        fn apply(mut model: Model, transition: &Self::Transition) -> Model {
+            match transition {
+                Transition::Send(request, _) => {
+                    if let RespRequest::Command(RespCommand::SetName(name), _) = request {
+                        model.name = Some(*name);
+                    }
+                    model.responses.extend(request.response(model.name));
+                    model.closed = matches!(request, RespRequest::TooLarge);
+                }
+                Transition::Receive => model.read = model.responses.len(),
+            }
+            model
+        }
+    }
+
+    // runs the requests of a model against a connection, and checks what is
+    // read back against the responses the model expects
+    struct ConnectionTest<M>(std::marker::PhantomData<M>);
+
+    trait Encode {
+        fn encode(&self) -> Vec<u8>;
+        fn listener() -> Listener;
+    }
+
+    impl Encode for MemcacheRequest {
+        fn encode(&self) -> Vec<u8> {
+            MemcacheRequest::encode(self)
+        }
+
+        fn listener() -> Listener {
+            Listener::memcache(REQUEST_LIMIT)
+        }
+    }
+
+    impl Encode for RespRequest {
+        fn encode(&self) -> Vec<u8> {
+            RespRequest::encode(self)
+        }
+
+        fn listener() -> Listener {
+            Listener::resp(REQUEST_LIMIT)
+        }
+    }
+
+    impl<R, M> StateMachineTest for ConnectionTest<M>
+    where
+        R: Encode,
+        M: ReferenceStateMachine<State = Model, Transition = Transition<R>>,
+    {
+        type SystemUnderTest = Client;
+        type Reference = M;
+
+        fn init_test(_: &Model) -> Client {
+            Client::new(&R::listener())
+        }
+
+        fn apply(mut client: Client, model: &Model, transition: Transition<R>) -> Client {
+            match transition {
+                Transition::Send(request, split) => {
+                    let bytes = request.encode();
+                    let (first, second) = bytes.split_at(split.index(bytes.len() + 1));
+                    client.send(&[first, second]);
+                }
+                Transition::Receive => {
+                    let received = client.receive(model.read);
+                    assert_eq!(
+                        received
+                            .get(..model.read)
+                            .unwrap_or(received)
+                            .escape_ascii()
+                            .to_string(),
+                        model.responses[..model.read].escape_ascii().to_string(),
+                    );
+                }
+            }
+            client
+        }
+
+        fn teardown(client: Client, model: Model) {
+            assert_eq!(
+                client.close().escape_ascii().to_string(),
+                model.responses.escape_ascii().to_string(),
+            );
+        }
+    }
+
+    type MemcacheTest = ConnectionTest<MemcacheModel>;
+    type RespTest = ConnectionTest<RespModel>;
+
+    prop_state_machine! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn memcache_connection(sequential 1..32 => MemcacheTest);
+
+        #[test]
+        fn resp_connection(sequential 1..32 => RespTest);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn memcache_stream(stream: Vec<u8>, reads: Vec<u8>) {
+            memcache(&stream, &reads);
+        }
+
+        #[test]
+        fn resp_stream(stream: Vec<u8>, reads: Vec<u8>) {
+            resp(&stream, &reads);
+        }
+    }
+}
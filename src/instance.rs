@@ -0,0 +1,69 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Identifies this proxy instance when several are behind a load balancer,
+//! reports the server identity to clients, and selects requests for verbose
+//! tracing by their key.
+
+use std::sync::OnceLock;
+
+static INSTANCE_ID: OnceLock<String> = OnceLock::new();
+
+static TRACE_KEY_PREFIX: OnceLock<Vec<u8>> = OnceLock::new();
+
+static SERVER_NAME: OnceLock<String> = OnceLock::new();
+
+static SERVER_VERSION: OnceLock<String> = OnceLock::new();
+
+/// Sets the instance id and trace key prefix from the config. Only the first
+/// call has any effect.
+pub(crate) fn init(instance_id: Option<String>, trace_key_prefix: Option<String>) {
+    if let Some(id) = instance_id {
+        let _ = INSTANCE_ID.set(id);
+    }
+    if let Some(prefix) = trace_key_prefix.filter(|prefix| !prefix.is_empty()) {
+        let _ = TRACE_KEY_PREFIX.set(prefix.into_bytes());
+    }
+}
+
+/// Sets the server name and version reported to clients from the config.
+/// Only the first call has any effect.
+pub(crate) fn init_server(name: Option<String>, version: Option<String>) {
+    if let Some(name) = name {
+        let _ = SERVER_NAME.set(name);
+    }
+    if let Some(version) = version {
+        let _ = SERVER_VERSION.set(version);
+    }
+}
+
+/// The server name reported by resp `HELLO` and `INFO`.
+pub(crate) fn server_name() -> &'static str {
+    SERVER_NAME
+        .get()
+        .map(|name| name.as_str())
+        .unwrap_or("momento-proxy")
+}
+
+/// The server version reported by memcache `version` and resp `HELLO` and
+/// `INFO`, which is the proxy version unless the config overrides it.
+pub(crate) fn server_version() -> &'static str {
+    SERVER_VERSION
+        .get()
+        .map(|version| version.as_str())
+        .unwrap_or(env!("CARGO_PKG_VERSION"))
+}
+
+/// The id of this instance, if one is configured.
+pub(crate) fn id() -> Option<&'static str> {
+    INSTANCE_ID.get().map(|id| id.as_str())
+}
+
+/// Whether requests for `key` are traced.
+pub(crate) fn is_traced(key: &[u8]) -> bool {
+    TRACE_KEY_PREFIX
+        .get()
+        .map(|prefix| key.starts_with(prefix))
+        .unwrap_or(false)
+}
@@ -0,0 +1,89 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Transforms keys before they are sent to Momento, so that identifiers in
+//! keys never leave the proxy. The memory cache and the responses to clients
+//! keep using the original keys.
+
+use std::borrow::Cow;
+use std::fmt::Write;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::momento_proxy::KeyTransformMode;
+
+/// How keys are rewritten for the backend.
+#[derive(Clone)]
+pub enum KeyTransform {
+    None,
+    /// the whole key is replaced by its HMAC-SHA256
+    Hmac(Hmac<Sha256>),
+    /// the key up to and including the last delimiter is kept, and the rest
+    /// is replaced by its HMAC-SHA256
+    PrefixHmac {
+        mac: Hmac<Sha256>,
+        delimiter: u8,
+    },
+}
+
+impl KeyTransform {
+    pub fn new(
+        mode: KeyTransformMode,
+        secret: Option<&str>,
+        delimiter: u8,
+    ) -> Result<Self, String> {
+        let mac = || {
+            let secret = secret.filter(|secret| !secret.is_empty()).ok_or(
+                "a key transform requires `key_transform_secret` or the \
+                KEY_TRANSFORM_SECRET environment variable",
+            )?;
+            Hmac::<Sha256>::new_from_slice(secret.as_bytes()).map_err(|e| e.to_string())
+        };
+
+        Ok(match mode {
+            KeyTransformMode::None => Self::None,
+            KeyTransformMode::HmacSha256 => Self::Hmac(mac()?),
+            KeyTransformMode::PrefixHmacSha256 => Self::PrefixHmac {
+                mac: mac()?,
+                delimiter,
+            },
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, Self::None)
+    }
+
+    /// The key to use for the backend.
+    pub fn apply<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        match self {
+            Self::None => Cow::Borrowed(key),
+            Self::Hmac(mac) => Cow::Owned(digest(mac, key).into_bytes()),
+            Self::PrefixHmac { mac, delimiter } => {
+                let split = key
+                    .iter()
+                    .rposition(|b| b == delimiter)
+                    .map(|position| position + 1)
+                    .unwrap_or(0);
+
+                let mut transformed = key[..split].to_vec();
+                transformed.extend_from_slice(digest(mac, &key[split..]).as_bytes());
+                Cow::Owned(transformed)
+            }
+        }
+    }
+}
+
+// the hex encoded HMAC of `input`
+fn digest(mac: &Hmac<Sha256>, input: &[u8]) -> String {
+    let mut mac = mac.clone();
+    mac.update(input);
+
+    let mut hex = String::with_capacity(64);
+    for byte in mac.finalize().into_bytes() {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
@@ -2,11 +2,128 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
-use core::fmt::Display;
+use core::fmt::{Arguments, Display};
+use core::sync::atomic::{AtomicU64, Ordering};
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::metrics::logs::{self, CommandEvent};
+
+// additional sampling applied on top of the configured klog sampling, this
+// may be changed at runtime through the admin port
+static SAMPLE: AtomicU64 = AtomicU64::new(1);
+static SAMPLE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the runtime klog sampling, one in every `n` entries is logged.
+pub(crate) fn sample() -> u64 {
+    SAMPLE.load(Ordering::Relaxed)
+}
+
+/// Sets the runtime klog sampling. A value of `1` logs every entry that passes
+/// the configured sampling and `0` disables the command log.
+pub(crate) fn set_sample(n: u64) {
+    SAMPLE.store(n, Ordering::Relaxed);
+}
+
+/// The klog sampling of a single listener, which can be changed with
+/// `proxy_config`. Until it is set, the listener uses the runtime sampling.
+#[derive(Default)]
+pub(crate) struct ListenerSample {
+    // the sampling plus one, zero while it is unset
+    sample: AtomicU64,
+    counter: AtomicU64,
+}
+
+impl ListenerSample {
+    /// Returns the sampling of the listener, or the runtime sampling if it is
+    /// unset.
+    pub fn get(&self) -> u64 {
+        match self.sample.load(Ordering::Relaxed) {
+            0 => sample(),
+            n => n - 1,
+        }
+    }
+
+    /// Sets the sampling of the listener, which replaces the runtime sampling
+    /// for its requests.
+    pub fn set(&self, n: u64) {
+        self.sample.store(n.saturating_add(1), Ordering::Relaxed);
+    }
+}
+
+tokio::task_local! {
+    // the name of the client whose request is being served
+    static CLIENT_NAME: Option<Arc<str>>;
+    static LISTENER_SAMPLE: Arc<ListenerSample>;
+}
+
+/// Polls the request future with the name of its client, which is added to
+/// the entries it logs.
+pub(crate) async fn with_client_name<F: Future>(name: Option<Arc<str>>, future: F) -> F::Output {
+    CLIENT_NAME.scope(name, future).await
+}
+
+/// Polls the request future with the klog sampling of its listener.
+pub(crate) async fn with_listener_sample<F: Future>(
+    sample: Arc<ListenerSample>,
+    future: F,
+) -> F::Output {
+    LISTENER_SAMPLE.scope(sample, future).await
+}
+
+fn sampled() -> bool {
+    let listener =
+        LISTENER_SAMPLE.try_with(|listener| match listener.sample.load(Ordering::Relaxed) {
+            0 => None,
+            n => Some(is_sampled(n - 1, &listener.counter)),
+        });
+
+    match listener {
+        Ok(Some(sampled)) => sampled,
+        _ => is_sampled(SAMPLE.load(Ordering::Relaxed), &SAMPLE_COUNTER),
+    }
+}
+
+// whether this entry is the one in every `n` which is logged
+fn is_sampled(n: u64, counter: &AtomicU64) -> bool {
+    match n {
+        0 => false,
+        1 => true,
+        n => counter.fetch_add(1, Ordering::Relaxed) % n == 0,
+    }
+}
+
+// writes the entry, followed by the client name and stamped with the instance
+// id if there are any. Entries for traced keys are also written to the proxy
+// log, and entries are exported over OTLP if that is enabled.
+fn log_entry(traced: bool, event: CommandEvent, entry: Arguments) {
+    let client = CLIENT_NAME.try_with(|name| name.clone()).ok().flatten();
+    logs::export(&event, client.as_deref(), &entry);
+
+    let client = client
+        .map(|name| format!(" client={name}"))
+        .unwrap_or_default();
+
+    match crate::instance::id() {
+        Some(id) => {
+            klog!("{}{} {}", entry, client, id);
+            if traced {
+                info!("trace {}: {}{}", id, entry, client);
+            }
+        }
+        None => {
+            klog!("{}{}", entry, client);
+            if traced {
+                info!("trace: {}{}", entry, client);
+            }
+        }
+    }
+}
 
 #[allow(dead_code)]
 /// A collection of klog status codes taken from:
 /// legacy/src/protocol/data/memcache/response.h
+#[derive(Clone, Copy)]
 pub enum Status {
     Miss = 0,
     Hit = 4,
@@ -26,12 +143,30 @@ pub(crate) fn klog_1(
     status: Status,
     response_len: usize,
 ) {
-    klog!(
-        "\"{} {}\" {} {}",
-        command,
-        EscapedStr::new(key),
-        status as u8,
-        response_len
+    let traced = crate::instance::is_traced(key.as_ref());
+    if !traced && !sampled() {
+        return;
+    }
+
+    log_entry(
+        traced,
+        CommandEvent {
+            command,
+            key: key.as_ref(),
+            field: None,
+            flags: None,
+            ttl: None,
+            value_len: None,
+            status: status as u8,
+            response_len,
+        },
+        format_args!(
+            "\"{} {}\" {} {}",
+            command,
+            EscapedStr::new(key),
+            status as u8,
+            response_len
+        ),
     );
 }
 
@@ -42,13 +177,31 @@ pub(crate) fn klog_2(
     status: Status,
     response_len: usize,
 ) {
-    klog!(
-        "\"{} {} {}\" {} {}",
-        command,
-        EscapedStr::new(key),
-        EscapedStr::new(field),
-        status as u8,
-        response_len
+    let traced = crate::instance::is_traced(key.as_ref());
+    if !traced && !sampled() {
+        return;
+    }
+
+    log_entry(
+        traced,
+        CommandEvent {
+            command,
+            key: key.as_ref(),
+            field: Some(field.as_ref()),
+            flags: None,
+            ttl: None,
+            value_len: None,
+            status: status as u8,
+            response_len,
+        },
+        format_args!(
+            "\"{} {} {}\" {} {}",
+            command,
+            EscapedStr::new(key),
+            EscapedStr::new(field),
+            status as u8,
+            response_len
+        ),
     );
 }
 
@@ -61,15 +214,33 @@ pub(crate) fn klog_7(
     status: Status,
     response_len: usize,
 ) {
-    klog!(
-        "\"{} {} {} {} {}\" {} {}",
-        command,
-        EscapedStr::new(key),
-        EscapedStr::new(field),
-        ttl,
-        value_len,
-        status as u8,
-        response_len
+    let traced = crate::instance::is_traced(key.as_ref());
+    if !traced && !sampled() {
+        return;
+    }
+
+    log_entry(
+        traced,
+        CommandEvent {
+            command,
+            key: key.as_ref(),
+            field: Some(field.as_ref()),
+            flags: None,
+            ttl: Some(ttl),
+            value_len: Some(value_len),
+            status: status as u8,
+            response_len,
+        },
+        format_args!(
+            "\"{} {} {} {} {}\" {} {}",
+            command,
+            EscapedStr::new(key),
+            EscapedStr::new(field),
+            ttl,
+            value_len,
+            status as u8,
+            response_len
+        ),
     );
 }
 
@@ -81,14 +252,41 @@ pub fn klog_set(
     status: Status,
     response_len: usize,
 ) {
+    let traced = crate::instance::is_traced(key.as_ref());
+    if !traced && !sampled() {
+        return;
+    }
+
+    log_entry(
+        traced,
+        CommandEvent {
+            command: &"set",
+            key: key.as_ref(),
+            field: None,
+            flags: Some(flags),
+            ttl: Some(ttl),
+            value_len: Some(value_len),
+            status: status as u8,
+            response_len,
+        },
+        format_args!(
+            "\"set {} {} {} {}\" {} {}",
+            EscapedStr::new(key),
+            flags,
+            ttl,
+            value_len,
+            status as u8,
+            response_len
+        ),
+    );
+}
+
+/// Records a panic which closed a client connection.
+pub(crate) fn klog_panic(message: &str) {
     klog!(
-        "\"set {} {} {} {}\" {} {}",
-        EscapedStr::new(key),
-        flags,
-        ttl,
-        value_len,
-        status as u8,
-        response_len
+        "\"panic {}\" {} 0",
+        EscapedStr::new(&message),
+        Status::ServerError as u8
     );
 }
 
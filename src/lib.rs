@@ -0,0 +1,768 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+#[macro_use]
+extern crate logger;
+
+use ::config::{AdminConfig, TimeType};
+use backtrace::Backtrace;
+use cache::MCache;
+use core::num::NonZeroUsize;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use logger::configure_logging;
+use metriken::*;
+use momento::cache::{configurations, CollectionTtl};
+use momento::*;
+pub use momento_proxy::MomentoProxyConfig;
+use pelikan_net::{TCP_RECV_BYTE, TCP_SEND_BYTE};
+use protocol_admin::*;
+use session::*;
+use std::borrow::{Borrow, BorrowMut};
+use std::io::{Error, ErrorKind};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::runtime::Builder;
+use tokio::time::timeout;
+
+use crate::deadline::backend_timeout;
+use crate::error::{ProxyError, ProxyResult};
+use crate::protocol::memcache::ValueCodec;
+
+pub const KB: usize = 1024;
+pub const MB: usize = 1024 * KB;
+
+#[cfg(unix)]
+const S: u64 = 1_000_000_000; // one second in nanoseconds
+#[cfg(unix)]
+const US: u64 = 1_000; // one microsecond in nanoseconds
+
+mod admin;
+mod audit;
+mod availability;
+mod backend;
+mod buffers;
+mod cache;
+mod check;
+mod compression;
+mod connections;
+mod consistency;
+mod copy;
+mod deadline;
+mod embed;
+mod enforce;
+mod error;
+mod fds;
+mod frontend;
+mod instance;
+mod keys;
+mod klog;
+mod listener;
+mod metrics;
+mod middleware;
+mod momento_proxy;
+mod panic;
+mod pinned;
+mod policy;
+mod pressure;
+mod preview;
+mod protocol;
+mod quota;
+mod routing;
+mod shards;
+mod startup;
+mod systemd;
+mod tuning;
+mod uring;
+mod warmup;
+
+pub use audit::verify_audit_log;
+pub use check::check;
+pub use copy::{copy, CopyOptions, CopySource, TtlPolicy};
+pub use embed::{ProxyBuilder, ProxyHandle};
+pub use metrics::*;
+
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub use frontend::fuzz;
+
+// NOTES:
+//
+// This is a simple proxy which translates requests between memcache protocol
+// and Momento gRPC. This allows for a standard memcache client to communicate
+// with the Momento cache service without any code changes.
+//
+// The following environment variables are necessary to configure the proxy
+// until the config file is finalized:
+//
+// MOMENTO_API_KEY - the Momento API key to use for authentication
+
+// Default for linux, used where the page size can't be queried.
+pub const PAGESIZE: usize = 4096;
+
+/// The page size of the platform, which is larger than the default on some
+/// platforms such as macOS on Apple silicon.
+pub fn pagesize() -> usize {
+    static SIZE: std::sync::OnceLock<usize> = std::sync::OnceLock::new();
+
+    *SIZE.get_or_init(|| {
+        #[cfg(unix)]
+        {
+            // SAFETY: sysconf has no preconditions and returns -1 on error
+            let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+            if size > 0 {
+                return size as usize;
+            }
+        }
+
+        PAGESIZE
+    })
+}
+// the default buffer size is matched to the upper-bound on TLS fragment size as
+// per RFC 5246 https://datatracker.ietf.org/doc/html/rfc5246#section-6.2.1
+pub const INITIAL_BUFFER_SIZE: usize = 16 * KB;
+
+// the default upper bound on how large a request can be
+pub const MAX_REQUEST_SIZE: usize = 100 * MB;
+
+/// Default collection TTL policy used on collection operations.
+///
+/// Basically, we use the cache's configured `default_ttl` and never update the
+/// TTL of the item within the momento cache.
+const COLLECTION_TTL: CollectionTtl = CollectionTtl::new(None, false);
+
+// we interpret TTLs the same way memcached would
+pub const TIME_TYPE: TimeType = TimeType::Memcache;
+
+pub const fn default_buffer_size() -> NonZeroUsize {
+    NonZeroUsize::new(INITIAL_BUFFER_SIZE).expect("initial buffer size cannot be zero")
+}
+
+/// Installs a panic hook which terminates the process after a panic, unless
+/// the panic is isolated to a single connection. A program embedding the proxy
+/// keeps its own panic handling, and panics in connections only close them.
+pub fn set_panic_hook() {
+    panic::set_hook();
+}
+
+/// Prints the name and type of every metric.
+pub fn print_stats() {
+    common::metrics::init();
+    metrics::register_rpcs();
+
+    println!("{:<31} {:<15} DESCRIPTION", "NAME", "TYPE");
+
+    let mut metrics = Vec::new();
+
+    for metric in &metriken::metrics() {
+        let any = match metric.as_any() {
+            Some(any) => any,
+            None => {
+                continue;
+            }
+        };
+
+        if any.downcast_ref::<Counter>().is_some() {
+            metrics.push(format!("{:<31} counter", metric.name()));
+        } else if any.downcast_ref::<Gauge>().is_some() {
+            metrics.push(format!("{:<31} gauge", metric.name()));
+        } else if any.downcast_ref::<AtomicHistogram>().is_some()
+            || any.downcast_ref::<RwLockHistogram>().is_some()
+        {
+            for (label, _) in metrics::PERCENTILES {
+                let name = format!("{}_{}", metric.name(), label);
+                metrics.push(format!("{name:<31} percentile"));
+            }
+        } else {
+            continue;
+        }
+    }
+
+    metrics.sort();
+    for metric in metrics {
+        println!("{metric}");
+    }
+}
+
+// checks the config parameters which apply to the whole proxy, the caches are
+// checked by the startup diagnostics
+fn validate(config: &MomentoProxyConfig) -> Result<(), String> {
+    if config.caches().is_empty() {
+        return Err("no caches specified in the config".to_string());
+    }
+
+    if config.fd_high_water_percent() > 100 {
+        return Err(format!(
+            "fd high-water mark of {}% is greater than 100%",
+            config.fd_high_water_percent()
+        ));
+    }
+
+    if config.admin_tls_incomplete() {
+        return Err(
+            "`admin_tls_cert_file` and `admin_tls_key_file` must be set together".to_string(),
+        );
+    }
+
+    if config.metrics().client_identity_incomplete() {
+        return Err("`cert_file` and `key_file` in `[metrics]` must be set together".to_string());
+    }
+
+    Ok(())
+}
+
+async fn spawn(
+    config: MomentoProxyConfig,
+    proxy_metrics: std::sync::Arc<DefaultProxyMetrics>,
+    admin_runtime: tokio::runtime::Handle,
+    shutdown: impl std::future::Future<Output = ()>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let admin_listener = admin::Admin::bind(&config);
+
+    // initialize the Momento cache client
+    let credential_provider = std::env::var("MOMENTO_API_KEY")
+        .map_err(|_| "environment variable `MOMENTO_API_KEY` is not set".to_string())
+        .and_then(|key| {
+            CredentialProvider::from_string(key)
+                .map_err(|e| format!("failed to initialize credential provider. error: {e}"))
+        });
+
+    // every problem is reported before giving up, rather than just the first
+    let startup = startup::diagnose(
+        &config,
+        admin_listener.as_ref().err(),
+        credential_provider.as_ref().err(),
+    );
+    startup.report.log();
+    if let Some(path) = config.startup_report_file() {
+        if let Err(e) = startup.report.write_json(path) {
+            warn!("could not write the startup report to `{path}`: {e}");
+        }
+    }
+    if !startup.report.can_start(config.partial_start()) {
+        return Err(format!("could not start the proxy:\n{}", startup.report).into());
+    }
+
+    let admin_listener = admin_listener?;
+    info!(
+        "starting proxy admin listener on: {}",
+        admin_listener.local_addr()
+    );
+    let credential_provider = credential_provider?;
+    preview::check(&config);
+    for conflict in config.value_format_conflicts() {
+        warn!("{conflict}");
+    }
+
+    // memory caches which are saved to disk on shutdown
+    let mut snapshots = Vec::new();
+
+    // the pinned workers run on their own threads, and are stopped when this
+    // is dropped on shutdown
+    let pinned_workers = config.pinned_workers().then(|| pinned::workers(&config));
+    let (pinned_stop, _) = tokio::sync::watch::channel(());
+
+    // the io_uring workers, if any, are shared by every listener
+    let uring = uring::start(&config)?;
+
+    for (i, tcp_listeners) in startup.listeners.into_iter().enumerate() {
+        // a degraded cache isn't served
+        let Some(tcp_listeners) = tcp_listeners else {
+            continue;
+        };
+
+        let cache = config.caches()[i].clone();
+
+        let client_builder = CacheClient::builder()
+            .default_ttl(cache.default_ttl())
+            .configuration(configurations::Laptop::latest())
+            .credential_provider(credential_provider.clone())
+            .with_num_connections(cache.connection_count());
+
+        // sorted sets may be served by momento leaderboards instead
+        let leaderboards = preview::leaderboards(&config, &cache, &credential_provider)?;
+
+        let local_cache_bytes = cache.memory_cache_bytes();
+        let local_cache = if 0 < local_cache_bytes {
+            let ttl = if cache.memory_cache_ttl_seconds() == 0 {
+                Duration::MAX
+            } else {
+                Duration::from_secs(cache.memory_cache_ttl_seconds())
+            };
+            let stale = Duration::from_secs(cache.memory_cache_stale_seconds());
+            Some(
+                MCache::with_stale(
+                    cache.memory_cache_bytes(),
+                    ttl,
+                    stale,
+                    cache.memory_cache_eviction(),
+                )
+                .with_admission(cache.memory_cache_admission()),
+            )
+        } else {
+            None
+        };
+
+        if let (Some(path), Some(local_cache)) = (cache.memory_cache_snapshot_file(), &local_cache)
+        {
+            let path = std::path::Path::new(path);
+            if path.exists() {
+                match local_cache.restore(path) {
+                    Ok(count) => info!(
+                        "restored {count} entries into memory cache for cache `{}`",
+                        cache.cache_name()
+                    ),
+                    Err(e) => warn!(
+                        "could not restore memory cache for cache `{}` from `{}`: {}",
+                        cache.cache_name(),
+                        path.display(),
+                        e
+                    ),
+                }
+            }
+            snapshots.push((local_cache.clone(), path.to_path_buf()));
+        }
+
+        let mut shards = shards::Shards::new(cache.shards());
+        if let Some(policy) = cache
+            .routing_policy()
+            .expect("routing policy is validated on startup")
+        {
+            shards = shards.with_routes(routing::Routes::new(&policy));
+        }
+        let shards = std::sync::Arc::new(
+            shards
+                .with_policy(cache.key_policy())
+                .with_databases(cache.resp_databases(), cache.key_policy()),
+        );
+
+        // the quotas of key prefixes, applied by the middleware for resp and
+        // before dispatch for memcache
+        let quotas = std::sync::Arc::new(quota::Quotas::new(&cache));
+
+        // the policies applied to each resp command before it is dispatched
+        let middleware = std::sync::Arc::new(middleware::Chain::new(&cache, quotas.clone()));
+
+        // each listener reports its own time series
+        let mut listeners = Vec::new();
+        for tcp_listener in tcp_listeners {
+            let addr = tcp_listener.local_addr()?;
+            let mut dimensions = vec![
+                ("cache_name".to_string(), cache.cache_name()),
+                ("listen_address".to_string(), addr.to_string()),
+            ];
+            dimensions.extend(cache.metric_dimensions());
+            let proxy_metrics = std::sync::Arc::new(
+                proxy_metrics.with_dimensions(&dimensions, cache.latency_by_value_size()),
+            );
+            listeners.push((tcp_listener, addr, proxy_metrics));
+        }
+
+        // each listener, or each pinned worker, warms up its own client. They
+        // are counted now so that the proxy isn't ready before they start
+        let warmups = if cache.client_warmup_requests() > 0 {
+            pinned_workers.unwrap_or(1)
+        } else {
+            0
+        };
+        listener::expect_client_warmup(listeners.len() * warmups);
+
+        let pinned_stop = pinned_stop.subscribe();
+        let uring = uring.clone();
+
+        tokio::spawn(async move {
+            debug!("cache {} config: protocol={:?} flags={} value_format={} local_cache_bytes={} local_cache_ttl_seconds={} buffer_size={}",
+                cache.cache_name(),
+                cache.protocol(),
+                cache.flags(),
+                cache.value_format().as_str(),
+                cache.memory_cache_bytes(),
+                cache.memory_cache_ttl_seconds(),
+                cache.buffer_size(),
+            );
+            let key_transform = cache
+                .key_transform()
+                .expect("key transform is validated on startup");
+
+            if let (Some(path), Some(local_cache)) = (cache.warmup_key_file(), &local_cache) {
+                match client_builder.clone().build() {
+                    Ok(client) => {
+                        tokio::spawn(warmup::warmup(
+                            client,
+                            cache.cache_name(),
+                            shards.clone(),
+                            key_transform.clone(),
+                            path.to_string(),
+                            ValueCodec::new(&cache),
+                            cache.warmup_rate(),
+                            local_cache.clone(),
+                        ));
+                    }
+                    Err(e) => {
+                        error!(
+                            "could not create cache client for warmup of cache `{}`: {}",
+                            cache.cache_name(),
+                            e
+                        );
+                    }
+                }
+            }
+
+            if let Some(local_cache) = local_cache
+                .as_ref()
+                .filter(|_| cache.memory_cache_eviction_log())
+            {
+                tokio::spawn(crate::cache::log_evictions(
+                    local_cache.subscribe_evictions(),
+                ));
+            }
+
+            if let (Some(interval), Some(local_cache)) =
+                (cache.memory_cache_consistency_interval(), &local_cache)
+            {
+                match client_builder.clone().build() {
+                    Ok(client) => {
+                        tokio::spawn(consistency::sampler(
+                            client,
+                            shards.clone(),
+                            key_transform.clone(),
+                            ValueCodec::new(&cache),
+                            interval,
+                            cache.memory_cache_consistency_sample_size(),
+                            local_cache.clone(),
+                        ));
+                    }
+                    Err(e) => {
+                        error!(
+                            "could not create cache client for consistency checks of cache `{}`: {}",
+                            cache.cache_name(),
+                            e
+                        );
+                    }
+                }
+            }
+
+            if cache.value_compression() == momento_proxy::ValueCompression::ZstdDictionary {
+                match client_builder.clone().build() {
+                    Ok(client) => {
+                        tokio::spawn(compression::trainer(
+                            client,
+                            compression::dictionaries(&cache.cache_name()),
+                            cache.compression_dictionary_interval(),
+                            cache.compression_dictionary_bytes(),
+                            cache.compression_dictionary_ttl(),
+                        ));
+                    }
+                    Err(e) => {
+                        error!(
+                            "could not create cache client for compression dictionaries of cache `{}`: {}",
+                            cache.cache_name(),
+                            e
+                        );
+                    }
+                }
+            }
+
+            if let Some(interval) = cache.backend_probe_interval() {
+                match client_builder.clone().build() {
+                    Ok(client) => {
+                        tokio::spawn(availability::prober(client, cache.cache_name(), interval));
+                    }
+                    Err(e) => {
+                        error!(
+                            "could not create cache client for backend probes of cache `{}`: {}",
+                            cache.cache_name(),
+                            e
+                        );
+                    }
+                }
+            }
+
+            let cache = std::sync::Arc::new(cache);
+
+            // shared by the connections, so a change made on one applies to all
+            let tuning =
+                std::sync::Arc::new(tuning::Tuning::new(cache.clone(), local_cache.clone()));
+
+            // a cache may listen on several addresses, which share its
+            // shards, memory cache, and tuning
+            for (tcp_listener, addr, proxy_metrics) in listeners {
+                info!(
+                    "starting proxy frontend listener for cache `{}` on: {}",
+                    cache.cache_name(),
+                    addr
+                );
+
+                if let Some(workers) = pinned_workers {
+                    info!(
+                        "serving cache `{}` on {addr} from {workers} pinned workers",
+                        cache.cache_name()
+                    );
+                    if let Err(e) = pinned::serve(
+                        tcp_listener,
+                        workers,
+                        client_builder.clone(),
+                        cache.clone(),
+                        shards.clone(),
+                        proxy_metrics,
+                        local_cache.clone(),
+                        tuning.clone(),
+                        leaderboards.clone(),
+                        middleware.clone(),
+                        quotas.clone(),
+                        uring.clone(),
+                        pinned_stop.clone(),
+                    ) {
+                        error!("could not serve cache `{}`: {e}", cache.cache_name());
+                        for _ in 0..warmups {
+                            listener::client_warmup_done();
+                        }
+                    }
+                    continue;
+                }
+
+                let tcp_listener = TcpListener::from_std(tcp_listener)
+                    .expect("could not convert to tokio listener");
+
+                let served = listener::listener(
+                    tcp_listener,
+                    client_builder.clone(),
+                    cache.clone(),
+                    shards.clone(),
+                    proxy_metrics,
+                    local_cache.clone(),
+                    tuning.clone(),
+                    leaderboards.clone(),
+                    middleware.clone(),
+                    quotas.clone(),
+                    uring.clone(),
+                );
+                let cache_name = cache.cache_name();
+                tokio::spawn(async move {
+                    if let Err(e) = served.await {
+                        error!("could not serve cache `{cache_name}` on {addr}: {e}");
+                    }
+                });
+            }
+        });
+    }
+
+    // the admin listener runs on its own runtime so that it stays reachable
+    // while the workers are saturated, and reports not ready until every
+    // listener has warmed up its client
+    let admin = admin_runtime.spawn(admin_listener.serve());
+
+    // all of the listeners are bound, so the service is ready once the
+    // backend is known to be reachable and the clients have warmed up
+    if systemd::is_enabled() {
+        if let Err(e) = verify_backend(credential_provider, &config).await {
+            systemd::notify(&format!("STATUS=could not verify the momento backend: {e}"));
+            return Err(format!("could not verify the momento backend: {e}").into());
+        }
+        if listener::is_warming_up() {
+            systemd::notify("STATUS=warming up momento clients");
+        }
+        listener::client_warmup_complete().await;
+        systemd::notify("READY=1");
+    }
+
+    tokio::select! {
+        _ = admin => {}
+        _ = shutdown => {
+            info!("shutting down");
+        }
+    }
+
+    systemd::notify("STOPPING=1");
+
+    for (local_cache, path) in snapshots {
+        match local_cache.snapshot(&path) {
+            Ok(count) => info!("saved {count} memory cache entries to `{}`", path.display()),
+            Err(e) => error!("could not save memory cache to `{}`: {}", path.display(), e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the credentials are accepted and that every configured cache
+/// exists.
+async fn verify_backend(
+    credential_provider: CredentialProvider,
+    config: &MomentoProxyConfig,
+) -> Result<(), String> {
+    let client = CacheClient::builder()
+        .default_ttl(Duration::from_secs(60))
+        .configuration(configurations::Laptop::latest())
+        .credential_provider(credential_provider)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let existing: Vec<String> = match timeout(Duration::from_secs(5), client.list_caches()).await {
+        Ok(Ok(response)) => response.caches.into_iter().map(|c| c.name).collect(),
+        Ok(Err(e)) => return Err(e.to_string()),
+        Err(_) => return Err("timed out listing caches".to_string()),
+    };
+
+    for cache in config.caches() {
+        let mut names = cache.shards();
+        if let Some(policy) = cache.routing_policy()? {
+            names.extend(policy.cache_names().cloned());
+        }
+        names.extend(
+            cache
+                .key_policy()
+                .rules()
+                .iter()
+                .filter_map(|rule| rule.cache_name().map(str::to_string)),
+        );
+        names.extend(cache.resp_databases());
+
+        for name in names {
+            if !existing.contains(&name) {
+                return Err(format!("cache `{name}` does not exist"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Completes when the process receives SIGINT or SIGTERM.
+#[cfg(unix)]
+pub async fn shutdown_signal() {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("failed to register signal handler");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+/// Completes when the process receives ctrl-c, there is no SIGTERM outside of
+/// unix.
+#[cfg(not(unix))]
+pub async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Reads from the socket into the buffer, growing the buffer by `buffer_size`
+/// when it is low on space.
+async fn do_read(
+    socket: &mut (impl tokio::io::AsyncRead + Unpin),
+    buf: &mut Buffer,
+    buffer_size: usize,
+) -> Result<NonZeroUsize, Error> {
+    match socket.read(buf.borrow_mut()).await {
+        Ok(0) => {
+            SESSION_RECV.increment();
+            // zero length reads mean we got a HUP. close it
+            Err(Error::from(ErrorKind::ConnectionReset))
+        }
+        Ok(n) => {
+            batch::increment(&SESSION_RECV);
+            batch::add(&SESSION_RECV_BYTE, n as _);
+            batch::add(&TCP_RECV_BYTE, n as _);
+            // non-zero means we have some data, mark the buffer as
+            // having additional content
+            unsafe {
+                buf.advance_mut(n);
+            }
+
+            // if the buffer is low on space, we will grow the
+            // buffer
+            if buf.remaining_mut() * 2 < buffer_size {
+                buf.reserve(buffer_size);
+            }
+
+            // SAFETY: we have already checked that the number of bytes read was
+            // greater than zero, so this unchecked conversion is safe
+            Ok(unsafe { NonZeroUsize::new_unchecked(n) })
+        }
+        Err(e) => {
+            SESSION_RECV.increment();
+            SESSION_RECV_EX.increment();
+            // we has some other error reading from the socket,
+            // return an error so the connection can be closed
+            Err(e)
+        }
+    }
+}
+
+async fn do_read2(
+    socket: &mut (impl tokio::io::AsyncRead + Unpin),
+    buf: &mut Buffer,
+    buffer_size: usize,
+) -> Result<NonZeroUsize, Error> {
+    match socket.read(buf.borrow_mut()).await {
+        Ok(0) => {
+            SESSION_RECV.increment();
+            // zero length reads mean we got a HUP. close it
+            Err(Error::from(ErrorKind::ConnectionReset))
+        }
+        Ok(n) => {
+            batch::increment(&SESSION_RECV);
+            batch::add(&SESSION_RECV_BYTE, n as _);
+            batch::add(&TCP_RECV_BYTE, n as _);
+
+            // non-zero means we have some data, mark the buffer as
+            // having additional content
+            unsafe {
+                buf.advance_mut(n);
+            }
+
+            // if the buffer is low on space, we will grow the
+            // buffer
+            if buf.remaining_mut() * 2 < buffer_size {
+                buf.reserve(buffer_size);
+            }
+
+            // SAFETY: we have already checked that the number of bytes read was
+            // greater than zero, so this unchecked conversion is safe
+            Ok(unsafe { NonZeroUsize::new_unchecked(n) })
+        }
+        Err(e) => {
+            SESSION_RECV.increment();
+            SESSION_RECV_EX.increment();
+            // we has some other error reading from the socket,
+            // return an error so the connection can be closed
+            Err(e)
+        }
+    }
+}
+
+async fn do_write2(
+    socket: &mut (impl tokio::io::AsyncWrite + Unpin),
+    buf: &mut Buffer,
+) -> Result<NonZeroUsize, Error> {
+    match socket.write(buf.chunk()).await {
+        Ok(0) => {
+            SESSION_SEND.increment();
+            // zero length reads mean we got a HUP. close it
+            Err(Error::from(ErrorKind::ConnectionReset))
+        }
+        Ok(n) => {
+            batch::increment(&SESSION_SEND);
+            batch::add(&SESSION_SEND_BYTE, n as _);
+            batch::add(&TCP_SEND_BYTE, n as _);
+
+            // NOTE: buffer will automatically compact
+            buf.advance(n);
+
+            // // SAFETY: we have already checked that the number of bytes read was
+            // // greater than zero, so this unchecked conversion is safe
+            Ok(unsafe { NonZeroUsize::new_unchecked(n) })
+        }
+        Err(e) => {
+            SESSION_SEND.increment();
+            SESSION_SEND_EX.increment();
+            // we has some other error reading from the socket,
+            // return an error so the connection can be closed
+            Err(e)
+        }
+    }
+}
+
+common::metrics::test_no_duplicates!();
@@ -2,75 +2,369 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
+use crate::frontend::ClientSocket;
+use crate::metrics::util::{accept_errno, ACCEPT_ERRNOS};
+use crate::preview::Leaderboards;
+use crate::shards::Shards;
+use crate::tuning::Tuning;
 use crate::*;
 use momento::CacheClientBuilder;
 use momento_proxy::Protocol;
 use pelikan_net::{TCP_ACCEPT, TCP_CLOSE, TCP_CONN_CURR};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::Arc;
+
+// when set, the listeners close new connections as soon as they are accepted
+// while existing connections continue to be served
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+// the listeners which are still warming up their clients, the proxy isn't
+// ready until none are
+static WARMING_UP: AtomicUsize = AtomicUsize::new(0);
+
+// the key read by warm-up requests, whether or not it exists
+const CLIENT_WARMUP_KEY: &str = "momento-proxy-client-warmup";
+
+// how long a warm-up request may take, so that an unreachable backend
+// doesn't keep a listener from ever serving
+const CLIENT_WARMUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+// how often readiness is checked while listeners are warming up
+const CLIENT_WARMUP_POLL: Duration = Duration::from_millis(50);
+
+// how often the listen backlog is sampled
+const ACCEPT_QUEUE_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+// the pause after a failed accept doubles with each failure in a row, between
+// these bounds
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(1);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+#[metric(name = "listener_drain_reject")]
+pub static LISTENER_DRAIN_REJECT: Counter = Counter::new();
+
+#[metric(name = "listener_accept_ex")]
+pub static LISTENER_ACCEPT_EX: Counter = Counter::new();
+
+#[metric(name = "listener_accept_shed")]
+pub static LISTENER_ACCEPT_SHED: Counter = Counter::new();
+
+#[metric(name = "client_warmup_request")]
+pub static CLIENT_WARMUP_REQUEST: Counter = Counter::new();
+
+#[metric(name = "client_warmup_ex")]
+pub static CLIENT_WARMUP_EX: Counter = Counter::new();
+
+/// Stops the listeners from serving new connections.
+pub(crate) fn drain() {
+    DRAINING.store(true, Ordering::Relaxed);
+}
+
+/// Resumes serving new connections after a `drain()`.
+pub(crate) fn resume() {
+    DRAINING.store(false, Ordering::Relaxed);
+}
+
+pub(crate) fn is_draining() -> bool {
+    DRAINING.load(Ordering::Relaxed)
+}
+
+/// Records that `count` more listeners will warm up their clients before
+/// they accept connections. Called before the listeners are started, so that
+/// the proxy isn't reported ready in the meantime.
+pub(crate) fn expect_client_warmup(count: usize) {
+    WARMING_UP.fetch_add(count, Ordering::Relaxed);
+}
+
+/// Records that a listener finished warming up its client, or won't.
+pub(crate) fn client_warmup_done() {
+    let _ = WARMING_UP.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| v.checked_sub(1));
+}
+
+/// Whether any listener is still warming up its client.
+pub(crate) fn is_warming_up() -> bool {
+    WARMING_UP.load(Ordering::Relaxed) > 0
+}
+
+/// Completes once no listener is warming up its client.
+pub(crate) async fn client_warmup_complete() {
+    while is_warming_up() {
+        tokio::time::sleep(CLIENT_WARMUP_POLL).await;
+    }
+}
+
+// sends `requests` reads at once, spread across the caches of the listener,
+// so that the client establishes its channels before the first client request
+// needs them. Failures are counted and logged, but don't hold the listener back
+async fn warm_up_client(client: &CacheClient, config: &momento_proxy::Cache, shards: &Shards) {
+    let start = std::time::Instant::now();
+    let cache_names: Vec<&str> = shards.cache_names().collect();
+
+    let results = futures::future::join_all((0..config.client_warmup_requests()).map(|i| {
+        CLIENT_WARMUP_REQUEST.increment();
+        let cache_name = cache_names[i % cache_names.len()];
+        timeout(
+            CLIENT_WARMUP_TIMEOUT,
+            client.get(cache_name, CLIENT_WARMUP_KEY),
+        )
+    }))
+    .await;
+
+    let failed = results
+        .iter()
+        .filter(|result| !matches!(result, Ok(Ok(_))))
+        .count();
+    CLIENT_WARMUP_EX.add(failed as u64);
+
+    if failed > 0 {
+        warn!(
+            "{failed} of {} client warm-up requests for cache `{}` failed",
+            results.len(),
+            config.cache_name()
+        );
+    }
+    debug!(
+        "warmed up the client for cache `{}` in {:?}",
+        config.cache_name(),
+        start.elapsed()
+    );
+}
+
+// the number of connections waiting to be accepted. For a listening socket,
+// linux reports the length of the accept queue as `tcpi_unacked`, which is the
+// same value that sock_diag reports as its receive queue
+#[cfg(target_os = "linux")]
+fn accept_queue_depth(listener: &TcpListener) -> Option<u32> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: tcp_info is plain data, for which all zeroes is valid
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+
+    // SAFETY: the fd is a valid socket for the lifetime of the listener, and
+    // the buffer and its length describe `info`
+    let result = unsafe {
+        libc::getsockopt(
+            listener.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut libc::tcp_info as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    (result == 0).then_some(info.tcpi_unacked)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn accept_queue_depth(_listener: &TcpListener) -> Option<u32> {
+    None
+}
 
 pub(crate) async fn listener(
     listener: TcpListener,
     client_builder: CacheClientBuilder<ReadyToBuild>,
-    cache_name: String,
-    protocol: Protocol,
-    flags: bool,
+    config: Arc<momento_proxy::Cache>,
+    shards: Arc<Shards>,
     proxy_metrics: impl ProxyMetrics,
     memory_cache: Option<MCache>,
-    buffer_size: usize,
-) {
+    tuning: Arc<Tuning>,
+    leaderboards: Option<Leaderboards>,
+    middleware: Arc<middleware::Chain>,
+    quotas: Arc<quota::Quotas>,
+    uring: Option<Arc<uring::Workers>>,
+) -> Result<(), String> {
     // Establishing a gRPC connection is expensive, so the client needs to be created outside the
     // loop and reused to avoid paying that cost with each request. A Momento client can handle 100
     // simultaneous requests per gRPC connection. Increase connection_count in the config to add
     // more connections.
-    let client = client_builder.clone().build().unwrap_or_else(|e| {
-        // Note: this will not happen since we validated the client build in the main thread already
-        eprintln!("could not create cache client: {}", e);
-        std::process::exit(1);
-    });
+    let client = match client_builder.clone().build() {
+        Ok(client) => client,
+        Err(e) => {
+            // the listener won't warm up, which mustn't hold back readiness
+            if config.client_warmup_requests() > 0 {
+                client_warmup_done();
+            }
+            return Err(format!("could not create cache client: {e}"));
+        }
+    };
+
+    if config.client_warmup_requests() > 0 {
+        warm_up_client(&client, &config, &shards).await;
+        client_warmup_done();
+    }
+
+    // the socket was only bound, so that clients were refused until now
+    if config.listen_after_client_warmup() {
+        socket2::SockRef::from(&listener)
+            .listen(config.listen_backlog())
+            .map_err(|e| format!("could not listen: {e}"))?;
+    }
+
+    let service = Service {
+        client,
+        config: config.clone(),
+        shards,
+        proxy_metrics: proxy_metrics.clone(),
+        memory_cache,
+        tuning,
+        leaderboards,
+        middleware,
+        quotas,
+    };
+
+    let mut queue_sample = tokio::time::interval(ACCEPT_QUEUE_SAMPLE_INTERVAL);
+    let mut backoff = Duration::ZERO;
+
     // this acts as our listener thread and spawns tasks for each client
     loop {
         // accept a new client
-        if let Ok((socket, _)) = listener.accept().await {
-            TCP_ACCEPT.increment();
-
-            let client = client.clone();
-            let cache_name = cache_name.clone();
-
-            // spawn a task for managing requests for the client
-            let proxy_metrics = proxy_metrics.clone();
-            let memory_cache = memory_cache.clone();
-
-            tokio::spawn(async move {
-                TCP_CONN_CURR.increment();
-                let _connection_metric = proxy_metrics.begin_connection();
-
-                match protocol {
-                    Protocol::Memcache => {
-                        crate::frontend::handle_memcache_client(
-                            socket,
-                            client,
-                            cache_name,
-                            flags,
-                            proxy_metrics,
-                            memory_cache,
-                            buffer_size,
-                        )
-                        .await;
-                    }
-                    Protocol::Resp => {
-                        crate::frontend::handle_resp_client(
-                            socket,
-                            client,
-                            cache_name,
-                            proxy_metrics,
-                            buffer_size,
-                        )
-                        .await;
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = queue_sample.tick() => {
+                if let Some(depth) = accept_queue_depth(&listener) {
+                    proxy_metrics.record_accept_queue(depth);
+                }
+                continue;
+            }
+        };
+
+        let (socket, addr) = match accepted {
+            Ok(accepted) => {
+                backoff = Duration::ZERO;
+                accepted
+            }
+            Err(e) => {
+                LISTENER_ACCEPT_EX.increment();
+                proxy_metrics.record_accept_error(&e);
+
+                // without a free file descriptor every accept fails at once,
+                // so back off instead of spinning on the error. The first
+                // failure and those at the longest backoff are logged
+                backoff = (backoff * 2).clamp(ACCEPT_BACKOFF_MIN, ACCEPT_BACKOFF_MAX);
+                if backoff == ACCEPT_BACKOFF_MIN || backoff == ACCEPT_BACKOFF_MAX {
+                    warn!("could not accept a connection, retrying in {backoff:?}: {e}");
+                } else {
+                    debug!("could not accept a connection, retrying in {backoff:?}: {e}");
+                }
+
+                if config.accept_shed_idle() && ACCEPT_ERRNOS[accept_errno(&e)] == "EMFILE" {
+                    if let Some(idlest) = connections::kill_idlest() {
+                        LISTENER_ACCEPT_SHED.increment();
+                        warn!("closing the idlest connection, from {idlest}, to free a file descriptor");
                     }
                 }
 
-                TCP_CONN_CURR.decrement();
-                TCP_CLOSE.increment();
-            });
+                tokio::time::sleep(backoff).await;
+                continue;
+            }
+        };
+
+        let accept_start = std::time::Instant::now();
+        TCP_ACCEPT.increment();
+
+        if is_draining() {
+            debug!("draining, closing new connection from {addr}");
+            LISTENER_DRAIN_REJECT.increment();
+            TCP_CLOSE.increment();
+            drop(socket);
+            proxy_metrics.record_accept(accept_start.elapsed());
+            continue;
+        }
+
+        if fds::is_over_high_water() {
+            debug!("too many file descriptors open, closing new connection from {addr}");
+            fds::FD_REJECT.increment();
+            TCP_CLOSE.increment();
+            drop(socket);
+            proxy_metrics.record_accept(accept_start.elapsed());
+            continue;
+        }
+
+        if let Some(keepalive) = config.tcp_keepalive() {
+            let keepalive = socket2::TcpKeepalive::new()
+                .with_time(keepalive)
+                .with_interval(keepalive);
+            if let Err(e) = socket2::SockRef::from(&socket).set_tcp_keepalive(&keepalive) {
+                debug!("could not enable keepalive for {addr}: {e}");
+            }
+        }
+
+        match &uring {
+            // the connection is moved to an io_uring worker, which serves it
+            // on its own thread
+            Some(uring) => {
+                let service = service.clone();
+                uring.dispatch(socket, move |socket| service.serve(socket, addr));
+            }
+            None => {
+                tokio::spawn(service.clone().serve(socket, addr));
+            }
+        }
+
+        proxy_metrics.record_accept(accept_start.elapsed());
+    }
+}
+
+// what a listener needs to serve its connections
+#[derive(Clone)]
+struct Service<M> {
+    client: CacheClient,
+    config: Arc<momento_proxy::Cache>,
+    shards: Arc<Shards>,
+    proxy_metrics: M,
+    memory_cache: Option<MCache>,
+    tuning: Arc<Tuning>,
+    leaderboards: Option<Leaderboards>,
+    middleware: Arc<middleware::Chain>,
+    quotas: Arc<quota::Quotas>,
+}
+
+impl<M: ProxyMetrics> Service<M> {
+    // serves a client until its connection is closed
+    async fn serve(self, socket: impl ClientSocket, addr: SocketAddr) {
+        TCP_CONN_CURR.increment();
+        let _connection_metric = self.proxy_metrics.begin_connection();
+        let registration = connections::register(
+            addr,
+            self.config.cache_name(),
+            self.config.connection_summary_log(),
+        );
+
+        // a panic while serving the client drops the socket, which closes
+        // the connection
+        match self.config.protocol() {
+            Protocol::Memcache => {
+                panic::isolate(crate::frontend::handle_memcache_client(
+                    socket,
+                    self.client,
+                    self.config,
+                    self.shards,
+                    self.proxy_metrics,
+                    self.memory_cache,
+                    self.tuning,
+                    self.quotas,
+                    registration.connection(),
+                ))
+                .await;
+            }
+            Protocol::Resp => {
+                panic::isolate(crate::frontend::handle_resp_client(
+                    socket,
+                    self.client,
+                    self.config,
+                    self.shards,
+                    self.proxy_metrics,
+                    self.leaderboards,
+                    self.middleware,
+                    registration.connection(),
+                ))
+                .await;
+            }
         }
+
+        TCP_CONN_CURR.decrement();
+        TCP_CLOSE.increment();
     }
 }
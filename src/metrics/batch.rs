@@ -0,0 +1,185 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Batched metrics. A burst of requests makes the per-request atomic updates
+//! of the shared counters measurable, as every worker contends for the same
+//! cache lines, so while a request is batched its counts are held by the
+//! thread and added to the shared counters periodically.
+//!
+//! The requests of every listener are batched with the `thread_local`
+//! aggregation of the `[metrics]` section, which only defers the counts. The
+//! `noreply` writes of a cache with `noreply_metrics_batching` are batched on
+//! their own, and only a sample of their latencies is also recorded.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use metriken::Counter;
+
+use super::bridge::BridgedRpc;
+use super::rpc::OUTCOMES;
+
+// how long a thread holds counts before adding them to the shared counters
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+// how many updates a thread makes between checks of how long it has held its
+// counts, so that reading the clock doesn't cost more than it saves
+const FLUSH_CHECK: u64 = 64;
+
+// one in this many batched calls records its latency
+const LATENCY_SAMPLE: u64 = 64;
+
+// each thread holds its counts in a table of this many slots, with a counter
+// in the slot its address maps to
+const SLOT_BITS: u32 = 8;
+const SLOTS: usize = 1 << SLOT_BITS;
+
+// set when the counts of every request are aggregated by their thread
+static THREAD_LOCAL: AtomicBool = AtomicBool::new(false);
+
+tokio::task_local! {
+    // set while polling a request whose metrics are batched and sampled
+    static BATCHED: ();
+}
+
+struct Batch {
+    counters: Vec<Option<(&'static Counter, u64)>>,
+    rpcs: Vec<(Arc<BridgedRpc>, [u64; OUTCOMES])>,
+    calls: u64,
+    updates: u64,
+    started: Option<Instant>,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self {
+            counters: vec![None; SLOTS],
+            rpcs: Vec::new(),
+            calls: 0,
+            updates: 0,
+            started: None,
+        }
+    }
+}
+
+thread_local! {
+    static BATCH: RefCell<Batch> = RefCell::new(Batch::default());
+}
+
+/// Aggregates the counts of every request in the thread which handles it,
+/// rather than only those of batched requests.
+pub fn set_thread_local(enabled: bool) {
+    THREAD_LOCAL.store(enabled, Ordering::Relaxed);
+}
+
+/// Polls the request future with its metrics batched, and its latencies
+/// sampled.
+pub(crate) async fn batched<F: Future>(future: F) -> F::Output {
+    BATCHED.scope((), future).await
+}
+
+/// Whether the current request has its metrics batched.
+pub(crate) fn is_batched() -> bool {
+    THREAD_LOCAL.load(Ordering::Relaxed) || is_sampled()
+}
+
+// whether the current request only records a sample of its latencies
+fn is_sampled() -> bool {
+    BATCHED.try_with(|_| ()).is_ok()
+}
+
+/// Increments the counter, or the thread's count for it while batched.
+pub fn increment(counter: &'static Counter) {
+    add(counter, 1);
+}
+
+/// Adds to the counter, or to the thread's count for it while batched.
+pub fn add(counter: &'static Counter, value: u64) {
+    if !is_batched() {
+        counter.add(value);
+        return;
+    }
+
+    with_batch(|batch| match &mut batch.counters[slot(counter)] {
+        Some((c, count)) if std::ptr::eq(*c, counter) => *count += value,
+        // another counter holds the slot, so its count is added to it now
+        slot => {
+            if let Some((c, count)) = slot.replace((counter, value)) {
+                c.add(count);
+            }
+        }
+    });
+}
+
+// the slot of the counter in the table of each thread. Counters are statics,
+// so their addresses are fixed and a hash of the address spreads them over
+// the slots.
+fn slot(counter: &'static Counter) -> usize {
+    let address = (counter as *const Counter as usize >> 3) as u64;
+    (address.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (u64::BITS - SLOT_BITS)) as usize
+}
+
+/// Counts a batched call of `rpc` with `outcome`. Returns whether the call
+/// records its latency, which a sampled call only does if it is in the
+/// sample.
+pub(crate) fn record_rpc(rpc: &Arc<BridgedRpc>, outcome: usize) -> bool {
+    let sampled = is_sampled();
+
+    with_batch(|batch| {
+        match batch.rpcs.iter_mut().find(|(r, _)| Arc::ptr_eq(r, rpc)) {
+            Some((_, counts)) => counts[outcome] += 1,
+            None => {
+                let mut counts = [0; OUTCOMES];
+                counts[outcome] = 1;
+                batch.rpcs.push((rpc.clone(), counts));
+            }
+        }
+
+        if !sampled {
+            return true;
+        }
+        batch.calls += 1;
+        batch.calls % LATENCY_SAMPLE == 0
+    })
+}
+
+fn with_batch<T>(f: impl FnOnce(&mut Batch) -> T) -> T {
+    BATCH.with(|batch| {
+        let mut batch = batch.borrow_mut();
+        let started = *batch.started.get_or_insert_with(Instant::now);
+
+        let result = f(&mut batch);
+
+        batch.updates += 1;
+        if batch.updates % FLUSH_CHECK == 0 && started.elapsed() >= FLUSH_INTERVAL {
+            batch.flush();
+        }
+        result
+    })
+}
+
+/// Adds the counts held by this thread to the shared counters. The runtime
+/// workers call this before they park, so an idle thread holds no counts.
+pub fn flush() {
+    let _ = BATCH.try_with(|batch| {
+        if let Ok(mut batch) = batch.try_borrow_mut() {
+            batch.flush();
+        }
+    });
+}
+
+impl Batch {
+    fn flush(&mut self) {
+        for (counter, count) in self.counters.iter_mut().filter_map(Option::take) {
+            counter.add(count);
+        }
+        for (rpc, counts) in self.rpcs.drain(..) {
+            rpc.add(counts);
+        }
+        self.started = None;
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Mirrors the per-command metrics, which are exported over OTLP, into
+//! metriken so that they are also served by the admin `stats` command and
+//! listed by `--stats`. The OTLP metrics are kept per listener, while the
+//! metriken ones are totals across all listeners, except for the connection
+//! metrics which are also kept per cache.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use metriken::{metric, AtomicHistogram, Counter, DynBoxedMetric, Gauge, MetricBuilder};
+
+use super::rpc::{ERROR, HIT_MCACHE, MISS, OK, OUTCOMES, TIMEOUT};
+
+#[metric(name = "connections_opened")]
+pub static CONNECTIONS_OPENED: Counter = Counter::new();
+
+#[metric(name = "connections_closed")]
+pub static CONNECTIONS_CLOSED: Counter = Counter::new();
+
+#[metric(name = "total_active_connections")]
+pub static TOTAL_ACTIVE_CONNECTIONS: Gauge = Gauge::new();
+
+/// The metriken connection metrics of a cache, shared by its listeners. The
+/// cache name is part of the metric names, as the admin `stats` command does
+/// not show metadata, and is also set as the `cache_name` metadata.
+pub struct BridgedCache {
+    pub(crate) conn_curr: DynBoxedMetric<Gauge>,
+    pub(crate) conn_accept: DynBoxedMetric<Counter>,
+    pub(crate) conn_close: DynBoxedMetric<Counter>,
+}
+
+impl BridgedCache {
+    fn new(cache_name: &str) -> Self {
+        let builder = |metric: &str, description: &str| {
+            MetricBuilder::new(format!("cache/{cache_name}/{metric}"))
+                .description(format!("{description} for cache {cache_name}"))
+                .metadata("cache_name", cache_name)
+        };
+
+        Self {
+            conn_curr: builder("conn_curr", "open client connections").build(Gauge::new()),
+            conn_accept: builder("conn_accept", "client connections accepted")
+                .build(Counter::new()),
+            conn_close: builder("conn_close", "client connections closed").build(Counter::new()),
+        }
+    }
+}
+
+impl std::fmt::Debug for BridgedCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BridgedCache").finish_non_exhaustive()
+    }
+}
+
+/// The metriken connection metrics of `cache_name`, which are registered on
+/// first use.
+pub(crate) fn cache(cache_name: &str) -> Arc<BridgedCache> {
+    static CACHES: OnceLock<Mutex<HashMap<String, Arc<BridgedCache>>>> = OnceLock::new();
+
+    CACHES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(cache_name.to_string())
+        .or_insert_with(|| Arc::new(BridgedCache::new(cache_name)))
+        .clone()
+}
+
+/// The metriken metrics of a command, shared by every listener.
+pub struct BridgedRpc {
+    ok: DynBoxedMetric<Counter>,
+    error: DynBoxedMetric<Counter>,
+    timeout: DynBoxedMetric<Counter>,
+    miss: DynBoxedMetric<Counter>,
+    hit_mcache: DynBoxedMetric<Counter>,
+    hit_momento: DynBoxedMetric<Counter>,
+    latency: DynBoxedMetric<AtomicHistogram>,
+}
+
+impl BridgedRpc {
+    fn new(rpc: &'static str) -> Self {
+        let counter = |result: &str| {
+            MetricBuilder::new(format!("{rpc}_{result}"))
+                .description(format!("{rpc} calls with a result of {result}"))
+                .build(Counter::new())
+        };
+
+        Self {
+            ok: counter("ok"),
+            error: counter("error"),
+            timeout: counter("timeout"),
+            miss: counter("miss"),
+            hit_mcache: counter("hit_mcache"),
+            hit_momento: counter("hit_momento"),
+            latency: MetricBuilder::new(format!("{rpc}_latency"))
+                .description(format!("{rpc} latency in nanoseconds"))
+                .build(AtomicHistogram::new(7, 64)),
+        }
+    }
+
+    fn counter(&self, outcome: usize) -> &Counter {
+        match outcome {
+            OK => &self.ok,
+            ERROR => &self.error,
+            TIMEOUT => &self.timeout,
+            MISS => &self.miss,
+            HIT_MCACHE => &self.hit_mcache,
+            _ => &self.hit_momento,
+        }
+    }
+
+    /// Records a call with the outcome index used by `RpcCallGuard`.
+    pub(crate) fn record(&self, outcome: usize, elapsed: Duration) {
+        self.counter(outcome).increment();
+        self.record_latency(elapsed);
+    }
+
+    pub(crate) fn record_latency(&self, elapsed: Duration) {
+        let _ = self.latency.increment(elapsed.as_nanos() as u64);
+    }
+
+    /// Adds a batch of calls, counted by outcome index.
+    pub(crate) fn add(&self, counts: [u64; OUTCOMES]) {
+        for (outcome, count) in counts.into_iter().enumerate() {
+            if count > 0 {
+                self.counter(outcome).add(count);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for BridgedRpc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BridgedRpc").finish_non_exhaustive()
+    }
+}
+
+/// The metriken metrics of `rpc`, which are registered on first use.
+pub(crate) fn rpc(rpc: &'static str) -> Arc<BridgedRpc> {
+    static RPCS: OnceLock<Mutex<HashMap<&'static str, Arc<BridgedRpc>>>> = OnceLock::new();
+
+    RPCS.get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(rpc)
+        .or_insert_with(|| Arc::new(BridgedRpc::new(rpc)))
+        .clone()
+}
@@ -7,13 +7,20 @@ use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio_rustls::rustls::RootCertStore;
-use tonic::metadata::MetadataValue;
+use tonic::metadata::{Ascii, MetadataValue};
 
+use super::export::Exporter;
+use super::logs;
 use super::proxy::DefaultProxyMetrics;
+use super::statsd;
+use crate::momento_proxy::Metrics;
 
 pub struct ProxyMetricsBuilder {
     batch_interval: Duration,
     batch_capacity: usize,
+    instance_id: Option<String>,
+    klog_sample: u64,
+    config: Metrics,
 }
 
 impl ProxyMetricsBuilder {
@@ -21,13 +28,89 @@ impl ProxyMetricsBuilder {
         Self {
             batch_interval: Duration::from_secs(1),
             batch_capacity: 128,
+            instance_id: None,
+            klog_sample: 0,
+            config: Metrics::default(),
         }
     }
 
-    pub async fn build(self) -> Arc<DefaultProxyMetrics> {
+    /// Uses the exporter settings from the `[metrics]` config section. If no
+    /// endpoint is configured there, the environment variables are used.
+    pub fn config(mut self, config: &Metrics) -> Self {
+        self.batch_interval = config.export_interval();
+        self.config = config.clone();
+        self
+    }
+
+    /// Adds an `instance_id` dimension to all of the exported metrics.
+    pub fn instance_id(mut self, instance_id: Option<String>) -> Self {
+        self.instance_id = instance_id;
+        self
+    }
+
+    /// Exports one in every `sample` command log entries as OTLP log records,
+    /// 0 disables the export.
+    pub fn klog_sample(mut self, sample: u64) -> Self {
+        self.klog_sample = sample;
+        self
+    }
+
+    /// Starts exporting the metrics. Fails if an exporter can't be created.
+    pub async fn build(self) -> Result<Arc<DefaultProxyMetrics>, String> {
         let (batch_sender, batch_receiver) = mpsc::channel(self.batch_capacity);
         let gauge_factory = default_gauge_factory();
 
+        if let Some(address) = self.config.statsd_address() {
+            info!("Sending metrics to the DogStatsD agent at {address}");
+
+            let mut tags = self.config.statsd_tags().to_vec();
+            if let Some(instance_id) = &self.instance_id {
+                tags.push(format!("instance_id:{instance_id}"));
+            }
+
+            statsd::start(
+                address,
+                self.config.statsd_prefix(),
+                &tags,
+                self.batch_interval,
+            )
+            .map_err(|e| format!("could not send metrics to `{address}`: {e}"))?;
+        }
+
+        if let Some(endpoint) = self.config.endpoint() {
+            info!(
+                "Configuring OTLP downstream for {endpoint} over {:?}",
+                self.config.protocol()
+            );
+
+            let exporter = Exporter::new(&self.config)?;
+
+            // the command log is exported to the same collector
+            if self.klog_sample > 0 {
+                logs::start(
+                    exporter.logs()?,
+                    base_environment_dimensions(self.instance_id.clone()),
+                    self.klog_sample,
+                    self.batch_interval,
+                    self.batch_capacity,
+                );
+            }
+
+            tokio::spawn(exporter.send_batches_forever(
+                base_environment_dimensions(self.instance_id.clone()),
+                batch_receiver,
+            ));
+
+            tokio::spawn(gauge_factory.clone().report_gauges_forever(
+                self.batch_interval,
+                batch_sender,
+                OpentelemetryBatcher,
+            ));
+
+            let metrics = DefaultProxyMetrics::new(gauge_factory, self.batch_interval, &[], false);
+            return Ok(Arc::new(metrics));
+        }
+
         let endpoint = get_environment_variable_or_none("OTLP_ENDPOINT");
         let api_token = get_environment_variable_or_none("OTLP_API_TOKEN");
 
@@ -44,13 +127,15 @@ impl ProxyMetricsBuilder {
                         })
                     },
                     goodmetrics::proto::opentelemetry::collector::metrics::v1::metrics_service_client::MetricsServiceClient::with_origin
-                ).expect("Failed to create client");
+                ).map_err(|e| format!("could not create the OTLP client for `{endpoint}`: {e}"))?;
+                let api_token: MetadataValue<Ascii> = MetadataValue::try_from(api_token)
+                    .map_err(|e| format!("invalid OTLP_API_TOKEN: {e}"))?;
 
                 // Set up the OTLP downstream
                 let otlp_downstream = OpenTelemetryDownstream::new_with_dimensions(
                     channel,
-                    Some(("api-token", MetadataValue::try_from(api_token).unwrap())),
-                    get_base_environment_dimensions(),
+                    Some(("api-token", api_token.clone())),
+                    get_base_environment_dimensions(self.instance_id.clone()),
                 );
                 tokio::spawn(otlp_downstream.send_batches_forever(batch_receiver));
 
@@ -60,6 +145,31 @@ impl ProxyMetricsBuilder {
                     batch_sender,
                     OpentelemetryBatcher,
                 ));
+
+                // Set up the command log export on its own channel to the
+                // same collector
+                if self.klog_sample > 0 {
+                    let channel = get_client(
+                        endpoint,
+                        || {
+                            Some(RootCertStore {
+                                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec()
+                            })
+                        },
+                        opentelemetry_proto::tonic::collector::logs::v1::logs_service_client::LogsServiceClient::with_origin
+                    ).map_err(|e| format!("could not create the OTLP client for `{endpoint}`: {e}"))?;
+
+                    logs::start(
+                        logs::Sink::Environment {
+                            client: channel,
+                            api_token,
+                        },
+                        base_environment_dimensions(self.instance_id.clone()),
+                        self.klog_sample,
+                        self.batch_interval,
+                        self.batch_capacity,
+                    );
+                }
             }
             (None, _) => {
                 info!("OTLP endpoint not provided: not configuring OTLP downstream. Set the OTLP_ENDPOINT environment variable to configure.");
@@ -69,30 +179,39 @@ impl ProxyMetricsBuilder {
             }
         }
 
-        let metrics = DefaultProxyMetrics::new(gauge_factory, self.batch_interval);
-        Arc::new(metrics)
+        let metrics = DefaultProxyMetrics::new(gauge_factory, self.batch_interval, &[], false);
+        Ok(Arc::new(metrics))
     }
 }
 
-fn get_base_environment_dimensions() -> DimensionPosition {
+fn get_base_environment_dimensions(instance_id: Option<String>) -> DimensionPosition {
     DimensionPosition::from_iter(
-        vec![
-            // We require a standard Otel Collector `service.instance.id` and `service.name`
-            // dimensions in order to ingest metrics, otherwise they are rejected.
-            // We don't really "need" a distinct value, we just need something, which
-            // will default to `unknown`. If we need something in the future, we can
-            // add that as necessary.
-            (
-                "service.instance.id",
-                get_environment_variable("SERVICE_INSTANCE_ID"),
-            ),
-            ("service.name", get_environment_variable("SERVICE_NAME")),
-        ]
-        .into_iter()
-        .map(|(n, v)| (n.into(), v.into())),
+        base_environment_dimensions(instance_id)
+            .into_iter()
+            .map(|(n, v)| (n.into(), v.into())),
     )
 }
 
+fn base_environment_dimensions(instance_id: Option<String>) -> Vec<(&'static str, String)> {
+    let mut dimensions = vec![
+        // We require a standard Otel Collector `service.instance.id` and `service.name`
+        // dimensions in order to ingest metrics, otherwise they are rejected.
+        // We don't really "need" a distinct value, we just need something, which
+        // will default to `unknown`. If we need something in the future, we can
+        // add that as necessary.
+        (
+            "service.instance.id",
+            get_environment_variable("SERVICE_INSTANCE_ID"),
+        ),
+        ("service.name", get_environment_variable("SERVICE_NAME")),
+    ];
+    if let Some(instance_id) = instance_id {
+        dimensions.push(("instance_id", instance_id));
+    }
+
+    dimensions
+}
+
 fn get_environment_variable(variable: &str) -> String {
     match std::env::var(variable) {
         Ok(val) => val,
@@ -5,9 +5,20 @@ use std::sync::{
 
 use goodmetrics::SumHandle;
 
+use super::bridge::{
+    BridgedCache, CONNECTIONS_CLOSED, CONNECTIONS_OPENED, TOTAL_ACTIVE_CONNECTIONS,
+};
+use super::statsd;
+
 pub struct ConnectionGuard {
     connections_closed: SumHandle,
+    bridged: bool,
+    // the per cache metriken metrics, if the connection is counted in them
+    cache: Option<Arc<BridgedCache>>,
+    tags: Arc<str>,
     total_active_connections_count: Arc<AtomicI64>,
+    // the count of the whole process, if it isn't the count above
+    process_active_connections_count: Option<Arc<AtomicI64>>,
 }
 
 impl ConnectionGuard {
@@ -15,12 +26,33 @@ impl ConnectionGuard {
         connections_opened: SumHandle,
         connections_closed: SumHandle,
         total_active_connections_count: Arc<AtomicI64>,
+        process_active_connections_count: Option<Arc<AtomicI64>>,
+        tags: Arc<str>,
+        bridged: bool,
+        cache: Option<Arc<BridgedCache>>,
     ) -> Self {
         connections_opened.observe(1);
+        if bridged {
+            CONNECTIONS_OPENED.increment();
+            TOTAL_ACTIVE_CONNECTIONS.increment();
+        }
+        let cache = cache.filter(|_| bridged);
+        if let Some(cache) = &cache {
+            cache.conn_accept.increment();
+            cache.conn_curr.increment();
+        }
+        statsd::count("connections_opened", 1, &tags);
         total_active_connections_count.fetch_add(1, Ordering::Relaxed);
+        if let Some(process) = &process_active_connections_count {
+            process.fetch_add(1, Ordering::Relaxed);
+        }
         Self {
             connections_closed,
+            bridged,
+            cache,
+            tags,
             total_active_connections_count,
+            process_active_connections_count,
         }
     }
 }
@@ -29,7 +61,19 @@ impl Drop for ConnectionGuard {
     fn drop(&mut self) {
         // When the guard is dropped, we assume the connection is closed.
         self.connections_closed.observe(1);
+        if self.bridged {
+            CONNECTIONS_CLOSED.increment();
+            TOTAL_ACTIVE_CONNECTIONS.decrement();
+        }
+        if let Some(cache) = &self.cache {
+            cache.conn_close.increment();
+            cache.conn_curr.decrement();
+        }
+        statsd::count("connections_closed", 1, &self.tags);
         self.total_active_connections_count
             .fetch_sub(1, Ordering::Relaxed);
+        if let Some(process) = &self.process_active_connections_count {
+            process.fetch_sub(1, Ordering::Relaxed);
+        }
     }
 }
@@ -0,0 +1,224 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Exports metrics to an OTLP collector configured in the `[metrics]` section,
+//! over either gRPC or HTTP with protobuf bodies, optionally with mutual TLS.
+
+use goodmetrics::proto::opentelemetry::collector::metrics::v1::metrics_service_client::MetricsServiceClient;
+use goodmetrics::proto::opentelemetry::collector::metrics::v1::ExportMetricsServiceRequest;
+use goodmetrics::proto::opentelemetry::common::v1::{any_value, AnyValue, KeyValue};
+use goodmetrics::proto::opentelemetry::metrics::v1::{Metric, ResourceMetrics, ScopeMetrics};
+use goodmetrics::proto::opentelemetry::resource::v1::Resource;
+use metriken::{metric, Counter};
+use opentelemetry_proto::tonic::collector::logs::v1::logs_service_client::LogsServiceClient;
+use prost::Message;
+use tokio::sync::mpsc;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+
+use super::logs;
+use crate::momento_proxy::{Metrics, OtlpProtocol};
+
+#[metric(name = "metrics_export")]
+pub static METRICS_EXPORT: Counter = Counter::new();
+
+#[metric(name = "metrics_export_ex")]
+pub static METRICS_EXPORT_EX: Counter = Counter::new();
+
+pub(crate) enum Exporter {
+    Grpc {
+        channel: Channel,
+        client: MetricsServiceClient<Channel>,
+        headers: MetadataMap,
+    },
+    Http {
+        client: reqwest::Client,
+        endpoint: String,
+    },
+}
+
+impl Exporter {
+    /// Creates the exporter for the `[metrics]` config, which must have an
+    /// endpoint.
+    pub fn new(config: &Metrics) -> Result<Self, String> {
+        let endpoint = config
+            .endpoint()
+            .ok_or("no metrics endpoint is configured")?;
+
+        let ca = config
+            .ca_file()
+            .map(|path| read(path, "CA bundle"))
+            .transpose()?;
+        let identity = config
+            .client_identity()
+            .map(|(cert, key)| Ok::<_, String>((read(cert, "certificate")?, read(key, "key")?)))
+            .transpose()?;
+
+        match config.protocol() {
+            OtlpProtocol::Grpc => {
+                let mut tls = ClientTlsConfig::new().with_webpki_roots();
+                if let Some(ca) = ca {
+                    tls = tls.ca_certificate(Certificate::from_pem(ca));
+                }
+                if let Some((cert, key)) = identity {
+                    tls = tls.identity(Identity::from_pem(cert, key));
+                }
+
+                let channel = Endpoint::from_shared(endpoint.to_string())
+                    .map_err(|e| format!("invalid metrics endpoint `{endpoint}`: {e}"))?
+                    .tls_config(tls)
+                    .map_err(|e| format!("invalid metrics TLS config: {e}"))?
+                    .connect_lazy();
+
+                let mut headers = MetadataMap::new();
+                for (name, value) in config.headers() {
+                    let name = MetadataKey::from_bytes(name.to_lowercase().as_bytes())
+                        .map_err(|e| format!("invalid metrics header `{name}`: {e}"))?;
+                    let value = MetadataValue::try_from(value.as_str())
+                        .map_err(|e| format!("invalid value for metrics header `{name}`: {e}"))?;
+                    headers.insert(name, value);
+                }
+
+                Ok(Self::Grpc {
+                    client: MetricsServiceClient::new(channel.clone()),
+                    channel,
+                    headers,
+                })
+            }
+            OtlpProtocol::HttpProtobuf => {
+                let mut builder = reqwest::Client::builder().use_rustls_tls();
+                if let Some(ca) = ca {
+                    for cert in reqwest::Certificate::from_pem_bundle(&ca)
+                        .map_err(|e| format!("invalid metrics CA bundle: {e}"))?
+                    {
+                        builder = builder.add_root_certificate(cert);
+                    }
+                }
+                if let Some((cert, key)) = identity {
+                    let identity = reqwest::Identity::from_pem(&[cert, key].concat())
+                        .map_err(|e| format!("invalid metrics client identity: {e}"))?;
+                    builder = builder.identity(identity);
+                }
+
+                let mut headers = reqwest::header::HeaderMap::new();
+                for (name, value) in config.headers() {
+                    let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                        .map_err(|e| format!("invalid metrics header `{name}`: {e}"))?;
+                    let value = reqwest::header::HeaderValue::from_str(value)
+                        .map_err(|e| format!("invalid value for metrics header `{name}`: {e}"))?;
+                    headers.insert(name, value);
+                }
+
+                let client = builder
+                    .default_headers(headers)
+                    .build()
+                    .map_err(|e| format!("could not create metrics client: {e}"))?;
+
+                Ok(Self::Http {
+                    client,
+                    endpoint: endpoint.to_string(),
+                })
+            }
+        }
+    }
+
+    /// A sink for command log records to the same collector, with the same
+    /// TLS setup and headers. Over HTTP, the records are posted to the
+    /// endpoint with its `/v1/metrics` path replaced by `/v1/logs`.
+    pub fn logs(&self) -> Result<logs::Sink, String> {
+        match self {
+            Self::Grpc {
+                channel, headers, ..
+            } => Ok(logs::Sink::Grpc {
+                client: LogsServiceClient::new(channel.clone()),
+                headers: headers.clone(),
+            }),
+            Self::Http { client, endpoint } => {
+                let endpoint = endpoint
+                    .strip_suffix("/v1/metrics")
+                    .map(|base| format!("{base}/v1/logs"))
+                    .ok_or_else(|| {
+                        format!(
+                            "metrics endpoint `{endpoint}` doesn't end in `/v1/metrics`, so command log entries can't be exported next to it"
+                        )
+                    })?;
+                Ok(logs::Sink::Http {
+                    client: client.clone(),
+                    endpoint,
+                })
+            }
+        }
+    }
+
+    /// Sends each batch of metrics, with `dimensions` as the resource
+    /// attributes, until the batch channel closes.
+    pub async fn send_batches_forever(
+        mut self,
+        dimensions: Vec<(&'static str, String)>,
+        mut receiver: mpsc::Receiver<Vec<Metric>>,
+    ) {
+        let resource = Resource {
+            attributes: dimensions
+                .into_iter()
+                .map(|(key, value)| KeyValue {
+                    key: key.to_string(),
+                    value: Some(AnyValue {
+                        value: Some(any_value::Value::StringValue(value)),
+                    }),
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        while let Some(metrics) = receiver.recv().await {
+            let request = ExportMetricsServiceRequest {
+                resource_metrics: vec![ResourceMetrics {
+                    resource: Some(resource.clone()),
+                    scope_metrics: vec![ScopeMetrics {
+                        metrics,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+            };
+
+            match self.send(request).await {
+                Ok(()) => METRICS_EXPORT.increment(),
+                Err(e) => {
+                    METRICS_EXPORT_EX.increment();
+                    debug!("could not export metrics: {e}");
+                }
+            }
+        }
+    }
+
+    async fn send(&mut self, request: ExportMetricsServiceRequest) -> Result<(), String> {
+        match self {
+            Self::Grpc {
+                client, headers, ..
+            } => {
+                let mut request = tonic::Request::new(request);
+                *request.metadata_mut() = headers.clone();
+                client
+                    .export(request)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            }
+            Self::Http { client, endpoint } => client
+                .post(endpoint.as_str())
+                .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+                .body(request.encode_to_vec())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+fn read(path: &str, what: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("could not read metrics {what} `{path}`: {e}"))
+}
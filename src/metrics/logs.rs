@@ -0,0 +1,279 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Exports command log entries as OTLP log records, to the same collector as
+//! the metrics, so that command level events can be correlated with latency.
+
+use core::fmt::{Arguments, Display};
+use core::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use goodmetrics::downstream::ChannelType;
+use metriken::{metric, Counter};
+use opentelemetry_proto::tonic::collector::logs::v1::logs_service_client::LogsServiceClient;
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, KeyValue};
+use opentelemetry_proto::tonic::logs::v1::{LogRecord, ResourceLogs, ScopeLogs, SeverityNumber};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use prost::Message;
+use tokio::sync::mpsc;
+use tonic::metadata::{Ascii, MetadataMap, MetadataValue};
+use tonic::transport::Channel;
+
+#[metric(name = "klog_otlp_export")]
+pub static KLOG_OTLP_EXPORT: Counter = Counter::new();
+
+#[metric(name = "klog_otlp_drop")]
+pub static KLOG_OTLP_DROP: Counter = Counter::new();
+
+#[metric(name = "klog_otlp_ex")]
+pub static KLOG_OTLP_EX: Counter = Counter::new();
+
+// set once the exporter is running, entries are not exported otherwise
+static EXPORTER: OnceLock<Exporter> = OnceLock::new();
+
+struct Exporter {
+    sample: u64,
+    counter: AtomicU64,
+    sender: mpsc::Sender<LogRecord>,
+}
+
+/// Where the log records are sent, which is the collector the metrics are
+/// exported to.
+pub(crate) enum Sink {
+    /// the collector from the environment, authenticated with its api token
+    Environment {
+        client: LogsServiceClient<ChannelType>,
+        api_token: MetadataValue<Ascii>,
+    },
+    /// a gRPC collector from the `[metrics]` config
+    Grpc {
+        client: LogsServiceClient<Channel>,
+        headers: MetadataMap,
+    },
+    /// an HTTP collector from the `[metrics]` config
+    Http {
+        client: reqwest::Client,
+        endpoint: String,
+    },
+}
+
+impl Sink {
+    async fn send(&mut self, request: ExportLogsServiceRequest) -> Result<(), String> {
+        match self {
+            Self::Environment { client, api_token } => {
+                let mut request = tonic::Request::new(request);
+                request
+                    .metadata_mut()
+                    .insert("api-token", api_token.clone());
+                client
+                    .export(request)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            }
+            Self::Grpc { client, headers } => {
+                let mut request = tonic::Request::new(request);
+                *request.metadata_mut() = headers.clone();
+                client
+                    .export(request)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            }
+            Self::Http { client, endpoint } => client
+                .post(endpoint.as_str())
+                .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+                .body(request.encode_to_vec())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// The fields of a command log entry.
+pub(crate) struct CommandEvent<'a> {
+    pub command: &'a dyn Display,
+    pub key: &'a [u8],
+    pub field: Option<&'a [u8]>,
+    pub flags: Option<u32>,
+    pub ttl: Option<i32>,
+    pub value_len: Option<usize>,
+    pub status: u8,
+    pub response_len: usize,
+}
+
+impl CommandEvent<'_> {
+    fn to_record(&self, client: Option<&str>, line: &Arguments) -> LogRecord {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+
+        let mut attributes = vec![
+            attribute("command", string(self.command.to_string())),
+            attribute(
+                "key",
+                string(String::from_utf8_lossy(self.key).into_owned()),
+            ),
+            attribute("status", int(self.status as i64)),
+            attribute("response_len", int(self.response_len as i64)),
+        ];
+        if let Some(field) = self.field {
+            attributes.push(attribute(
+                "field",
+                string(String::from_utf8_lossy(field).into_owned()),
+            ));
+        }
+        if let Some(flags) = self.flags {
+            attributes.push(attribute("flags", int(flags as i64)));
+        }
+        if let Some(ttl) = self.ttl {
+            attributes.push(attribute("ttl", int(ttl as i64)));
+        }
+        if let Some(value_len) = self.value_len {
+            attributes.push(attribute("value_len", int(value_len as i64)));
+        }
+        if let Some(client) = client {
+            attributes.push(attribute("client_name", string(client.to_string())));
+        }
+
+        LogRecord {
+            time_unix_nano: now,
+            observed_time_unix_nano: now,
+            severity_number: SeverityNumber::Info as i32,
+            severity_text: "INFO".to_string(),
+            body: Some(AnyValue {
+                value: Some(string(line.to_string())),
+            }),
+            attributes,
+            ..Default::default()
+        }
+    }
+}
+
+/// Queues `event` for export, if the exporter is running and the event is
+/// sampled. Events are dropped rather than waiting when the queue is full.
+pub(crate) fn export(event: &CommandEvent, client: Option<&str>, line: &Arguments) {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+
+    if exporter.counter.fetch_add(1, Ordering::Relaxed) % exporter.sample != 0 {
+        return;
+    }
+
+    if exporter
+        .sender
+        .try_send(event.to_record(client, line))
+        .is_err()
+    {
+        KLOG_OTLP_DROP.increment();
+    }
+}
+
+/// Starts exporting one in every `sample` command log entries to `sink`.
+pub(crate) fn start(
+    sink: Sink,
+    dimensions: Vec<(&'static str, String)>,
+    sample: u64,
+    batch_interval: Duration,
+    batch_capacity: usize,
+) {
+    let (sender, receiver) = mpsc::channel(batch_capacity * 8);
+
+    let exporter = Exporter {
+        sample: sample.max(1),
+        counter: AtomicU64::new(0),
+        sender,
+    };
+    if EXPORTER.set(exporter).is_err() {
+        return;
+    }
+
+    let resource = Resource {
+        attributes: dimensions
+            .into_iter()
+            .map(|(key, value)| attribute(key, string(value)))
+            .collect(),
+        ..Default::default()
+    };
+
+    tokio::spawn(send_forever(
+        sink,
+        resource,
+        receiver,
+        batch_interval,
+        batch_capacity,
+    ));
+}
+
+async fn send_forever(
+    mut sink: Sink,
+    resource: Resource,
+    mut receiver: mpsc::Receiver<LogRecord>,
+    batch_interval: Duration,
+    batch_capacity: usize,
+) {
+    loop {
+        // wait for an entry, then gather a batch until it is full or the
+        // interval has passed
+        let Some(record) = receiver.recv().await else {
+            return;
+        };
+        let mut records = vec![record];
+
+        let deadline = tokio::time::sleep(batch_interval);
+        tokio::pin!(deadline);
+
+        while records.len() < batch_capacity {
+            tokio::select! {
+                _ = &mut deadline => break,
+                record = receiver.recv() => match record {
+                    Some(record) => records.push(record),
+                    None => break,
+                },
+            }
+        }
+
+        let count = records.len();
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(resource.clone()),
+                scope_logs: vec![ScopeLogs {
+                    log_records: records,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        match sink.send(request).await {
+            Ok(_) => KLOG_OTLP_EXPORT.add(count as _),
+            Err(e) => {
+                KLOG_OTLP_EX.increment();
+                debug!("could not export command log entries: {e}");
+            }
+        };
+    }
+}
+
+fn attribute(key: &str, value: any_value::Value) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue { value: Some(value) }),
+    }
+}
+
+fn string(value: String) -> any_value::Value {
+    any_value::Value::StringValue(value)
+}
+
+fn int(value: i64) -> any_value::Value {
+    any_value::Value::IntValue(value)
+}
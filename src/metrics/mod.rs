@@ -76,18 +76,26 @@ pub static RU_NVCSW: Counter = Counter::new();
 #[metric(name = "ru_nivcsw")]
 pub static RU_NIVCSW: Counter = Counter::new();
 
+pub mod batch;
+mod bridge;
 mod builder;
 mod connection;
+mod export;
+pub(crate) mod logs;
 mod proxy;
 mod rpc;
+mod runtime;
+mod statsd;
 pub mod util;
 
 pub use builder::ProxyMetricsBuilder;
 pub use connection::ConnectionGuard;
+pub(crate) use proxy::register_rpcs;
 pub use proxy::{
     ConnectionMetrics, DefaultProxyMetrics, MemcachedMetrics, ProxyMetrics, RespMetrics,
 };
 pub use rpc::{
-    with_rpc_call_guard, with_wrapped_error_response_rpc_call_guard, ResponseWrappingError,
-    RpcCallGuard, RpcMetrics,
+    classify, with_rpc_call_guard, with_wrapped_error_response_rpc_call_guard,
+    ResponseWrappingError, RpcCallGuard, RpcError, RpcMetrics, RpcOutcome,
 };
+pub use runtime::{monitor_runtime, ADMIN_RUNTIME, WORKER_RUNTIME};
@@ -1,21 +1,41 @@
 use std::{
+    collections::HashMap,
     sync::{
         atomic::{AtomicI64, Ordering},
-        Arc,
+        Arc, Mutex,
     },
     time::Duration,
 };
 
 use super::{
-    util::{proxy_statistic_set_gauge, proxy_sum_gauge},
+    bridge, statsd,
+    util::{
+        accept_errno, proxy_dimensioned_sum_gauge, proxy_histogram, proxy_statistic_set_gauge,
+        proxy_sum_gauge, ACCEPT_ERRNOS,
+    },
     ConnectionGuard,
 };
-use goodmetrics::{GaugeFactory, SumHandle};
+use goodmetrics::{
+    default_gauge_factory, GaugeFactory, HistogramHandle, StatisticSetHandle, SumHandle,
+};
 
 use super::{RpcCallGuard, RpcMetrics};
 
+// the most client names with their own metrics on a listener, the connections
+// of any other clients are counted under `other`
+const MAX_CLIENT_NAMES: usize = 100;
+
 pub trait ConnectionMetrics: Clone + Send + Sync + 'static {
     fn begin_connection(&self) -> ConnectionGuard;
+    /// The metrics for connections which identified themselves as `name`,
+    /// which have a `client_name` dimension.
+    fn with_client_name(&self, name: &str) -> Self;
+    /// Records an accepted connection, with how long the listener took to
+    /// handle it before it could accept the next one.
+    fn record_accept(&self, latency: Duration);
+    fn record_accept_error(&self, error: &std::io::Error);
+    /// Records how many connections are waiting in the listen backlog.
+    fn record_accept_queue(&self, depth: u32);
 }
 
 pub trait MemcachedMetrics: Clone + Send + Sync + 'static {
@@ -25,46 +45,118 @@ pub trait MemcachedMetrics: Clone + Send + Sync + 'static {
     fn begin_memcached_unimplemented(&self) -> RpcCallGuard;
 }
 
-pub trait RespMetrics: Clone + Send + Sync + 'static {
-    fn begin_resp_del(&self) -> RpcCallGuard;
-    fn begin_resp_get(&self) -> RpcCallGuard;
-    fn begin_resp_hdel(&self) -> RpcCallGuard;
-    fn begin_resp_hexists(&self) -> RpcCallGuard;
-    fn begin_resp_hget(&self) -> RpcCallGuard;
-    fn begin_resp_hgetall(&self) -> RpcCallGuard;
-    fn begin_resp_hincrby(&self) -> RpcCallGuard;
-    fn begin_resp_hkeys(&self) -> RpcCallGuard;
-    fn begin_resp_hlen(&self) -> RpcCallGuard;
-    fn begin_resp_hmget(&self) -> RpcCallGuard;
-    fn begin_resp_hset(&self) -> RpcCallGuard;
-    fn begin_resp_hvals(&self) -> RpcCallGuard;
-    fn begin_resp_lindex(&self) -> RpcCallGuard;
-    fn begin_resp_llen(&self) -> RpcCallGuard;
-    fn begin_resp_lpop(&self) -> RpcCallGuard;
-    fn begin_resp_lrange(&self) -> RpcCallGuard;
-    fn begin_resp_lpush(&self) -> RpcCallGuard;
-    fn begin_resp_rpush(&self) -> RpcCallGuard;
-    fn begin_resp_rpop(&self) -> RpcCallGuard;
-    fn begin_resp_set(&self) -> RpcCallGuard;
-    fn begin_resp_sadd(&self) -> RpcCallGuard;
-    fn begin_resp_srem(&self) -> RpcCallGuard;
-    fn begin_resp_sdiff(&self) -> RpcCallGuard;
-    fn begin_resp_sunion(&self) -> RpcCallGuard;
-    fn begin_resp_sinter(&self) -> RpcCallGuard;
-    fn begin_resp_smembers(&self) -> RpcCallGuard;
-    fn begin_resp_sismember(&self) -> RpcCallGuard;
-    fn begin_resp_zcard(&self) -> RpcCallGuard;
-    fn begin_resp_zincrby(&self) -> RpcCallGuard;
-    fn begin_resp_zscore(&self) -> RpcCallGuard;
-    fn begin_resp_zmscore(&self) -> RpcCallGuard;
-    fn begin_resp_zrem(&self) -> RpcCallGuard;
-    fn begin_resp_zrank(&self) -> RpcCallGuard;
-    fn begin_resp_zrange(&self) -> RpcCallGuard;
-    fn begin_resp_zadd(&self) -> RpcCallGuard;
-    fn begin_resp_zrevrank(&self) -> RpcCallGuard;
-    fn begin_resp_zcount(&self) -> RpcCallGuard;
-    fn begin_resp_zunionstore(&self) -> RpcCallGuard;
-    fn begin_resp_unimplemented(&self) -> RpcCallGuard;
+/// Declares the `RespMetrics` trait, the `RpcMetrics` for each RESP command,
+/// and the trait implementations from a single list. Adding a command is a
+/// one-line change here.
+macro_rules! resp_metrics {
+    ($($field:ident => $begin:ident,)*) => {
+        // the name of each RESP command's metrics
+        const RESP_RPCS: &[&str] = &[$(stringify!($field),)*];
+
+        pub trait RespMetrics: Clone + Send + Sync + 'static {
+            $(fn $begin(&self) -> RpcCallGuard;)*
+        }
+
+        #[derive(Clone, Debug)]
+        pub struct RespRpcMetrics {
+            $(pub(crate) $field: RpcMetrics,)*
+        }
+
+        impl RespRpcMetrics {
+            pub(crate) fn new(gauge_factory: &GaugeFactory, base: &[(String, String)]) -> Self {
+                Self {
+                    $($field: RpcMetrics::new(gauge_factory, base, stringify!($field), false),)*
+                }
+            }
+        }
+
+        impl RespMetrics for DefaultProxyMetrics {
+            $(fn $begin(&self) -> RpcCallGuard {
+                self.resp.$field.record_api_call()
+            })*
+        }
+
+        impl RespMetrics for Arc<DefaultProxyMetrics> {
+            $(fn $begin(&self) -> RpcCallGuard {
+                self.as_ref().$begin()
+            })*
+        }
+    };
+}
+
+resp_metrics! {
+    resp_bitcount => begin_resp_bitcount,
+    resp_bitop => begin_resp_bitop,
+    resp_del => begin_resp_del,
+    resp_dump => begin_resp_dump,
+    resp_get => begin_resp_get,
+    resp_getbit => begin_resp_getbit,
+    resp_getrange => begin_resp_getrange,
+    resp_hdel => begin_resp_hdel,
+    resp_hexists => begin_resp_hexists,
+    resp_hget => begin_resp_hget,
+    resp_hgetall => begin_resp_hgetall,
+    resp_hincrby => begin_resp_hincrby,
+    resp_hkeys => begin_resp_hkeys,
+    resp_hlen => begin_resp_hlen,
+    resp_hmget => begin_resp_hmget,
+    resp_hset => begin_resp_hset,
+    resp_hvals => begin_resp_hvals,
+    resp_lindex => begin_resp_lindex,
+    resp_llen => begin_resp_llen,
+    resp_lmpop => begin_resp_lmpop,
+    resp_lpop => begin_resp_lpop,
+    resp_lpos => begin_resp_lpos,
+    resp_lrange => begin_resp_lrange,
+    resp_memory => begin_resp_memory,
+    resp_object => begin_resp_object,
+    resp_lpush => begin_resp_lpush,
+    resp_pttl => begin_resp_pttl,
+    resp_pfadd => begin_resp_pfadd,
+    resp_pfcount => begin_resp_pfcount,
+    resp_pfmerge => begin_resp_pfmerge,
+    resp_rpush => begin_resp_rpush,
+    resp_rpop => begin_resp_rpop,
+    resp_restore => begin_resp_restore,
+    resp_set => begin_resp_set,
+    resp_setbit => begin_resp_setbit,
+    resp_setrange => begin_resp_setrange,
+    resp_sadd => begin_resp_sadd,
+    resp_srem => begin_resp_srem,
+    resp_sdiff => begin_resp_sdiff,
+    resp_sunion => begin_resp_sunion,
+    resp_sinter => begin_resp_sinter,
+    resp_smembers => begin_resp_smembers,
+    resp_strlen => begin_resp_strlen,
+    resp_ttl => begin_resp_ttl,
+    resp_sismember => begin_resp_sismember,
+    resp_zcard => begin_resp_zcard,
+    resp_zincrby => begin_resp_zincrby,
+    resp_zscore => begin_resp_zscore,
+    resp_zmscore => begin_resp_zmscore,
+    resp_zrem => begin_resp_zrem,
+    resp_zrank => begin_resp_zrank,
+    resp_zrange => begin_resp_zrange,
+    resp_zadd => begin_resp_zadd,
+    resp_zrevrank => begin_resp_zrevrank,
+    resp_zcount => begin_resp_zcount,
+    resp_zunionstore => begin_resp_zunionstore,
+    resp_unimplemented => begin_resp_unimplemented,
+}
+
+const MEMCACHED_RPCS: &[&str] = &[
+    "memcached_get",
+    "memcached_set",
+    "memcached_delete",
+    "memcached_unimplemented",
+];
+
+/// Registers the metriken metrics of every command, so they are listed before
+/// any listener has created its metrics.
+pub(crate) fn register_rpcs() {
+    for rpc in MEMCACHED_RPCS.iter().chain(RESP_RPCS).copied() {
+        bridge::rpc(rpc);
+    }
 }
 
 pub trait ProxyMetrics: ConnectionMetrics + MemcachedMetrics + RespMetrics {}
@@ -72,10 +164,32 @@ impl<T: ConnectionMetrics + MemcachedMetrics + RespMetrics> ProxyMetrics for T {
 
 #[derive(Clone, Debug)]
 pub struct DefaultProxyMetrics {
+    batch_interval: Duration,
+    dimensions: Arc<[(String, String)]>,
+    by_size: bool,
+    // the dimensions formatted as statsd tags
+    tags: Arc<str>,
+
+    // the metrics of each named client of the listener
+    clients: Arc<Mutex<HashMap<String, Arc<DefaultProxyMetrics>>>>,
+    // set for the metrics of a named client
+    client_name: Option<String>,
+    // the metriken connection metrics of the cache named by the dimensions
+    cache: Option<Arc<bridge::BridgedCache>>,
+
     // connection handles
     pub(crate) connections_opened: SumHandle,
     pub(crate) connections_closed: SumHandle,
     pub(crate) total_active_connections_count: Arc<AtomicI64>,
+    // the count of the metrics without dimensions, which is the total for the
+    // whole process, shared by every set of metrics derived from them
+    process_active_connections_count: Arc<AtomicI64>,
+
+    // listener handles
+    pub(crate) accepts: SumHandle,
+    pub(crate) accept_errors: Arc<[SumHandle; ACCEPT_ERRNOS.len()]>,
+    pub(crate) accept_latency: HistogramHandle,
+    pub(crate) accept_queue_depth: StatisticSetHandle,
 
     // memcached handles
     pub(crate) memcached_get: RpcMetrics,
@@ -84,53 +198,20 @@ pub struct DefaultProxyMetrics {
     pub(crate) memcached_unimplemented: RpcMetrics,
 
     // resp handles
-    pub(crate) resp_del: RpcMetrics,
-    pub(crate) resp_get: RpcMetrics,
-    pub(crate) resp_hdel: RpcMetrics,
-    pub(crate) resp_hexists: RpcMetrics,
-    pub(crate) resp_hget: RpcMetrics,
-    pub(crate) resp_hgetall: RpcMetrics,
-    pub(crate) resp_hincrby: RpcMetrics,
-    pub(crate) resp_hkeys: RpcMetrics,
-    pub(crate) resp_hlen: RpcMetrics,
-    pub(crate) resp_hmget: RpcMetrics,
-    pub(crate) resp_hset: RpcMetrics,
-    pub(crate) resp_hvals: RpcMetrics,
-    pub(crate) resp_lindex: RpcMetrics,
-    pub(crate) resp_llen: RpcMetrics,
-    pub(crate) resp_lpop: RpcMetrics,
-    pub(crate) resp_lrange: RpcMetrics,
-    pub(crate) resp_lpush: RpcMetrics,
-    pub(crate) resp_rpush: RpcMetrics,
-    pub(crate) resp_rpop: RpcMetrics,
-    pub(crate) resp_set: RpcMetrics,
-    pub(crate) resp_sadd: RpcMetrics,
-    pub(crate) resp_srem: RpcMetrics,
-    pub(crate) resp_sdiff: RpcMetrics,
-    pub(crate) resp_sunion: RpcMetrics,
-    pub(crate) resp_sinter: RpcMetrics,
-    pub(crate) resp_smembers: RpcMetrics,
-    pub(crate) resp_sismember: RpcMetrics,
-    pub(crate) resp_zcard: RpcMetrics,
-    pub(crate) resp_zincrby: RpcMetrics,
-    pub(crate) resp_zscore: RpcMetrics,
-    pub(crate) resp_zmscore: RpcMetrics,
-    pub(crate) resp_zrem: RpcMetrics,
-    pub(crate) resp_zrank: RpcMetrics,
-    pub(crate) resp_zrange: RpcMetrics,
-    pub(crate) resp_zadd: RpcMetrics,
-    pub(crate) resp_zrevrank: RpcMetrics,
-    pub(crate) resp_zcount: RpcMetrics,
-    pub(crate) resp_zunionstore: RpcMetrics,
-    pub(crate) resp_unimplemented: RpcMetrics,
+    pub(crate) resp: RespRpcMetrics,
 }
 
 impl DefaultProxyMetrics {
-    pub(crate) fn new(gauge_factory: &GaugeFactory, batch_interval: Duration) -> Self {
+    pub(crate) fn new(
+        gauge_factory: &GaugeFactory,
+        batch_interval: Duration,
+        dimensions: &[(String, String)],
+        by_size: bool,
+    ) -> Self {
         // Keep track of the total number of active connections to the proxy
         let total_active_connections_count = Arc::new(AtomicI64::new(0));
         let total_active_connections =
-            proxy_statistic_set_gauge(gauge_factory, "total_active_connections");
+            proxy_statistic_set_gauge(gauge_factory, dimensions, "total_active_connections");
 
         // Emit the total active connections count every batch_interval seconds
         let count_clone = total_active_connections_count.clone();
@@ -143,64 +224,141 @@ impl DefaultProxyMetrics {
 
         // Create the remaining gauge handles
         Self {
-            memcached_get: RpcMetrics::new(gauge_factory, "memcached_get"),
-            memcached_set: RpcMetrics::new(gauge_factory, "memcached_set"),
-            memcached_delete: RpcMetrics::new(gauge_factory, "memcached_delete"),
-            memcached_unimplemented: RpcMetrics::new(gauge_factory, "memcached_unimplemented"),
-            resp_del: RpcMetrics::new(gauge_factory, "resp_del"),
-            resp_get: RpcMetrics::new(gauge_factory, "resp_get"),
-            resp_hdel: RpcMetrics::new(gauge_factory, "resp_hdel"),
-            resp_hexists: RpcMetrics::new(gauge_factory, "resp_hexists"),
-            resp_hget: RpcMetrics::new(gauge_factory, "resp_hget"),
-            resp_hgetall: RpcMetrics::new(gauge_factory, "resp_hgetall"),
-            resp_hincrby: RpcMetrics::new(gauge_factory, "resp_hincrby"),
-            resp_hkeys: RpcMetrics::new(gauge_factory, "resp_hkeys"),
-            resp_hlen: RpcMetrics::new(gauge_factory, "resp_hlen"),
-            resp_hmget: RpcMetrics::new(gauge_factory, "resp_hmget"),
-            resp_hset: RpcMetrics::new(gauge_factory, "resp_hset"),
-            resp_hvals: RpcMetrics::new(gauge_factory, "resp_hvals"),
-            resp_lindex: RpcMetrics::new(gauge_factory, "resp_lindex"),
-            resp_llen: RpcMetrics::new(gauge_factory, "resp_llen"),
-            resp_lpop: RpcMetrics::new(gauge_factory, "resp_lpop"),
-            resp_lrange: RpcMetrics::new(gauge_factory, "resp_lrange"),
-            resp_lpush: RpcMetrics::new(gauge_factory, "resp_lpush"),
-            resp_rpush: RpcMetrics::new(gauge_factory, "resp_rpush"),
-            resp_rpop: RpcMetrics::new(gauge_factory, "resp_rpop"),
-            resp_set: RpcMetrics::new(gauge_factory, "resp_set"),
-            resp_sadd: RpcMetrics::new(gauge_factory, "resp_sadd"),
-            resp_srem: RpcMetrics::new(gauge_factory, "resp_srem"),
-            resp_sdiff: RpcMetrics::new(gauge_factory, "resp_sdiff"),
-            resp_sunion: RpcMetrics::new(gauge_factory, "resp_sunion"),
-            resp_sinter: RpcMetrics::new(gauge_factory, "resp_sinter"),
-            resp_smembers: RpcMetrics::new(gauge_factory, "resp_smembers"),
-            resp_sismember: RpcMetrics::new(gauge_factory, "resp_sismember"),
-            resp_zcard: RpcMetrics::new(gauge_factory, "resp_zcard"),
-            resp_zincrby: RpcMetrics::new(gauge_factory, "resp_zincrby"),
-            resp_zscore: RpcMetrics::new(gauge_factory, "resp_zscore"),
-            resp_zmscore: RpcMetrics::new(gauge_factory, "resp_zmscore"),
-            resp_zrem: RpcMetrics::new(gauge_factory, "resp_zrem"),
-            resp_zrank: RpcMetrics::new(gauge_factory, "resp_zrank"),
-            resp_zrange: RpcMetrics::new(gauge_factory, "resp_zrange"),
-            resp_zadd: RpcMetrics::new(gauge_factory, "resp_zadd"),
-            resp_zrevrank: RpcMetrics::new(gauge_factory, "resp_zrevrank"),
-            resp_zcount: RpcMetrics::new(gauge_factory, "resp_zcount"),
-            resp_zunionstore: RpcMetrics::new(gauge_factory, "resp_zunionstore"),
-            resp_unimplemented: RpcMetrics::new(gauge_factory, "resp_unimplemented"),
-            connections_opened: proxy_sum_gauge(gauge_factory, "connections_opened"),
-            connections_closed: proxy_sum_gauge(gauge_factory, "connections_closed"),
+            batch_interval,
+            dimensions: dimensions.into(),
+            by_size,
+            clients: Default::default(),
+            client_name: None,
+            cache: dimensions
+                .iter()
+                .find(|(name, _)| name == "cache_name")
+                .map(|(_, cache_name)| bridge::cache(cache_name)),
+            tags: statsd::tags(dimensions.iter().map(|(n, v)| (n.as_str(), v.as_str()))).into(),
+            memcached_get: RpcMetrics::new(gauge_factory, dimensions, "memcached_get", by_size),
+            memcached_set: RpcMetrics::new(gauge_factory, dimensions, "memcached_set", by_size),
+            memcached_delete: RpcMetrics::new(
+                gauge_factory,
+                dimensions,
+                "memcached_delete",
+                by_size,
+            ),
+            memcached_unimplemented: RpcMetrics::new(
+                gauge_factory,
+                dimensions,
+                "memcached_unimplemented",
+                false,
+            ),
+            resp: RespRpcMetrics::new(gauge_factory, dimensions),
+            connections_opened: proxy_sum_gauge(gauge_factory, dimensions, "connections_opened"),
+            connections_closed: proxy_sum_gauge(gauge_factory, dimensions, "connections_closed"),
+            process_active_connections_count: total_active_connections_count.clone(),
             total_active_connections_count,
+            accepts: proxy_sum_gauge(gauge_factory, dimensions, "accepts"),
+            accept_errors: Arc::new(ACCEPT_ERRNOS.map(|errno| {
+                proxy_dimensioned_sum_gauge(
+                    gauge_factory,
+                    dimensions,
+                    "accept_errors",
+                    ("errno", errno),
+                )
+            })),
+            accept_latency: proxy_histogram(gauge_factory, dimensions, "accept_latency"),
+            accept_queue_depth: proxy_statistic_set_gauge(
+                gauge_factory,
+                dimensions,
+                "accept_queue_depth",
+            ),
+        }
+    }
+
+    /// A separate set of metrics with the provided dimensions, so that each
+    /// listener reports its own time series. With `by_size`, memcache get and
+    /// set latencies are also recorded by value size.
+    pub fn with_dimensions(&self, dimensions: &[(String, String)], by_size: bool) -> Self {
+        Self {
+            process_active_connections_count: self.process_active_connections_count.clone(),
+            ..Self::new(
+                default_gauge_factory(),
+                self.batch_interval,
+                dimensions,
+                by_size,
+            )
         }
     }
+
+    /// The metrics of the named client, created on first use. A listener has
+    /// metrics for at most `MAX_CLIENT_NAMES` clients.
+    fn client_metrics(&self, name: &str) -> Arc<Self> {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+
+        let name = if clients.contains_key(name) || clients.len() < MAX_CLIENT_NAMES {
+            name
+        } else {
+            "other"
+        };
+
+        clients
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                let mut dimensions = self.dimensions.to_vec();
+                dimensions.push(("client_name".to_string(), name.to_string()));
+
+                let mut metrics = self.with_dimensions(&dimensions, self.by_size);
+                metrics.client_name = Some(name.to_string());
+                Arc::new(metrics)
+            })
+            .clone()
+    }
 }
 
 impl ConnectionMetrics for DefaultProxyMetrics {
     fn begin_connection(&self) -> ConnectionGuard {
+        // a named connection is also counted by the listener's metrics, so it
+        // is only counted once in the totals
+        let process_active_connections_count = Some(&self.process_active_connections_count)
+            .filter(|process| {
+                self.client_name.is_none()
+                    && !Arc::ptr_eq(process, &self.total_active_connections_count)
+            })
+            .cloned();
+
         ConnectionGuard::new(
             self.connections_opened.clone(),
             self.connections_closed.clone(),
             self.total_active_connections_count.clone(),
+            process_active_connections_count,
+            self.tags.clone(),
+            self.client_name.is_none(),
+            self.cache.clone(),
         )
     }
+
+    fn with_client_name(&self, name: &str) -> Self {
+        self.client_metrics(name).as_ref().clone()
+    }
+
+    fn record_accept(&self, latency: Duration) {
+        self.accepts.observe(1);
+        self.accept_latency.observe(latency.as_nanos() as i64);
+    }
+
+    fn record_accept_error(&self, error: &std::io::Error) {
+        let errno = accept_errno(error);
+        self.accept_errors[errno].observe(1);
+
+        if statsd::is_enabled() {
+            let mut tags = statsd::tags([("errno", ACCEPT_ERRNOS[errno])]);
+            if !self.tags.is_empty() {
+                tags.push(',');
+                tags.push_str(&self.tags);
+            }
+            statsd::count("accept_errors", 1, &tags);
+        }
+    }
+
+    fn record_accept_queue(&self, depth: u32) {
+        self.accept_queue_depth.observe(depth as i64);
+    }
 }
 
 impl MemcachedMetrics for DefaultProxyMetrics {
@@ -221,129 +379,25 @@ impl MemcachedMetrics for DefaultProxyMetrics {
     }
 }
 
-impl RespMetrics for DefaultProxyMetrics {
-    fn begin_resp_del(&self) -> RpcCallGuard {
-        self.resp_del.record_api_call()
-    }
-    fn begin_resp_get(&self) -> RpcCallGuard {
-        self.resp_get.record_api_call()
-    }
-    fn begin_resp_hdel(&self) -> RpcCallGuard {
-        self.resp_hdel.record_api_call()
-    }
-    fn begin_resp_hexists(&self) -> RpcCallGuard {
-        self.resp_hexists.record_api_call()
-    }
-    fn begin_resp_hget(&self) -> RpcCallGuard {
-        self.resp_hget.record_api_call()
-    }
-    fn begin_resp_hgetall(&self) -> RpcCallGuard {
-        self.resp_hgetall.record_api_call()
-    }
-    fn begin_resp_hincrby(&self) -> RpcCallGuard {
-        self.resp_hincrby.record_api_call()
-    }
-    fn begin_resp_hkeys(&self) -> RpcCallGuard {
-        self.resp_hkeys.record_api_call()
-    }
-    fn begin_resp_hlen(&self) -> RpcCallGuard {
-        self.resp_hlen.record_api_call()
-    }
-    fn begin_resp_hmget(&self) -> RpcCallGuard {
-        self.resp_hmget.record_api_call()
-    }
-    fn begin_resp_hset(&self) -> RpcCallGuard {
-        self.resp_hset.record_api_call()
-    }
-    fn begin_resp_hvals(&self) -> RpcCallGuard {
-        self.resp_hvals.record_api_call()
-    }
-    fn begin_resp_lindex(&self) -> RpcCallGuard {
-        self.resp_lindex.record_api_call()
-    }
-    fn begin_resp_llen(&self) -> RpcCallGuard {
-        self.resp_llen.record_api_call()
-    }
-    fn begin_resp_lpop(&self) -> RpcCallGuard {
-        self.resp_lpop.record_api_call()
-    }
-    fn begin_resp_lrange(&self) -> RpcCallGuard {
-        self.resp_lrange.record_api_call()
-    }
-    fn begin_resp_lpush(&self) -> RpcCallGuard {
-        self.resp_lpush.record_api_call()
-    }
-    fn begin_resp_rpush(&self) -> RpcCallGuard {
-        self.resp_rpush.record_api_call()
-    }
-    fn begin_resp_rpop(&self) -> RpcCallGuard {
-        self.resp_rpop.record_api_call()
-    }
-    fn begin_resp_set(&self) -> RpcCallGuard {
-        self.resp_set.record_api_call()
-    }
-    fn begin_resp_sadd(&self) -> RpcCallGuard {
-        self.resp_sadd.record_api_call()
-    }
-    fn begin_resp_srem(&self) -> RpcCallGuard {
-        self.resp_srem.record_api_call()
-    }
-    fn begin_resp_sdiff(&self) -> RpcCallGuard {
-        self.resp_sdiff.record_api_call()
-    }
-    fn begin_resp_sunion(&self) -> RpcCallGuard {
-        self.resp_sunion.record_api_call()
-    }
-    fn begin_resp_sinter(&self) -> RpcCallGuard {
-        self.resp_sinter.record_api_call()
-    }
-    fn begin_resp_smembers(&self) -> RpcCallGuard {
-        self.resp_smembers.record_api_call()
-    }
-    fn begin_resp_sismember(&self) -> RpcCallGuard {
-        self.resp_sismember.record_api_call()
-    }
-    fn begin_resp_zcard(&self) -> RpcCallGuard {
-        self.resp_zcard.record_api_call()
-    }
-    fn begin_resp_zincrby(&self) -> RpcCallGuard {
-        self.resp_zincrby.record_api_call()
-    }
-    fn begin_resp_zscore(&self) -> RpcCallGuard {
-        self.resp_zscore.record_api_call()
-    }
-    fn begin_resp_zmscore(&self) -> RpcCallGuard {
-        self.resp_zmscore.record_api_call()
-    }
-    fn begin_resp_zrem(&self) -> RpcCallGuard {
-        self.resp_zrem.record_api_call()
-    }
-    fn begin_resp_zrank(&self) -> RpcCallGuard {
-        self.resp_zrank.record_api_call()
-    }
-    fn begin_resp_zrange(&self) -> RpcCallGuard {
-        self.resp_zrange.record_api_call()
-    }
-    fn begin_resp_zadd(&self) -> RpcCallGuard {
-        self.resp_zadd.record_api_call()
-    }
-    fn begin_resp_zrevrank(&self) -> RpcCallGuard {
-        self.resp_zrevrank.record_api_call()
+impl ConnectionMetrics for Arc<DefaultProxyMetrics> {
+    fn begin_connection(&self) -> ConnectionGuard {
+        self.as_ref().begin_connection()
     }
-    fn begin_resp_zcount(&self) -> RpcCallGuard {
-        self.resp_zcount.record_api_call()
+
+    fn with_client_name(&self, name: &str) -> Self {
+        self.client_metrics(name)
     }
-    fn begin_resp_zunionstore(&self) -> RpcCallGuard {
-        self.resp_zunionstore.record_api_call()
+
+    fn record_accept(&self, latency: Duration) {
+        self.as_ref().record_accept(latency)
     }
-    fn begin_resp_unimplemented(&self) -> RpcCallGuard {
-        self.resp_unimplemented.record_api_call()
+
+    fn record_accept_error(&self, error: &std::io::Error) {
+        self.as_ref().record_accept_error(error)
     }
-}
 
-impl ConnectionMetrics for Arc<DefaultProxyMetrics> {
-    fn begin_connection(&self) -> ConnectionGuard {
-        self.as_ref().begin_connection()
+    fn record_accept_queue(&self, depth: u32) {
+        self.as_ref().record_accept_queue(depth)
     }
 }
 
@@ -364,123 +418,3 @@ impl MemcachedMetrics for Arc<DefaultProxyMetrics> {
         self.as_ref().begin_memcached_unimplemented()
     }
 }
-
-impl RespMetrics for Arc<DefaultProxyMetrics> {
-    fn begin_resp_del(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_del()
-    }
-    fn begin_resp_get(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_get()
-    }
-    fn begin_resp_hdel(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_hdel()
-    }
-    fn begin_resp_hexists(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_hexists()
-    }
-    fn begin_resp_hget(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_hget()
-    }
-    fn begin_resp_hgetall(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_hgetall()
-    }
-    fn begin_resp_hincrby(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_hincrby()
-    }
-    fn begin_resp_hkeys(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_hkeys()
-    }
-    fn begin_resp_hlen(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_hlen()
-    }
-    fn begin_resp_hmget(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_hmget()
-    }
-    fn begin_resp_hset(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_hset()
-    }
-    fn begin_resp_hvals(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_hvals()
-    }
-    fn begin_resp_lindex(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_lindex()
-    }
-    fn begin_resp_llen(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_llen()
-    }
-    fn begin_resp_lpop(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_lpop()
-    }
-    fn begin_resp_lrange(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_lrange()
-    }
-    fn begin_resp_lpush(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_lpush()
-    }
-    fn begin_resp_rpush(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_rpush()
-    }
-    fn begin_resp_rpop(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_rpop()
-    }
-    fn begin_resp_set(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_set()
-    }
-    fn begin_resp_sadd(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_sadd()
-    }
-    fn begin_resp_srem(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_srem()
-    }
-    fn begin_resp_sdiff(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_sdiff()
-    }
-    fn begin_resp_sunion(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_sunion()
-    }
-    fn begin_resp_sinter(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_sinter()
-    }
-    fn begin_resp_smembers(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_smembers()
-    }
-    fn begin_resp_sismember(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_sismember()
-    }
-    fn begin_resp_zcard(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_zcard()
-    }
-    fn begin_resp_zincrby(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_zincrby()
-    }
-    fn begin_resp_zscore(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_zscore()
-    }
-    fn begin_resp_zmscore(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_zmscore()
-    }
-    fn begin_resp_zrem(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_zrem()
-    }
-    fn begin_resp_zrank(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_zrank()
-    }
-    fn begin_resp_zrange(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_zrange()
-    }
-    fn begin_resp_zadd(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_zadd()
-    }
-    fn begin_resp_zrevrank(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_zrevrank()
-    }
-    fn begin_resp_zcount(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_zcount()
-    }
-    fn begin_resp_zunionstore(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_zunionstore()
-    }
-    fn begin_resp_unimplemented(&self) -> RpcCallGuard {
-        self.as_ref().begin_resp_unimplemented()
-    }
-}
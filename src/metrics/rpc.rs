@@ -8,46 +8,104 @@ use std::{
 };
 
 use goodmetrics::{GaugeFactory, HistogramHandle};
+use momento::MomentoErrorCode;
 
+use crate::error::ProxyError;
+
+use super::batch;
+use super::bridge::{self, BridgedRpc};
+use super::statsd;
 use super::util::{
     proxy_request_latency_error_histogram, proxy_request_latency_hit_histogram,
     proxy_request_latency_miss_histogram, proxy_request_latency_ok_histogram,
-    proxy_request_latency_timeout_histogram,
+    proxy_request_latency_size_histograms, proxy_request_latency_timeout_histogram, size_bucket,
 };
 
+// the latency histograms split by value size, for each outcome in the order
+// ok, error, timeout, miss, memory cache hit, and momento hit
+type SizedLatency = [[HistogramHandle; 4]; 6];
+
+pub(super) const OK: usize = 0;
+pub(super) const ERROR: usize = 1;
+pub(super) const TIMEOUT: usize = 2;
+pub(super) const MISS: usize = 3;
+pub(super) const HIT_MCACHE: usize = 4;
+pub(super) const HIT_MOMENTO: usize = 5;
+pub(super) const OUTCOMES: usize = 6;
+
 #[derive(Clone, Debug)]
 pub struct RpcMetrics {
     rpc: &'static str,
+    tags: Arc<str>,
     latency_ok: HistogramHandle,
     latency_error: HistogramHandle,
     latency_timeout: HistogramHandle,
     latency_miss: HistogramHandle,
     latency_hit_mcache: HistogramHandle,
     latency_hit_momento: HistogramHandle,
+    latency_by_size: Option<Arc<SizedLatency>>,
+    bridged: Arc<BridgedRpc>,
 }
 
 impl RpcMetrics {
-    pub fn new(gauge_factory: &GaugeFactory, rpc: &'static str) -> Self {
+    /// Creates the metrics for `rpc`. With `by_size`, latencies are also
+    /// recorded by value size for calls which report one.
+    pub fn new(
+        gauge_factory: &GaugeFactory,
+        base: &[(String, String)],
+        rpc: &'static str,
+        by_size: bool,
+    ) -> Self {
+        let latency_by_size = by_size.then(|| {
+            let sized = |result, source| {
+                proxy_request_latency_size_histograms(gauge_factory, base, rpc, result, source)
+            };
+            Arc::new([
+                sized("ok", None),
+                sized("error", None),
+                sized("timeout", None),
+                sized("miss", None),
+                sized("hit", Some("mcache")),
+                sized("hit", Some("momento")),
+            ])
+        });
+
         Self {
             rpc,
-            latency_ok: proxy_request_latency_ok_histogram(gauge_factory, rpc),
-            latency_error: proxy_request_latency_error_histogram(gauge_factory, rpc),
-            latency_timeout: proxy_request_latency_timeout_histogram(gauge_factory, rpc),
-            latency_miss: proxy_request_latency_miss_histogram(gauge_factory, rpc),
-            latency_hit_mcache: proxy_request_latency_hit_histogram(gauge_factory, rpc, "mcache"),
-            latency_hit_momento: proxy_request_latency_hit_histogram(gauge_factory, rpc, "momento"),
+            tags: statsd::tags(base.iter().map(|(n, v)| (n.as_str(), v.as_str()))).into(),
+            latency_ok: proxy_request_latency_ok_histogram(gauge_factory, base, rpc),
+            latency_error: proxy_request_latency_error_histogram(gauge_factory, base, rpc),
+            latency_timeout: proxy_request_latency_timeout_histogram(gauge_factory, base, rpc),
+            latency_miss: proxy_request_latency_miss_histogram(gauge_factory, base, rpc),
+            latency_hit_mcache: proxy_request_latency_hit_histogram(
+                gauge_factory,
+                base,
+                rpc,
+                "mcache",
+            ),
+            latency_hit_momento: proxy_request_latency_hit_histogram(
+                gauge_factory,
+                base,
+                rpc,
+                "momento",
+            ),
+            latency_by_size,
+            bridged: bridge::rpc(rpc),
         }
     }
 
     pub fn record_api_call(&self) -> RpcCallGuard {
         RpcCallGuard::new(
             self.rpc,
+            self.tags.clone(),
             self.latency_ok.clone(),
             self.latency_error.clone(),
             self.latency_timeout.clone(),
             self.latency_miss.clone(),
             self.latency_hit_mcache.clone(),
             self.latency_hit_momento.clone(),
+            self.latency_by_size.clone(),
+            self.bridged.clone(),
         )
     }
 }
@@ -55,6 +113,7 @@ impl RpcMetrics {
 #[derive(Clone)]
 pub struct RpcCallGuard {
     rpc: &'static str,
+    tags: Arc<str>,
     start_time: Instant,
     latency_ok: HistogramHandle,
     latency_error: HistogramHandle,
@@ -62,21 +121,28 @@ pub struct RpcCallGuard {
     latency_miss: HistogramHandle,
     latency_hit_mcache: HistogramHandle,
     latency_hit_momento: HistogramHandle,
+    latency_by_size: Option<Arc<SizedLatency>>,
+    bridged: Arc<BridgedRpc>,
+    value_size: Option<usize>,
     recorded: Arc<AtomicBool>,
 }
 
 impl RpcCallGuard {
     pub fn new(
         rpc: &'static str,
+        tags: Arc<str>,
         latency_ok: HistogramHandle,
         latency_error: HistogramHandle,
         latency_timeout: HistogramHandle,
         latency_miss: HistogramHandle,
         latency_hit_mcache: HistogramHandle,
         latency_hit_momento: HistogramHandle,
+        latency_by_size: Option<Arc<SizedLatency>>,
+        bridged: Arc<BridgedRpc>,
     ) -> Self {
         Self {
             rpc,
+            tags,
             start_time: Instant::now(),
             latency_ok,
             latency_error,
@@ -84,17 +150,25 @@ impl RpcCallGuard {
             latency_miss,
             latency_hit_mcache,
             latency_hit_momento,
+            latency_by_size,
+            bridged,
+            value_size: None,
             recorded: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Sets the size of the value the call stored or returned, so that its
+    /// latency is also recorded by value size.
+    pub fn set_value_size(&mut self, bytes: usize) {
+        self.value_size = Some(bytes);
+    }
+
     pub fn complete_ok(&mut self) {
         if let Ok(false) =
             self.recorded
                 .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed)
         {
-            self.latency_ok
-                .observe(self.start_time.elapsed().as_nanos() as i64);
+            self.observe(&self.latency_ok, OK, "ok", None);
         }
     }
 
@@ -103,15 +177,25 @@ impl RpcCallGuard {
             self.recorded
                 .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed)
         {
-            self.latency_error
-                .observe(self.start_time.elapsed().as_nanos() as i64);
+            self.observe(&self.latency_error, ERROR, "error", None);
+        }
+    }
+
+    pub fn complete_timeout(&mut self) {
+        if let Ok(false) =
+            self.recorded
+                .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            debug!("{} complete_timeout", self.rpc);
+            self.observe(&self.latency_timeout, TIMEOUT, "timeout", None);
         }
     }
 
-    pub fn complete<T, E>(&mut self, result: &Result<T, E>) {
-        match result {
-            Ok(_) => self.complete_ok(),
-            Err(_) => self.complete_error(),
+    pub fn complete<T, E: RpcError>(&mut self, result: &Result<T, E>) {
+        match classify(result) {
+            RpcOutcome::Ok => self.complete_ok(),
+            RpcOutcome::Error => self.complete_error(),
+            RpcOutcome::Timeout => self.complete_timeout(),
         };
     }
 
@@ -122,8 +206,7 @@ impl RpcCallGuard {
         let _ = self
             .recorded
             .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed);
-        self.latency_miss
-            .observe(self.start_time.elapsed().as_nanos() as i64);
+        self.observe(&self.latency_miss, MISS, "miss", None);
     }
 
     pub fn complete_hit_mcache(&mut self) {
@@ -133,8 +216,7 @@ impl RpcCallGuard {
         let _ = self
             .recorded
             .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed);
-        self.latency_hit_mcache
-            .observe(self.start_time.elapsed().as_nanos() as i64);
+        self.observe(&self.latency_hit_mcache, HIT_MCACHE, "hit", Some("mcache"));
     }
 
     pub fn complete_hit_momento(&mut self) {
@@ -144,25 +226,115 @@ impl RpcCallGuard {
         let _ = self
             .recorded
             .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed);
-        self.latency_hit_momento
-            .observe(self.start_time.elapsed().as_nanos() as i64);
+        self.observe(
+            &self.latency_hit_momento,
+            HIT_MOMENTO,
+            "hit",
+            Some("momento"),
+        );
+    }
+
+    // records the latency of the call in `histogram` and in metriken, and as a
+    // timing if the statsd sink is running. Sampled calls are only counted,
+    // unless they are in the latency sample
+    fn observe(
+        &self,
+        histogram: &HistogramHandle,
+        outcome: usize,
+        result: &'static str,
+        source: Option<&str>,
+    ) {
+        let elapsed = self.start_time.elapsed();
+
+        // a batched call is counted by its thread, and only a sample of the
+        // sampled ones go on to record their latency
+        if batch::is_batched() {
+            if !batch::record_rpc(&self.bridged, outcome) {
+                return;
+            }
+            self.bridged.record_latency(elapsed);
+        } else {
+            self.bridged.record(outcome, elapsed);
+        }
+
+        histogram.observe(elapsed.as_nanos() as i64);
+
+        if let (Some(sized), Some(size)) = (&self.latency_by_size, self.value_size) {
+            sized[outcome][size_bucket(size)].observe(elapsed.as_nanos() as i64);
+        }
+
+        if statsd::is_enabled() {
+            let mut tags = statsd::tags([("rpc", self.rpc), ("result", result)]);
+            if let Some(source) = source {
+                tags.push_str(",source:");
+                tags.push_str(source);
+            }
+            if !self.tags.is_empty() {
+                tags.push(',');
+                tags.push_str(&self.tags);
+            }
+            statsd::timing("latency", elapsed, &tags);
+        }
     }
 }
 
 impl Drop for RpcCallGuard {
     fn drop(&mut self) {
-        if let Ok(false) =
-            self.recorded
-                .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed)
-        {
-            debug!("{} complete_timeout", self.rpc);
-            self.latency_timeout
-                .observe(self.start_time.elapsed().as_nanos() as i64);
+        // a call which never completed was abandoned, most likely by a timeout
+        self.complete_timeout();
+    }
+}
+
+/// Which latency histogram the result of a call is recorded in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RpcOutcome {
+    Ok,
+    Error,
+    Timeout,
+}
+
+/// Errors which can tell whether they were caused by a timeout.
+pub trait RpcError {
+    fn outcome(&self) -> RpcOutcome;
+}
+
+impl RpcError for ProxyError {
+    fn outcome(&self) -> RpcOutcome {
+        match self {
+            ProxyError::Timeout(_) => RpcOutcome::Timeout,
+            ProxyError::Momento(e) if matches!(e.error_code, MomentoErrorCode::TimeoutError) => {
+                RpcOutcome::Timeout
+            }
+            ProxyError::Momento(_)
+            | ProxyError::Io(_)
+            | ProxyError::Custom(_)
+            | ProxyError::UnsupportedCommand(_) => RpcOutcome::Error,
+        }
+    }
+}
+
+impl RpcError for std::io::Error {
+    fn outcome(&self) -> RpcOutcome {
+        if self.kind() == std::io::ErrorKind::TimedOut {
+            RpcOutcome::Timeout
+        } else {
+            RpcOutcome::Error
         }
     }
 }
 
-pub async fn with_rpc_call_guard<T, E, F>(mut recorder: RpcCallGuard, fut: F) -> Result<T, E>
+/// Classifies the result of a call for latency recording.
+pub fn classify<T, E: RpcError>(result: &Result<T, E>) -> RpcOutcome {
+    match result {
+        Ok(_) => RpcOutcome::Ok,
+        Err(e) => e.outcome(),
+    }
+}
+
+pub async fn with_rpc_call_guard<T, E: RpcError, F>(
+    mut recorder: RpcCallGuard,
+    fut: F,
+) -> Result<T, E>
 where
     F: Future<Output = Result<T, E>>,
 {
@@ -175,7 +347,7 @@ pub trait ResponseWrappingError {
     fn is_error(&self) -> bool;
 }
 
-pub async fn with_wrapped_error_response_rpc_call_guard<R: ResponseWrappingError, E, F>(
+pub async fn with_wrapped_error_response_rpc_call_guard<R: ResponseWrappingError, E: RpcError, F>(
     mut recorder: RpcCallGuard,
     fut: F,
 ) -> Result<R, E>
@@ -191,9 +363,10 @@ where
                 recorder.complete_ok();
             }
         }
-        Err(_) => {
-            recorder.complete_error();
-        }
+        Err(e) => match e.outcome() {
+            RpcOutcome::Timeout => recorder.complete_timeout(),
+            _ => recorder.complete_error(),
+        },
     }
     result
 }
@@ -0,0 +1,133 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Periodic sampling of tokio runtime health. Saturated workers or a growing
+//! injection queue point at the proxy itself rather than at the backend when
+//! latency rises.
+
+use std::time::Duration;
+
+use goodmetrics::{default_gauge_factory, GaugeDimensions, StatisticSetHandle, SumHandle};
+use metriken::{metric, Counter, Gauge};
+use tokio::runtime::Handle;
+
+#[metric(name = "runtime_worker_workers")]
+pub static RUNTIME_WORKER_WORKERS: Gauge = Gauge::new();
+
+#[metric(name = "runtime_worker_alive_tasks")]
+pub static RUNTIME_WORKER_ALIVE_TASKS: Gauge = Gauge::new();
+
+#[metric(name = "runtime_worker_global_queue_depth")]
+pub static RUNTIME_WORKER_GLOBAL_QUEUE_DEPTH: Gauge = Gauge::new();
+
+#[metric(name = "runtime_worker_busy_ns")]
+pub static RUNTIME_WORKER_BUSY_NS: Counter = Counter::new();
+
+#[metric(name = "runtime_admin_workers")]
+pub static RUNTIME_ADMIN_WORKERS: Gauge = Gauge::new();
+
+#[metric(name = "runtime_admin_alive_tasks")]
+pub static RUNTIME_ADMIN_ALIVE_TASKS: Gauge = Gauge::new();
+
+#[metric(name = "runtime_admin_global_queue_depth")]
+pub static RUNTIME_ADMIN_GLOBAL_QUEUE_DEPTH: Gauge = Gauge::new();
+
+#[metric(name = "runtime_admin_busy_ns")]
+pub static RUNTIME_ADMIN_BUSY_NS: Counter = Counter::new();
+
+/// The metriken statics for one runtime.
+pub struct RuntimeGauges {
+    pub workers: &'static Gauge,
+    pub alive_tasks: &'static Gauge,
+    pub global_queue_depth: &'static Gauge,
+    pub busy_ns: &'static Counter,
+}
+
+pub static WORKER_RUNTIME: RuntimeGauges = RuntimeGauges {
+    workers: &RUNTIME_WORKER_WORKERS,
+    alive_tasks: &RUNTIME_WORKER_ALIVE_TASKS,
+    global_queue_depth: &RUNTIME_WORKER_GLOBAL_QUEUE_DEPTH,
+    busy_ns: &RUNTIME_WORKER_BUSY_NS,
+};
+
+pub static ADMIN_RUNTIME: RuntimeGauges = RuntimeGauges {
+    workers: &RUNTIME_ADMIN_WORKERS,
+    alive_tasks: &RUNTIME_ADMIN_ALIVE_TASKS,
+    global_queue_depth: &RUNTIME_ADMIN_GLOBAL_QUEUE_DEPTH,
+    busy_ns: &RUNTIME_ADMIN_BUSY_NS,
+};
+
+struct RuntimeHandles {
+    workers: StatisticSetHandle,
+    alive_tasks: StatisticSetHandle,
+    global_queue_depth: StatisticSetHandle,
+    busy_ns: SumHandle,
+}
+
+impl RuntimeHandles {
+    fn new(runtime: &'static str) -> Self {
+        let g = default_gauge_factory();
+        let dimensions = || GaugeDimensions::new([("runtime", runtime)]);
+
+        Self {
+            workers: g.dimensioned_gauge_statistic_set(
+                "momento_proxy",
+                "runtime_workers",
+                dimensions(),
+            ),
+            alive_tasks: g.dimensioned_gauge_statistic_set(
+                "momento_proxy",
+                "runtime_alive_tasks",
+                dimensions(),
+            ),
+            global_queue_depth: g.dimensioned_gauge_statistic_set(
+                "momento_proxy",
+                "runtime_global_queue_depth",
+                dimensions(),
+            ),
+            busy_ns: g.dimensioned_gauge_sum("momento_proxy", "runtime_busy_ns", dimensions()),
+        }
+    }
+}
+
+/// Samples the metrics of the runtime behind `handle` every `interval`,
+/// publishing them to both metriken and goodmetrics under the `runtime` name.
+/// The blocking pool metrics are only available with `tokio_unstable`, so
+/// they are not collected.
+pub async fn monitor_runtime(
+    runtime: &'static str,
+    handle: Handle,
+    gauges: &'static RuntimeGauges,
+    interval: Duration,
+) {
+    let handles = RuntimeHandles::new(runtime);
+    let mut busy_ns = 0;
+
+    loop {
+        let metrics = handle.metrics();
+
+        let workers = metrics.num_workers();
+        let alive_tasks = metrics.num_alive_tasks();
+        let global_queue_depth = metrics.global_queue_depth();
+        let total_busy_ns: u64 = (0..workers)
+            .map(|worker| metrics.worker_total_busy_duration(worker).as_nanos() as u64)
+            .sum();
+
+        gauges.workers.set(workers as _);
+        gauges.alive_tasks.set(alive_tasks as _);
+        gauges.global_queue_depth.set(global_queue_depth as _);
+        gauges.busy_ns.add(total_busy_ns.saturating_sub(busy_ns));
+
+        handles.workers.observe(workers as _);
+        handles.alive_tasks.observe(alive_tasks as _);
+        handles.global_queue_depth.observe(global_queue_depth as _);
+        handles
+            .busy_ns
+            .observe(total_busy_ns.saturating_sub(busy_ns) as _);
+
+        busy_ns = total_busy_ns;
+
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Emits metrics to a local Datadog agent using the DogStatsD protocol over
+//! UDP. Request latencies are sent as timings as they are recorded, and the
+//! proxy's counters and gauges are sent periodically.
+
+use std::collections::HashMap;
+use std::net::UdpSocket;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use metriken::{Counter, Gauge};
+
+// set once the sink is running, nothing is sent otherwise
+static SINK: OnceLock<Sink> = OnceLock::new();
+
+struct Sink {
+    socket: UdpSocket,
+    prefix: String,
+    // tags added to every metric, already formatted
+    tags: String,
+}
+
+impl Sink {
+    fn send(&self, name: &str, value: &str, kind: &str, tags: &str) {
+        let mut datagram = format!("{}.{name}:{value}|{kind}", self.prefix);
+
+        if !self.tags.is_empty() || !tags.is_empty() {
+            datagram.push_str("|#");
+            datagram.push_str(&self.tags);
+            if !self.tags.is_empty() && !tags.is_empty() {
+                datagram.push(',');
+            }
+            datagram.push_str(tags);
+        }
+
+        // the agent is local and statsd is lossy by design, so a failed send is
+        // dropped rather than retried
+        let _ = self.socket.send(datagram.as_bytes());
+    }
+}
+
+/// Starts sending metrics to the agent at `address`, with every metric name
+/// prefixed by `prefix` and tagged with `tags`.
+pub(crate) fn start(
+    address: &str,
+    prefix: &str,
+    tags: &[String],
+    interval: Duration,
+) -> std::io::Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(address)?;
+    socket.set_nonblocking(true)?;
+
+    let sink = Sink {
+        socket,
+        prefix: prefix.to_string(),
+        tags: tags.join(","),
+    };
+
+    if SINK.set(sink).is_ok() {
+        tokio::spawn(report_forever(interval));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn is_enabled() -> bool {
+    SINK.get().is_some()
+}
+
+/// Sends a timing, in milliseconds.
+pub(crate) fn timing(name: &str, elapsed: Duration, tags: &str) {
+    if let Some(sink) = SINK.get() {
+        sink.send(
+            name,
+            &format!("{:.3}", elapsed.as_secs_f64() * 1000.0),
+            "ms",
+            tags,
+        );
+    }
+}
+
+/// Sends an increment of a counter.
+pub(crate) fn count(name: &str, value: u64, tags: &str) {
+    if let Some(sink) = SINK.get() {
+        sink.send(name, &value.to_string(), "c", tags);
+    }
+}
+
+/// Formats dimensions as DogStatsD tags.
+pub(crate) fn tags<'a>(dimensions: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    dimensions
+        .into_iter()
+        .map(|(name, value)| format!("{name}:{value}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// sends the proxy's counters, as the increase since the last report, and its
+// gauges every interval
+async fn report_forever(interval: Duration) {
+    let mut previous: HashMap<String, u64> = HashMap::new();
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let Some(sink) = SINK.get() else {
+            return;
+        };
+
+        for metric in &metriken::metrics() {
+            let Some(any) = metric.as_any() else {
+                continue;
+            };
+
+            if let Some(counter) = any.downcast_ref::<Counter>() {
+                let value = counter.value();
+                let last = previous
+                    .insert(metric.name().to_string(), value)
+                    .unwrap_or(0);
+                if value > last {
+                    sink.send(metric.name(), &(value - last).to_string(), "c", "");
+                }
+            } else if let Some(gauge) = any.downcast_ref::<Gauge>() {
+                sink.send(metric.name(), &gauge.value().to_string(), "g", "");
+            }
+        }
+    }
+}
@@ -1,27 +1,116 @@
 use goodmetrics::{GaugeDimensions, GaugeFactory, HistogramHandle, StatisticSetHandle, SumHandle};
 
-pub fn proxy_sum_gauge(g: &GaugeFactory, name: &'static str) -> SumHandle {
-    g.dimensioned_gauge_sum("momento_proxy", name, Default::default())
+// the base dimensions of a set of metrics, eg: the cache they are for, along
+// with any dimensions particular to the metric
+fn dimensions(
+    base: &[(String, String)],
+    extra: impl IntoIterator<Item = (&'static str, &'static str)>,
+) -> GaugeDimensions {
+    GaugeDimensions::new(
+        base.iter().cloned().chain(
+            extra
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        ),
+    )
+}
+
+/// Labels of the value size buckets for the `latency_by_size` histograms.
+pub const SIZE_BUCKETS: [&str; 4] = ["lt_1kb", "1kb_to_32kb", "32kb_to_1mb", "gt_1mb"];
+
+/// The index in `SIZE_BUCKETS` of the bucket for a value of `bytes`.
+pub fn size_bucket(bytes: usize) -> usize {
+    if bytes < 1024 {
+        0
+    } else if bytes < 32 * 1024 {
+        1
+    } else if bytes < 1024 * 1024 {
+        2
+    } else {
+        3
+    }
+}
+
+/// The errno dimension values of the `accept_errors` sums, errors other than
+/// these are counted as `other`.
+pub const ACCEPT_ERRNOS: [&str; 6] = [
+    "EMFILE",
+    "ENFILE",
+    "ENOBUFS",
+    "ENOMEM",
+    "ECONNABORTED",
+    "other",
+];
+
+/// The index in `ACCEPT_ERRNOS` of the errno of a failed accept.
+pub fn accept_errno(error: &std::io::Error) -> usize {
+    #[cfg(unix)]
+    let errnos = [
+        libc::EMFILE,
+        libc::ENFILE,
+        libc::ENOBUFS,
+        libc::ENOMEM,
+        libc::ECONNABORTED,
+    ];
+    #[cfg(not(unix))]
+    let errnos: [i32; 0] = [];
+
+    error
+        .raw_os_error()
+        .and_then(|errno| errnos.iter().position(|e| *e == errno))
+        .unwrap_or(ACCEPT_ERRNOS.len() - 1)
 }
 
-pub fn proxy_statistic_set_gauge(g: &GaugeFactory, name: &'static str) -> StatisticSetHandle {
-    g.dimensioned_gauge_statistic_set("momento_proxy", name, Default::default())
+pub fn proxy_sum_gauge(
+    g: &GaugeFactory,
+    base: &[(String, String)],
+    name: &'static str,
+) -> SumHandle {
+    g.dimensioned_gauge_sum("momento_proxy", name, dimensions(base, []))
+}
+
+pub fn proxy_statistic_set_gauge(
+    g: &GaugeFactory,
+    base: &[(String, String)],
+    name: &'static str,
+) -> StatisticSetHandle {
+    g.dimensioned_gauge_statistic_set("momento_proxy", name, dimensions(base, []))
+}
+
+/// A sum with one extra dimension.
+pub fn proxy_dimensioned_sum_gauge(
+    g: &GaugeFactory,
+    base: &[(String, String)],
+    name: &'static str,
+    dimension: (&'static str, &'static str),
+) -> SumHandle {
+    g.dimensioned_gauge_sum("momento_proxy", name, dimensions(base, [dimension]))
+}
+
+pub fn proxy_histogram(
+    g: &GaugeFactory,
+    base: &[(String, String)],
+    name: &'static str,
+) -> HistogramHandle {
+    g.dimensioned_gauge_histogram("momento_proxy", name, dimensions(base, []))
 }
 
 fn proxy_request_latency_histogram(
     gauge_factory: &GaugeFactory,
+    base: &[(String, String)],
     rpc: &'static str,
     result: &'static str,
 ) -> HistogramHandle {
     gauge_factory.dimensioned_gauge_histogram(
         "momento_proxy",
         "latency",
-        GaugeDimensions::new([("rpc", rpc), ("result", result)]),
+        dimensions(base, [("rpc", rpc), ("result", result)]),
     )
 }
 
 fn proxy_hit_response_latency_histogram(
     gauge_factory: &GaugeFactory,
+    base: &[(String, String)],
     rpc: &'static str,
     result: &'static str,
     source: &'static str, // momento or mcache
@@ -29,39 +118,68 @@ fn proxy_hit_response_latency_histogram(
     gauge_factory.dimensioned_gauge_histogram(
         "momento_proxy",
         "latency",
-        GaugeDimensions::new([("rpc", rpc), ("result", result), ("source", source)]),
+        dimensions(base, [("rpc", rpc), ("result", result), ("source", source)]),
     )
 }
 
 pub fn proxy_request_latency_hit_histogram(
     g: &GaugeFactory,
+    base: &[(String, String)],
     rpc: &'static str,
     source: &'static str,
 ) -> HistogramHandle {
-    proxy_hit_response_latency_histogram(g, rpc, "hit", source)
+    proxy_hit_response_latency_histogram(g, base, rpc, "hit", source)
 }
 
 pub fn proxy_request_latency_miss_histogram(
     g: &GaugeFactory,
+    base: &[(String, String)],
     rpc: &'static str,
 ) -> HistogramHandle {
-    proxy_request_latency_histogram(g, rpc, "miss")
+    proxy_request_latency_histogram(g, base, rpc, "miss")
 }
 
-pub fn proxy_request_latency_ok_histogram(g: &GaugeFactory, rpc: &'static str) -> HistogramHandle {
-    proxy_request_latency_histogram(g, rpc, "ok")
+pub fn proxy_request_latency_ok_histogram(
+    g: &GaugeFactory,
+    base: &[(String, String)],
+    rpc: &'static str,
+) -> HistogramHandle {
+    proxy_request_latency_histogram(g, base, rpc, "ok")
 }
 
 pub fn proxy_request_latency_error_histogram(
     gauge_factory: &GaugeFactory,
+    base: &[(String, String)],
     rpc: &'static str,
 ) -> HistogramHandle {
-    proxy_request_latency_histogram(gauge_factory, rpc, "error")
+    proxy_request_latency_histogram(gauge_factory, base, rpc, "error")
 }
 
 pub fn proxy_request_latency_timeout_histogram(
     gauge_factory: &GaugeFactory,
+    base: &[(String, String)],
     rpc: &'static str,
 ) -> HistogramHandle {
-    proxy_request_latency_histogram(gauge_factory, rpc, "timeout")
+    proxy_request_latency_histogram(gauge_factory, base, rpc, "timeout")
+}
+
+/// A latency histogram for each of the `SIZE_BUCKETS`.
+pub fn proxy_request_latency_size_histograms(
+    gauge_factory: &GaugeFactory,
+    base: &[(String, String)],
+    rpc: &'static str,
+    result: &'static str,
+    source: Option<&'static str>,
+) -> [HistogramHandle; 4] {
+    SIZE_BUCKETS.map(|size| {
+        let mut extra = vec![("rpc", rpc), ("result", result), ("size", size)];
+        if let Some(source) = source {
+            extra.push(("source", source));
+        }
+        gauge_factory.dimensioned_gauge_histogram(
+            "momento_proxy",
+            "latency_by_size",
+            dimensions(base, extra),
+        )
+    })
 }
@@ -0,0 +1,241 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Policies applied to each RESP command before it is dispatched. A policy
+//! implements [`Middleware`] and is added to the [`Chain`] of a listener in
+//! [`Chain::new`], so that a new policy doesn't need to be added to each
+//! command handler.
+//!
+//! The chain sees the command before it is parsed, so it applies to the
+//! commands the proxy serves itself as well as to those sent to Momento.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::connections::Connection;
+use crate::enforce::{self, Policy};
+use crate::protocol::resp::Command;
+use crate::quota::{Admission, Quotas};
+use crate::*;
+
+#[metric(name = "middleware_denied")]
+pub static MIDDLEWARE_DENIED: Counter = Counter::new();
+
+#[metric(name = "middleware_rate_limited")]
+pub static MIDDLEWARE_RATE_LIMITED: Counter = Counter::new();
+
+#[metric(name = "middleware_audited")]
+pub static MIDDLEWARE_AUDITED: Counter = Counter::new();
+
+/// A command about to be dispatched.
+pub(crate) struct RequestContext<'a> {
+    pub connection: &'a Connection,
+    /// the uppercased name of the command
+    pub name: &'a str,
+    /// the command, which may be rewritten
+    pub command: &'a mut Command,
+}
+
+/// Whether a command is served.
+pub(crate) enum Decision {
+    /// the command is passed on to the rest of the chain
+    Continue,
+    /// the command is passed on to the rest of the chain, and dispatched
+    /// after waiting
+    Throttle(Duration),
+    /// the command is answered with this error, which starts with its prefix
+    Reject(String),
+}
+
+/// A policy applied to each command before it is dispatched.
+pub(crate) trait Middleware: Send + Sync {
+    fn on_request(&self, ctx: &mut RequestContext) -> Decision;
+}
+
+/// The middleware of a listener, applied in order until one rejects the
+/// command.
+#[derive(Default)]
+pub(crate) struct Chain {
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl Chain {
+    /// The chain configured for the cache. The command is audited before any
+    /// policy can reject it. Keys are rewritten by the frontend once the chain
+    /// admits the command, so that every middleware sees the keys the client
+    /// sent.
+    pub fn new(cache: &momento_proxy::Cache, quotas: Arc<Quotas>) -> Self {
+        let mut chain = Self::default();
+
+        let audited = cache.audit_commands();
+        if !audited.is_empty() {
+            chain.push(Audit {
+                commands: audited.into_iter().collect(),
+            });
+        }
+
+        let denied = cache.denied_commands();
+        if !denied.is_empty() {
+            chain.push(Acl {
+                denied: denied.into_iter().collect(),
+            });
+        }
+
+        if cache.rate_limit() > 0 {
+            chain.push(RateLimit::new(cache.rate_limit()));
+        }
+
+        if !quotas.is_empty() {
+            chain.push(Quota(quotas));
+        }
+
+        chain
+    }
+
+    /// Adds a middleware to the end of the chain.
+    pub fn push(&mut self, middleware: impl Middleware + 'static) {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.middleware.is_empty()
+    }
+
+    /// Applies the chain to the command. The command waits for the longest of
+    /// the throttles of the middleware which let it through.
+    pub fn on_request(&self, connection: &Connection, command: &mut Command) -> Decision {
+        let name = command.name();
+        let mut ctx = RequestContext {
+            connection,
+            name: &name,
+            command,
+        };
+
+        let mut wait = Duration::ZERO;
+        for middleware in &self.middleware {
+            match middleware.on_request(&mut ctx) {
+                Decision::Continue => {}
+                Decision::Throttle(delay) => wait = wait.max(delay),
+                Decision::Reject(error) => return Decision::Reject(error),
+            }
+        }
+
+        if wait.is_zero() {
+            Decision::Continue
+        } else {
+            Decision::Throttle(wait)
+        }
+    }
+}
+
+/// Writes the configured commands to the proxy log with the client which
+/// sent them.
+struct Audit {
+    commands: HashSet<String>,
+}
+
+impl Middleware for Audit {
+    fn on_request(&self, ctx: &mut RequestContext) -> Decision {
+        if self.commands.contains(ctx.name) {
+            MIDDLEWARE_AUDITED.increment();
+
+            let arg = ctx
+                .command
+                .args()
+                .first()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .unwrap_or_default();
+            info!(
+                "audit: {} {arg} from {} name={}",
+                ctx.name,
+                ctx.connection.addr(),
+                ctx.connection.name().as_deref().unwrap_or("")
+            );
+        }
+
+        Decision::Continue
+    }
+}
+
+/// Refuses the configured commands.
+struct Acl {
+    denied: HashSet<String>,
+}
+
+impl Middleware for Acl {
+    fn on_request(&self, ctx: &mut RequestContext) -> Decision {
+        if !self.denied.contains(ctx.name)
+            || !enforce::enforce(Policy::Acl, || {
+                format!("{} from {}", ctx.name, ctx.connection.addr())
+            })
+        {
+            return Decision::Continue;
+        }
+
+        MIDDLEWARE_DENIED.increment();
+        Decision::Reject(format!(
+            "NOPERM this proxy does not allow the '{}' command",
+            ctx.name.to_ascii_lowercase()
+        ))
+    }
+}
+
+/// Limits the commands per second across every connection of the listener,
+/// allowing a burst of up to a second's worth.
+struct RateLimit {
+    rate: f64,
+    // the tokens available, and when they were last refilled
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimit {
+    fn new(rate: u64) -> Self {
+        let rate = rate as f64;
+        Self {
+            rate,
+            bucket: Mutex::new((rate, Instant::now())),
+        }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn on_request(&self, ctx: &mut RequestContext) -> Decision {
+        let mut bucket = self.bucket.lock().unwrap();
+        let (tokens, refilled) = &mut *bucket;
+
+        let now = Instant::now();
+        *tokens =
+            (*tokens + now.duration_since(*refilled).as_secs_f64() * self.rate).min(self.rate);
+        *refilled = now;
+
+        if *tokens < 1.0 {
+            if !enforce::enforce(Policy::RateLimit, || {
+                format!("{} from {}", ctx.name, ctx.connection.addr())
+            }) {
+                return Decision::Continue;
+            }
+            MIDDLEWARE_RATE_LIMITED.increment();
+            return Decision::Reject("ERR rate limit exceeded, try again later".to_string());
+        }
+
+        *tokens -= 1.0;
+        Decision::Continue
+    }
+}
+
+/// Applies the quotas of the prefixes of the command's keys. Commands served
+/// by the proxy, which have no keys, aren't counted.
+struct Quota(Arc<Quotas>);
+
+impl Middleware for Quota {
+    fn on_request(&self, ctx: &mut RequestContext) -> Decision {
+        let bytes = ctx.command.args().iter().map(Vec::len).sum();
+        match self.0.admit(ctx.command.keys(), bytes) {
+            Admission::Admit => Decision::Continue,
+            Admission::Throttle(delay) => Decision::Throttle(delay),
+            Admission::Reject(error) => Decision::Reject(format!("ERR {error}")),
+        }
+    }
+}
@@ -1,8 +1,14 @@
+use crate::cache::AdmissionPolicy;
 use crate::default_buffer_size;
-use crate::PAGESIZE;
+use crate::keys::KeyTransform;
+use crate::pagesize;
+use crate::policy::{KeyPolicy, KeyRule};
+use crate::quota::QuotaConfig;
+use crate::routing::RoutingPolicy;
+use crate::MAX_REQUEST_SIZE;
 use core::num::NonZeroU64;
-use std::net::AddrParseError;
-use std::net::SocketAddr;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
 use std::num::NonZeroUsize;
 use std::time::Duration;
 
@@ -29,20 +35,246 @@ impl Default for Protocol {
     }
 }
 
+/// How the proxy responds to memcache commands it does not support.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnsupportedCommandBehavior {
+    /// reply with `ERROR` (or the binary protocol equivalent) and continue
+    ClientError,
+    /// close the connection
+    Hangup,
+}
+
+impl Default for UnsupportedCommandBehavior {
+    fn default() -> Self {
+        Self::ClientError
+    }
+}
+
+/// Whether the policies which reject requests enforce them.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyMode {
+    /// reject the requests which violate a policy
+    Enforce,
+    /// count and log the requests which violate a policy, and serve them
+    Observe,
+}
+
+impl Default for PolicyMode {
+    fn default() -> Self {
+        Self::Enforce
+    }
+}
+
+/// How values are stored in Momento. Listeners which share a Momento cache
+/// must store values the same way, whatever flags they accept from clients.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueFormat {
+    /// four bytes of memcache flags ahead of the value
+    Flags,
+    /// the value alone
+    Raw,
+}
+
+impl ValueFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValueFormat::Flags => "flags",
+            ValueFormat::Raw => "raw",
+        }
+    }
+}
+
+/// Whether memcache values are compressed before they are stored in Momento.
+/// Listeners which share a Momento cache must compress values the same way.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueCompression {
+    /// values are stored as they are
+    None,
+    /// values are compressed with zstd dictionaries trained on the values
+    /// stored through the proxy
+    ZstdDictionary,
+}
+
+impl Default for ValueCompression {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl ValueCompression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValueCompression::None => "none",
+            ValueCompression::ZstdDictionary => "zstd_dictionary",
+        }
+    }
+}
+
+/// How a multi-key memcache get responds when the backend fails for some of
+/// the keys.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiGetFailurePolicy {
+    /// reply with `SERVER_ERROR`
+    Fail,
+    /// omit the failed keys, as if they were misses
+    Partial,
+    /// serve the failed keys from recently expired memory cache entries when
+    /// possible, otherwise omit them
+    PartialWithStale,
+}
+
+impl Default for MultiGetFailurePolicy {
+    fn default() -> Self {
+        Self::Fail
+    }
+}
+
+/// How a memcache set responds when Momento stores the item but the memory
+/// cache could not.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryCacheWriteFailurePolicy {
+    /// reply `STORED`, the memory cache no longer holds an entry for the key
+    Ignore,
+    /// reply with `SERVER_ERROR`
+    ServerError,
+}
+
+impl Default for MemoryCacheWriteFailurePolicy {
+    fn default() -> Self {
+        Self::Ignore
+    }
+}
+
+/// Which entries the memory cache evicts when it is full.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryCacheEviction {
+    /// admits and evicts by recent frequency as well as recency, which keeps
+    /// hot entries from being displaced by a scan
+    #[default]
+    TinyLfu,
+    /// evicts the least recently used entry
+    Lru,
+}
+
+/// How keys are transformed before they are sent to Momento.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyTransformMode {
+    /// keys are sent as is
+    None,
+    /// keys are replaced by their HMAC-SHA256
+    HmacSha256,
+    /// keys keep their prefix up to the last `key_transform_delimiter`, and
+    /// the rest is replaced by its HMAC-SHA256
+    PrefixHmacSha256,
+}
+
+impl Default for KeyTransformMode {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+/// Which Momento collection serves resp sorted set commands.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ZsetBackend {
+    /// momento sorted sets
+    SortedSet,
+    /// momento leaderboards, which hold more elements but support fewer
+    /// commands
+    Leaderboard,
+}
+
+impl Default for ZsetBackend {
+    fn default() -> Self {
+        Self::SortedSet
+    }
+}
+
+/// The kinds of command which may have their own backend timeout.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandClass {
+    /// reads of a single key or field, such as get and hget
+    Read,
+    /// writes and deletes
+    Write,
+    /// reads of a whole collection or of several keys, such as hgetall,
+    /// lrange, and sunion
+    Range,
+}
+
+/// A Momento preview API, which is only used once it is both compiled in with
+/// its Cargo feature and listed in `preview_apis`.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewApi {
+    /// leaderboards, for `zset_backend = "leaderboard"`
+    Leaderboards,
+}
+
+impl PreviewApi {
+    /// The name of the API in `preview_apis`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Leaderboards => "leaderboards",
+        }
+    }
+
+    /// The Cargo feature which compiles the API in
+    pub fn feature(&self) -> &'static str {
+        match self {
+            Self::Leaderboards => "preview-leaderboards",
+        }
+    }
+
+    /// Whether this build includes the API
+    pub fn is_compiled(&self) -> bool {
+        match self {
+            Self::Leaderboards => cfg!(feature = "preview-leaderboards"),
+        }
+    }
+}
+
 // support for memcache flags is on by default
 fn flags() -> bool {
     true
 }
 
+/// The current version of the config schema. Configs without a
+/// `config_version` are version 1.
+pub const CONFIG_VERSION: u64 = 1;
+
+// keys which were renamed, as the schema version which renamed them, the
+// section, the old key, and the new key. A key is only renamed once it has
+// been deprecated
+const RENAMED_KEYS: &[RenamedKey] = &[];
+
+type RenamedKey = (u64, &'static str, &'static str, &'static str);
+
 // struct definitions
-#[derive(Clone, Serialize, Default, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct MomentoProxyConfig {
+    /// schema version of the config, older configs are migrated on load
+    #[serde(default)]
+    config_version: u64,
+
     // application modules
     #[serde(default)]
     admin: Admin,
     #[serde(default)]
     proxy: Proxy,
     #[serde(default)]
+    metrics: Metrics,
+    #[serde(default)]
     cache: Vec<Cache>,
     #[serde(default)]
     debug: Debug,
@@ -50,43 +282,674 @@ pub struct MomentoProxyConfig {
     klog: Klog,
 }
 
-#[derive(Default, Clone, Copy, Serialize, Deserialize, Debug)]
+#[derive(Default, Clone, Serialize, Deserialize, Debug)]
 pub struct Proxy {
     threads: Option<usize>,
+    /// terminate the process on any panic instead of only closing the
+    /// connection whose task panicked
+    #[serde(default)]
+    abort_on_panic: bool,
+    /// whether `denied_commands`, `rate_limit`, and `max_item_size` reject
+    /// the requests which violate them, or only count and log them
+    #[serde(default)]
+    policy_mode: PolicyMode,
+    /// identifies this instance in klog lines, metrics, and the `stats` and
+    /// `version` responses
+    #[serde(default)]
+    instance_id: Option<String>,
+    /// requests for keys with this prefix are always written to the command
+    /// log and traced in the proxy log
+    #[serde(default)]
+    trace_key_prefix: Option<String>,
+    /// the server name reported by resp `HELLO` and `INFO`
+    #[serde(default)]
+    server_name: Option<String>,
+    /// the server version reported to clients, which some clients check
+    /// before using a feature
+    #[serde(default)]
+    server_version: Option<String>,
+    /// serve the caches which passed the startup checks even if others failed
+    #[serde(default)]
+    partial_start: bool,
+    /// optional file the startup report is written to as JSON
+    #[serde(default)]
+    startup_report_file: Option<String>,
+    /// export one in every `n` command log entries as OTLP log records to the
+    /// metrics collector, 0 to disable
+    #[serde(default)]
+    klog_otlp_sample: u64,
+    /// serve each listener from one single threaded runtime per worker, each
+    /// accepting its own connections with `SO_REUSEPORT`, instead of from the
+    /// shared work-stealing runtime
+    #[serde(default)]
+    pinned_workers: bool,
+    /// serve connections from io_uring workers, which read and write the
+    /// sockets through io_uring rather than epoll. Needs a linux build with
+    /// the `io-uring` Cargo feature
+    #[serde(default)]
+    io_uring: bool,
+    /// momento preview APIs to use, each of which must also be compiled in
+    /// with its cargo feature
+    #[serde(default)]
+    preview_apis: Vec<PreviewApi>,
+    /// file the audit log of writes and deletes is appended to
+    #[serde(default)]
+    audit_log_file: Option<String>,
+    /// only keys with one of these prefixes are audited, every key if empty
+    #[serde(default)]
+    audit_key_prefixes: Vec<String>,
+    /// reject new connections while more than this percent of the file
+    /// descriptor limit is open, 0 to never reject them
+    #[serde(default)]
+    fd_high_water_percent: u8,
+    /// path of a unix socket the admin listener binds instead of the address
+    /// in the `[admin]` section
+    #[serde(default)]
+    admin_unix_socket: Option<String>,
+    /// token admin clients must send with `auth <token>` before any other
+    /// command, defaults to the `ADMIN_AUTH_TOKEN` environment variable
+    #[serde(default, serialize_with = "redact")]
+    admin_auth_token: Option<String>,
+    /// PEM certificate chain and key the admin listener serves TLS with
+    #[serde(default)]
+    admin_tls_cert_file: Option<String>,
+    #[serde(default)]
+    admin_tls_key_file: Option<String>,
+}
+
+/// The wire protocol used to export metrics.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OtlpProtocol {
+    Grpc,
+    HttpProtobuf,
+}
+
+impl Default for OtlpProtocol {
+    fn default() -> Self {
+        Self::Grpc
+    }
+}
+
+/// How the counters updated by each request are aggregated.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricsAggregation {
+    /// every update goes straight to the shared counters
+    #[default]
+    Global,
+    /// each thread holds its counts and adds them to the shared counters
+    /// every 100ms, or when it goes idle
+    ThreadLocal,
+}
+
+/// Where and how metrics are exported. Without an `endpoint`, the exporter is
+/// configured from the `OTLP_ENDPOINT` and `OTLP_API_TOKEN` environment
+/// variables.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Metrics {
+    /// OTLP collector endpoint, the full url including the path (eg:
+    /// `/v1/metrics`) for `http_protobuf`
+    #[serde(default)]
+    endpoint: Option<String>,
+    #[serde(default)]
+    protocol: OtlpProtocol,
+    /// PEM bundle of CAs trusted for the collector, in addition to the
+    /// default roots
+    #[serde(default)]
+    ca_file: Option<String>,
+    /// PEM client certificate and key for mutual TLS
+    #[serde(default)]
+    cert_file: Option<String>,
+    #[serde(default)]
+    key_file: Option<String>,
+    /// headers sent with every export request, which may carry credentials
+    #[serde(default, serialize_with = "redact_values")]
+    headers: BTreeMap<String, String>,
+    #[serde(default = "export_interval_ms")]
+    export_interval_ms: u64,
+    /// address of a DogStatsD agent to send metrics to, alongside or instead
+    /// of OTLP
+    #[serde(default)]
+    statsd_address: Option<String>,
+    /// prefix for the names of the metrics sent to the agent
+    #[serde(default = "statsd_prefix")]
+    statsd_prefix: String,
+    /// tags added to every metric sent to the agent, eg: `env:prod`
+    #[serde(default)]
+    statsd_tags: Vec<String>,
+    /// how the counters of each request are aggregated, `thread_local` avoids
+    /// contention on the shared counters at very high request rates
+    #[serde(default)]
+    aggregation: MetricsAggregation,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            protocol: Default::default(),
+            ca_file: None,
+            cert_file: None,
+            key_file: None,
+            headers: Default::default(),
+            export_interval_ms: export_interval_ms(),
+            statsd_address: None,
+            statsd_prefix: statsd_prefix(),
+            statsd_tags: Vec::new(),
+            aggregation: Default::default(),
+        }
+    }
+}
+
+fn export_interval_ms() -> u64 {
+    1000
+}
+
+fn statsd_prefix() -> String {
+    "momento_proxy".to_string()
+}
+
+impl Metrics {
+    /// The collector endpoint, if the exporter is configured here rather than
+    /// from the environment
+    pub fn endpoint(&self) -> Option<&str> {
+        self.endpoint.as_deref()
+    }
+
+    pub fn protocol(&self) -> OtlpProtocol {
+        self.protocol
+    }
+
+    /// Path of a PEM bundle of additional trusted CAs
+    pub fn ca_file(&self) -> Option<&str> {
+        self.ca_file.as_deref()
+    }
+
+    /// Paths of the PEM client certificate and key, if both are set
+    pub fn client_identity(&self) -> Option<(&str, &str)> {
+        match (&self.cert_file, &self.key_file) {
+            (Some(cert), Some(key)) => Some((cert.as_str(), key.as_str())),
+            _ => None,
+        }
+    }
+
+    /// Whether only one of the client certificate and key is set
+    pub fn client_identity_incomplete(&self) -> bool {
+        self.cert_file.is_some() != self.key_file.is_some()
+    }
+
+    /// Headers sent with every export request
+    pub fn headers(&self) -> &BTreeMap<String, String> {
+        &self.headers
+    }
+
+    /// How often metrics are exported
+    pub fn export_interval(&self) -> Duration {
+        Duration::from_millis(std::cmp::max(1, self.export_interval_ms))
+    }
+
+    /// Address of the DogStatsD agent, if metrics are sent to one
+    pub fn statsd_address(&self) -> Option<&str> {
+        self.statsd_address.as_deref()
+    }
+
+    pub fn statsd_prefix(&self) -> &str {
+        &self.statsd_prefix
+    }
+
+    pub fn statsd_tags(&self) -> &[String] {
+        &self.statsd_tags
+    }
+
+    /// How the counters of each request are aggregated
+    pub fn aggregation(&self) -> MetricsAggregation {
+        self.aggregation
+    }
+}
+
+/// The hosts a cache listens on, each either an address or a hostname.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Hosts {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Hosts {
+    fn iter(&self) -> impl Iterator<Item = &str> {
+        let hosts = match self {
+            Self::One(host) => std::slice::from_ref(host),
+            Self::Many(hosts) => hosts.as_slice(),
+        };
+        // an ipv6 literal may be given in brackets
+        hosts
+            .iter()
+            .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+    }
 }
 
 // definitions
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Cache {
-    host: String,
+    /// a host to listen on, or a list of them
+    host: Hosts,
     port: String,
+    /// the host which clients are told to connect to, for a proxy behind NAT
+    /// or a load balancer. Defaults to `host`
+    #[serde(default)]
+    advertised_host: Option<String>,
+    /// the port which clients are told to connect to. Defaults to `port`
+    #[serde(default)]
+    advertised_port: Option<String>,
+    /// whether an ipv6 listener only accepts ipv6 connections. When false, a
+    /// listener on `::` also accepts ipv4. Defaults to the system setting
+    #[serde(default)]
+    ipv6_only: Option<bool>,
     cache_name: String,
     default_ttl: NonZeroU64,
     #[serde(default = "four")]
     connection_count: NonZeroUsize,
+    /// requests sent to momento by each listener before it accepts
+    /// connections, so that its client's channels are established first. 0
+    /// to accept connections right away
+    #[serde(default)]
+    client_warmup_requests: usize,
+    /// bind the listening sockets on startup but only listen once the client
+    /// warm-up completes, so that clients are refused rather than queued
+    #[serde(default)]
+    listen_after_client_warmup: bool,
     #[serde(default)]
     protocol: Protocol,
     #[serde(default = "flags")]
     flags: bool,
+    /// how values are stored in momento, by default with the flags ahead of
+    /// the value if `flags` (or for resp, `resp_memcache_flags`) is set
+    #[serde(default)]
+    value_format: Option<ValueFormat>,
+    /// whether memcache values are compressed with trained zstd dictionaries
+    #[serde(default)]
+    value_compression: ValueCompression,
+    /// how often a new compression dictionary is trained from the sampled
+    /// values
+    #[serde(default = "compression_dictionary_interval_seconds")]
+    compression_dictionary_interval_seconds: u64,
+    /// the largest compression dictionary trained
+    #[serde(default = "compression_dictionary_bytes")]
+    compression_dictionary_bytes: usize,
+    /// how long compression dictionaries are kept in momento, which should be
+    /// at least the longest ttl of the values compressed with them
+    #[serde(default = "compression_dictionary_ttl_seconds")]
+    compression_dictionary_ttl_seconds: u64,
     /// 0 to disable
     #[serde(default)]
     memory_cache_bytes: usize,
     /// 0 means no expiration
     #[serde(default)]
     memory_cache_ttl_seconds: u64,
+    /// initial size of each connection buffer, which is also the step it
+    /// grows by
     #[serde(default = "default_buffer_size")]
     buffer_size: NonZeroUsize,
+    /// largest request accepted from a client, a larger request gets an error
+    /// response and the connection is closed
+    #[serde(default = "max_request_size")]
+    max_request_size: NonZeroUsize,
+    /// how long a connection must be idle before a grown read buffer is
+    /// returned to `buffer_size`, 0 to shrink as soon as it is empty
+    #[serde(default)]
+    buffer_shrink_idle_seconds: u64,
+    /// seconds a client connection is idle before tcp keepalive probes are
+    /// sent, 0 to leave keepalive off
+    #[serde(default)]
+    tcp_keepalive_seconds: u64,
+    /// the most connections waiting to be accepted by each listener
+    #[serde(default = "listen_backlog")]
+    listen_backlog: u32,
+    /// when accept fails for lack of file descriptors, close the connection
+    /// which has been idle the longest to free one
+    #[serde(default)]
+    accept_shed_idle: bool,
+    /// seconds without a read before a client connection is closed, 0 to keep
+    /// idle connections open
+    #[serde(default)]
+    idle_timeout_seconds: u64,
+    /// log the summary of each connection as it closes at info rather than
+    /// debug level
+    #[serde(default)]
+    connection_summary_log: bool,
+    /// optional file of newline-delimited keys to load into the memory cache
+    /// at startup
+    #[serde(default)]
+    warmup_key_file: Option<String>,
+    /// maximum warmup requests per second, 0 for unlimited
+    #[serde(default = "warmup_rate")]
+    warmup_rate: u64,
+    /// optional file used to persist the memory cache across restarts
+    #[serde(default)]
+    memory_cache_snapshot_file: Option<String>,
+    /// largest value admitted to the memory cache, 0 for no limit
+    #[serde(default)]
+    memory_cache_max_value_bytes: usize,
+    /// if set, only keys with one of these prefixes use the memory cache
+    #[serde(default)]
+    memory_cache_include_prefixes: Vec<String>,
+    /// keys with one of these prefixes never use the memory cache
+    #[serde(default)]
+    memory_cache_exclude_prefixes: Vec<String>,
+    /// only admit keys to the memory cache once they have been seen recently
+    #[serde(default)]
+    memory_cache_frequency_filter: bool,
+    /// which entries the memory cache evicts when it is full
+    #[serde(default)]
+    memory_cache_eviction: MemoryCacheEviction,
+    /// write the keys which leave the memory cache to the command log
+    #[serde(default)]
+    memory_cache_eviction_log: bool,
+    /// how to respond to memcache commands which are not supported
+    #[serde(default)]
+    unsupported_command_behavior: UnsupportedCommandBehavior,
+    /// how a multi-key get handles backend failures for some of the keys
+    #[serde(default)]
+    multi_get_failure_policy: MultiGetFailurePolicy,
+    /// the most backend requests in flight for a single multi-key get
+    #[serde(default = "multi_get_concurrency")]
+    multi_get_concurrency: NonZeroUsize,
+    /// multi-key gets for more keys than this are rejected, unlimited if unset
+    #[serde(default)]
+    multi_get_max_keys: Option<NonZeroUsize>,
+    /// how long expired memory cache entries are kept for the
+    /// `partial_with_stale` policy, 0 to disable
+    #[serde(default)]
+    memory_cache_stale_seconds: u64,
+    /// whether memory cache entries filled from momento hits expire with the
+    /// item in momento, at the cost of reading the ttl on each backend get
+    #[serde(default = "memory_cache_preserve_ttl")]
+    memory_cache_preserve_ttl: bool,
+    /// most requests a memcache connection may have awaiting a response
+    /// before the proxy stops reading from it, 0 for no limit
+    #[serde(default)]
+    max_pipeline_depth: usize,
+    /// most responses a memcache connection may have queued, the proxy stops
+    /// reading from it once the queue is full
+    #[serde(default = "response_queue_limit")]
+    response_queue_limit: usize,
+    /// how long a full response queue may go without draining before the
+    /// connection is closed with an error, in milliseconds
+    #[serde(default = "response_queue_timeout_ms")]
+    response_queue_timeout_ms: u64,
+    /// momento caches to spread the keys across, in place of `cache_name`
+    #[serde(default)]
+    shards: Vec<String>,
+    /// file with the prefix routes to other pools of caches
+    #[serde(default)]
+    routing_policy_file: Option<String>,
+    /// per-key overrides of the ttl, cache and memory cache admission, the
+    /// first rule whose pattern matches a key applies
+    #[serde(default)]
+    key_rules: Vec<KeyRule>,
+    /// limits and usage counters for the keys under each prefix, the first
+    /// quota whose prefix a key starts with applies to it
+    #[serde(default)]
+    quotas: Vec<QuotaConfig>,
+    /// how a set responds when the memory cache could not store the item
+    #[serde(default)]
+    memory_cache_write_failure_policy: MemoryCacheWriteFailurePolicy,
+    /// how often a sample of memory cache entries is compared against
+    /// momento, 0 to disable
+    #[serde(default)]
+    memory_cache_consistency_interval_seconds: u64,
+    /// number of memory cache entries compared each interval
+    #[serde(default = "memory_cache_consistency_sample_size")]
+    memory_cache_consistency_sample_size: usize,
+    /// how often momento is probed to report the state of the channel to it,
+    /// 0 to disable
+    #[serde(default)]
+    backend_probe_interval_seconds: u64,
+    /// extra dimensions added to the exported metrics for this cache
+    #[serde(default)]
+    metric_dimensions: BTreeMap<String, String>,
+    /// also record memcache get and set latencies by value size
+    #[serde(default)]
+    latency_by_value_size: bool,
+    /// batch the metrics of noreply sets and deletes, which only samples their
+    /// latency
+    #[serde(default)]
+    noreply_metrics_batching: bool,
+    /// check that a key exists before deleting it, so that a missing key gets
+    /// `NOT_FOUND` rather than `DELETED`
+    #[serde(default)]
+    strict_delete: bool,
+    /// let memcache text clients change runtime settings of the listener with
+    /// `proxy_config` commands
+    #[serde(default)]
+    proxy_config_commands: bool,
+    /// how keys are transformed before they are sent to momento
+    #[serde(default)]
+    key_transform: KeyTransformMode,
+    /// secret for the key transform, defaults to the `KEY_TRANSFORM_SECRET`
+    /// environment variable
+    #[serde(default, serialize_with = "redact")]
+    key_transform_secret: Option<String>,
+    /// the prefix kept by `prefix_hmac_sha256` ends at the last occurrence of
+    /// this character
+    #[serde(default = "key_transform_delimiter")]
+    key_transform_delimiter: char,
+    /// emulate the CH, XX, NX, GT, and LT arguments of ZADD by reading the
+    /// current scores first, which is not atomic
+    #[serde(default)]
+    zadd_conditional_emulation: bool,
+    /// the largest sorted set which ZRANGE BYLEX will fetch and filter
+    #[serde(default = "zrange_bylex_max_set_size")]
+    zrange_bylex_max_set_size: usize,
+    /// which momento collection serves resp sorted set commands
+    #[serde(default)]
+    zset_backend: ZsetBackend,
+    /// the momento caches of resp databases 1 and up, which clients choose
+    /// with SELECT. database 0 is the cache of the listener
+    #[serde(default)]
+    resp_databases: Vec<String>,
+    /// resp GET and SET use the memcache storage format, with the flags ahead
+    /// of the value, so that a memcache listener can share the cache
+    #[serde(default)]
+    resp_memcache_flags: bool,
+    /// serve resp DUMP and RESTORE with a serialized format of the proxy's
+    /// own, which is not compatible with redis
+    #[serde(default)]
+    resp_dump_restore: bool,
+    /// serve resp PFADD, PFCOUNT, and PFMERGE by keeping HyperLogLog sketches
+    /// as values in momento
+    #[serde(default)]
+    resp_hyperloglog: bool,
+    /// the precision of new HyperLogLog sketches, each of which has
+    /// `2^precision` one byte registers
+    #[serde(default = "hyperloglog_precision")]
+    hyperloglog_precision: u8,
+    /// serve resp SETBIT, GETBIT, BITCOUNT, and BITOP by reading and writing
+    /// back the whole value
+    #[serde(default)]
+    resp_bit_operations: bool,
+    /// the largest value which SETBIT and BITOP will write
+    #[serde(default = "bit_operations_max_bytes")]
+    bit_operations_max_bytes: usize,
+    /// serve resp STRLEN, GETRANGE, and SETRANGE by reading the whole value
+    #[serde(default = "resp_emulation")]
+    resp_string_range: bool,
+    /// the largest value which resp SETRANGE will write
+    #[serde(default = "setrange_max_bytes")]
+    setrange_max_bytes: usize,
+    /// serve resp LPOS and LMPOP by reading whole lists
+    #[serde(default = "resp_emulation")]
+    resp_list_commands: bool,
+    /// serve resp MEMORY USAGE and OBJECT ENCODING by reading whole values
+    #[serde(default = "resp_emulation")]
+    resp_introspection: bool,
+    /// serve resp TTL and PTTL by reading the remaining ttl of the item
+    #[serde(default = "resp_emulation")]
+    resp_ttl: bool,
+    /// the largest value which a set will store, as momento limits the size
+    /// of an item. 0 for no limit
+    #[serde(default = "max_item_size")]
+    max_item_size: usize,
+    /// resp commands which clients of this cache may not run
+    #[serde(default)]
+    denied_commands: Vec<String>,
+    /// the most resp commands per second served for this cache, across all
+    /// of its connections, 0 for no limit
+    #[serde(default)]
+    rate_limit: u64,
+    /// resp commands which are written to the proxy log with the client
+    /// which sent them
+    #[serde(default)]
+    audit_commands: Vec<String>,
+    /// reply to resp writes with an `OOM` error while momento is throttling
+    /// the proxy
+    #[serde(default)]
+    resp_oom_on_throttle: bool,
+    /// how long writes are refused after momento throttles a request, in
+    /// milliseconds
+    #[serde(default = "resp_oom_cooldown_ms")]
+    resp_oom_cooldown_ms: u64,
+    /// timeout for each call to momento, in milliseconds
+    #[serde(default = "backend_timeout_ms")]
+    backend_timeout_ms: u64,
+    /// timeouts for calls to momento made by each class of command, in
+    /// milliseconds, in place of `backend_timeout_ms`
+    #[serde(default)]
+    backend_timeouts_ms: BTreeMap<CommandClass, u64>,
+    /// accept a trailing `TIMEOUT <milliseconds>` on resp commands as the
+    /// backend timeout for that command
+    #[serde(default)]
+    timeout_hints: bool,
+    /// the smallest timeout a hint may set, in milliseconds
+    #[serde(default = "timeout_hint_min_ms")]
+    timeout_hint_min_ms: u64,
+    /// the largest timeout a hint may set, in milliseconds
+    #[serde(default = "timeout_hint_max_ms")]
+    timeout_hint_max_ms: u64,
 }
 
 const fn four() -> NonZeroUsize {
     NonZeroUsize::new(4).expect("4 is nonzero")
 }
 
+const fn multi_get_concurrency() -> NonZeroUsize {
+    NonZeroUsize::new(64).expect("64 is nonzero")
+}
+
+// memory cache backfills are capped at the remaining momento ttl by default, so
+// the memory cache doesn't serve items which momento has expired
+fn memory_cache_preserve_ttl() -> bool {
+    true
+}
+
+fn warmup_rate() -> u64 {
+    1000
+}
+
+fn memory_cache_consistency_sample_size() -> usize {
+    16
+}
+
+fn compression_dictionary_interval_seconds() -> u64 {
+    3600
+}
+
+fn compression_dictionary_bytes() -> usize {
+    16 * 1024
+}
+
+fn compression_dictionary_ttl_seconds() -> u64 {
+    86400
+}
+
+fn key_transform_delimiter() -> char {
+    ':'
+}
+
+fn zrange_bylex_max_set_size() -> usize {
+    1000
+}
+
+// the precision redis uses, for a standard error of 0.81%
+fn hyperloglog_precision() -> u8 {
+    14
+}
+
+fn bit_operations_max_bytes() -> usize {
+    1024 * 1024
+}
+
+// the resp commands which the proxy serves itself, rather than forwarding
+// them to a momento api of their own, are on by default
+fn resp_emulation() -> bool {
+    true
+}
+
+fn setrange_max_bytes() -> usize {
+    1024 * 1024
+}
+
+fn max_item_size() -> usize {
+    5 * 1024 * 1024
+}
+
+fn resp_oom_cooldown_ms() -> u64 {
+    1000
+}
+
+fn listen_backlog() -> u32 {
+    1024
+}
+
+fn backend_timeout_ms() -> u64 {
+    200
+}
+
+fn response_queue_limit() -> usize {
+    1024
+}
+
+fn response_queue_timeout_ms() -> u64 {
+    10000
+}
+
+fn timeout_hint_min_ms() -> u64 {
+    10
+}
+
+fn timeout_hint_max_ms() -> u64 {
+    1000
+}
+
+const fn max_request_size() -> NonZeroUsize {
+    NonZeroUsize::new(MAX_REQUEST_SIZE).expect("max request size is nonzero")
+}
+
+// printed in place of a secret by `effective`
+const REDACTED: &str = "<redacted>";
+
+// a secret is printed as set or not, without its value
+fn redact<S: serde::Serializer>(secret: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    match secret {
+        Some(_) => serializer.serialize_some(REDACTED),
+        None => serializer.serialize_none(),
+    }
+}
+
+// headers are printed by name, without their values
+fn redact_values<S: serde::Serializer>(
+    headers: &BTreeMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(headers.keys().map(|name| (name, REDACTED)))
+}
+
 // implementation
 impl Cache {
-    /// Host address to listen on
+    /// Host address to listen on, the first if there are several
     pub fn host(&self) -> String {
-        self.host.clone()
+        self.host.iter().next().unwrap_or_default().to_string()
     }
 
     /// Port to listen on
@@ -94,9 +957,72 @@ impl Cache {
         self.port.clone()
     }
 
-    /// Return the result of parsing the host and port
-    pub fn socket_addr(&self) -> Result<SocketAddr, AddrParseError> {
-        format!("{}:{}", self.host(), self.port()).parse()
+    /// Each host and the port, with ipv6 literals in brackets
+    pub fn listen_address(&self) -> String {
+        self.host
+            .iter()
+            .map(|host| {
+                if host.contains(':') {
+                    format!("[{host}]:{}", self.port)
+                } else {
+                    format!("{host}:{}", self.port)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// The addresses to listen on. Each host may be an ipv4 or ipv6 literal,
+    /// with or without brackets, or a hostname, which listens on every
+    /// address it resolves to
+    pub fn socket_addrs(&self) -> Result<Vec<SocketAddr>, String> {
+        let port: u16 = self
+            .port
+            .parse()
+            .map_err(|_| format!("port `{}` is not a valid port", self.port))?;
+
+        let mut addrs = Vec::new();
+        for host in self.host.iter() {
+            if let Ok(ip) = host.parse::<IpAddr>() {
+                addrs.push(SocketAddr::new(ip, port));
+                continue;
+            }
+
+            let resolved: Vec<SocketAddr> = (host, port)
+                .to_socket_addrs()
+                .map_err(|e| format!("could not resolve host `{host}`: {e}"))?
+                .collect();
+            if resolved.is_empty() {
+                return Err(format!("host `{host}` did not resolve to any address"));
+            }
+            addrs.extend(resolved);
+        }
+
+        if addrs.is_empty() {
+            return Err("no host to listen on".to_string());
+        }
+
+        // the same address may be listed and also resolved from a hostname
+        addrs.sort();
+        addrs.dedup();
+
+        Ok(addrs)
+    }
+
+    /// Whether ipv6 listeners only accept ipv6 connections, `None` to leave it
+    /// to the system
+    pub fn ipv6_only(&self) -> Option<bool> {
+        self.ipv6_only
+    }
+
+    /// Host which clients are told to connect to
+    pub fn advertised_host(&self) -> String {
+        self.advertised_host.clone().unwrap_or_else(|| self.host())
+    }
+
+    /// Port which clients are told to connect to
+    pub fn advertised_port(&self) -> String {
+        self.advertised_port.clone().unwrap_or_else(|| self.port())
     }
 
     /// Returns the name of the momento cache that requests will be sent to
@@ -113,6 +1039,16 @@ impl Cache {
         self.connection_count.get()
     }
 
+    /// Requests sent by each listener to warm up its client, 0 if none are
+    pub fn client_warmup_requests(&self) -> usize {
+        self.client_warmup_requests
+    }
+
+    /// Whether listeners only listen once their client has warmed up
+    pub fn listen_after_client_warmup(&self) -> bool {
+        self.listen_after_client_warmup
+    }
+
     pub fn protocol(&self) -> Protocol {
         self.protocol
     }
@@ -121,6 +1057,41 @@ impl Cache {
         self.flags
     }
 
+    /// How values are stored in Momento, which unless configured follows
+    /// whether the listener supports flags
+    pub fn value_format(&self) -> ValueFormat {
+        let flags = match self.protocol {
+            Protocol::Memcache => self.flags,
+            Protocol::Resp => self.resp_memcache_flags,
+        };
+
+        match self.value_format {
+            Some(format) => format,
+            None if flags => ValueFormat::Flags,
+            None => ValueFormat::Raw,
+        }
+    }
+
+    /// Whether memcache values are compressed before they are stored
+    pub fn value_compression(&self) -> ValueCompression {
+        self.value_compression
+    }
+
+    /// How often a new compression dictionary is trained
+    pub fn compression_dictionary_interval(&self) -> Duration {
+        Duration::from_secs(self.compression_dictionary_interval_seconds)
+    }
+
+    /// The largest compression dictionary trained, in bytes
+    pub fn compression_dictionary_bytes(&self) -> usize {
+        self.compression_dictionary_bytes
+    }
+
+    /// How long compression dictionaries are kept in Momento
+    pub fn compression_dictionary_ttl(&self) -> Duration {
+        Duration::from_secs(self.compression_dictionary_ttl_seconds)
+    }
+
     /// 0 to disable
     pub fn memory_cache_bytes(&self) -> usize {
         self.memory_cache_bytes
@@ -133,7 +1104,404 @@ impl Cache {
     pub fn buffer_size(&self) -> usize {
         // rounds the buffer size up to the next nearest multiple of the
         // pagesize
-        std::cmp::max(1, self.buffer_size.get()).div_ceil(PAGESIZE)
+        std::cmp::max(1, self.buffer_size.get()).div_ceil(pagesize()) * pagesize()
+    }
+
+    /// The largest request accepted from a client, in bytes
+    pub fn max_request_size(&self) -> usize {
+        self.max_request_size.get()
+    }
+
+    /// How long a connection is idle before its grown read buffer shrinks
+    pub fn buffer_shrink_idle(&self) -> Duration {
+        Duration::from_secs(self.buffer_shrink_idle_seconds)
+    }
+
+    /// How long a client connection is idle before keepalive probes are sent
+    pub fn tcp_keepalive(&self) -> Option<Duration> {
+        (self.tcp_keepalive_seconds > 0).then(|| Duration::from_secs(self.tcp_keepalive_seconds))
+    }
+
+    /// The most connections waiting to be accepted by each listener
+    pub fn listen_backlog(&self) -> i32 {
+        self.listen_backlog.try_into().unwrap_or(i32::MAX)
+    }
+
+    /// Whether the idlest connection is closed when accept runs out of file
+    /// descriptors
+    pub fn accept_shed_idle(&self) -> bool {
+        self.accept_shed_idle
+    }
+
+    /// How long a client connection may go without a read before it is closed
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        (self.idle_timeout_seconds > 0).then(|| Duration::from_secs(self.idle_timeout_seconds))
+    }
+
+    /// Whether the summary of each closed connection is logged at info level
+    pub fn connection_summary_log(&self) -> bool {
+        self.connection_summary_log
+    }
+
+    /// Path to a file of keys to pre-fetch into the memory cache on startup
+    pub fn warmup_key_file(&self) -> Option<&str> {
+        self.warmup_key_file.as_deref()
+    }
+
+    /// Maximum number of warmup requests per second, 0 for unlimited
+    pub fn warmup_rate(&self) -> u64 {
+        self.warmup_rate
+    }
+
+    /// Path of the file the memory cache is saved to on shutdown and loaded
+    /// from on startup
+    pub fn memory_cache_snapshot_file(&self) -> Option<&str> {
+        self.memory_cache_snapshot_file.as_deref()
+    }
+
+    /// The admission policy for the memory cache
+    pub fn memory_cache_admission(&self) -> AdmissionPolicy {
+        AdmissionPolicy {
+            max_value_bytes: self.memory_cache_max_value_bytes,
+            include_prefixes: self
+                .memory_cache_include_prefixes
+                .iter()
+                .map(|prefix| prefix.as_bytes().to_vec())
+                .collect(),
+            exclude_prefixes: self
+                .memory_cache_exclude_prefixes
+                .iter()
+                .map(|prefix| prefix.as_bytes().to_vec())
+                .collect(),
+            frequency_filter: self.memory_cache_frequency_filter,
+            key_policy: self.key_policy(),
+        }
+    }
+
+    /// Which entries the memory cache evicts when it is full
+    pub fn memory_cache_eviction(&self) -> MemoryCacheEviction {
+        self.memory_cache_eviction
+    }
+
+    /// Whether keys which leave the memory cache are written to the command
+    /// log
+    pub fn memory_cache_eviction_log(&self) -> bool {
+        self.memory_cache_eviction_log
+    }
+
+    /// How to respond to unsupported memcache commands
+    pub fn unsupported_command_behavior(&self) -> UnsupportedCommandBehavior {
+        self.unsupported_command_behavior
+    }
+
+    /// How a multi-key get handles partial backend failures
+    pub fn multi_get_failure_policy(&self) -> MultiGetFailurePolicy {
+        self.multi_get_failure_policy
+    }
+
+    /// The most backend requests in flight for a single multi-key get
+    pub fn multi_get_concurrency(&self) -> usize {
+        self.multi_get_concurrency.get()
+    }
+
+    /// The most keys accepted in a multi-key get
+    pub fn multi_get_max_keys(&self) -> Option<usize> {
+        self.multi_get_max_keys.map(NonZeroUsize::get)
+    }
+
+    /// How long expired entries are kept in the memory cache to be served if
+    /// the backend fails
+    pub fn memory_cache_stale_seconds(&self) -> u64 {
+        self.memory_cache_stale_seconds
+    }
+
+    /// Whether entries filled from momento hits are capped at the item's
+    /// remaining TTL
+    pub fn memory_cache_preserve_ttl(&self) -> bool {
+        self.memory_cache_preserve_ttl
+    }
+
+    /// The most requests a connection may have in flight, if limited
+    pub fn max_pipeline_depth(&self) -> Option<usize> {
+        if self.max_pipeline_depth == 0 {
+            None
+        } else {
+            Some(self.max_pipeline_depth)
+        }
+    }
+
+    /// The most responses a connection may have queued
+    pub fn response_queue_limit(&self) -> usize {
+        self.response_queue_limit.max(1)
+    }
+
+    /// How long a full response queue may go without draining
+    pub fn response_queue_timeout(&self) -> Duration {
+        Duration::from_millis(self.response_queue_timeout_ms)
+    }
+
+    /// Returns the names of the momento caches that requests are sharded
+    /// across, which is just `cache_name` if no shards are configured
+    pub fn shards(&self) -> Vec<String> {
+        if self.shards.is_empty() {
+            vec![self.cache_name.clone()]
+        } else {
+            self.shards.clone()
+        }
+    }
+
+    /// The prefix routes to other pools of caches, if a routing policy file
+    /// is configured
+    pub fn routing_policy(&self) -> Result<Option<RoutingPolicy>, String> {
+        self.routing_policy_file
+            .as_deref()
+            .map(RoutingPolicy::load)
+            .transpose()
+    }
+
+    /// The per-key overrides, in the order they were configured
+    pub fn key_policy(&self) -> KeyPolicy {
+        KeyPolicy::new(self.key_rules.clone())
+    }
+
+    /// Checks the per-key overrides
+    pub fn validate_key_rules(&self) -> Result<(), String> {
+        KeyPolicy::validate(&self.key_rules)
+    }
+
+    /// The quotas of key prefixes, in the order they were configured
+    pub fn quotas(&self) -> &[QuotaConfig] {
+        &self.quotas
+    }
+
+    /// How a set responds when the memory cache could not store the item
+    pub fn memory_cache_write_failure_policy(&self) -> MemoryCacheWriteFailurePolicy {
+        self.memory_cache_write_failure_policy
+    }
+
+    /// How often memory cache entries are checked against momento, if enabled
+    pub fn memory_cache_consistency_interval(&self) -> Option<Duration> {
+        if self.memory_cache_consistency_interval_seconds == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(
+                self.memory_cache_consistency_interval_seconds,
+            ))
+        }
+    }
+
+    /// Number of memory cache entries checked each interval
+    pub fn memory_cache_consistency_sample_size(&self) -> usize {
+        self.memory_cache_consistency_sample_size
+    }
+
+    /// How often momento is probed for the state of the channel, if enabled
+    pub fn backend_probe_interval(&self) -> Option<Duration> {
+        if self.backend_probe_interval_seconds == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(self.backend_probe_interval_seconds))
+        }
+    }
+
+    /// Extra dimensions for the metrics of this cache, as name and value
+    pub fn metric_dimensions(&self) -> Vec<(String, String)> {
+        self.metric_dimensions
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Whether get and set latencies are also recorded by value size
+    pub fn latency_by_value_size(&self) -> bool {
+        self.latency_by_value_size
+    }
+
+    /// Whether a memcache delete checks that the key exists first
+    pub fn strict_delete(&self) -> bool {
+        self.strict_delete
+    }
+
+    /// Whether memcache text clients may use `proxy_config` commands
+    pub fn proxy_config_commands(&self) -> bool {
+        self.proxy_config_commands
+    }
+
+    /// The transform applied to keys before they are sent to momento
+    pub fn key_transform(&self) -> Result<KeyTransform, String> {
+        let secret = self
+            .key_transform_secret
+            .clone()
+            .or_else(|| std::env::var("KEY_TRANSFORM_SECRET").ok());
+
+        if !self.key_transform_delimiter.is_ascii() {
+            return Err("`key_transform_delimiter` must be an ascii character".to_string());
+        }
+
+        KeyTransform::new(
+            self.key_transform,
+            secret.as_deref(),
+            self.key_transform_delimiter as u8,
+        )
+    }
+
+    /// Whether the metrics of noreply requests are batched
+    pub fn noreply_metrics_batching(&self) -> bool {
+        self.noreply_metrics_batching
+    }
+
+    /// Whether ZADD emulates its conditional arguments
+    pub fn zadd_conditional_emulation(&self) -> bool {
+        self.zadd_conditional_emulation
+    }
+
+    /// The largest sorted set which ZRANGE BYLEX will fetch and filter
+    pub fn zrange_bylex_max_set_size(&self) -> usize {
+        self.zrange_bylex_max_set_size
+    }
+
+    /// Which momento collection serves resp sorted set commands
+    pub fn zset_backend(&self) -> ZsetBackend {
+        self.zset_backend
+    }
+
+    /// The momento caches of resp databases 1 and up, in order
+    pub fn resp_databases(&self) -> Vec<String> {
+        self.resp_databases.clone()
+    }
+
+    /// Whether resp GET and SET use the memcache storage format
+    pub fn resp_memcache_flags(&self) -> bool {
+        self.value_format() == ValueFormat::Flags
+    }
+
+    /// Whether resp DUMP and RESTORE are served
+    pub fn resp_dump_restore(&self) -> bool {
+        self.resp_dump_restore
+    }
+
+    /// Whether resp PFADD, PFCOUNT, and PFMERGE are served
+    pub fn resp_hyperloglog(&self) -> bool {
+        self.resp_hyperloglog
+    }
+
+    /// The precision of new HyperLogLog sketches
+    pub fn hyperloglog_precision(&self) -> u8 {
+        self.hyperloglog_precision
+    }
+
+    /// Whether resp SETBIT, GETBIT, BITCOUNT, and BITOP are served
+    pub fn resp_bit_operations(&self) -> bool {
+        self.resp_bit_operations
+    }
+
+    /// The largest value which SETBIT and BITOP will write
+    pub fn bit_operations_max_bytes(&self) -> usize {
+        self.bit_operations_max_bytes
+    }
+
+    /// Whether resp STRLEN, GETRANGE, and SETRANGE are served
+    pub fn resp_string_range(&self) -> bool {
+        self.resp_string_range
+    }
+
+    /// The largest value which resp SETRANGE will write
+    pub fn setrange_max_bytes(&self) -> usize {
+        self.setrange_max_bytes
+    }
+
+    /// Whether resp LPOS and LMPOP are served
+    pub fn resp_list_commands(&self) -> bool {
+        self.resp_list_commands
+    }
+
+    /// Whether resp MEMORY USAGE and OBJECT ENCODING are served
+    pub fn resp_introspection(&self) -> bool {
+        self.resp_introspection
+    }
+
+    /// Whether resp TTL and PTTL are served
+    pub fn resp_ttl(&self) -> bool {
+        self.resp_ttl
+    }
+
+    /// The largest value which a set will store, 0 for no limit
+    pub fn max_item_size(&self) -> usize {
+        self.max_item_size
+    }
+
+    /// Resp commands which clients may not run, uppercased
+    pub fn denied_commands(&self) -> Vec<String> {
+        self.denied_commands
+            .iter()
+            .map(|command| command.to_ascii_uppercase())
+            .collect()
+    }
+
+    /// The most resp commands per second, 0 for no limit
+    pub fn rate_limit(&self) -> u64 {
+        self.rate_limit
+    }
+
+    /// Resp commands which are written to the proxy log, uppercased
+    pub fn audit_commands(&self) -> Vec<String> {
+        self.audit_commands
+            .iter()
+            .map(|command| command.to_ascii_uppercase())
+            .collect()
+    }
+
+    /// How long resp writes are refused with an `OOM` error after momento
+    /// throttles a request, `None` if they never are
+    pub fn resp_oom_cooldown(&self) -> Option<Duration> {
+        self.resp_oom_on_throttle
+            .then(|| Duration::from_millis(self.resp_oom_cooldown_ms))
+    }
+
+    /// Whether resp commands may carry a timeout hint
+    pub fn timeout_hints(&self) -> bool {
+        self.timeout_hints
+    }
+
+    /// The timeout for calls to momento, a hint from the client is clamped to
+    /// the configured range
+    pub fn backend_timeout(&self, hint: Option<Duration>) -> Duration {
+        match hint {
+            Some(hint) => {
+                let min = Duration::from_millis(self.timeout_hint_min_ms);
+                let max = Duration::from_millis(self.timeout_hint_max_ms).max(min);
+                hint.clamp(min, max)
+            }
+            None => Duration::from_millis(self.backend_timeout_ms),
+        }
+    }
+
+    /// The timeout for calls to momento made by a class of command. A hint
+    /// from the client takes precedence over the timeout of the class
+    pub fn command_timeout(&self, class: CommandClass, hint: Option<Duration>) -> Duration {
+        match (hint, self.backend_timeouts_ms.get(&class)) {
+            (None, Some(ms)) => Duration::from_millis(*ms),
+            _ => self.backend_timeout(hint),
+        }
+    }
+}
+
+/// Parses a config, first bringing one written for an older schema up to
+/// `CONFIG_VERSION`. A config deserialized directly is not migrated, so
+/// programs which embed the proxy should parse their config as `load` does.
+impl std::str::FromStr for MomentoProxyConfig {
+    type Err = String;
+
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        let mut table = toml::from_str::<toml::Table>(content).map_err(|e| e.to_string())?;
+
+        for warning in migrate(&mut table, RENAMED_KEYS)? {
+            eprintln!("warning: {warning}");
+        }
+
+        toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| e.to_string())
     }
 }
 
@@ -143,7 +1511,8 @@ impl MomentoProxyConfig {
         let mut file = std::fs::File::open(file)?;
         let mut content = String::new();
         file.read_to_string(&mut content)?;
-        match toml::from_str(&content) {
+
+        match content.parse() {
             Ok(t) => Ok(t),
             Err(e) => {
                 eprintln!("{e}");
@@ -155,6 +1524,12 @@ impl MomentoProxyConfig {
         }
     }
 
+    /// The fully resolved config, after defaults and migrations, as TOML.
+    /// Secrets are redacted
+    pub fn effective(&self) -> String {
+        toml::to_string_pretty(self).unwrap_or_else(|e| format!("# {e}"))
+    }
+
     pub fn caches(&self) -> &[Cache] {
         &self.cache
     }
@@ -162,6 +1537,252 @@ impl MomentoProxyConfig {
     pub fn threads(&self) -> Option<usize> {
         self.proxy.threads
     }
+
+    pub fn abort_on_panic(&self) -> bool {
+        self.proxy.abort_on_panic
+    }
+
+    /// Whether policies reject the requests which violate them
+    pub fn policy_mode(&self) -> PolicyMode {
+        self.proxy.policy_mode
+    }
+
+    /// The instance id from the config, or else from the
+    /// `SERVICE_INSTANCE_ID` environment variable
+    pub fn instance_id(&self) -> Option<String> {
+        self.proxy
+            .instance_id
+            .clone()
+            .or_else(|| std::env::var("SERVICE_INSTANCE_ID").ok())
+    }
+
+    pub fn trace_key_prefix(&self) -> Option<String> {
+        self.proxy.trace_key_prefix.clone()
+    }
+
+    /// Whether the proxy starts with only some of its caches healthy
+    pub fn partial_start(&self) -> bool {
+        self.proxy.partial_start
+    }
+
+    pub fn startup_report_file(&self) -> Option<&str> {
+        self.proxy.startup_report_file.as_deref()
+    }
+
+    pub fn server_name(&self) -> Option<String> {
+        self.proxy.server_name.clone()
+    }
+
+    pub fn server_version(&self) -> Option<String> {
+        self.proxy.server_version.clone()
+    }
+
+    /// One in every `n` command log entries is exported over OTLP, 0 if the
+    /// export is disabled
+    pub fn klog_otlp_sample(&self) -> u64 {
+        self.proxy.klog_otlp_sample
+    }
+
+    /// The file the audit log is appended to, if any
+    pub fn audit_log_file(&self) -> Option<String> {
+        self.proxy.audit_log_file.clone()
+    }
+
+    /// The key prefixes which are audited, every key if empty
+    pub fn audit_key_prefixes(&self) -> Vec<String> {
+        self.proxy.audit_key_prefixes.clone()
+    }
+
+    /// The percent of the file descriptor limit past which new connections
+    /// are rejected, 0 if they never are
+    pub fn fd_high_water_percent(&self) -> u8 {
+        self.proxy.fd_high_water_percent
+    }
+
+    /// Path of the unix socket the admin listener binds, if it doesn't bind
+    /// the address in the `[admin]` section
+    pub fn admin_unix_socket(&self) -> Option<&str> {
+        self.proxy.admin_unix_socket.as_deref()
+    }
+
+    /// The token admin clients authenticate with, if they must
+    pub fn admin_auth_token(&self) -> Option<String> {
+        self.proxy
+            .admin_auth_token
+            .clone()
+            .or_else(|| std::env::var("ADMIN_AUTH_TOKEN").ok())
+            .filter(|token| !token.is_empty())
+    }
+
+    /// Paths of the PEM certificate chain and key of the admin listener, if
+    /// both are set
+    pub fn admin_tls_identity(&self) -> Option<(&str, &str)> {
+        match (
+            &self.proxy.admin_tls_cert_file,
+            &self.proxy.admin_tls_key_file,
+        ) {
+            (Some(cert), Some(key)) => Some((cert.as_str(), key.as_str())),
+            _ => None,
+        }
+    }
+
+    /// Whether only one of the admin certificate and key is set
+    pub fn admin_tls_incomplete(&self) -> bool {
+        self.proxy.admin_tls_cert_file.is_some() != self.proxy.admin_tls_key_file.is_some()
+    }
+
+    /// Whether connections are served by pinned workers rather than the
+    /// shared runtime
+    pub fn pinned_workers(&self) -> bool {
+        self.proxy.pinned_workers
+    }
+
+    /// Whether connections are served by io_uring workers
+    pub fn io_uring(&self) -> bool {
+        self.proxy.io_uring
+    }
+
+    /// Describes each Momento cache shared by listeners which store values in
+    /// different formats, as each would read the values written by the others
+    /// with their flags lost, with four bytes of junk ahead of them, or still
+    /// compressed.
+    pub fn value_format_conflicts(&self) -> Vec<String> {
+        let mut formats: BTreeMap<String, BTreeMap<String, Vec<String>>> = BTreeMap::new();
+
+        for cache in &self.cache {
+            let mut names = cache.shards();
+            names.extend(
+                cache
+                    .key_policy()
+                    .rules()
+                    .iter()
+                    .filter_map(|rule| rule.cache_name().map(str::to_string)),
+            );
+            if let Ok(Some(policy)) = cache.routing_policy() {
+                names.extend(policy.cache_names().cloned());
+            }
+            names.extend(cache.resp_databases());
+            names.sort();
+            names.dedup();
+
+            for name in names {
+                formats
+                    .entry(name)
+                    .or_default()
+                    .entry(match cache.value_compression() {
+                        ValueCompression::None => cache.value_format().as_str().to_string(),
+                        compression => format!(
+                            "{} with {}",
+                            cache.value_format().as_str(),
+                            compression.as_str()
+                        ),
+                    })
+                    .or_default()
+                    .push(cache.listen_address());
+            }
+        }
+
+        formats
+            .into_iter()
+            .filter(|(_, formats)| formats.len() > 1)
+            .map(|(name, formats)| {
+                let listeners: Vec<String> = formats
+                    .iter()
+                    .map(|(format, listeners)| format!("{format} by {}", listeners.join(", ")))
+                    .collect();
+                format!(
+                    "momento cache `{name}` is shared by listeners which store values differently ({}), set the same `value_format` and `value_compression` on each of them",
+                    listeners.join("; ")
+                )
+            })
+            .collect()
+    }
+
+    /// The preview APIs listed in the config
+    pub fn preview_apis(&self) -> &[PreviewApi] {
+        &self.proxy.preview_apis
+    }
+
+    /// Whether a preview API is both listed in the config and compiled in
+    pub fn preview_enabled(&self, api: PreviewApi) -> bool {
+        api.is_compiled() && self.proxy.preview_apis.contains(&api)
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+impl Default for MomentoProxyConfig {
+    fn default() -> Self {
+        Self {
+            config_version: CONFIG_VERSION,
+            admin: Default::default(),
+            proxy: Default::default(),
+            metrics: Default::default(),
+            cache: Default::default(),
+            debug: Default::default(),
+            klog: Default::default(),
+        }
+    }
+}
+
+// brings a config written for an older schema up to `CONFIG_VERSION` by
+// renaming its deprecated keys, and returns a warning for each of them
+fn migrate(table: &mut toml::Table, renamed: &[RenamedKey]) -> Result<Vec<String>, String> {
+    let version = match table.get("config_version") {
+        None => 1,
+        Some(version) => version
+            .as_integer()
+            .filter(|version| *version > 0)
+            .ok_or("`config_version` must be a positive integer")? as u64,
+    };
+
+    if version > CONFIG_VERSION {
+        return Err(format!(
+            "`config_version` {version} is newer than the supported version {CONFIG_VERSION}"
+        ));
+    }
+
+    let mut warnings = Vec::new();
+    for (since, section, old, new) in renamed {
+        if version >= *since {
+            continue;
+        }
+
+        // a section is either a table or, like `[[cache]]`, an array of them
+        let tables: Vec<&mut toml::Table> = match table.get_mut(*section) {
+            Some(toml::Value::Table(table)) => vec![table],
+            Some(toml::Value::Array(array)) => {
+                array.iter_mut().filter_map(|v| v.as_table_mut()).collect()
+            }
+            _ => continue,
+        };
+
+        for table in tables {
+            let Some(value) = table.remove(*old) else {
+                continue;
+            };
+
+            if table.contains_key(*new) {
+                warnings.push(format!(
+                    "ignoring deprecated `{section}.{old}` as `{new}` is also set"
+                ));
+            } else {
+                warnings.push(format!(
+                    "`{section}.{old}` is deprecated, use `{new}` instead"
+                ));
+                table.insert(new.to_string(), value);
+            }
+        }
+    }
+
+    table.insert(
+        "config_version".to_string(),
+        toml::Value::Integer(CONFIG_VERSION as i64),
+    );
+
+    Ok(warnings)
 }
 
 impl AdminConfig for MomentoProxyConfig {
@@ -181,3 +1802,123 @@ impl KlogConfig for MomentoProxyConfig {
         &self.klog
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a rename made by a schema newer than any config in the tests
+    const RENAMED: &[RenamedKey] = &[
+        (2, "proxy", "worker_threads", "threads"),
+        (2, "cache", "ttl", "default_ttl"),
+    ];
+
+    fn table(content: &str) -> toml::Table {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn renames_deprecated_keys() {
+        let mut config = table(
+            r#"
+            [proxy]
+            worker_threads = 4
+
+            [[cache]]
+            cache_name = "a"
+            ttl = 60
+
+            [[cache]]
+            cache_name = "b"
+            default_ttl = 30
+            "#,
+        );
+
+        let warnings = migrate(&mut config, RENAMED).unwrap();
+
+        assert_eq!(
+            config,
+            table(&format!(
+                r#"
+                config_version = {CONFIG_VERSION}
+
+                [proxy]
+                threads = 4
+
+                [[cache]]
+                cache_name = "a"
+                default_ttl = 60
+
+                [[cache]]
+                cache_name = "b"
+                default_ttl = 30
+                "#
+            ))
+        );
+        assert_eq!(
+            warnings,
+            [
+                "`proxy.worker_threads` is deprecated, use `threads` instead",
+                "`cache.ttl` is deprecated, use `default_ttl` instead",
+            ]
+        );
+    }
+
+    #[test]
+    fn replacement_takes_precedence() {
+        let mut config = table(
+            r#"
+            [proxy]
+            worker_threads = 4
+            threads = 8
+            "#,
+        );
+
+        let warnings = migrate(&mut config, RENAMED).unwrap();
+
+        assert_eq!(config["proxy"]["threads"].as_integer(), Some(8));
+        assert!(config["proxy"].get("worker_threads").is_none());
+        assert_eq!(
+            warnings,
+            ["ignoring deprecated `proxy.worker_threads` as `threads` is also set"]
+        );
+    }
+
+    #[test]
+    fn current_configs_are_unchanged() {
+        let content = format!("config_version = {CONFIG_VERSION}\n\n[proxy]\nthreads = 4\n");
+        let mut config = table(&content);
+
+        // keys renamed by versions up to the config's own are left as they are
+        let renamed = [(CONFIG_VERSION, "proxy", "threads", "worker_threads")];
+        let warnings = migrate(&mut config, &renamed).unwrap();
+
+        assert_eq!(config, table(&content));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn newer_configs_are_rejected() {
+        let mut config = table(&format!("config_version = {}", CONFIG_VERSION + 1));
+        assert!(migrate(&mut config, RENAMED).is_err());
+
+        let mut config = table("config_version = 0");
+        assert!(migrate(&mut config, RENAMED).is_err());
+    }
+
+    #[test]
+    fn parsed_configs_are_migrated() {
+        let config: MomentoProxyConfig = r#"
+            [[cache]]
+            host = "127.0.0.1"
+            port = "11211"
+            cache_name = "test"
+            default_ttl = 60
+            "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(config.config_version, CONFIG_VERSION);
+        assert!("config_version = 0".parse::<MomentoProxyConfig>().is_err());
+    }
+}
@@ -0,0 +1,57 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Panic handling. A panic inside a connection or request task only closes
+//! that connection, while a panic anywhere else terminates the process.
+
+use crate::*;
+use core::sync::atomic::AtomicBool;
+use futures::FutureExt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+
+#[metric(name = "proxy_panics_total")]
+pub static PROXY_PANICS_TOTAL: Counter = Counter::new();
+
+// restores the behavior of terminating the process on any panic
+static ABORT_ON_PANIC: AtomicBool = AtomicBool::new(false);
+
+tokio::task_local! {
+    // set while polling a task whose panics are isolated
+    static ISOLATED: ();
+}
+
+/// Installs the process panic hook.
+pub(crate) fn set_hook() {
+    std::panic::set_hook(Box::new(|s| {
+        if !ABORT_ON_PANIC.load(Ordering::Relaxed) && ISOLATED.try_with(|_| ()).is_ok() {
+            error!("panic in connection task, closing the connection: {}", s);
+            error!("{:?}", Backtrace::new());
+            klog::klog_panic(&s.to_string());
+            return;
+        }
+
+        // custom panic hook to terminate whole process after unwinding
+        error!("{}", s);
+        println!("{:?}", Backtrace::new());
+        std::process::exit(101);
+    }));
+}
+
+/// Makes every panic terminate the process, including those in connection
+/// tasks.
+pub(crate) fn set_abort_on_panic(abort: bool) {
+    ABORT_ON_PANIC.store(abort, Ordering::Relaxed);
+}
+
+/// Polls the future with its panics isolated. Returns `None` if it panicked.
+pub(crate) async fn isolate<F: Future>(future: F) -> Option<F::Output> {
+    let result = ISOLATED
+        .scope((), AssertUnwindSafe(future).catch_unwind())
+        .await;
+    if result.is_err() {
+        PROXY_PANICS_TOTAL.increment();
+    }
+    result.ok()
+}
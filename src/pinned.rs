@@ -0,0 +1,176 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Pinned workers for deployments with very many connections. Instead of the
+//! shared work-stealing runtime, each listener is served by one single threaded
+//! runtime per worker, and each worker accepts from its own socket bound with
+//! `SO_REUSEPORT`. The kernel spreads new connections across the sockets, and a
+//! connection is served by the worker which accepted it for its whole life, so
+//! its tasks and buffers never move between threads.
+
+use crate::preview::Leaderboards;
+use crate::shards::Shards;
+use crate::tuning::Tuning;
+use crate::*;
+use momento::CacheClientBuilder;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// The number of pinned workers for each listener, which is the configured
+/// number of threads or else one per core.
+pub(crate) fn workers(config: &MomentoProxyConfig) -> usize {
+    config
+        .threads()
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(NonZeroUsize::get)
+                .unwrap_or(1)
+        })
+        .max(1)
+}
+
+/// Binds a socket which can share its address with the sockets of the other
+/// workers. Without `listen`, the socket is only bound and listens later.
+#[cfg(unix)]
+pub(crate) fn bind_reuse_port(
+    addr: SocketAddr,
+    ipv6_only: Option<bool>,
+    backlog: i32,
+    listen: bool,
+) -> std::io::Result<std::net::TcpListener> {
+    use socket2::{Domain, Socket, Type};
+    use std::os::fd::AsRawFd;
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if let (true, Some(ipv6_only)) = (addr.is_ipv6(), ipv6_only) {
+        socket.set_only_v6(ipv6_only)?;
+    }
+    socket.set_reuse_address(true)?;
+
+    let enable: libc::c_int = 1;
+
+    // SAFETY: the fd is a valid socket for the lifetime of `socket`, and the
+    // value and its length describe `enable`
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_REUSEPORT,
+            &enable as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    socket.bind(&addr.into())?;
+    if listen {
+        socket.listen(backlog)?;
+    }
+    socket.set_nonblocking(true)?;
+
+    Ok(socket.into())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn bind_reuse_port(
+    _addr: SocketAddr,
+    _ipv6_only: Option<bool>,
+    _backlog: i32,
+    _listen: bool,
+) -> std::io::Result<std::net::TcpListener> {
+    Err(std::io::Error::new(
+        ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+/// Starts `workers` pinned workers for a listener. The first accepts from
+/// `listener` and the others bind their own sockets on its address. The
+/// workers stop once every sender of `stop` has been dropped.
+pub(crate) fn serve(
+    listener: std::net::TcpListener,
+    workers: usize,
+    client_builder: CacheClientBuilder<ReadyToBuild>,
+    config: Arc<momento_proxy::Cache>,
+    shards: Arc<Shards>,
+    proxy_metrics: Arc<DefaultProxyMetrics>,
+    memory_cache: Option<MCache>,
+    tuning: Arc<Tuning>,
+    leaderboards: Option<Leaderboards>,
+    middleware: Arc<middleware::Chain>,
+    quotas: Arc<quota::Quotas>,
+    uring: Option<Arc<uring::Workers>>,
+    stop: watch::Receiver<()>,
+) -> Result<(), String> {
+    let addr = listener.local_addr().map_err(|e| e.to_string())?;
+
+    let mut listeners = vec![listener];
+    for _ in 1..workers {
+        listeners.push(
+            bind_reuse_port(
+                addr,
+                config.ipv6_only(),
+                config.listen_backlog(),
+                !config.listen_after_client_warmup(),
+            )
+            .map_err(|e| format!("could not bind a pinned worker on `{addr}`: {e}"))?,
+        );
+    }
+
+    for (id, listener) in listeners.into_iter().enumerate() {
+        let client_builder = client_builder.clone();
+        let config = config.clone();
+        let shards = shards.clone();
+        let proxy_metrics = proxy_metrics.clone();
+        let memory_cache = memory_cache.clone();
+        let tuning = tuning.clone();
+        let leaderboards = leaderboards.clone();
+        let middleware = middleware.clone();
+        let quotas = quotas.clone();
+        let uring = uring.clone();
+        let mut stop = stop.clone();
+
+        std::thread::Builder::new()
+            .name(format!("pelikan_pin_{}_{id}", addr.port()))
+            .spawn(move || {
+                let runtime = Builder::new_current_thread()
+                    .enable_all()
+                    .on_thread_park(metrics::batch::flush)
+                    .build()
+                    .expect("failed to launch tokio runtime");
+
+                runtime.block_on(async move {
+                    let listener = TcpListener::from_std(listener)
+                        .expect("could not convert to tokio listener");
+
+                    tokio::select! {
+                        result = listener::listener(
+                            listener,
+                            client_builder,
+                            config,
+                            shards,
+                            proxy_metrics,
+                            memory_cache,
+                            tuning,
+                            leaderboards,
+                            middleware,
+                            quotas,
+                            uring,
+                        ) => {
+                            if let Err(e) = result {
+                                error!("could not serve pinned worker {id} on {addr}: {e}");
+                            }
+                        }
+                        _ = stop.changed() => {}
+                    }
+                });
+            })
+            .map_err(|e| format!("could not start a pinned worker: {e}"))?;
+    }
+
+    Ok(())
+}
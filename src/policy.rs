@@ -0,0 +1,150 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Per-key overrides of a cache's defaults. Each rule matches keys against a
+//! glob, where `*` matches any run of bytes and `?` matches any one byte, and
+//! may override the TTL of writes without one, the Momento cache the key is
+//! stored in, and whether the key is admitted into the memory cache. The
+//! first matching rule applies, and keys which match no rule use the cache's
+//! own settings.
+//!
+//! ```toml
+//! [[cache.key_rules]]
+//! pattern = "session:*"
+//! ttl_seconds = 1800
+//! memory_cache = false
+//!
+//! [[cache.key_rules]]
+//! pattern = "report:*:daily"
+//! cache_name = "reports"
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A rule as configured.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct KeyRule {
+    /// glob matched against the whole key
+    pattern: String,
+    /// ttl of writes which don't set one
+    #[serde(default)]
+    ttl_seconds: Option<u64>,
+    /// momento cache the keys are stored in, instead of the shards and routes
+    #[serde(default)]
+    cache_name: Option<String>,
+    /// whether the keys are admitted into the memory cache, regardless of the
+    /// admission prefixes
+    #[serde(default)]
+    memory_cache: Option<bool>,
+}
+
+impl KeyRule {
+    /// The TTL of writes which don't set one, if overridden
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl_seconds.map(Duration::from_secs)
+    }
+
+    /// The cache the keys are stored in, if overridden
+    pub fn cache_name(&self) -> Option<&str> {
+        self.cache_name.as_deref()
+    }
+
+    /// Whether the keys are admitted into the memory cache, if overridden
+    pub fn memory_cache(&self) -> Option<bool> {
+        self.memory_cache
+    }
+
+    fn matches(&self, key: &[u8]) -> bool {
+        glob(self.pattern.as_bytes(), key)
+    }
+}
+
+/// The rules of a cache, in the order they were configured.
+#[derive(Clone, Debug, Default)]
+pub struct KeyPolicy {
+    rules: Vec<KeyRule>,
+}
+
+impl KeyPolicy {
+    pub fn new(rules: Vec<KeyRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Checks that every rule has a pattern and overrides something.
+    pub fn validate(rules: &[KeyRule]) -> Result<(), String> {
+        for rule in rules {
+            if rule.pattern.is_empty() {
+                return Err("key rule has an empty pattern".to_string());
+            }
+            if rule.ttl_seconds.is_none()
+                && rule.cache_name.is_none()
+                && rule.memory_cache.is_none()
+            {
+                return Err(format!(
+                    "key rule for `{}` does not override anything",
+                    rule.pattern
+                ));
+            }
+            if rule.ttl_seconds == Some(0) {
+                return Err(format!("key rule for `{}` has a ttl of 0", rule.pattern));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn rules(&self) -> &[KeyRule] {
+        &self.rules
+    }
+
+    /// The index of the first rule matching `key`, along with the rule.
+    pub fn position(&self, key: &[u8]) -> Option<(usize, &KeyRule)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.matches(key))
+    }
+
+    /// The first rule matching `key`, if any.
+    pub fn rule(&self, key: &[u8]) -> Option<&KeyRule> {
+        self.position(key).map(|(_, rule)| rule)
+    }
+}
+
+// matches the whole of `key` against a glob, backtracking to the most recent
+// `*` on a mismatch, so that patterns are matched in linear space
+fn glob(pattern: &[u8], key: &[u8]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while k < key.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, k));
+                p += 1;
+            }
+            Some(b'?') => {
+                p += 1;
+                k += 1;
+            }
+            Some(b) if *b == key[k] => {
+                p += 1;
+                k += 1;
+            }
+            _ => match star {
+                // let the last star take one more byte
+                Some((star_p, star_k)) => {
+                    star = Some((star_p, star_k + 1));
+                    p = star_p + 1;
+                    k = star_k + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|b| *b == b'*')
+}
@@ -0,0 +1,45 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Tracks whether Momento is throttling the proxy, so that RESP listeners can
+//! refuse writes with the `OOM` error which redis clients already back off
+//! from. Throttling applies to the account rather than to a cache, so the
+//! state is shared by every listener.
+
+use crate::*;
+use core::sync::atomic::AtomicU64;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+#[metric(name = "resp_oom")]
+pub static RESP_OOM: Counter = Counter::new();
+
+// when the current period of pressure ends, in milliseconds since `start()`
+static THROTTLED_UNTIL: AtomicU64 = AtomicU64::new(0);
+
+fn start() -> Instant {
+    static START: OnceLock<Instant> = OnceLock::new();
+    *START.get_or_init(Instant::now)
+}
+
+fn now_ms() -> u64 {
+    start().elapsed().as_millis() as u64
+}
+
+/// Whether the error means Momento is throttling requests.
+pub(crate) fn is_throttled_error(error: &MomentoError) -> bool {
+    matches!(error.error_code, MomentoErrorCode::LimitExceededError)
+}
+
+/// Records that Momento throttled a request, which puts the proxy under
+/// pressure for `cooldown`.
+pub(crate) fn throttled(cooldown: Duration) {
+    let until = now_ms() + cooldown.as_millis() as u64;
+    THROTTLED_UNTIL.fetch_max(until, Ordering::Relaxed);
+}
+
+/// Whether the proxy is under pressure.
+pub(crate) fn is_throttled() -> bool {
+    now_ms() < THROTTLED_UNTIL.load(Ordering::Relaxed)
+}
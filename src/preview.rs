@@ -0,0 +1,138 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Momento preview APIs. Each is compiled in only with its Cargo feature, so
+//! the default build doesn't depend on APIs which may still change, and is
+//! used only when it is also listed in `preview_apis`. A command which needs a
+//! preview API which isn't enabled gets an error saying so, rather than being
+//! served another way.
+
+use crate::momento_proxy::{PreviewApi, ZsetBackend};
+use crate::protocol::resp::Request;
+use crate::*;
+
+#[metric(name = "preview_not_enabled")]
+pub static PREVIEW_NOT_ENABLED: Counter = Counter::new();
+
+#[cfg(feature = "preview-leaderboards")]
+pub(crate) use momento::leaderboard::LeaderboardClient as Leaderboards;
+
+/// Stands in for the leaderboard client in builds without leaderboards, and
+/// can't be created.
+#[cfg(not(feature = "preview-leaderboards"))]
+#[derive(Clone)]
+pub(crate) enum Leaderboards {}
+
+/// Warns about each preview API in the config which this build doesn't
+/// include.
+pub(crate) fn check(config: &MomentoProxyConfig) {
+    for api in config.preview_apis() {
+        if !api.is_compiled() {
+            warn!(
+                "preview api `{}` is listed in `preview_apis`, but this build does not include it. Rebuild with `--features {}` to enable it",
+                api.name(),
+                api.feature()
+            );
+        }
+    }
+}
+
+/// The leaderboard client for a cache which serves its sorted sets from
+/// leaderboards, or `None` if it doesn't or leaderboards aren't enabled.
+pub(crate) fn leaderboards(
+    config: &MomentoProxyConfig,
+    cache: &momento_proxy::Cache,
+    credential_provider: &CredentialProvider,
+) -> Result<Option<Leaderboards>, String> {
+    if cache.zset_backend() != ZsetBackend::Leaderboard
+        || !config.preview_enabled(PreviewApi::Leaderboards)
+    {
+        return Ok(None);
+    }
+
+    leaderboard_client(cache, credential_provider).map(Some)
+}
+
+#[cfg(feature = "preview-leaderboards")]
+fn leaderboard_client(
+    cache: &momento_proxy::Cache,
+    credential_provider: &CredentialProvider,
+) -> Result<Leaderboards, String> {
+    Leaderboards::builder()
+        .configuration(momento::leaderboard::configurations::LowLatency::latest())
+        .credential_provider(credential_provider.clone())
+        .build()
+        .map_err(|e| {
+            format!(
+                "could not create leaderboard client for cache `{}`: {e}",
+                cache.cache_name()
+            )
+        })
+}
+
+#[cfg(not(feature = "preview-leaderboards"))]
+fn leaderboard_client(
+    _cache: &momento_proxy::Cache,
+    _credential_provider: &CredentialProvider,
+) -> Result<Leaderboards, String> {
+    unreachable!("leaderboards are never enabled without the `preview-leaderboards` feature")
+}
+
+/// Serves a sorted set command for a cache with `zset_backend =
+/// "leaderboard"`, or replies that leaderboards are not enabled if there is
+/// no client. Returns `None` if the request is not a sorted set command.
+#[cfg_attr(not(feature = "preview-leaderboards"), allow(unused_variables))]
+pub(crate) async fn leaderboard_command(
+    leaderboards: Option<&Leaderboards>,
+    client: &mut CacheClient,
+    cache_name: &str,
+    request: &Request,
+    proxy_metrics: &impl RespMetrics,
+    response_buf: &mut Vec<u8>,
+) -> Option<ProxyResult> {
+    match leaderboards {
+        #[cfg(feature = "preview-leaderboards")]
+        Some(leaderboards) => {
+            protocol::resp::leaderboard_command(
+                leaderboards,
+                client,
+                cache_name,
+                request,
+                proxy_metrics,
+                response_buf,
+            )
+            .await
+        }
+        #[cfg(not(feature = "preview-leaderboards"))]
+        Some(leaderboards) => match *leaderboards {},
+        None => is_sorted_set(request).then(|| not_enabled(PreviewApi::Leaderboards)),
+    }
+}
+
+fn is_sorted_set(request: &Request) -> bool {
+    matches!(
+        request,
+        Request::SortedSetAdd(_)
+            | Request::SortedSetCardinality(_)
+            | Request::SortedSetCount(_)
+            | Request::SortedSetIncrement(_)
+            | Request::SortedSetMultiScore(_)
+            | Request::SortedSetRange(_)
+            | Request::SortedSetRank(_)
+            | Request::SortedSetRemove(_)
+            | Request::SortedSetReverseRank(_)
+            | Request::SortedSetScore(_)
+            | Request::SortedSetUnionStore(_)
+    )
+}
+
+// the error for a command which needs a preview API which isn't enabled
+fn not_enabled(api: PreviewApi) -> ProxyResult {
+    PREVIEW_NOT_ENABLED.increment();
+    debug!("preview api `{}` is not enabled", api.name());
+
+    Err(ProxyError::custom(match api {
+        PreviewApi::Leaderboards => "leaderboards are not enabled on this proxy",
+    }))
+}
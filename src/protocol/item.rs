@@ -0,0 +1,35 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Metadata about items, as reported by both frontends: RESP TTL and PTTL,
+//! and the `t` flag of a memcache meta get. Momento keeps a TTL for every
+//! item, from the request or the cache default, so an item written through
+//! the proxy always has one.
+
+use std::time::Duration;
+
+use momento::cache::ItemGetTtlResponse;
+use momento::CacheClient;
+
+use crate::deadline::backend_timeout;
+use crate::error::ProxyResult;
+
+/// Reads the remaining TTL of an item, which is `None` if the item is not in
+/// the cache.
+pub(crate) async fn remaining_ttl(
+    client: &CacheClient,
+    cache_name: &str,
+    key: &[u8],
+) -> ProxyResult<Option<Duration>> {
+    match tokio::time::timeout(backend_timeout(), client.item_get_ttl(cache_name, key)).await?? {
+        ItemGetTtlResponse::Hit { remaining_ttl } => Ok(Some(remaining_ttl)),
+        ItemGetTtlResponse::Miss => Ok(None),
+    }
+}
+
+/// A remaining TTL in whole seconds, rounded to the nearest second as redis
+/// rounds TTL.
+pub(crate) fn ttl_seconds(ttl: Duration) -> u64 {
+    ((ttl.as_millis() + 500) / 1000) as u64
+}
@@ -2,16 +2,32 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
+use crate::keys::KeyTransform;
 use crate::klog::{klog_1, Status};
+use crate::metrics::batch;
+use crate::routing::{self, Target, ROUTE_FAILOVER};
 use crate::{Error, *};
 use protocol_memcache::*;
 
+#[metric(name = "delete_exists_check")]
+pub static DELETE_EXISTS_CHECK: Counter = Counter::new();
+
+#[metric(name = "delete_exists_check_ex")]
+pub static DELETE_EXISTS_CHECK_EX: Counter = Counter::new();
+
+/// Deletes the key. With `strict`, the key is first looked up so that a
+/// missing key gets `NOT_FOUND`, at the cost of an extra round trip.
 pub async fn delete(
     client: &mut CacheClient,
-    cache_name: &str,
+    target: Target<'_>,
     request: &Delete,
+    key_transform: &KeyTransform,
+    strict: bool,
+    recorder: &RpcCallGuard,
 ) -> Result<Response, Error> {
-    DELETE.increment();
+    let mut recorder = recorder.clone();
+
+    batch::increment(&DELETE);
 
     let key = request.key().to_owned();
 
@@ -23,20 +39,71 @@ pub async fn delete(
         return Ok(Response::client_error("invalid key"));
     }
 
-    BACKEND_REQUEST.increment();
+    let backend_key = key_transform.apply(&key).into_owned();
+
+    // a key which is known to be missing gets NOT_FOUND. If the check fails,
+    // the key is deleted anyway, the same as without the check
+    if strict {
+        DELETE_EXISTS_CHECK.increment();
+        batch::increment(&BACKEND_REQUEST);
+
+        match timeout(
+            backend_timeout(),
+            client.key_exists(target.cache_name, backend_key.clone()),
+        )
+        .await
+        {
+            Ok(Ok(response)) => {
+                if !response.exists {
+                    batch::increment(&DELETE_NOT_FOUND);
+                    recorder.complete_ok();
+
+                    klog_1(&"delete", &key, Status::NotFound, 0);
+                    return Ok(Response::not_found(request.noreply()));
+                }
+            }
+            result => {
+                BACKEND_EX.increment();
+                if result.is_err() {
+                    BACKEND_EX_TIMEOUT.increment();
+                }
+                DELETE_EXISTS_CHECK_EX.increment();
+            }
+        }
+    }
+
+    batch::increment(&BACKEND_REQUEST);
 
-    match timeout(
-        Duration::from_millis(200),
-        client.delete(cache_name, key.clone()),
+    let mut result = timeout(
+        backend_timeout(),
+        client.delete(target.cache_name, backend_key.clone()),
     )
-    .await
-    {
+    .await;
+
+    if let (false, Some(failover)) = (matches!(result, Ok(Ok(_))), target.failover) {
+        ROUTE_FAILOVER.increment();
+        batch::increment(&BACKEND_REQUEST);
+        result = timeout(
+            backend_timeout(),
+            client.delete(failover, backend_key.clone()),
+        )
+        .await;
+    }
+
+    if let Some(shadow) = target.shadow {
+        let client = client.clone();
+        let shadow = shadow.to_string();
+        routing::shadow(async move { client.delete(shadow, backend_key).await });
+    }
+
+    match result {
         Ok(Ok(_result)) => {
-            // it appears we can't tell deleted from not found in the momento
-            // protocol, so we treat all non-error responses as if the key has
-            // been deleted
+            // we can't tell deleted from not found in the momento protocol,
+            // so unless the key was checked first, we treat all non-error
+            // responses as if the key has been deleted
 
-            DELETE_DELETED.increment();
+            batch::increment(&DELETE_DELETED);
+            recorder.complete_ok();
 
             if request.noreply() {
                 klog_1(&"delete", &key, Status::Deleted, 0);
@@ -49,6 +116,7 @@ pub async fn delete(
         }
         Ok(Err(e)) => {
             BACKEND_EX.increment();
+            recorder.complete_error();
 
             DELETE_EX.increment();
             SESSION_SEND.increment();
@@ -62,6 +130,7 @@ pub async fn delete(
             // timeout
             BACKEND_EX.increment();
             BACKEND_EX_TIMEOUT.increment();
+            recorder.complete_timeout();
 
             DELETE_EX.increment();
             SESSION_SEND.increment();
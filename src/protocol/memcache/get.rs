@@ -3,66 +3,186 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use crate::cache::CacheValue;
+use crate::keys::KeyTransform;
 use crate::klog::{klog_1, Status};
+use crate::metrics::batch;
+use crate::momento_proxy::MultiGetFailurePolicy;
+use crate::routing::ROUTE_FAILOVER;
+use crate::shards::Shards;
 use crate::{Error, *};
-use futures::StreamExt;
-use momento::cache::GetResponse;
+use futures::{FutureExt, StreamExt};
+use momento::cache::{GetResponse, ItemGetTtlResponse};
 use protocol_memcache::*;
 
+use super::ValueCodec;
+
+#[metric(name = "get_partial")]
+pub static GET_PARTIAL: Counter = Counter::new();
+
+#[metric(name = "get_partial_key_stale")]
+pub static GET_PARTIAL_KEY_STALE: Counter = Counter::new();
+
+// how long ago the stale entries which were served had been stored, in
+// milliseconds
+#[metric(name = "get_partial_key_stale_age")]
+pub static GET_PARTIAL_KEY_STALE_AGE: AtomicHistogram = AtomicHistogram::new(7, 64);
+
+#[metric(name = "get_too_many_keys")]
+pub static GET_TOO_MANY_KEYS: Counter = Counter::new();
+
+#[metric(name = "get_backfill_ttl_unknown")]
+pub static GET_BACKFILL_TTL_UNKNOWN: Counter = Counter::new();
+
+#[metric(name = "get_keys_per_request")]
+pub static GET_KEYS_PER_REQUEST: AtomicHistogram = AtomicHistogram::new(7, 64);
+
+/// Gets each key, from the memory cache when it holds the key and otherwise
+/// from Momento with at most `concurrency` backend requests in flight.
+/// Requests for more than `max_keys` keys are rejected. With `preserve_ttl`,
+/// the remaining TTL of each item is read alongside its value so that the
+/// memory cache doesn't hold it past its expiry in Momento.
 pub async fn get(
     client: &CacheClient,
-    cache_name: &str,
+    shards: &Shards,
     request: &Get,
-    flags: bool,
+    key_transform: &KeyTransform,
+    codec: ValueCodec,
+    failure_policy: MultiGetFailurePolicy,
+    concurrency: usize,
+    max_keys: Option<usize>,
     memory_cache: Option<MCache>,
+    preserve_ttl: bool,
     recorder: &RpcCallGuard,
 ) -> Result<Response, Error> {
-    let mut tasks = futures::stream::FuturesOrdered::new();
+    let _ = GET_KEYS_PER_REQUEST.increment(request.keys().len() as _);
+
+    if max_keys.is_some_and(|max| request.keys().len() > max) {
+        GET_TOO_MANY_KEYS.increment();
+        return Ok(Response::client_error("too many keys"));
+    }
+
+    let fetch_ttl = preserve_ttl && memory_cache.is_some();
+
+    // a get of a single key fails as it always has, whatever the policy for
+    // multi-key gets
+    let failure_policy = if request.keys().len() > 1 {
+        failure_policy
+    } else {
+        MultiGetFailurePolicy::Fail
+    };
+
+    let mut tasks = Vec::new();
     let mut eager_hits = Vec::new();
     let mut mcache_recorder = recorder.clone();
     for key in request.keys() {
-        if let Some(memory_cache) = &memory_cache {
-            match memory_cache.get(&**key) {
-                Some(hit) => {
-                    eager_hits.push(match hit.into_value() {
-                        cache::CacheValue::Memcached { value } => value,
-                    });
-                    debug!("eager hit for key {:?}", key);
-                    mcache_recorder.complete_hit_mcache();
-                }
-                None => {
-                    BACKEND_REQUEST.increment();
-                    tasks.push_back(run_get(client, cache_name, flags, key, recorder));
-                }
-            }
+        if let Some(hit) = memory_cache.as_ref().and_then(|c| c.get(&**key)) {
+            let value = match hit.into_value() {
+                cache::CacheValue::Memcached { value } => value,
+            };
+            debug!("eager hit for key {:?}", key);
+            mcache_recorder.set_value_size(value.len().unwrap_or_default());
+            mcache_recorder.complete_hit_mcache();
+            eager_hits.push(value);
         } else {
-            BACKEND_REQUEST.increment();
-            tasks.push_back(run_get(client, cache_name, flags, key, recorder));
+            batch::increment(&BACKEND_REQUEST);
+            // keys are fetched from their own shards
+            let target = shards.target_for(key);
+            let backend_key = key_transform.apply(key).into_owned();
+            tasks.push(
+                async move {
+                    let result = run_get(
+                        client,
+                        target.cache_name,
+                        codec,
+                        fetch_ttl,
+                        key,
+                        backend_key.clone(),
+                        recorder,
+                    )
+                    .await;
+
+                    match (result, target.failover) {
+                        (Err(_), Some(failover)) => {
+                            ROUTE_FAILOVER.increment();
+                            batch::increment(&BACKEND_REQUEST);
+                            run_get(
+                                client,
+                                failover,
+                                codec,
+                                fetch_ttl,
+                                key,
+                                backend_key,
+                                recorder,
+                            )
+                            .await
+                        }
+                        (result, _) => result,
+                    }
+                }
+                .map(move |v| (key, v)),
+            );
         }
     }
 
-    // If we had received an auth or timeout error, we should return the error immediately
-    let values_from_upstream: Vec<Result<Option<protocol_memcache::Value>, Error>> =
-        tasks.collect().await;
-    let mut values: Vec<protocol_memcache::Value> = Vec::new();
-    for value in values_from_upstream.into_iter() {
-        if let Ok(Some(v)) = value {
-            values.push(v);
-        } else if let Err(e) = value {
-            return Ok(Response::server_error(format!("{e}")));
+    // Unless the failure policy allows a partial response, an auth or timeout
+    // error for any key fails the whole request
+    let values_from_upstream: Vec<(_, Result<Option<Fetched>, Error>)> =
+        futures::stream::iter(tasks)
+            .buffered(concurrency)
+            .collect()
+            .await;
+    let mut fetched = Vec::new();
+    let mut stale_hits = Vec::new();
+    let mut partial = false;
+    for (key, value) in values_from_upstream.into_iter() {
+        match value {
+            Ok(Some(v)) => fetched.push(v),
+            Ok(None) => {}
+            Err(e) => match failure_policy {
+                MultiGetFailurePolicy::Fail => {
+                    return Ok(Response::server_error(format!("{e}")));
+                }
+                MultiGetFailurePolicy::Partial => {
+                    partial = true;
+                }
+                MultiGetFailurePolicy::PartialWithStale => {
+                    partial = true;
+                    if let Some(hit) = memory_cache.as_ref().and_then(|c| c.get_stale(&**key)) {
+                        GET_PARTIAL_KEY_STALE.increment();
+                        let _ = GET_PARTIAL_KEY_STALE_AGE.increment(hit.age().as_millis() as _);
+                        stale_hits.push(match hit.into_value() {
+                            cache::CacheValue::Memcached { value } => value,
+                        });
+                    }
+                }
+            },
         }
     }
+    if partial {
+        GET_PARTIAL.increment();
+    }
     if let Some(memory_cache) = &memory_cache {
-        for value in values.iter() {
-            memory_cache.set(
-                value.key().to_vec(),
-                CacheValue::Memcached {
-                    value: value.clone(),
-                },
-            );
+        for (value, ttl) in fetched.iter() {
+            let key = value.key().to_vec();
+            let value = CacheValue::Memcached {
+                value: value.clone(),
+            };
+            match ttl {
+                None => {
+                    memory_cache.set(key, value);
+                }
+                // the item is gone from momento or its ttl is unknown
+                Some(ttl) if ttl.is_zero() => {}
+                Some(ttl) => {
+                    memory_cache.set_with_ttl(key, value, *ttl);
+                }
+            }
         }
     }
+    let mut values: Vec<protocol_memcache::Value> =
+        fetched.into_iter().map(|(value, _)| value).collect();
     values.extend(eager_hits);
+    values.extend(stale_hits);
 
     if !values.is_empty() {
         Ok(Response::values(values.into()))
@@ -71,44 +191,75 @@ pub async fn get(
     }
 }
 
+// A value from momento, with the most it may be held by the memory cache if
+// its ttl was read. A ttl of zero means it must not be held at all.
+type Fetched = (protocol_memcache::Value, Option<Duration>);
+
 async fn run_get(
     client: &CacheClient,
     cache_name: &str,
-    flags: bool,
+    codec: ValueCodec,
+    fetch_ttl: bool,
     key: &[u8],
+    backend_key: Vec<u8>,
     recorder: &RpcCallGuard,
-) -> Result<Option<protocol_memcache::Value>, Error> {
+) -> Result<Option<Fetched>, Error> {
     let mut recorder = recorder.clone();
-    match timeout(Duration::from_millis(200), client.get(cache_name, key)).await {
+
+    // the ttl is read concurrently, so it only adds to the latency of a get
+    // when it's the slower of the two calls
+    let ttl = async {
+        if fetch_ttl {
+            Some(
+                timeout(
+                    backend_timeout(),
+                    client.item_get_ttl(cache_name, backend_key.clone()),
+                )
+                .await,
+            )
+        } else {
+            None
+        }
+    };
+    let (response, ttl) = tokio::join!(
+        timeout(
+            backend_timeout(),
+            client.get(cache_name, backend_key.clone())
+        ),
+        ttl
+    );
+    let ttl = ttl.map(|ttl| match ttl {
+        Ok(Ok(ItemGetTtlResponse::Hit { remaining_ttl })) => remaining_ttl,
+        Ok(Ok(ItemGetTtlResponse::Miss)) => Duration::ZERO,
+        _ => {
+            GET_BACKFILL_TTL_UNKNOWN.increment();
+            Duration::ZERO
+        }
+    });
+
+    match response {
         Ok(Ok(response)) => match response {
             GetResponse::Hit { value } => {
-                GET_KEY_HIT.increment();
+                batch::increment(&GET_KEY_HIT);
 
                 let value: Vec<u8> = value.into();
 
-                if flags && value.len() < 5 {
-                    recorder.complete_miss();
-                    klog_1(&"get", &key, Status::Miss, 0);
-                    Ok(None)
-                } else if flags {
-                    let flags: u32 = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
-                    let length = value.len() - 4;
-
-                    recorder.complete_hit_momento();
-                    klog_1(&"get", &key, Status::Hit, length);
-                    Ok(Some(protocol_memcache::Value::new(
-                        key, flags, None, &value[4..],
-                    )))
-                } else {
-                    let length = value.len();
-
-                    recorder.complete_hit_momento();
-                    klog_1(&"get", &key, Status::Hit, length);
-                    Ok(Some(protocol_memcache::Value::new(key, 0, None, &value)))
+                match codec.decode(key, &value) {
+                    Some(value) => {
+                        recorder.set_value_size(value.len().unwrap_or_default());
+                        recorder.complete_hit_momento();
+                        klog_1(&"get", &key, Status::Hit, value.len().unwrap_or_default());
+                        Ok(Some((value, ttl)))
+                    }
+                    None => {
+                        recorder.complete_miss();
+                        klog_1(&"get", &key, Status::Miss, 0);
+                        Ok(None)
+                    }
                 }
             }
             GetResponse::Miss => {
-                GET_KEY_MISS.increment();
+                batch::increment(&GET_KEY_MISS);
 
                 recorder.complete_miss();
                 klog_1(&"get", &key, Status::Miss, 0);
@@ -122,6 +273,8 @@ async fn run_get(
             error!("backend error for get: {}", e);
             BACKEND_EX.increment();
 
+            recorder.complete_error();
+
             klog_1(&"get", &key, Status::ServerError, 0);
             Err(Error::new(ErrorKind::Other, format!("{e}")))
         }
@@ -130,6 +283,8 @@ async fn run_get(
             BACKEND_EX.increment();
             BACKEND_EX_TIMEOUT.increment();
 
+            recorder.complete_timeout();
+
             klog_1(&"get", &key, Status::Timeout, 0);
             Err(Error::new(ErrorKind::Other, format!("backend timeout")))
         }
@@ -0,0 +1,196 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! The meta get command, `mg <key> <flags>*`, for the flags which can be
+//! answered from Momento: `v` for the value, `t` for the remaining TTL in
+//! seconds, `f` for the client flags, `s` for the size, `k` for the key, `O`
+//! for an opaque token, and `q` to send nothing on a miss. Meta get isn't
+//! known to the memcache parser, so it is framed here and served from Momento
+//! rather than the memory cache, whose TTLs may be shorter.
+
+use momento::cache::GetResponse;
+
+use super::ValueCodec;
+use crate::error::ProxyError;
+use crate::keys::KeyTransform;
+use crate::klog::{klog_1, Status};
+use crate::protocol::item::{remaining_ttl, ttl_seconds};
+use crate::shards::Shards;
+use crate::{Error, *};
+
+// the longest key memcache accepts
+const MAX_KEY_LEN: usize = 250;
+
+/// A meta get request.
+pub struct MetaGet {
+    key: Vec<u8>,
+    flags: Vec<MetaFlag>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum MetaFlag {
+    Value,
+    Ttl,
+    ClientFlags,
+    Size,
+    Key,
+    Opaque(String),
+    Quiet,
+}
+
+impl MetaGet {
+    /// Frames a meta get at the start of the buffer. Returns `None` if the
+    /// buffer doesn't start with a complete meta get, and otherwise the bytes
+    /// it takes up with either the request or the error to reply with.
+    pub fn parse(buf: &[u8]) -> Option<(usize, Result<Self, &'static str>)> {
+        let end = buf.iter().position(|b| *b == b'\n')?;
+        let line = std::str::from_utf8(&buf[..end]).ok()?;
+        let mut tokens = line.trim_end_matches('\r').split_whitespace();
+        if tokens.next() != Some("mg") {
+            return None;
+        }
+
+        let Some(key) = tokens.next() else {
+            return Some((end + 1, Err("bad command line format")));
+        };
+        if key.len() > MAX_KEY_LEN {
+            return Some((end + 1, Err("key too long")));
+        }
+
+        let mut flags = Vec::new();
+        for token in tokens {
+            let (flag, arg) = token.split_at(token.chars().next().map_or(0, char::len_utf8));
+            let flag = match (flag, arg) {
+                ("v", "") => MetaFlag::Value,
+                ("t", "") => MetaFlag::Ttl,
+                ("f", "") => MetaFlag::ClientFlags,
+                ("s", "") => MetaFlag::Size,
+                ("k", "") => MetaFlag::Key,
+                ("q", "") => MetaFlag::Quiet,
+                ("O", opaque) if opaque.len() <= 32 => MetaFlag::Opaque(opaque.to_string()),
+                _ => return Some((end + 1, Err("invalid flag"))),
+            };
+            flags.push(flag);
+        }
+
+        Some((
+            end + 1,
+            Ok(Self {
+                key: key.as_bytes().to_vec(),
+                flags,
+            }),
+        ))
+    }
+
+    /// The key of the request.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    fn has(&self, flag: MetaFlag) -> bool {
+        self.flags.contains(&flag)
+    }
+}
+
+/// Serves a meta get, returning the composed response. The value is only read
+/// when a flag asks for it, and the TTL only with `t`. A request for neither
+/// reads the TTL to find whether the key exists.
+pub async fn meta_get(
+    client: &CacheClient,
+    shards: &Shards,
+    request: &MetaGet,
+    key_transform: &KeyTransform,
+    codec: ValueCodec,
+) -> Result<Vec<u8>, Error> {
+    let key = request.key();
+    let cache_name = shards.target_for(key).cache_name;
+    let backend_key = key_transform.apply(key).into_owned();
+
+    let read_value = request.has(MetaFlag::Value)
+        || request.has(MetaFlag::ClientFlags)
+        || request.has(MetaFlag::Size);
+    let read_ttl = request.has(MetaFlag::Ttl) || !read_value;
+
+    let value = async {
+        if !read_value {
+            return Ok(None);
+        }
+        let response = timeout(
+            backend_timeout(),
+            client.get(cache_name, backend_key.as_slice()),
+        )
+        .await??;
+        Ok::<_, ProxyError>(match response {
+            GetResponse::Hit { value } => {
+                let value: Vec<u8> = value.into();
+                codec.decode(key, &value)
+            }
+            GetResponse::Miss => None,
+        })
+    };
+    let ttl = async {
+        if read_ttl {
+            remaining_ttl(client, cache_name, &backend_key).await
+        } else {
+            Ok(None)
+        }
+    };
+
+    let (value, ttl) = match tokio::join!(value, ttl) {
+        (Ok(value), Ok(ttl)) => (value, ttl),
+        (Err(e), _) | (_, Err(e)) => {
+            BACKEND_EX.increment();
+            let status = match e {
+                ProxyError::Timeout(_) => {
+                    BACKEND_EX_TIMEOUT.increment();
+                    Status::Timeout
+                }
+                _ => Status::ServerError,
+            };
+            klog_1(&"mg", &key, status, 0);
+            return Err(Error::new(ErrorKind::Other, format!("{e}")));
+        }
+    };
+
+    // an item which expired between the two reads is a miss
+    let hit = (!read_value || value.is_some()) && (!read_ttl || ttl.is_some());
+    if !hit {
+        klog_1(&"mg", &key, Status::Miss, 0);
+        if request.has(MetaFlag::Quiet) {
+            return Ok(Vec::new());
+        }
+        return Ok(b"EN\r\n".to_vec());
+    }
+
+    let (flags, data) = match &value {
+        Some(value) => (value.flags(), value.value().unwrap_or_default()),
+        None => (0, &[][..]),
+    };
+
+    let mut returned = String::new();
+    for flag in &request.flags {
+        let token = match flag {
+            MetaFlag::Ttl => format!("t{}", ttl.map_or(0, ttl_seconds)),
+            MetaFlag::ClientFlags => format!("f{flags}"),
+            MetaFlag::Size => format!("s{}", data.len()),
+            MetaFlag::Key => format!("k{}", String::from_utf8_lossy(key)),
+            MetaFlag::Opaque(opaque) => format!("O{opaque}"),
+            MetaFlag::Value | MetaFlag::Quiet => continue,
+        };
+        returned.push(' ');
+        returned.push_str(&token);
+    }
+
+    let response = if request.has(MetaFlag::Value) {
+        let mut response = format!("VA {}{returned}\r\n", data.len()).into_bytes();
+        response.extend_from_slice(data);
+        response.extend_from_slice(b"\r\n");
+        response
+    } else {
+        format!("HD{returned}\r\n").into_bytes()
+    };
+    klog_1(&"mg", &key, Status::Hit, response.len());
+
+    Ok(response)
+}
@@ -6,8 +6,14 @@ pub use protocol_memcache::Request;
 
 mod delete;
 mod get;
+mod meta;
 mod set;
+mod ttl;
+mod value;
 
 pub use delete::*;
 pub use get::*;
+pub use meta::*;
 pub use set::*;
+pub use ttl::*;
+pub use value::*;
@@ -2,20 +2,38 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
-use crate::cache::CacheValue;
+use crate::cache::{CacheValue, CacheWrite, MEMORY_CACHE_SET_INVALIDATE};
+use crate::enforce::{self, Policy};
+use crate::keys::KeyTransform;
 use crate::klog::{klog_set, Status};
+use crate::metrics::batch;
+use crate::momento_proxy::MemoryCacheWriteFailurePolicy;
+use crate::routing::{self, Target, ROUTE_FAILOVER};
 use crate::{Error, *};
 use momento::cache::SetRequest;
 use protocol_memcache::*;
 
+use super::{Expiry, ValueCodec};
+
+#[metric(name = "set_too_large")]
+pub static SET_TOO_LARGE: Counter = Counter::new();
+
 pub async fn set(
     client: &mut CacheClient,
-    cache_name: &str,
+    target: Target<'_>,
     request: &Set,
-    flags: bool,
+    key_transform: &KeyTransform,
+    codec: ValueCodec,
+    default_ttl: Duration,
+    max_item_size: usize,
     memory_cache: Option<MCache>,
+    write_failure_policy: MemoryCacheWriteFailurePolicy,
+    recorder: &RpcCallGuard,
 ) -> Result<Response, Error> {
-    SET.increment();
+    let mut recorder = recorder.clone();
+    recorder.set_value_size(request.value().len());
+
+    batch::increment(&SET);
 
     if request.value().is_empty() {
         SET_EX.increment();
@@ -28,14 +46,54 @@ pub async fn set(
     // The value does not yet have flags embedded.
     let value_len = request.value().len();
 
-    let value = if flags {
-        let mut value = request.flags().to_be_bytes().to_vec();
-        value.extend_from_slice(request.value());
-        value
-    } else {
-        (*request.value()).to_owned()
+    let value = codec.encode(request.flags(), request.value());
+
+    // momento would refuse the item with an opaque error, so it is refused
+    // here with the error memcached gives, and isn't counted as a failure
+    if max_item_size > 0
+        && value.len() > max_item_size
+        && enforce::enforce(Policy::ItemSize, || {
+            format!(
+                "set of {} bytes to {}",
+                value.len(),
+                String::from_utf8_lossy(&key)
+            )
+        })
+    {
+        SET_TOO_LARGE.increment();
+        klog_set(
+            &key,
+            request.flags(),
+            request.ttl().get().unwrap_or(0),
+            value_len,
+            Status::NotStored,
+            0,
+        );
+        return Ok(Response::server_error("object too large for cache"));
+    }
+
+    // the parser has already converted absolute exptimes to a relative ttl
+    let ttl = match Expiry::from_ttl(request.ttl().get()) {
+        Expiry::Expired => {
+            // memcached accepts writes with an expiry in the past, but the
+            // item is never visible afterwards, so remove any existing value
+            return expire(
+                client,
+                target,
+                request,
+                key_transform,
+                value_len,
+                memory_cache,
+                &mut recorder,
+            )
+            .await;
+        }
+        // a key rule may give the write a ttl when the client didn't
+        expiry => expiry.ttl().or(target.ttl),
     };
 
+    let mut local_write = None;
+
     if let Some(memory_cache) = &memory_cache {
         // On write, populate the local in-memory cache immediately.
         //
@@ -46,26 +104,64 @@ pub async fn set(
         // This ensures that:
         // (1) A proxy process restart doesn't degrade performance (cache warms on read)
         // (2) Multiple proxies each keep a warm local cache, even if writes are done by others
-        let flags = if flags { request.flags() } else { 0 };
+        let flags = codec.flags(request.flags());
         let value = protocol_memcache::Value::new(&key, flags, None, &request.value());
-        memory_cache.set(key.to_vec(), CacheValue::Memcached { value });
+        local_write = Some(memory_cache.set_with_ttl(
+            key.to_vec(),
+            CacheValue::Memcached { value },
+            ttl.unwrap_or(default_ttl),
+        ));
     }
 
-    BACKEND_REQUEST.increment();
+    batch::increment(&BACKEND_REQUEST);
 
-    let ttl = request
-        .ttl()
-        .get()
-        .map(|ttl| Duration::from_secs(ttl.max(1) as u64));
+    let backend_key = key_transform.apply(&key).into_owned();
+    let set_request =
+        |cache_name: &str| SetRequest::new(cache_name, backend_key.clone(), value.clone()).ttl(ttl);
 
-    match timeout(
-        Duration::from_millis(200),
-        client.send_request(SetRequest::new(cache_name, key.clone(), value.clone()).ttl(ttl)),
+    let mut result = timeout(
+        backend_timeout(),
+        client.send_request(set_request(target.cache_name)),
     )
-    .await
-    {
+    .await;
+
+    if let (false, Some(failover)) = (matches!(result, Ok(Ok(_))), target.failover) {
+        ROUTE_FAILOVER.increment();
+        batch::increment(&BACKEND_REQUEST);
+        result = timeout(
+            backend_timeout(),
+            client.send_request(set_request(failover)),
+        )
+        .await;
+    }
+
+    if let Some(shadow) = target.shadow {
+        let client = client.clone();
+        let set_request = set_request(shadow);
+        routing::shadow(async move { client.send_request(set_request).await });
+    }
+
+    match result {
         Ok(Ok(_result)) => {
-            SET_STORED.increment();
+            batch::increment(&SET_STORED);
+            recorder.complete_ok();
+
+            if local_write == Some(CacheWrite::Failed)
+                && write_failure_policy == MemoryCacheWriteFailurePolicy::ServerError
+            {
+                SET_EX.increment();
+
+                klog_set(
+                    &key,
+                    request.flags(),
+                    request.ttl().get().unwrap_or(0),
+                    value_len,
+                    Status::ServerError,
+                    0,
+                );
+
+                return Ok(Response::server_error("memory cache write failed"));
+            }
 
             if request.noreply() {
                 klog_set(
@@ -93,10 +189,13 @@ pub async fn set(
         }
         Ok(Err(e)) => {
             BACKEND_EX.increment();
+            recorder.complete_error();
 
             SET_EX.increment();
             SESSION_SEND.increment();
 
+            invalidate(&memory_cache, &key);
+
             klog_set(
                 &key,
                 request.flags(),
@@ -113,10 +212,13 @@ pub async fn set(
             // timeout
             BACKEND_EX.increment();
             BACKEND_EX_TIMEOUT.increment();
+            recorder.complete_timeout();
 
             SET_EX.increment();
             SESSION_SEND.increment();
 
+            invalidate(&memory_cache, &key);
+
             klog_set(
                 &key,
                 request.flags(),
@@ -130,3 +232,82 @@ pub async fn set(
         }
     }
 }
+
+// the memory cache was written ahead of momento, so the entry must not outlive
+// a failed backend write or it would serve a value momento never stored
+fn invalidate(memory_cache: &Option<MCache>, key: &[u8]) {
+    if let Some(memory_cache) = memory_cache {
+        if memory_cache.delete(key).is_some() {
+            MEMORY_CACHE_SET_INVALIDATE.increment();
+        }
+    }
+}
+
+async fn expire(
+    client: &mut CacheClient,
+    target: Target<'_>,
+    request: &Set,
+    key_transform: &KeyTransform,
+    value_len: usize,
+    memory_cache: Option<MCache>,
+    recorder: &mut RpcCallGuard,
+) -> Result<Response, Error> {
+    let key = request.key();
+
+    if let Some(memory_cache) = &memory_cache {
+        memory_cache.delete(key);
+    }
+
+    batch::increment(&BACKEND_REQUEST);
+
+    let backend_key = key_transform.apply(key).into_owned();
+
+    let mut result = timeout(
+        backend_timeout(),
+        client.delete(target.cache_name, backend_key.clone()),
+    )
+    .await;
+
+    if let (false, Some(failover)) = (matches!(result, Ok(Ok(_))), target.failover) {
+        ROUTE_FAILOVER.increment();
+        batch::increment(&BACKEND_REQUEST);
+        result = timeout(
+            backend_timeout(),
+            client.delete(failover, backend_key.clone()),
+        )
+        .await;
+    }
+
+    if let Some(shadow) = target.shadow {
+        let client = client.clone();
+        let shadow = shadow.to_string();
+        routing::shadow(async move { client.delete(shadow, backend_key).await });
+    }
+
+    let status = match result {
+        Ok(Ok(_)) => {
+            batch::increment(&SET_STORED);
+            recorder.complete_ok();
+            Status::Stored
+        }
+        Ok(Err(e)) => {
+            BACKEND_EX.increment();
+            SET_EX.increment();
+            recorder.complete_error();
+            error!("backend error for set: {}", e);
+            klog_set(&key, request.flags(), -1, value_len, Status::ServerError, 0);
+            return Ok(Response::server_error(format!("{e}")));
+        }
+        Err(_) => {
+            BACKEND_EX.increment();
+            BACKEND_EX_TIMEOUT.increment();
+            SET_EX.increment();
+            recorder.complete_timeout();
+            klog_set(&key, request.flags(), -1, value_len, Status::Timeout, 0);
+            return Ok(Response::server_error("backend timeout"));
+        }
+    };
+
+    klog_set(&key, request.flags(), -1, value_len, status, 0);
+    Ok(Response::stored(request.noreply()))
+}
@@ -0,0 +1,137 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Memcached treats expiration times larger than 30 days as absolute unix
+/// timestamps rather than a number of seconds from now.
+pub const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+
+/// The expiration requested by a memcache client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// No expiration was provided, the cache default TTL applies.
+    Default,
+    /// The item expires after the duration.
+    After(Duration),
+    /// The item is already expired and must not be stored.
+    Expired,
+}
+
+impl Expiry {
+    /// Interprets a memcache exptime the same way memcached does. No exptime
+    /// means the cache default applies, non-positive values expire
+    /// immediately, values up to 30 days are relative, and anything larger is
+    /// an absolute unix timestamp.
+    pub fn from_exptime(exptime: Option<i32>, now_unix_secs: u64) -> Self {
+        let exptime = match exptime {
+            None => return Expiry::Default,
+            Some(v) if v <= 0 => return Expiry::Expired,
+            Some(v) => v as i64,
+        };
+
+        let seconds = if exptime > MAX_RELATIVE_EXPTIME {
+            exptime - now_unix_secs as i64
+        } else {
+            exptime
+        };
+
+        if seconds <= 0 {
+            Expiry::Expired
+        } else {
+            Expiry::After(Duration::from_secs(seconds as u64))
+        }
+    }
+
+    /// Interprets a ttl which the memcache parser has already converted from
+    /// the client's exptime, so it is always relative. No ttl means the cache
+    /// default applies and non-positive values expire immediately.
+    pub fn from_ttl(ttl: Option<i32>) -> Self {
+        match ttl {
+            None => Expiry::Default,
+            Some(v) if v <= 0 => Expiry::Expired,
+            Some(v) => Expiry::After(Duration::from_secs(v as u64)),
+        }
+    }
+
+    /// Like `from_exptime` using the current system time.
+    pub fn from_exptime_now(exptime: Option<i32>) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        Self::from_exptime(exptime, now)
+    }
+
+    /// The TTL to send to Momento, `None` meaning the client default.
+    pub fn ttl(&self) -> Option<Duration> {
+        match self {
+            Expiry::Default => None,
+            Expiry::After(ttl) => Some(*ttl),
+            Expiry::Expired => Some(Duration::ZERO),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn relative_up_to_thirty_days() {
+        assert_eq!(
+            Expiry::from_exptime(Some(2592000), NOW),
+            Expiry::After(Duration::from_secs(2592000))
+        );
+    }
+
+    #[test]
+    fn absolute_past_thirty_days() {
+        // as an absolute time, 2592001 is long in the past
+        assert_eq!(Expiry::from_exptime(Some(2592001), NOW), Expiry::Expired);
+        assert_eq!(
+            Expiry::from_exptime(Some(2592001), 2592000),
+            Expiry::After(Duration::from_secs(1))
+        );
+        assert_eq!(
+            Expiry::from_exptime(Some(NOW as i32 + 3600), NOW),
+            Expiry::After(Duration::from_secs(3600))
+        );
+    }
+
+    #[test]
+    fn absolute_in_the_past() {
+        assert_eq!(
+            Expiry::from_exptime(Some(NOW as i32 - 10), NOW),
+            Expiry::Expired
+        );
+        assert_eq!(Expiry::from_exptime(Some(NOW as i32), NOW), Expiry::Expired);
+    }
+
+    #[test]
+    fn negative_and_missing() {
+        assert_eq!(Expiry::from_exptime(Some(-1), NOW), Expiry::Expired);
+        assert_eq!(Expiry::from_exptime(Some(i32::MIN), NOW), Expiry::Expired);
+        assert_eq!(Expiry::from_exptime(Some(0), NOW), Expiry::Expired);
+        assert_eq!(Expiry::from_exptime(None, NOW), Expiry::Default);
+        assert_eq!(Expiry::from_ttl(Some(-1)), Expiry::Expired);
+        assert_eq!(Expiry::from_ttl(None), Expiry::Default);
+    }
+
+    #[test]
+    fn parsed_ttls_are_not_converted_again() {
+        // an exptime 60 days out, which the parser has made relative
+        assert_eq!(
+            Expiry::from_ttl(Some(5184000)),
+            Expiry::After(Duration::from_secs(5184000))
+        );
+        assert_eq!(
+            Expiry::from_ttl(Some(2592001)),
+            Expiry::After(Duration::from_secs(2592001))
+        );
+    }
+}
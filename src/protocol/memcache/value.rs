@@ -0,0 +1,91 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Converts memcache values to and from the form they are stored in Momento.
+//! How values are stored is set by `value_format`, separately from whether a
+//! listener supports flags, so that listeners with and without flags can
+//! share a cache. A listener without flags stores zero flags and returns zero
+//! flags, whatever is stored. With `value_compression`, the value after the
+//! flags is compressed with the dictionaries of the cache.
+
+use crate::compression::{self, Dictionaries};
+use crate::momento_proxy::{Cache, ValueCompression, ValueFormat};
+use std::borrow::Cow;
+
+/// How a listener stores its values, and whether its clients see the flags.
+#[derive(Clone, Copy, Debug)]
+pub struct ValueCodec {
+    format: ValueFormat,
+    flags: bool,
+    dictionaries: Option<&'static Dictionaries>,
+    // the longest value which is decompressed
+    max_len: usize,
+}
+
+impl ValueCodec {
+    /// The codec for a listener.
+    pub fn new(config: &Cache) -> Self {
+        Self {
+            format: config.value_format(),
+            flags: config.flags(),
+            dictionaries: match config.value_compression() {
+                ValueCompression::None => None,
+                ValueCompression::ZstdDictionary => {
+                    Some(compression::dictionaries(&config.cache_name()))
+                }
+            },
+            max_len: match config.max_item_size() {
+                0 => compression::MAX_VALUE_LEN,
+                max_item_size => max_item_size,
+            },
+        }
+    }
+
+    /// The flags kept from a request, which are zero without flags support.
+    pub fn flags(&self, flags: u32) -> u32 {
+        if self.flags {
+            flags
+        } else {
+            0
+        }
+    }
+
+    /// Converts a memcache value into the form it is stored in Momento.
+    pub fn encode(&self, flags: u32, value: &[u8]) -> Vec<u8> {
+        let mut stored = match self.format {
+            ValueFormat::Flags => self.flags(flags).to_be_bytes().to_vec(),
+            ValueFormat::Raw => Vec::with_capacity(value.len()),
+        };
+        match self.dictionaries {
+            Some(dictionaries) => dictionaries.encode_into(value, &mut stored),
+            None => stored.extend_from_slice(value),
+        }
+        stored
+    }
+
+    /// Converts a value as stored in Momento into a memcache value. Returns
+    /// `None` if the stored value is too short to contain the flags, or can't
+    /// be decompressed.
+    pub fn decode(&self, key: &[u8], value: &[u8]) -> Option<protocol_memcache::Value> {
+        let (flags, value) = match self.format {
+            ValueFormat::Flags if value.len() < 5 => return None,
+            ValueFormat::Flags => (
+                u32::from_be_bytes([value[0], value[1], value[2], value[3]]),
+                &value[4..],
+            ),
+            ValueFormat::Raw => (0, value),
+        };
+        let value = match self.dictionaries {
+            Some(dictionaries) => dictionaries.decode(value, self.max_len)?,
+            None => Cow::Borrowed(value),
+        };
+
+        Some(protocol_memcache::Value::new(
+            key,
+            self.flags(flags),
+            None,
+            &*value,
+        ))
+    }
+}
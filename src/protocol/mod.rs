@@ -2,5 +2,6 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
+pub(crate) mod item;
 pub mod memcache;
 pub mod resp;
@@ -0,0 +1,493 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! SETBIT, GETBIT, BITCOUNT, and BITOP. Momento has no bit operations, so the
+//! whole value is read, the bits are worked on in the proxy, and the result is
+//! written back. As with PFADD, SETBIT only writes if the value is still the
+//! one which was read, and retries otherwise. Values which would grow past
+//! `bit_operations_max_bytes` are refused, since each bit set on them would
+//! rewrite the whole value. With `resp_memcache_flags`, the memcache flags
+//! ahead of the value are skipped and kept, as they are by GET and SET.
+
+use metriken::{metric, Counter};
+use momento::cache::SetRequest;
+use momento::CacheClient;
+use std::time::Duration;
+use tokio::time::timeout;
+
+use super::{read_for_update, read_value, update_method_metrics, Command, FLAGS_LEN};
+use crate::deadline::backend_timeout;
+use crate::error::{ProxyError, ProxyResult};
+use crate::klog::{klog_1, Status};
+use crate::metrics::{with_rpc_call_guard, RespMetrics};
+use crate::shards::{Router, SHARD_CROSSSLOT};
+
+// how many times a conflicting write is retried before giving up
+const MAX_ATTEMPTS: usize = 8;
+
+#[metric(name = "setbit")]
+pub static SETBIT: Counter = Counter::new();
+
+#[metric(name = "setbit_ex")]
+pub static SETBIT_EX: Counter = Counter::new();
+
+#[metric(name = "getbit")]
+pub static GETBIT: Counter = Counter::new();
+
+#[metric(name = "getbit_ex")]
+pub static GETBIT_EX: Counter = Counter::new();
+
+#[metric(name = "bitcount")]
+pub static BITCOUNT: Counter = Counter::new();
+
+#[metric(name = "bitcount_ex")]
+pub static BITCOUNT_EX: Counter = Counter::new();
+
+#[metric(name = "bitop")]
+pub static BITOP: Counter = Counter::new();
+
+#[metric(name = "bitop_ex")]
+pub static BITOP_EX: Counter = Counter::new();
+
+#[metric(name = "bit_value_too_large")]
+pub static BIT_VALUE_TOO_LARGE: Counter = Counter::new();
+
+#[metric(name = "bit_write_conflict")]
+pub static BIT_WRITE_CONFLICT: Counter = Counter::new();
+
+#[metric(name = "bit_write_conflict_ex")]
+pub static BIT_WRITE_CONFLICT_EX: Counter = Counter::new();
+
+#[derive(Copy, Clone, PartialEq)]
+enum Op {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+/// Serves SETBIT, GETBIT, BITCOUNT, and BITOP, refusing values larger than
+/// `max_bytes`. Returns the name of the command served, or `None` if the
+/// command is not one of them.
+pub(crate) async fn bit_operation(
+    client: &mut CacheClient,
+    router: &Router<'_>,
+    command: &Command,
+    memcache_flags: bool,
+    max_bytes: usize,
+    proxy_metrics: &impl RespMetrics,
+    response_buf: &mut Vec<u8>,
+) -> Option<&'static str> {
+    let name = match command.name().as_str() {
+        "SETBIT" => "setbit",
+        "GETBIT" => "getbit",
+        "BITCOUNT" => "bitcount",
+        "BITOP" => "bitop",
+        _ => return None,
+    };
+
+    let header = if memcache_flags { FLAGS_LEN } else { 0 };
+
+    let result = match (name, command.args()) {
+        ("setbit", [key, offset, bit]) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_setbit(),
+                update_method_metrics(
+                    &SETBIT,
+                    &SETBIT_EX,
+                    setbit(
+                        client,
+                        router.cache_for(key),
+                        response_buf,
+                        key,
+                        offset,
+                        bit,
+                        header,
+                        max_bytes,
+                        router.ttl_for(key),
+                    ),
+                ),
+            )
+            .await
+        }
+        ("getbit", [key, offset]) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_getbit(),
+                update_method_metrics(
+                    &GETBIT,
+                    &GETBIT_EX,
+                    getbit(
+                        client,
+                        router.cache_for(key),
+                        response_buf,
+                        key,
+                        offset,
+                        header,
+                    ),
+                ),
+            )
+            .await
+        }
+        ("bitcount", [key, range @ ..]) if range.len() <= 3 => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_bitcount(),
+                update_method_metrics(
+                    &BITCOUNT,
+                    &BITCOUNT_EX,
+                    bitcount(
+                        client,
+                        router.cache_for(key),
+                        response_buf,
+                        key,
+                        range,
+                        header,
+                    ),
+                ),
+            )
+            .await
+        }
+        ("bitop", [op, destination, sources @ ..]) if !sources.is_empty() => {
+            let keys = std::iter::once(destination)
+                .chain(sources)
+                .map(|key| key.as_slice());
+            let Some(cache_name) = router.cache_for_all(keys) else {
+                SHARD_CROSSSLOT.increment();
+                response_buf.extend_from_slice(
+                    b"-CROSSSLOT Keys in request don't hash to the same shard\r\n",
+                );
+                return Some(name);
+            };
+
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_bitop(),
+                update_method_metrics(
+                    &BITOP,
+                    &BITOP_EX,
+                    bitop(
+                        client,
+                        cache_name,
+                        response_buf,
+                        op,
+                        destination,
+                        sources,
+                        header,
+                        max_bytes,
+                        router.ttl_for(destination),
+                    ),
+                ),
+            )
+            .await
+        }
+        _ => {
+            response_buf.extend_from_slice(
+                format!("-ERR wrong number of arguments for '{name}' command\r\n").as_bytes(),
+            );
+            return Some(name);
+        }
+    };
+
+    if let Err(e) = result {
+        response_buf.clear();
+        super::proxy_error_to_resp_error(response_buf, name, e);
+    }
+
+    Some(name)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn setbit(
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    key: &[u8],
+    offset: &[u8],
+    bit: &[u8],
+    header: usize,
+    max_bytes: usize,
+    ttl: Option<Duration>,
+) -> ProxyResult {
+    let offset = parse_offset(offset)?;
+    let bit = match bit {
+        b"0" => false,
+        b"1" => true,
+        _ => {
+            return Err(ProxyError::custom(
+                "ERR bit is not an integer or out of range",
+            ))
+        }
+    };
+
+    let (byte, mask) = position(offset);
+    if byte >= max_bytes {
+        return Err(too_large(key, max_bytes));
+    }
+
+    let result: ProxyResult<bool> = async {
+        for _ in 0..MAX_ATTEMPTS {
+            let (previous, write_ttl) = read_for_update(client, cache_name, key, ttl).await?;
+
+            // a value too short to hold the flags is replaced
+            let mut value = match previous.as_ref().filter(|p| p.len() >= header) {
+                Some(previous) => previous.clone(),
+                None => vec![0; header],
+            };
+            let index = header + byte;
+            if value.len() <= index {
+                value.resize(index + 1, 0);
+            }
+            let original = value[index] & mask != 0;
+
+            // nothing changes, unless the value is created by the write
+            if original == bit && previous.as_ref().is_some_and(|p| p.len() > index) {
+                return Ok(original);
+            }
+
+            if bit {
+                value[index] |= mask;
+            } else {
+                value[index] &= !mask;
+            }
+
+            if super::write_if_unchanged(client, cache_name, key, value, previous, write_ttl)
+                .await?
+            {
+                return Ok(original);
+            }
+
+            BIT_WRITE_CONFLICT.increment();
+        }
+
+        BIT_WRITE_CONFLICT_EX.increment();
+        Err(ProxyError::custom(
+            "ERR too many concurrent writes to the key, try again",
+        ))
+    }
+    .await;
+
+    match result {
+        Ok(original) => {
+            response_buf.extend_from_slice(if original { b":1\r\n" } else { b":0\r\n" });
+            klog_1(&"setbit", &key, Status::Stored, response_buf.len());
+            Ok(())
+        }
+        Err(e) => {
+            klog_1(&"setbit", &key, status(&e), 0);
+            Err(e)
+        }
+    }
+}
+
+async fn getbit(
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    key: &[u8],
+    offset: &[u8],
+    header: usize,
+) -> ProxyResult {
+    let offset = parse_offset(offset)?;
+
+    let value = read_value(client, cache_name, key)
+        .await
+        .inspect_err(|e| klog_1(&"getbit", &key, status(e), 0))?;
+
+    // bits past the end of the value, or of a missing key, are zero
+    let (byte, mask) = position(offset);
+    let bit = value
+        .as_ref()
+        .and_then(|value| value.get(header + byte))
+        .is_some_and(|b| b & mask != 0);
+
+    response_buf.extend_from_slice(if bit { b":1\r\n" } else { b":0\r\n" });
+    let status = if value.is_some() {
+        Status::Hit
+    } else {
+        Status::Miss
+    };
+    klog_1(&"getbit", &key, status, response_buf.len());
+
+    Ok(())
+}
+
+async fn bitcount(
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    key: &[u8],
+    range: &[Vec<u8>],
+    header: usize,
+) -> ProxyResult {
+    // BITCOUNT key [start end [BYTE | BIT]]
+    let range = match range {
+        [] => None,
+        [start, end] => Some((
+            super::parse_integer(start)?,
+            super::parse_integer(end)?,
+            false,
+        )),
+        [start, end, unit] if unit.eq_ignore_ascii_case(b"BYTE") => Some((
+            super::parse_integer(start)?,
+            super::parse_integer(end)?,
+            false,
+        )),
+        [start, end, unit] if unit.eq_ignore_ascii_case(b"BIT") => Some((
+            super::parse_integer(start)?,
+            super::parse_integer(end)?,
+            true,
+        )),
+        _ => return Err(ProxyError::custom("ERR syntax error")),
+    };
+
+    let value = read_value(client, cache_name, key)
+        .await
+        .inspect_err(|e| klog_1(&"bitcount", &key, status(e), 0))?;
+
+    // a value too short to hold the flags has no bits set
+    let count = match value.as_ref().and_then(|value| value.get(header..)) {
+        None => 0,
+        Some(value) => match range {
+            None => value.iter().map(|b| b.count_ones() as u64).sum(),
+            Some((start, end, false)) => match super::normalize_range(start, end, value.len()) {
+                Some(range) => value[range].iter().map(|b| b.count_ones() as u64).sum(),
+                None => 0,
+            },
+            Some((start, end, true)) => match super::normalize_range(start, end, value.len() * 8) {
+                Some(range) => range
+                    .filter(|offset| {
+                        let (byte, mask) = position(*offset as u64);
+                        value[byte] & mask != 0
+                    })
+                    .count() as u64,
+                None => 0,
+            },
+        },
+    };
+
+    response_buf.extend_from_slice(format!(":{count}\r\n").as_bytes());
+    let status = if value.is_some() {
+        Status::Hit
+    } else {
+        Status::Miss
+    };
+    klog_1(&"bitcount", &key, status, response_buf.len());
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn bitop(
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    op: &[u8],
+    destination: &[u8],
+    sources: &[Vec<u8>],
+    header: usize,
+    max_bytes: usize,
+    ttl: Option<Duration>,
+) -> ProxyResult {
+    let op = match op.to_ascii_uppercase().as_slice() {
+        b"AND" => Op::And,
+        b"OR" => Op::Or,
+        b"XOR" => Op::Xor,
+        b"NOT" => Op::Not,
+        _ => return Err(ProxyError::custom("ERR syntax error")),
+    };
+    if op == Op::Not && sources.len() != 1 {
+        return Err(ProxyError::custom(
+            "ERR BITOP NOT must be called with a single source key.",
+        ));
+    }
+
+    let result: ProxyResult<usize> = async {
+        let mut values = Vec::with_capacity(sources.len());
+        for source in sources {
+            let value = read_value(client, cache_name, source).await?;
+            values.push(
+                value
+                    .and_then(|value| value.get(header..).map(<[u8]>::to_vec))
+                    .unwrap_or_default(),
+            );
+        }
+
+        // missing keys and the ends of shorter values count as zeros
+        let len = values.iter().map(Vec::len).max().unwrap_or(0);
+        if len > max_bytes {
+            return Err(too_large(destination, max_bytes));
+        }
+
+        // the result is written with empty flags and the TTL of a new key, as
+        // SET writes it
+        let mut result = vec![0; header + len];
+        for (i, byte) in result[header..].iter_mut().enumerate() {
+            let mut bytes = values
+                .iter()
+                .map(|value| value.get(i).copied().unwrap_or(0));
+            *byte = match op {
+                Op::And => bytes.fold(0xff, |a, b| a & b),
+                Op::Or => bytes.fold(0, |a, b| a | b),
+                Op::Xor => bytes.fold(0, |a, b| a ^ b),
+                Op::Not => !bytes.next().unwrap_or(0),
+            };
+        }
+
+        // as with redis, an empty result deletes the destination
+        if len == 0 {
+            timeout(backend_timeout(), client.delete(cache_name, destination)).await??;
+        } else {
+            timeout(
+                backend_timeout(),
+                client.send_request(SetRequest::new(cache_name, destination, result).ttl(ttl)),
+            )
+            .await??;
+        }
+
+        Ok(len)
+    }
+    .await;
+
+    match result {
+        Ok(len) => {
+            response_buf.extend_from_slice(format!(":{len}\r\n").as_bytes());
+            klog_1(&"bitop", &destination, Status::Stored, len);
+            Ok(())
+        }
+        Err(e) => {
+            klog_1(&"bitop", &destination, status(&e), 0);
+            Err(e)
+        }
+    }
+}
+
+// the byte holding the bit at `offset`, and the mask of the bit within it.
+// Bits are numbered from the most significant bit of the first byte
+fn position(offset: u64) -> (usize, u8) {
+    ((offset / 8) as usize, 0x80 >> (offset % 8))
+}
+
+// as with redis, offsets are limited to those of a 512MiB value
+fn parse_offset(offset: &[u8]) -> ProxyResult<u64> {
+    std::str::from_utf8(offset)
+        .ok()
+        .and_then(|offset| offset.parse::<u64>().ok())
+        .filter(|offset| *offset < 1 << 32)
+        .ok_or_else(|| ProxyError::custom("ERR bit offset is not an integer or out of range"))
+}
+
+fn too_large(key: &[u8], max_bytes: usize) -> ProxyError {
+    BIT_VALUE_TOO_LARGE.increment();
+    debug!(
+        "refused a bit operation on `{}` which would grow the value past {max_bytes} bytes",
+        String::from_utf8_lossy(key)
+    );
+    ProxyError::custom("ERR the value would be larger than bit_operations_max_bytes")
+}
+
+fn status(e: &ProxyError) -> Status {
+    match e {
+        ProxyError::Timeout(_) => Status::Timeout,
+        ProxyError::Custom(_) => Status::NotStored,
+        _ => Status::ServerError,
+    }
+}
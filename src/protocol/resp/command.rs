@@ -0,0 +1,532 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! A minimal parser for RESP commands which `protocol_resp` does not
+//! recognize, so that commands the proxy answers itself can be served, and
+//! for inline commands as typed into telnet.
+
+use crate::connections::{self, Connection};
+use crate::instance;
+use crate::keys::KeyTransform;
+use crate::momento_proxy::Cache;
+use core::time::Duration;
+
+/// A RESP command as a list of its raw arguments.
+pub(crate) struct Command {
+    consumed: usize,
+    args: Vec<Vec<u8>>,
+}
+
+impl Command {
+    /// Parses a command sent as a RESP array of bulk strings. Returns
+    /// `Ok(None)` if the buffer does not hold a complete command yet.
+    pub fn parse(buf: &[u8]) -> Result<Option<Self>, ()> {
+        let mut pos = 0;
+
+        let count = match read_header(buf, &mut pos, b'*')? {
+            Some(count) => count,
+            None => return Ok(None),
+        };
+
+        let mut args = Vec::with_capacity(count.min(64));
+        for _ in 0..count {
+            let len = match read_header(buf, &mut pos, b'$')? {
+                Some(len) => len,
+                None => return Ok(None),
+            };
+
+            // a length near usize::MAX, as sent by a hostile client, must not
+            // overflow
+            if buf.len() - pos < len.saturating_add(2) {
+                return Ok(None);
+            }
+            if &buf[pos + len..pos + len + 2] != b"\r\n" {
+                return Err(());
+            }
+
+            args.push(buf[pos..pos + len].to_vec());
+            pos += len + 2;
+        }
+
+        if args.is_empty() {
+            return Err(());
+        }
+
+        Ok(Some(Self {
+            consumed: pos,
+            args,
+        }))
+    }
+
+    /// Parses an inline command, a single line of space separated arguments.
+    /// Arguments may be quoted to include spaces. Returns `None` if the line
+    /// is not complete yet. A blank line parses as an empty command.
+    pub fn parse_inline(buf: &[u8]) -> Option<Self> {
+        let end = buf.iter().position(|b| *b == b'\n')?;
+
+        let mut line = &buf[..end];
+        if line.last() == Some(&b'\r') {
+            line = &line[..line.len() - 1];
+        }
+
+        Some(Self {
+            consumed: end + 1,
+            args: split_inline(line),
+        })
+    }
+
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    /// The command name, uppercased.
+    pub fn name(&self) -> String {
+        self.args
+            .first()
+            .map(|name| String::from_utf8_lossy(name).to_ascii_uppercase())
+            .unwrap_or_default()
+    }
+
+    /// The arguments following the command name.
+    pub fn args(&self) -> &[Vec<u8>] {
+        &self.args[self.args.len().min(1)..]
+    }
+
+    /// Removes a trailing `TIMEOUT <milliseconds>` from the command, returning
+    /// the timeout. Only the uppercase form is taken as a hint, and only after
+    /// the keys, fields, members and values of the command, so that a
+    /// `TIMEOUT` the command writes or reads is left as it is.
+    pub fn take_timeout_hint(&mut self) -> Option<Duration> {
+        let [.., name, value] = self.args.as_slice() else {
+            return None;
+        };
+        let first_unused = data_args(&self.name())?;
+        if self.args.len() < first_unused + 2 || name != b"TIMEOUT" {
+            return None;
+        }
+
+        let millis = std::str::from_utf8(value).ok()?.parse::<u64>().ok()?;
+        self.args.truncate(self.args.len() - 2);
+        Some(Duration::from_millis(millis))
+    }
+
+    /// The keys of the command.
+    pub fn keys(&self) -> Vec<&[u8]> {
+        let args = self.args();
+        self.key_indexes()
+            .into_iter()
+            .map(|i| args[i].as_slice())
+            .collect()
+    }
+
+    /// Rewrites the keys of the command for the backend. Other arguments, such
+    /// as fields, members and values, are left as they are. Returns each
+    /// rewritten key with the key the client sent, which requests are routed
+    /// by.
+    pub fn transform_keys(&mut self, key_transform: &KeyTransform) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let indexes = self.key_indexes();
+        let start = self.args.len().min(1);
+
+        let mut originals = Vec::new();
+        for i in indexes {
+            let key = &mut self.args[start + i];
+            let transformed = key_transform.apply(key).into_owned();
+            if transformed != *key {
+                let original = std::mem::replace(key, transformed.clone());
+                originals.push((transformed, original));
+            }
+        }
+        originals
+    }
+
+    // the positions of the keys among the arguments. Other arguments, such as
+    // fields, members and values, aren't keys
+    fn key_indexes(&self) -> Vec<usize> {
+        let args = self.args();
+        let count = |i: usize| {
+            args.get(i)
+                .and_then(|v| std::str::from_utf8(v).ok())
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(0)
+        };
+
+        match self.name().as_str() {
+            // commands served by the proxy have no keys
+            "CLIENT" | "CLUSTER" | "HELLO" | "INFO" | "WAIT" | "REPLICAOF" | "SLAVEOF"
+            | "FAILOVER" => Vec::new(),
+            // FLUSHALL [ASYNC | SYNC] affects every key
+            "FLUSHALL" | "FLUSHDB" => Vec::new(),
+            "DEL" | "SDIFF" | "SUNION" | "SINTER" | "PFCOUNT" | "PFMERGE" => {
+                (0..args.len()).collect()
+            }
+            // BITOP operation destkey key [key ...]
+            "BITOP" => (1..args.len()).collect(),
+            // MEMORY USAGE key ..., OBJECT ENCODING key
+            "MEMORY" | "OBJECT" => (1..args.len().min(2)).collect(),
+            // LMPOP numkeys key [key ...] LEFT|RIGHT [COUNT count]
+            "LMPOP" => (1..count(0).saturating_add(1).min(args.len())).collect(),
+            // ZUNIONSTORE destination numkeys key [key ...] ...
+            "ZUNIONSTORE" => (0..args.len().min(1))
+                .chain(2..count(1).saturating_add(2).min(args.len()))
+                .collect(),
+            _ => (0..args.len().min(1)).collect(),
+        }
+    }
+
+    /// Encodes the command as a RESP array of bulk strings.
+    pub fn to_resp(&self) -> Vec<u8> {
+        let mut buf = format!("*{}\r\n", self.args.len()).into_bytes();
+        for arg in &self.args {
+            buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            buf.extend_from_slice(arg);
+            buf.extend_from_slice(b"\r\n");
+        }
+        buf
+    }
+}
+
+// the arguments, including the name, which may hold keys, fields, members or
+// values, for commands where those are followed only by options and numbers.
+// Commands which take any number of them, such as `SADD` and `HSET`, never
+// take a hint.
+fn data_args(name: &str) -> Option<usize> {
+    let count = match name {
+        "GET" | "GETDEL" | "GETEX" | "STRLEN" | "INCR" | "DECR" | "TTL" | "PTTL" | "TYPE"
+        | "DUMP" | "HGETALL" | "HKEYS" | "HVALS" | "HLEN" | "LLEN" | "LPOP" | "RPOP"
+        | "SMEMBERS" | "SCARD" | "ZCARD" | "BITCOUNT" | "EXPIRE" | "PEXPIRE" | "PERSIST" => 2,
+        "SET" | "SETNX" | "APPEND" | "INCRBY" | "DECRBY" | "GETBIT" | "HGET" | "HEXISTS"
+        | "LINDEX" | "SISMEMBER" | "ZSCORE" | "ZRANK" | "ZREVRANK" => 3,
+        "SETEX" | "PSETEX" | "SETRANGE" | "GETRANGE" | "SETBIT" | "HINCRBY" | "HSETNX"
+        | "LRANGE" | "LSET" | "ZRANGE" | "ZCOUNT" | "ZINCRBY" | "RESTORE" => 4,
+        _ => return None,
+    };
+    Some(count)
+}
+
+// splits an inline command into its arguments, in double quotes `\"` and `\\`
+// are escapes, while single quotes are taken literally
+fn split_inline(line: &[u8]) -> Vec<Vec<u8>> {
+    let mut args = Vec::new();
+    let mut bytes = line.iter().copied().peekable();
+
+    loop {
+        while bytes.next_if(|b| b.is_ascii_whitespace()).is_some() {}
+
+        let Some(first) = bytes.next() else {
+            return args;
+        };
+
+        let mut arg = Vec::new();
+        match first {
+            b'"' => {
+                while let Some(b) = bytes.next() {
+                    match b {
+                        b'"' => break,
+                        b'\\' => arg.extend(bytes.next()),
+                        b => arg.push(b),
+                    }
+                }
+            }
+            b'\'' => {
+                for b in bytes.by_ref() {
+                    if b == b'\'' {
+                        break;
+                    }
+                    arg.push(b);
+                }
+            }
+            b => {
+                arg.push(b);
+                while let Some(b) = bytes.next_if(|b| !b.is_ascii_whitespace()) {
+                    arg.push(b);
+                }
+            }
+        }
+        args.push(arg);
+    }
+}
+
+// reads a `<prefix><number>\r\n` header, advancing `pos` past it
+fn read_header(buf: &[u8], pos: &mut usize, prefix: u8) -> Result<Option<usize>, ()> {
+    let rest = &buf[*pos..];
+    if rest.is_empty() {
+        return Ok(None);
+    }
+    if rest[0] != prefix {
+        return Err(());
+    }
+
+    let end = match rest.windows(2).position(|w| w == b"\r\n") {
+        Some(end) => end,
+        None => return Ok(None),
+    };
+
+    let value = std::str::from_utf8(&rest[1..end])
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .ok_or(())?;
+
+    *pos += end + 2;
+    Ok(Some(value))
+}
+
+/// Serves commands which the proxy answers without calling Momento. Returns
+/// the name of the command served, or `None` if the command is not one of
+/// them.
+pub(crate) fn proxy_command(
+    command: &Command,
+    connection: &Connection,
+    cache: &Cache,
+    response_buf: &mut Vec<u8>,
+) -> Option<&'static str> {
+    match command.name().as_str() {
+        "CLUSTER" => {
+            cluster(command, cache, response_buf);
+            Some("cluster")
+        }
+        "CLIENT" => {
+            client(command, connection, response_buf);
+            Some("client")
+        }
+        "HELLO" => {
+            hello(command, connection, response_buf);
+            Some("hello")
+        }
+        "INFO" => {
+            info(command, cache, response_buf);
+            Some("info")
+        }
+        "WAIT" => {
+            wait(command, response_buf);
+            Some("wait")
+        }
+        "SELECT" => {
+            select(command, connection, cache, response_buf);
+            Some("select")
+        }
+        // there are no replicas to promote or follow, but clients which probe
+        // for them are told why rather than that the command is unknown
+        "REPLICAOF" | "SLAVEOF" | "FAILOVER" => {
+            response_buf.extend_from_slice(
+                format!(
+                    "-ERR {} is not supported: the proxy has no replicas\r\n",
+                    command.name()
+                )
+                .as_bytes(),
+            );
+            Some(if command.name() == "FAILOVER" {
+                "failover"
+            } else {
+                "replicaof"
+            })
+        }
+        _ => None,
+    }
+}
+
+// only RESP2 is spoken, so HELLO succeeds without arguments or with protocol
+// version 2, and replies with the server identity as a flat array
+fn hello(command: &Command, connection: &Connection, response_buf: &mut Vec<u8>) {
+    if let Some(version) = command.args().first() {
+        if version.as_slice() != b"2" {
+            response_buf.extend_from_slice(b"-NOPROTO unsupported protocol version\r\n");
+            return;
+        }
+    }
+
+    let bulk = |buf: &mut Vec<u8>, value: &str| {
+        buf.extend_from_slice(format!("${}\r\n{value}\r\n", value.len()).as_bytes());
+    };
+
+    response_buf.extend_from_slice(b"*14\r\n");
+    bulk(response_buf, "server");
+    bulk(response_buf, instance::server_name());
+    bulk(response_buf, "version");
+    bulk(response_buf, instance::server_version());
+    bulk(response_buf, "proto");
+    response_buf.extend_from_slice(b":2\r\n");
+    bulk(response_buf, "id");
+    response_buf.extend_from_slice(format!(":{}\r\n", connection.id()).as_bytes());
+    bulk(response_buf, "mode");
+    bulk(response_buf, "standalone");
+    bulk(response_buf, "role");
+    bulk(response_buf, "master");
+    bulk(response_buf, "modules");
+    response_buf.extend_from_slice(b"*0\r\n");
+}
+
+// the server and replication sections of INFO. The server section is left out
+// only when replication alone is asked for, and is returned for any other
+// sections
+fn info(command: &Command, cache: &Cache, response_buf: &mut Vec<u8>) {
+    let sections: Vec<String> = command
+        .args()
+        .iter()
+        .map(|section| String::from_utf8_lossy(section).to_ascii_lowercase())
+        .collect();
+    let asked = |name: &str| {
+        sections.is_empty()
+            || sections.iter().any(|section| {
+                matches!(section.as_str(), "all" | "default" | "everything") || section == name
+            })
+    };
+    let replication_only =
+        !sections.is_empty() && sections.iter().all(|section| section == "replication");
+
+    let mut info = String::new();
+    if !replication_only {
+        info.push_str(&server_info(cache));
+    }
+    if asked("replication") {
+        if !info.is_empty() {
+            info.push_str("\r\n");
+        }
+        // the proxy is a primary with no replicas, as Momento replicates
+        // behind it
+        info.push_str("# Replication\r\nrole:master\r\nconnected_slaves:0\r\nmaster_failover_state:no-failover\r\nmaster_repl_offset:0\r\n");
+    }
+
+    response_buf.extend_from_slice(format!("${}\r\n{info}\r\n", info.len()).as_bytes());
+}
+
+fn server_info(cache: &Cache) -> String {
+    let mut info = format!(
+        "# Server\r\nredis_version:{}\r\nredis_mode:standalone\r\nserver_name:{}\r\nproxy_version:{}\r\ntcp_port:{}\r\nadvertised_address:{}:{}\r\n",
+        instance::server_version(),
+        instance::server_name(),
+        env!("CARGO_PKG_VERSION"),
+        cache.advertised_port(),
+        cache.advertised_host(),
+        cache.advertised_port(),
+    );
+    if let Some(id) = instance::id() {
+        info.push_str(&format!("instance_id:{id}\r\n"));
+    }
+    info
+}
+
+// CLUSTER SLOTS, so that cluster clients can use the proxy as a cluster of one
+// node which serves every slot at the advertised address
+fn cluster(command: &Command, cache: &Cache, response_buf: &mut Vec<u8>) {
+    match command.args() {
+        [subcommand] if subcommand.eq_ignore_ascii_case(b"SLOTS") => {
+            let host = cache.advertised_host();
+            let port = cache.advertised_port();
+
+            response_buf.extend_from_slice(b"*1\r\n*3\r\n:0\r\n:16383\r\n*2\r\n");
+            response_buf
+                .extend_from_slice(format!("${}\r\n{host}\r\n:{port}\r\n", host.len()).as_bytes());
+        }
+        _ => response_buf.extend_from_slice(
+            b"-ERR unknown subcommand for 'cluster'. Only SLOTS is supported.\r\n",
+        ),
+    }
+}
+
+// WAIT numreplicas timeout. Replies come only after Momento has acknowledged
+// the write, so earlier writes are already as durable as they will be, and no
+// replicas acknowledge them
+// database 0 is the cache of the listener and the others are those listed in
+// `resp_databases`, the selection lasts until the connection closes
+fn select(command: &Command, connection: &Connection, cache: &Cache, response_buf: &mut Vec<u8>) {
+    let [index] = command.args() else {
+        response_buf.extend_from_slice(b"-ERR wrong number of arguments for 'select' command\r\n");
+        return;
+    };
+
+    let Some(index) = std::str::from_utf8(index)
+        .ok()
+        .and_then(|index| index.parse::<i64>().ok())
+    else {
+        response_buf.extend_from_slice(b"-ERR value is not an integer or out of range\r\n");
+        return;
+    };
+
+    match usize::try_from(index) {
+        Ok(index) if index <= cache.resp_databases().len() => {
+            connection.select(index);
+            response_buf.extend_from_slice(b"+OK\r\n");
+        }
+        _ => response_buf.extend_from_slice(b"-ERR DB index is out of range\r\n"),
+    }
+}
+
+fn wait(command: &Command, response_buf: &mut Vec<u8>) {
+    let integer = |value: &Vec<u8>| {
+        std::str::from_utf8(value)
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+    };
+
+    match command.args() {
+        [replicas, timeout] => match (integer(replicas), integer(timeout)) {
+            (Some(_), Some(0..)) => response_buf.extend_from_slice(b":0\r\n"),
+            (Some(_), Some(_)) => response_buf.extend_from_slice(b"-ERR timeout is negative\r\n"),
+            _ => {
+                response_buf.extend_from_slice(b"-ERR value is not an integer or out of range\r\n")
+            }
+        },
+        _ => {
+            response_buf.extend_from_slice(b"-ERR wrong number of arguments for 'wait' command\r\n")
+        }
+    }
+}
+
+fn client(command: &Command, connection: &Connection, response_buf: &mut Vec<u8>) {
+    let args = command.args();
+    let subcommand = args
+        .first()
+        .map(|v| String::from_utf8_lossy(v).to_ascii_uppercase())
+        .unwrap_or_default();
+
+    match (subcommand.as_str(), &args[args.len().min(1)..]) {
+        ("LIST", _) => {
+            let list = connections::list();
+            response_buf.extend_from_slice(format!("${}\r\n", list.len()).as_bytes());
+            response_buf.extend_from_slice(list.as_bytes());
+            response_buf.extend_from_slice(b"\r\n");
+        }
+        ("ID", []) => {
+            response_buf.extend_from_slice(format!(":{}\r\n", connection.id()).as_bytes());
+        }
+        // the name is added to the metrics and command log of the connection
+        ("SETNAME", [name]) => {
+            if connection.set_name(&String::from_utf8_lossy(name)) {
+                response_buf.extend_from_slice(b"+OK\r\n");
+            } else {
+                response_buf.extend_from_slice(
+                    b"-ERR Client names cannot contain spaces, newlines or special characters.\r\n",
+                );
+            }
+        }
+        ("GETNAME", []) => match connection.name() {
+            Some(name) => {
+                response_buf.extend_from_slice(format!("${}\r\n{name}\r\n", name.len()).as_bytes())
+            }
+            None => response_buf.extend_from_slice(b"$-1\r\n"),
+        },
+        // the old form kills by address and replies with a status
+        ("KILL", [addr]) => {
+            if connections::kill_addr(&String::from_utf8_lossy(addr)) > 0 {
+                response_buf.extend_from_slice(b"+OK\r\n");
+            } else {
+                response_buf.extend_from_slice(b"-ERR No such client\r\n");
+            }
+        }
+        ("KILL", [filter, addr]) if filter.eq_ignore_ascii_case(b"ADDR") => {
+            let killed = connections::kill_addr(&String::from_utf8_lossy(addr));
+            response_buf.extend_from_slice(format!(":{killed}\r\n").as_bytes());
+        }
+        _ => {
+            response_buf.extend_from_slice(
+                format!("-ERR unknown subcommand or wrong number of arguments for 'CLIENT {subcommand}'\r\n")
+                    .as_bytes(),
+            );
+        }
+    }
+}
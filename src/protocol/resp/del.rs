@@ -1,4 +1,5 @@
 use crate::klog::*;
+use crate::shards::Router;
 use crate::*;
 use protocol_resp::*;
 use std::io::Write;
@@ -7,7 +8,7 @@ use super::update_method_metrics;
 
 pub async fn del(
     client: &mut CacheClient,
-    cache_name: &str,
+    router: &Router<'_>,
     response_buf: &mut Vec<u8>,
     req: &Del,
 ) -> ProxyResult {
@@ -15,9 +16,11 @@ pub async fn del(
 
     for key in keys {
         let client = client.clone();
+        // each key is deleted from its own shard
+        let cache_name = router.cache_for(key);
 
         update_method_metrics(&DEL, &DEL_EX, async move {
-            match timeout(Duration::from_millis(200), client.delete(cache_name, key)).await {
+            match timeout(backend_timeout(), client.delete(cache_name, key)).await {
                 Ok(Ok(_)) => {}
                 Ok(Err(e)) => {
                     klog_1(&"hdel", &key, Status::ServerError, 0);
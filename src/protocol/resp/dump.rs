@@ -0,0 +1,433 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! DUMP and RESTORE, so that migration tooling built on them can copy keys
+//! between caches behind proxies. The serialized format is the proxy's own and
+//! is not compatible with redis: a value dumped by redis can't be restored
+//! through the proxy, nor the other way around.
+//!
+//! A dump starts with `MPDUMP`, a version byte, and a type byte. Then come the
+//! parts of the value, each prefixed with its length as a big-endian u32, with
+//! collections starting with their element count and each sorted set member
+//! followed by its score as a big-endian f64. The dump ends with the first 8
+//! bytes of the SHA-256 of everything before them.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use metriken::{metric, Counter};
+use momento::cache::{
+    CollectionTtl, DictionaryFetchResponse, DictionarySetFieldsRequest, GetResponse,
+    ItemGetTypeResponse, ItemType, ListConcatenateBackRequest, ListFetchResponse,
+    SetAddElementsRequest, SetFetchResponse, SetRequest, SortedSetElement, SortedSetFetchResponse,
+    SortedSetOrder, SortedSetPutElementsRequest,
+};
+use momento::CacheClient;
+use sha2::{Digest, Sha256};
+
+use super::{update_method_metrics, Command};
+use crate::deadline::backend_timeout;
+use crate::error::{ProxyError, ProxyResult};
+use crate::klog::{klog_1, Status};
+use crate::metrics::{with_rpc_call_guard, RespMetrics};
+use crate::shards::Router;
+use crate::COLLECTION_TTL;
+
+#[metric(name = "dump")]
+pub static DUMP: Counter = Counter::new();
+
+#[metric(name = "dump_ex")]
+pub static DUMP_EX: Counter = Counter::new();
+
+#[metric(name = "restore")]
+pub static RESTORE: Counter = Counter::new();
+
+#[metric(name = "restore_ex")]
+pub static RESTORE_EX: Counter = Counter::new();
+
+const MAGIC: &[u8] = b"MPDUMP";
+const VERSION: u8 = 1;
+const CHECKSUM_LEN: usize = 8;
+
+/// A value read from or written to momento.
+pub(super) enum Value {
+    Scalar(Vec<u8>),
+    Dictionary(Vec<(Vec<u8>, Vec<u8>)>),
+    List(Vec<Vec<u8>>),
+    Set(Vec<Vec<u8>>),
+    SortedSet(Vec<(Vec<u8>, f64)>),
+}
+
+impl Value {
+    fn encode(&self) -> Vec<u8> {
+        fn part(buf: &mut Vec<u8>, part: &[u8]) {
+            buf.extend_from_slice(&(part.len() as u32).to_be_bytes());
+            buf.extend_from_slice(part);
+        }
+
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+
+        match self {
+            Self::Scalar(value) => {
+                buf.push(0);
+                part(&mut buf, value);
+            }
+            Self::Dictionary(pairs) => {
+                buf.push(1);
+                buf.extend_from_slice(&(pairs.len() as u32).to_be_bytes());
+                for (field, value) in pairs {
+                    part(&mut buf, field);
+                    part(&mut buf, value);
+                }
+            }
+            Self::List(values) | Self::Set(values) => {
+                buf.push(if matches!(self, Self::List(_)) { 2 } else { 3 });
+                buf.extend_from_slice(&(values.len() as u32).to_be_bytes());
+                for value in values {
+                    part(&mut buf, value);
+                }
+            }
+            Self::SortedSet(elements) => {
+                buf.push(4);
+                buf.extend_from_slice(&(elements.len() as u32).to_be_bytes());
+                for (value, score) in elements {
+                    part(&mut buf, value);
+                    buf.extend_from_slice(&score.to_be_bytes());
+                }
+            }
+        }
+
+        let checksum = Sha256::digest(&buf);
+        buf.extend_from_slice(&checksum[..CHECKSUM_LEN]);
+        buf
+    }
+
+    /// Decodes a dump, returning `None` if it is malformed or its checksum
+    /// doesn't match.
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let (body, checksum) = buf.split_at(buf.len().checked_sub(CHECKSUM_LEN)?);
+        if Sha256::digest(body)[..CHECKSUM_LEN] != *checksum {
+            return None;
+        }
+
+        let rest = body.strip_prefix(MAGIC)?;
+        let (&[version, kind], mut rest) = rest.split_first_chunk::<2>()?;
+        if version != VERSION {
+            return None;
+        }
+
+        let value = match kind {
+            0 => Self::Scalar(take_part(&mut rest)?),
+            1 => {
+                let count = take_u32(&mut rest)?;
+                let mut pairs = Vec::new();
+                for _ in 0..count {
+                    pairs.push((take_part(&mut rest)?, take_part(&mut rest)?));
+                }
+                Self::Dictionary(pairs)
+            }
+            2 | 3 => {
+                let count = take_u32(&mut rest)?;
+                let mut values = Vec::new();
+                for _ in 0..count {
+                    values.push(take_part(&mut rest)?);
+                }
+                if kind == 2 {
+                    Self::List(values)
+                } else {
+                    Self::Set(values)
+                }
+            }
+            4 => {
+                let count = take_u32(&mut rest)?;
+                let mut elements = Vec::new();
+                for _ in 0..count {
+                    let value = take_part(&mut rest)?;
+                    let (score, tail) = rest.split_first_chunk::<8>()?;
+                    rest = tail;
+                    elements.push((value, f64::from_be_bytes(*score)));
+                }
+                Self::SortedSet(elements)
+            }
+            _ => return None,
+        };
+
+        rest.is_empty().then_some(value)
+    }
+}
+
+fn take_u32(buf: &mut &[u8]) -> Option<u32> {
+    let (value, rest) = buf.split_first_chunk::<4>()?;
+    *buf = rest;
+    Some(u32::from_be_bytes(*value))
+}
+
+fn take_part(buf: &mut &[u8]) -> Option<Vec<u8>> {
+    let len = take_u32(buf)? as usize;
+    if buf.len() < len {
+        return None;
+    }
+    let (part, rest) = buf.split_at(len);
+    *buf = rest;
+    Some(part.to_vec())
+}
+
+/// Serves DUMP and RESTORE. Returns the name of the command served, or `None`
+/// if the command is not one of them.
+pub(crate) async fn dump_restore(
+    client: &mut CacheClient,
+    router: &Router<'_>,
+    command: &Command,
+    proxy_metrics: &impl RespMetrics,
+    response_buf: &mut Vec<u8>,
+) -> Option<&'static str> {
+    let name = match command.name().as_str() {
+        "DUMP" => "dump",
+        "RESTORE" => "restore",
+        _ => return None,
+    };
+
+    let result = match (name, command.args()) {
+        ("dump", [key]) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_dump(),
+                update_method_metrics(
+                    &DUMP,
+                    &DUMP_EX,
+                    dump(client, router.cache_for(key), response_buf, key),
+                ),
+            )
+            .await
+        }
+        ("restore", [key, ttl, payload, options @ ..]) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_restore(),
+                update_method_metrics(
+                    &RESTORE,
+                    &RESTORE_EX,
+                    restore(
+                        client,
+                        router.cache_for(key),
+                        response_buf,
+                        key,
+                        ttl,
+                        payload,
+                        options,
+                    ),
+                ),
+            )
+            .await
+        }
+        _ => {
+            response_buf.extend_from_slice(
+                format!("-ERR wrong number of arguments for '{name}' command\r\n").as_bytes(),
+            );
+            return Some(name);
+        }
+    };
+
+    if let Err(e) = result {
+        response_buf.clear();
+        super::proxy_error_to_resp_error(response_buf, name, e);
+    }
+
+    Some(name)
+}
+
+async fn dump(
+    client: &mut CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    key: &[u8],
+) -> ProxyResult {
+    let value = fetch(client, cache_name, key).await.inspect_err(|e| {
+        let status = match e {
+            ProxyError::Timeout(_) => Status::Timeout,
+            _ => Status::ServerError,
+        };
+        klog_1(&"dump", &key, status, 0);
+    })?;
+
+    match value {
+        Some(value) => {
+            let dump = value.encode();
+            response_buf.extend_from_slice(format!("${}\r\n", dump.len()).as_bytes());
+            response_buf.extend_from_slice(&dump);
+            response_buf.extend_from_slice(b"\r\n");
+            klog_1(&"dump", &key, Status::Hit, dump.len());
+        }
+        None => {
+            response_buf.extend_from_slice(b"$-1\r\n");
+            klog_1(&"dump", &key, Status::Miss, 0);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the whole value of the key, whatever its type.
+pub(super) async fn fetch(
+    client: &CacheClient,
+    cache_name: &str,
+    key: &[u8],
+) -> ProxyResult<Option<Value>> {
+    let timeout = backend_timeout();
+
+    let kind = match tokio::time::timeout(timeout, client.item_get_type(cache_name, key)).await?? {
+        ItemGetTypeResponse::Hit { key_type } => key_type,
+        ItemGetTypeResponse::Miss => return Ok(None),
+    };
+
+    // the key may be deleted between the two calls, which reads as a miss
+    let value = match kind {
+        ItemType::Scalar => {
+            match tokio::time::timeout(timeout, client.get(cache_name, key)).await?? {
+                GetResponse::Hit { value } => Some(Value::Scalar(value.into())),
+                GetResponse::Miss => None,
+            }
+        }
+        ItemType::Dictionary => {
+            match tokio::time::timeout(timeout, client.dictionary_fetch(cache_name, key)).await?? {
+                DictionaryFetchResponse::Hit { value } => {
+                    let map: HashMap<Vec<u8>, Vec<u8>> = value.into();
+                    Some(Value::Dictionary(map.into_iter().collect()))
+                }
+                DictionaryFetchResponse::Miss => None,
+            }
+        }
+        ItemType::List => {
+            match tokio::time::timeout(timeout, client.list_fetch(cache_name, key)).await?? {
+                ListFetchResponse::Hit { values } => Some(Value::List(values.into())),
+                ListFetchResponse::Miss => None,
+            }
+        }
+        ItemType::Set => {
+            match tokio::time::timeout(timeout, client.set_fetch(cache_name, key)).await?? {
+                SetFetchResponse::Hit { values } => {
+                    let set: HashSet<Vec<u8>> = values.into();
+                    Some(Value::Set(set.into_iter().collect()))
+                }
+                SetFetchResponse::Miss => None,
+            }
+        }
+        ItemType::SortedSet => {
+            match tokio::time::timeout(
+                timeout,
+                client.sorted_set_fetch_by_rank(
+                    cache_name,
+                    key,
+                    SortedSetOrder::Ascending,
+                    None,
+                    None,
+                ),
+            )
+            .await??
+            {
+                SortedSetFetchResponse::Hit { value } => Some(Value::SortedSet(value.elements)),
+                SortedSetFetchResponse::Miss => None,
+            }
+        }
+    };
+
+    Ok(value)
+}
+
+async fn restore(
+    client: &mut CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    key: &[u8],
+    ttl: &[u8],
+    payload: &[u8],
+    options: &[Vec<u8>],
+) -> ProxyResult {
+    let replace = match options {
+        [] => false,
+        [option] if option.eq_ignore_ascii_case(b"REPLACE") => true,
+        _ => return Err(ProxyError::custom("ERR syntax error")),
+    };
+
+    // the ttl is in milliseconds, and 0 means the default ttl of the cache
+    let ttl = std::str::from_utf8(ttl)
+        .ok()
+        .and_then(|ttl| ttl.parse::<u64>().ok())
+        .ok_or_else(|| ProxyError::custom("ERR Invalid TTL value, must be >= 0"))?;
+    let ttl = (ttl > 0).then(|| Duration::from_millis(ttl));
+
+    let value = Value::decode(payload)
+        .ok_or_else(|| ProxyError::custom("ERR DUMP payload version or checksum are wrong"))?;
+
+    let timeout = backend_timeout();
+
+    let result: ProxyResult = async {
+        // collections would be merged with an existing value, so it is
+        // removed first
+        let exists = tokio::time::timeout(timeout, client.key_exists(cache_name, key))
+            .await??
+            .exists;
+        if exists {
+            if !replace {
+                return Err(ProxyError::custom(
+                    "BUSYKEY Target key name already exists.",
+                ));
+            }
+            tokio::time::timeout(timeout, client.delete(cache_name, key)).await??;
+        }
+
+        let collection_ttl = match ttl {
+            Some(ttl) => CollectionTtl::new(Some(ttl), true),
+            None => COLLECTION_TTL,
+        };
+
+        match value {
+            Value::Scalar(value) => {
+                let mut request = SetRequest::new(cache_name, key, value);
+                if let Some(ttl) = ttl {
+                    request = request.ttl(ttl);
+                }
+                tokio::time::timeout(timeout, client.send_request(request)).await??;
+            }
+            Value::Dictionary(pairs) => {
+                let request =
+                    DictionarySetFieldsRequest::new(cache_name, key, pairs).ttl(collection_ttl);
+                tokio::time::timeout(timeout, client.send_request(request)).await??;
+            }
+            Value::List(values) => {
+                let request =
+                    ListConcatenateBackRequest::new(cache_name, key, values).ttl(collection_ttl);
+                tokio::time::timeout(timeout, client.send_request(request)).await??;
+            }
+            Value::Set(values) => {
+                let request =
+                    SetAddElementsRequest::new(cache_name, key, values).ttl(collection_ttl);
+                tokio::time::timeout(timeout, client.send_request(request)).await??;
+            }
+            Value::SortedSet(elements) => {
+                let elements: Vec<SortedSetElement<Vec<u8>>> = elements
+                    .into_iter()
+                    .map(|(value, score)| SortedSetElement { value, score })
+                    .collect();
+                let request =
+                    SortedSetPutElementsRequest::new(cache_name, key, elements).ttl(collection_ttl);
+                tokio::time::timeout(timeout, client.send_request(request)).await??;
+            }
+        }
+
+        Ok(())
+    }
+    .await;
+
+    match &result {
+        Ok(()) => {
+            response_buf.extend_from_slice(b"+OK\r\n");
+            klog_1(&"restore", &key, Status::Stored, payload.len());
+        }
+        Err(ProxyError::Timeout(_)) => klog_1(&"restore", &key, Status::Timeout, 0),
+        Err(ProxyError::Custom(_)) => klog_1(&"restore", &key, Status::NotStored, 0),
+        Err(_) => klog_1(&"restore", &key, Status::ServerError, 0),
+    }
+
+    result
+}
@@ -3,6 +3,7 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use crate::klog::{klog_1, Status};
+use crate::metrics::batch;
 use crate::*;
 
 use momento::cache::GetResponse;
@@ -10,17 +11,19 @@ use protocol_memcache::{GET, GET_EX, GET_KEY, GET_KEY_HIT, GET_KEY_MISS};
 
 use super::update_method_metrics;
 
+/// Gets the key. With `memcache_flags`, values are stored in the memcache
+/// format, and the leading memcache flags are stripped from the value.
 pub async fn get(
     client: &mut CacheClient,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     key: &[u8],
+    memcache_flags: bool,
 ) -> ProxyResult {
     update_method_metrics(&GET, &GET_EX, async move {
-        GET_KEY.increment();
+        batch::increment(&GET_KEY);
 
-        let response = match timeout(Duration::from_millis(200), client.get(cache_name, key)).await
-        {
+        let response = match timeout(backend_timeout(), client.get(cache_name, key)).await {
             Ok(Ok(r)) => r,
             Ok(Err(e)) => {
                 GET_EX.increment();
@@ -34,11 +37,23 @@ pub async fn get(
             }
         };
 
-        match response {
+        let value = match response {
             GetResponse::Hit { value } => {
-                GET_KEY_HIT.increment();
-
                 let value: Vec<u8> = value.into();
+                if memcache_flags {
+                    // a value too short to hold the flags reads as a miss, as
+                    // it does for memcache
+                    value.get(4..).map(|value| value.to_vec())
+                } else {
+                    Some(value)
+                }
+            }
+            GetResponse::Miss => None,
+        };
+
+        match value {
+            Some(value) => {
+                batch::increment(&GET_KEY_HIT);
 
                 let item_header = format!("${}\r\n", value.len());
 
@@ -48,8 +63,8 @@ pub async fn get(
 
                 klog_1(&"get", &key, Status::Hit, value.len());
             }
-            GetResponse::Miss => {
-                GET_KEY_MISS.increment();
+            None => {
+                batch::increment(&GET_KEY_MISS);
 
                 response_buf.extend_from_slice(b"$-1\r\n");
 
@@ -2,6 +2,7 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
+use crate::backend::DictionaryBackend;
 use crate::klog::*;
 use crate::*;
 use protocol_resp::*;
@@ -10,7 +11,7 @@ use std::io::Write;
 use super::update_method_metrics;
 
 pub async fn hdel(
-    client: &mut CacheClient,
+    client: &mut impl DictionaryBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &HashDelete,
@@ -18,7 +19,7 @@ pub async fn hdel(
     update_method_metrics(&HDEL, &HDEL_EX, async move {
         let fields: Vec<&[u8]> = req.fields().iter().map(|f| &**f).collect();
         match timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.dictionary_remove_fields(cache_name, req.key(), fields),
         )
         .await
@@ -3,11 +3,11 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use momento::cache::DictionaryGetFieldResponse;
-use momento::CacheClient;
 use protocol_resp::{HashExists, HEXISTS, HEXISTS_EX, HEXISTS_HIT, HEXISTS_MISS};
-use std::time::Duration;
 use tokio::time::timeout;
 
+use crate::backend::DictionaryBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_2, Status};
 use crate::ProxyError;
@@ -15,14 +15,14 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn hexists(
-    client: &mut CacheClient,
+    client: &mut impl DictionaryBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &HashExists,
 ) -> ProxyResult {
     update_method_metrics(&HEXISTS, &HEXISTS_EX, async move {
         let response = match timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.dictionary_get_field(cache_name, req.key(), req.field()),
         )
         .await
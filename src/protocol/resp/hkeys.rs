@@ -3,11 +3,11 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use momento::cache::DictionaryFetchResponse;
-use momento::CacheClient;
 use protocol_resp::{HashKeys, HKEYS, HKEYS_EX, HKEYS_HIT, HKEYS_MISS};
 use std::collections::HashMap;
-use std::time::Duration;
 
+use crate::backend::DictionaryBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
@@ -15,14 +15,14 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn hkeys(
-    client: &mut CacheClient,
+    client: &mut impl DictionaryBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &HashKeys,
 ) -> ProxyResult {
     update_method_metrics(&HKEYS, &HKEYS_EX, async move {
         let response = match tokio::time::timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.dictionary_fetch(cache_name, req.key()),
         )
         .await
@@ -3,10 +3,10 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use momento::cache::DictionaryLengthResponse;
-use momento::CacheClient;
 use protocol_resp::{HashLength, HLEN, HLEN_EX, HLEN_HIT, HLEN_MISS};
-use std::time::Duration;
 
+use crate::backend::DictionaryBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
@@ -14,14 +14,14 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn hlen(
-    client: &mut CacheClient,
+    client: &mut impl DictionaryBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &HashLength,
 ) -> ProxyResult {
     update_method_metrics(&HLEN, &HLEN_EX, async move {
         let response = match tokio::time::timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.dictionary_length(cache_name, req.key()),
         )
         .await
@@ -0,0 +1,432 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! PFADD, PFCOUNT, and PFMERGE. Momento has no HyperLogLog type, so each
+//! sketch is stored as a plain value and updated by the proxy. A write only
+//! succeeds if the value is still the one which was read, and is retried from
+//! a fresh read otherwise, so that concurrent updates are never lost.
+//!
+//! A sketch is `MPHLL`, a version byte, the precision `p`, and then `2^p`
+//! registers of one byte each. The format is the proxy's own, so sketches
+//! can't be copied to or from redis. A sketch keeps the precision it was
+//! created with, and sketches of different precisions merge at the lower one.
+
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use metriken::{metric, Counter};
+use momento::cache::GetResponse;
+use momento::CacheClient;
+use sha2::{Digest, Sha256};
+use tokio::time::timeout;
+
+use super::{update_method_metrics, Command};
+use crate::deadline::backend_timeout;
+use crate::error::{ProxyError, ProxyResult};
+use crate::klog::{klog_1, Status};
+use crate::metrics::{with_rpc_call_guard, RespMetrics};
+use crate::shards::{Router, SHARD_CROSSSLOT};
+
+const MAGIC: &[u8] = b"MPHLL";
+const VERSION: u8 = 1;
+
+/// The precisions a sketch may have.
+pub(crate) const HLL_PRECISIONS: RangeInclusive<u8> = 4..=18;
+
+// how many times a conflicting write is retried before giving up
+const MAX_ATTEMPTS: usize = 8;
+
+#[metric(name = "pfadd")]
+pub static PFADD: Counter = Counter::new();
+
+#[metric(name = "pfadd_ex")]
+pub static PFADD_EX: Counter = Counter::new();
+
+#[metric(name = "pfcount")]
+pub static PFCOUNT: Counter = Counter::new();
+
+#[metric(name = "pfcount_ex")]
+pub static PFCOUNT_EX: Counter = Counter::new();
+
+#[metric(name = "pfmerge")]
+pub static PFMERGE: Counter = Counter::new();
+
+#[metric(name = "pfmerge_ex")]
+pub static PFMERGE_EX: Counter = Counter::new();
+
+#[metric(name = "hll_write_conflict")]
+pub static HLL_WRITE_CONFLICT: Counter = Counter::new();
+
+#[metric(name = "hll_write_conflict_ex")]
+pub static HLL_WRITE_CONFLICT_EX: Counter = Counter::new();
+
+#[derive(Clone, PartialEq)]
+struct Sketch {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl Sketch {
+    fn new(precision: u8) -> Self {
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+        buf.push(self.precision);
+        buf.extend_from_slice(&self.registers);
+        buf
+    }
+
+    /// Decodes a sketch, returning `None` if the value is not one.
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let rest = buf.strip_prefix(MAGIC)?;
+        let (&[version, precision], registers) = rest.split_first_chunk::<2>()?;
+        if version != VERSION
+            || !HLL_PRECISIONS.contains(&precision)
+            || registers.len() != 1 << precision
+            || registers.iter().any(|r| *r > 65 - precision)
+        {
+            return None;
+        }
+
+        Some(Self {
+            precision,
+            registers: registers.to_vec(),
+        })
+    }
+
+    /// Adds an element, returning whether the sketch changed.
+    fn add(&mut self, element: &[u8]) -> bool {
+        let digest = Sha256::digest(element);
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&digest[..8]);
+        let hash = u64::from_be_bytes(bytes);
+
+        // the top bits pick the register, which holds the most leading zeros
+        // seen in the rest. The bit below them stops the count running past
+        // the end of the hash
+        let precision = u32::from(self.precision);
+        let index = (hash >> (64 - precision)) as usize;
+        let rank = ((hash << precision) | (1 << (precision - 1))).leading_zeros() as u8 + 1;
+
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+            true
+        } else {
+            false
+        }
+    }
+
+    // the same sketch at a lower precision. The low bits of each register's
+    // index become the leading bits of the hashes it counted
+    fn reduce(&self, precision: u8) -> Self {
+        if precision >= self.precision {
+            return self.clone();
+        }
+
+        let shift = u32::from(self.precision - precision);
+        let mut reduced = Self::new(precision);
+        for (index, rank) in self.registers.iter().enumerate() {
+            if *rank == 0 {
+                continue;
+            }
+
+            let low = (index & ((1 << shift) - 1)) as u32;
+            let rank = if low == 0 {
+                rank + shift as u8
+            } else {
+                (low.leading_zeros() - (32 - shift)) as u8 + 1
+            };
+
+            let register = &mut reduced.registers[index >> shift];
+            *register = (*register).max(rank);
+        }
+        reduced
+    }
+
+    fn merge(&mut self, other: &Self) {
+        if other.precision < self.precision {
+            *self = self.reduce(other.precision);
+        }
+        let other = other.reduce(self.precision);
+
+        for (register, rank) in self.registers.iter_mut().zip(other.registers) {
+            *register = (*register).max(rank);
+        }
+    }
+
+    /// The estimated number of distinct elements added to the sketch.
+    fn count(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|rank| 2f64.powi(-i32::from(*rank)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+
+        // small cardinalities are better estimated from the empty registers
+        let zeros = self.registers.iter().filter(|rank| **rank == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+/// Serves PFADD, PFCOUNT, and PFMERGE, creating sketches with `precision`.
+/// Returns the name of the command served, or `None` if the command is not
+/// one of them.
+pub(crate) async fn hyperloglog(
+    client: &mut CacheClient,
+    router: &Router<'_>,
+    command: &Command,
+    precision: u8,
+    proxy_metrics: &impl RespMetrics,
+    response_buf: &mut Vec<u8>,
+) -> Option<&'static str> {
+    let name = match command.name().as_str() {
+        "PFADD" => "pfadd",
+        "PFCOUNT" => "pfcount",
+        "PFMERGE" => "pfmerge",
+        _ => return None,
+    };
+
+    let args = command.args();
+    let Some((key, rest)) = args.split_first() else {
+        response_buf.extend_from_slice(
+            format!("-ERR wrong number of arguments for '{name}' command\r\n").as_bytes(),
+        );
+        return Some(name);
+    };
+
+    // the elements of PFADD are not keys
+    let keys = if name == "pfadd" { &args[..1] } else { args };
+    let Some(cache_name) = router.cache_for_all(keys.iter().map(|key| key.as_slice())) else {
+        SHARD_CROSSSLOT.increment();
+        response_buf
+            .extend_from_slice(b"-CROSSSLOT Keys in request don't hash to the same shard\r\n");
+        return Some(name);
+    };
+
+    let result = match name {
+        "pfadd" => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_pfadd(),
+                update_method_metrics(
+                    &PFADD,
+                    &PFADD_EX,
+                    pfadd(
+                        client,
+                        cache_name,
+                        response_buf,
+                        key,
+                        rest,
+                        precision,
+                        router.ttl_for(key),
+                    ),
+                ),
+            )
+            .await
+        }
+        "pfcount" => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_pfcount(),
+                update_method_metrics(
+                    &PFCOUNT,
+                    &PFCOUNT_EX,
+                    pfcount(client, cache_name, response_buf, args),
+                ),
+            )
+            .await
+        }
+        _ => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_pfmerge(),
+                update_method_metrics(
+                    &PFMERGE,
+                    &PFMERGE_EX,
+                    pfmerge(
+                        client,
+                        cache_name,
+                        response_buf,
+                        key,
+                        rest,
+                        precision,
+                        router.ttl_for(key),
+                    ),
+                ),
+            )
+            .await
+        }
+    };
+
+    if let Err(e) = result {
+        let status = match e {
+            ProxyError::Timeout(_) => Status::Timeout,
+            _ => Status::ServerError,
+        };
+        klog_1(&name, &key, status, 0);
+
+        response_buf.clear();
+        super::proxy_error_to_resp_error(response_buf, name, e);
+    }
+
+    Some(name)
+}
+
+async fn pfadd(
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    key: &[u8],
+    elements: &[Vec<u8>],
+    precision: u8,
+    ttl: Option<Duration>,
+) -> ProxyResult {
+    let changed = update(client, cache_name, key, precision, ttl, |sketch| {
+        elements
+            .iter()
+            .fold(false, |changed, element| sketch.add(element) || changed)
+    })
+    .await?;
+
+    response_buf.extend_from_slice(if changed { b":1\r\n" } else { b":0\r\n" });
+    klog_1(&"pfadd", &key, Status::Stored, elements.len());
+
+    Ok(())
+}
+
+async fn pfcount(
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    keys: &[Vec<u8>],
+) -> ProxyResult {
+    // the count of several keys is that of their union
+    let mut union: Option<Sketch> = None;
+    for key in keys {
+        if let Some((_, sketch)) = read(client, cache_name, key).await? {
+            match &mut union {
+                Some(union) => union.merge(&sketch),
+                None => union = Some(sketch),
+            }
+        }
+    }
+
+    let count = union.as_ref().map(Sketch::count).unwrap_or(0);
+    response_buf.extend_from_slice(format!(":{count}\r\n").as_bytes());
+
+    let status = if union.is_some() {
+        Status::Hit
+    } else {
+        Status::Miss
+    };
+    klog_1(&"pfcount", &keys[0], status, response_buf.len());
+
+    Ok(())
+}
+
+async fn pfmerge(
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    destination: &[u8],
+    sources: &[Vec<u8>],
+    precision: u8,
+    ttl: Option<Duration>,
+) -> ProxyResult {
+    let mut merged = Vec::new();
+    for source in sources {
+        if let Some((_, sketch)) = read(client, cache_name, source).await? {
+            merged.push(sketch);
+        }
+    }
+
+    update(client, cache_name, destination, precision, ttl, |sketch| {
+        let before = sketch.clone();
+        for source in &merged {
+            sketch.merge(source);
+        }
+        *sketch != before
+    })
+    .await?;
+
+    response_buf.extend_from_slice(b"+OK\r\n");
+    klog_1(&"pfmerge", &destination, Status::Stored, sources.len());
+
+    Ok(())
+}
+
+// reads the sketch of a key, along with the value it was decoded from
+async fn read(
+    client: &CacheClient,
+    cache_name: &str,
+    key: &[u8],
+) -> ProxyResult<Option<(Vec<u8>, Sketch)>> {
+    match timeout(backend_timeout(), client.get(cache_name, key)).await?? {
+        GetResponse::Hit { value } => {
+            let value: Vec<u8> = value.into();
+            let sketch = decode(&value)?;
+            Ok(Some((value, sketch)))
+        }
+        GetResponse::Miss => Ok(None),
+    }
+}
+
+fn decode(value: &[u8]) -> ProxyResult<Sketch> {
+    Sketch::decode(value)
+        .ok_or_else(|| ProxyError::custom("WRONGTYPE Key is not a valid HyperLogLog string value."))
+}
+
+/// Applies `apply` to the sketch of the key, creating it with `ttl` if it
+/// doesn't exist, and writes it back if it changed. The write is conditional
+/// on the value read, so a conflicting write by another client starts the
+/// update over. Returns whether the sketch was written.
+async fn update(
+    client: &CacheClient,
+    cache_name: &str,
+    key: &[u8],
+    precision: u8,
+    ttl: Option<Duration>,
+    mut apply: impl FnMut(&mut Sketch) -> bool,
+) -> ProxyResult<bool> {
+    for _ in 0..MAX_ATTEMPTS {
+        let (previous, write_ttl) = super::read_for_update(client, cache_name, key, ttl).await?;
+        let mut sketch = match &previous {
+            Some(value) => decode(value)?,
+            None => Sketch::new(precision),
+        };
+
+        let changed = apply(&mut sketch) || previous.is_none();
+        if !changed {
+            return Ok(false);
+        }
+
+        let value = sketch.encode();
+        if super::write_if_unchanged(client, cache_name, key, value, previous, write_ttl).await? {
+            return Ok(true);
+        }
+
+        HLL_WRITE_CONFLICT.increment();
+    }
+
+    HLL_WRITE_CONFLICT_EX.increment();
+    Err(ProxyError::custom(
+        "ERR too many concurrent writes to the HyperLogLog, try again",
+    ))
+}
@@ -3,12 +3,12 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use momento::cache::{DictionaryGetFieldResponse, DictionaryGetFieldsResponse};
-use momento::CacheClient;
 use protocol_resp::{
     HashMultiGet, HMGET, HMGET_EX, HMGET_FIELD, HMGET_FIELD_HIT, HMGET_FIELD_MISS,
 };
-use std::time::Duration;
 
+use crate::backend::DictionaryBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_2, Status};
 use crate::ProxyError;
@@ -16,7 +16,7 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn hmget(
-    client: &mut CacheClient,
+    client: &mut impl DictionaryBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &HashMultiGet,
@@ -24,7 +24,7 @@ pub async fn hmget(
     update_method_metrics(&HMGET, &HMGET_EX, async move {
         let fields: Vec<_> = req.fields().iter().map(|x| &**x).collect();
         let response = match tokio::time::timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.dictionary_get_fields(cache_name, req.key(), fields),
         )
         .await
@@ -2,12 +2,10 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
-use std::time::Duration;
-
-use momento::cache::DictionarySetFieldsRequest;
-use momento::CacheClient;
 use protocol_resp::{HashSet, HSET, HSET_EX, HSET_STORED};
 
+use crate::backend::DictionaryBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_7, Status};
 use crate::ProxyError;
@@ -16,7 +14,7 @@ use crate::COLLECTION_TTL;
 use super::update_method_metrics;
 
 pub async fn hset(
-    client: &mut CacheClient,
+    client: &mut impl DictionaryBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &HashSet,
@@ -28,11 +26,8 @@ pub async fn hset(
             .map(|(field, value)| (field.as_ref().to_vec(), value.as_ref().to_vec()))
             .collect();
         let _response = match tokio::time::timeout(
-            Duration::from_millis(200),
-            client.send_request(
-                DictionarySetFieldsRequest::new(cache_name, req.key(), elements.clone())
-                    .ttl(COLLECTION_TTL),
-            ),
+            backend_timeout(),
+            client.dictionary_set_fields(cache_name, req.key(), elements.clone(), COLLECTION_TTL),
         )
         .await
         {
@@ -85,3 +80,89 @@ pub async fn hset(
     })
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{request, MockBackend};
+    use crate::protocol::resp::{hdel, hget, hincrby, hlen, Request};
+
+    #[tokio::test]
+    async fn fields_are_read_back() {
+        let mut backend = MockBackend::default();
+        let mut response = Vec::new();
+
+        let Request::HashSet(r) = request(&["HSET", "h", "a", "1", "b", "22"]) else {
+            panic!("not an HSET");
+        };
+        hset(&mut backend, "cache", &mut response, &r)
+            .await
+            .unwrap();
+        assert_eq!(response, b":2\r\n");
+
+        response.clear();
+        let Request::HashGet(r) = request(&["HGET", "h", "b"]) else {
+            panic!("not an HGET");
+        };
+        hget(&mut backend, "cache", &mut response, &r)
+            .await
+            .unwrap();
+        assert_eq!(response, b"$2\r\n22\r\n");
+
+        response.clear();
+        let Request::HashDelete(r) = request(&["HDEL", "h", "a"]) else {
+            panic!("not an HDEL");
+        };
+        hdel(&mut backend, "cache", &mut response, &r)
+            .await
+            .unwrap();
+        assert_eq!(response, b":1\r\n");
+
+        response.clear();
+        let Request::HashLength(r) = request(&["HLEN", "h"]) else {
+            panic!("not an HLEN");
+        };
+        hlen(&mut backend, "cache", &mut response, &r)
+            .await
+            .unwrap();
+        assert_eq!(response, b":1\r\n");
+
+        // hashes are per cache
+        response.clear();
+        hlen(&mut backend, "other", &mut response, &r)
+            .await
+            .unwrap();
+        assert_eq!(response, b":0\r\n");
+    }
+
+    #[tokio::test]
+    async fn only_integers_are_incremented() {
+        let mut backend = MockBackend::default();
+        let mut response = Vec::new();
+
+        let Request::HashIncrBy(r) = request(&["HINCRBY", "h", "count", "5"]) else {
+            panic!("not an HINCRBY");
+        };
+        hincrby(&mut backend, "cache", &mut response, &r)
+            .await
+            .unwrap();
+        hincrby(&mut backend, "cache", &mut response, &r)
+            .await
+            .unwrap();
+        assert_eq!(response, b":5\r\n:10\r\n");
+
+        let Request::HashSet(r) = request(&["HSET", "h", "count", "ten"]) else {
+            panic!("not an HSET");
+        };
+        hset(&mut backend, "cache", &mut Vec::new(), &r)
+            .await
+            .unwrap();
+
+        let Request::HashIncrBy(r) = request(&["HINCRBY", "h", "count", "5"]) else {
+            panic!("not an HINCRBY");
+        };
+        assert!(hincrby(&mut backend, "cache", &mut Vec::new(), &r)
+            .await
+            .is_err());
+    }
+}
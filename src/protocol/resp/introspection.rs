@@ -0,0 +1,190 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! MEMORY USAGE and OBJECT ENCODING, so that monitoring scripts which call
+//! them keep working through the proxy. Momento doesn't report how it stores
+//! an item, so MEMORY USAGE fetches the whole value and estimates its size
+//! the way redis would count it, and OBJECT ENCODING reports a fixed encoding
+//! for each type.
+
+use metriken::{metric, Counter};
+use momento::cache::{ItemGetTypeResponse, ItemType};
+use momento::CacheClient;
+use tokio::time::timeout;
+
+use super::dump::{fetch, Value};
+use super::{update_method_metrics, Command};
+use crate::deadline::backend_timeout;
+use crate::error::{ProxyError, ProxyResult};
+use crate::klog::{klog_1, Status};
+use crate::metrics::{with_rpc_call_guard, RespMetrics};
+use crate::shards::Router;
+
+#[metric(name = "memory")]
+pub static MEMORY: Counter = Counter::new();
+
+#[metric(name = "memory_ex")]
+pub static MEMORY_EX: Counter = Counter::new();
+
+#[metric(name = "object")]
+pub static OBJECT: Counter = Counter::new();
+
+#[metric(name = "object_ex")]
+pub static OBJECT_EX: Counter = Counter::new();
+
+// the estimated overhead of a key, for its entry in the keyspace and the
+// header of its value
+const KEY_OVERHEAD: usize = 56;
+
+// the estimated overhead of each element of a collection
+const ELEMENT_OVERHEAD: usize = 16;
+
+/// Serves MEMORY USAGE and OBJECT ENCODING. Returns the name of the command
+/// served, or `None` if the command is not one of them.
+pub(crate) async fn introspection(
+    client: &mut CacheClient,
+    router: &Router<'_>,
+    command: &Command,
+    proxy_metrics: &impl RespMetrics,
+    response_buf: &mut Vec<u8>,
+) -> Option<&'static str> {
+    let name = match command.name().as_str() {
+        "MEMORY" => "memory",
+        "OBJECT" => "object",
+        _ => return None,
+    };
+
+    let result = match (name, command.args()) {
+        ("memory", [subcommand, key, options @ ..])
+            if subcommand.eq_ignore_ascii_case(b"USAGE") =>
+        {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_memory(),
+                update_method_metrics(
+                    &MEMORY,
+                    &MEMORY_EX,
+                    memory_usage(client, router.cache_for(key), response_buf, key, options),
+                ),
+            )
+            .await
+        }
+        ("object", [subcommand, key]) if subcommand.eq_ignore_ascii_case(b"ENCODING") => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_object(),
+                update_method_metrics(
+                    &OBJECT,
+                    &OBJECT_EX,
+                    object_encoding(client, router.cache_for(key), response_buf, key),
+                ),
+            )
+            .await
+        }
+        (_, [subcommand, ..]) => {
+            response_buf.extend_from_slice(
+                format!(
+                    "-ERR unknown subcommand or wrong number of arguments for '{}'. Try {} HELP.\r\n",
+                    String::from_utf8_lossy(subcommand),
+                    name.to_ascii_uppercase()
+                )
+                .as_bytes(),
+            );
+            return Some(name);
+        }
+        _ => {
+            response_buf.extend_from_slice(
+                format!("-ERR wrong number of arguments for '{name}' command\r\n").as_bytes(),
+            );
+            return Some(name);
+        }
+    };
+
+    if let Err(e) = result {
+        let status = match e {
+            ProxyError::Timeout(_) => Status::Timeout,
+            _ => Status::ServerError,
+        };
+        klog_1(&name, &command.args()[1], status, 0);
+
+        response_buf.clear();
+        super::proxy_error_to_resp_error(response_buf, name, e);
+    }
+
+    Some(name)
+}
+
+async fn memory_usage(
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    key: &[u8],
+    options: &[Vec<u8>],
+) -> ProxyResult {
+    // the whole value is always measured, so SAMPLES is checked and ignored
+    match options {
+        [] => {}
+        [option, samples] if option.eq_ignore_ascii_case(b"SAMPLES") => {
+            super::parse_integer(samples)?;
+        }
+        _ => return Err(ProxyError::custom("ERR syntax error")),
+    }
+
+    let Some(value) = fetch(client, cache_name, key).await? else {
+        response_buf.extend_from_slice(b"$-1\r\n");
+        klog_1(&"memory", &key, Status::Miss, 0);
+        return Ok(());
+    };
+
+    let size = KEY_OVERHEAD
+        + key.len()
+        + match &value {
+            Value::Scalar(value) => value.len(),
+            Value::Dictionary(pairs) => pairs
+                .iter()
+                .map(|(field, value)| field.len() + value.len() + ELEMENT_OVERHEAD)
+                .sum(),
+            Value::List(values) | Value::Set(values) => values
+                .iter()
+                .map(|value| value.len() + ELEMENT_OVERHEAD)
+                .sum(),
+            Value::SortedSet(elements) => elements
+                .iter()
+                .map(|(member, _)| member.len() + std::mem::size_of::<f64>() + ELEMENT_OVERHEAD)
+                .sum(),
+        };
+
+    response_buf.extend_from_slice(format!(":{size}\r\n").as_bytes());
+    klog_1(&"memory", &key, Status::Hit, response_buf.len());
+
+    Ok(())
+}
+
+async fn object_encoding(
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    key: &[u8],
+) -> ProxyResult {
+    let kind = match timeout(backend_timeout(), client.item_get_type(cache_name, key)).await?? {
+        ItemGetTypeResponse::Hit { key_type } => key_type,
+        ItemGetTypeResponse::Miss => {
+            response_buf.extend_from_slice(b"$-1\r\n");
+            klog_1(&"object", &key, Status::Miss, 0);
+            return Ok(());
+        }
+    };
+
+    // the encodings redis uses for values too large for its compact ones
+    let encoding = match kind {
+        ItemType::Scalar => "raw",
+        ItemType::Dictionary => "hashtable",
+        ItemType::List => "quicklist",
+        ItemType::Set => "hashtable",
+        ItemType::SortedSet => "skiplist",
+    };
+
+    response_buf.extend_from_slice(format!("${}\r\n{encoding}\r\n", encoding.len()).as_bytes());
+    klog_1(&"object", &key, Status::Hit, response_buf.len());
+
+    Ok(())
+}
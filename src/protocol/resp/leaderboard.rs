@@ -0,0 +1,959 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Sorted sets backed by Momento leaderboards, for caches with
+//! `zset_backend = "leaderboard"`. A leaderboard ranks its elements by score
+//! without the element count limits of a sorted set, but identifies them by a
+//! `u32` id rather than a byte string. Each member is given the id from a hash
+//! of the member, or the next free id after it on a collision, and the member
+//! of each id is recorded in a dictionary stored alongside the leaderboard.
+//!
+//! Leaderboards don't expire, so a sorted set is kept for as long as its
+//! dictionary instead, whose TTL is refreshed by every ZADD. Once the
+//! dictionary has expired the set reads as empty, and the elements left in the
+//! leaderboard are removed when they are next come across. Until then ids are
+//! not reused, so a removed member keeps its id if it is added again.
+//!
+//! A free id is claimed with a conditional write to a key of its own before it
+//! is recorded, so that concurrent adds of different members can't both be
+//! given it. A claim only has to outlive that window, since later adds find
+//! the id taken in the dictionary, and one left behind only makes a member
+//! which hashes to its id probe the next.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use momento::cache::{
+    CollectionTtl, DictionaryGetFieldResponse, DictionaryGetFieldsResponse,
+    DictionaryLengthResponse, GetResponse,
+};
+use momento::leaderboard::{Element, Order, RankedElement};
+use momento::CacheClient;
+use protocol_resp::{
+    RangeType, Request, SortedSetAdd, SortedSetCardinality, SortedSetMultiScore, SortedSetRange,
+    SortedSetRank, SortedSetRemove, SortedSetReverseRank, SortedSetScore, ZADD, ZADD_EX, ZCARD,
+    ZCARD_EX, ZMSCORE, ZMSCORE_EX, ZRANGE, ZRANGE_EX, ZRANK, ZRANK_EX, ZREM, ZREM_EX, ZREVRANK,
+    ZREVRANK_EX, ZSCORE, ZSCORE_EX,
+};
+use sha2::{Digest, Sha256};
+use tokio::time::timeout;
+
+use super::{parse_score_boundary_as_integer, update_method_metrics};
+use crate::backend::{DictionaryBackend, LeaderboardBackend};
+use crate::deadline::backend_timeout;
+use crate::error::{ProxyError, ProxyResult};
+use crate::klog::{klog_1, Status};
+use crate::metrics::{with_rpc_call_guard, RespMetrics};
+
+// appended to the key to name the dictionary of members by id
+const MEMBERS_SUFFIX: &[u8] = b"\0leaderboard_members";
+
+// appended to the key and an id to name the key which claims the id
+const CLAIM_SUFFIX: &[u8] = b"\0leaderboard_claim\0";
+
+// how many ids after a member's hashed id are tried on collisions
+const MAX_PROBES: u32 = 16;
+
+// the most elements a leaderboard returns from a single fetch
+const MAX_FETCH: u32 = 8192;
+
+// refreshed on every write, and each ZADD writes the ids of all of its
+// members, so the sorted set is kept for as long as it is added to
+const MEMBERS_TTL: CollectionTtl = CollectionTtl::new(None, true);
+
+/// Serves a sorted set command from a leaderboard. Returns `None` if the
+/// request is not a sorted set command.
+pub(crate) async fn leaderboard_command(
+    leaderboards: &impl LeaderboardBackend,
+    client: &CacheClient,
+    cache_name: &str,
+    request: &Request,
+    proxy_metrics: &impl RespMetrics,
+    response_buf: &mut Vec<u8>,
+) -> Option<ProxyResult> {
+    let result = match request {
+        Request::SortedSetAdd(r) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_zadd(),
+                zadd(leaderboards, client, cache_name, response_buf, r),
+            )
+            .await
+        }
+        Request::SortedSetScore(r) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_zscore(),
+                zscore(leaderboards, client, cache_name, response_buf, r),
+            )
+            .await
+        }
+        Request::SortedSetMultiScore(r) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_zmscore(),
+                zmscore(leaderboards, client, cache_name, response_buf, r),
+            )
+            .await
+        }
+        Request::SortedSetRank(r) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_zrank(),
+                zrank(leaderboards, client, cache_name, response_buf, r),
+            )
+            .await
+        }
+        Request::SortedSetReverseRank(r) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_zrevrank(),
+                zrevrank(leaderboards, client, cache_name, response_buf, r),
+            )
+            .await
+        }
+        Request::SortedSetRange(r) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_zrange(),
+                zrange(leaderboards, client, cache_name, response_buf, r),
+            )
+            .await
+        }
+        Request::SortedSetRemove(r) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_zrem(),
+                zrem(leaderboards, client, cache_name, response_buf, r),
+            )
+            .await
+        }
+        Request::SortedSetCardinality(r) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_zcard(),
+                zcard(leaderboards, client, cache_name, response_buf, r),
+            )
+            .await
+        }
+        Request::SortedSetIncrement(_) => Err(ProxyError::custom(
+            "ZINCRBY is not supported for leaderboard sorted sets",
+        )),
+        Request::SortedSetCount(_) => Err(ProxyError::custom(
+            "ZCOUNT is not supported for leaderboard sorted sets",
+        )),
+        Request::SortedSetUnionStore(_) => Err(ProxyError::custom(
+            "ZUNIONSTORE is not supported for leaderboard sorted sets",
+        )),
+        _ => return None,
+    };
+
+    Some(result)
+}
+
+async fn zadd(
+    leaderboards: &impl LeaderboardBackend,
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    req: &SortedSetAdd,
+) -> ProxyResult {
+    update_method_metrics(&ZADD, &ZADD_EX, async move {
+        let args = req.optional_args();
+        if args.ch || args.xx || args.nx || args.gt || args.lt || args.incr {
+            klog_1(&"zadd", &req.key(), Status::ServerError, 0);
+            return Err(ProxyError::custom(
+                "CH, XX, NX, GT, LT, and INCR are not supported for leaderboard sorted sets",
+            ));
+        }
+
+        // what is left of a set whose members have expired is removed before
+        // it is added to, so that it isn't counted or returned again
+        if !recorded(client, cache_name, req.key()).await? {
+            remove_orphans(leaderboards, client, cache_name, req.key()).await?;
+        }
+
+        let members: Vec<&[u8]> = req.members().iter().map(|m| &*m.1).collect();
+        let ids: Vec<u32> = ids(client, Some(client), cache_name, req.key(), &members)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        // the members which are already ranked are updated, not added
+        let name = leaderboard_name(req.key());
+        let unique: HashSet<u32> = ids.iter().copied().collect();
+        let existing = ranks(
+            leaderboards,
+            cache_name,
+            &name,
+            unique.iter().copied(),
+            Order::Ascending,
+        )
+        .await?
+        .len();
+
+        let elements: Vec<Element> = req
+            .members()
+            .iter()
+            .zip(&ids)
+            .map(|((score, _), id)| Element {
+                id: *id,
+                score: super::zadd::momento_score(*score),
+            })
+            .collect();
+        timeout(
+            backend_timeout(),
+            leaderboards.leaderboard_upsert(cache_name, &name, elements),
+        )
+        .await??;
+
+        write!(response_buf, ":{}\r\n", unique.len() - existing)?;
+        klog_1(&"zadd", &req.key(), Status::Hit, response_buf.len());
+
+        Ok(())
+    })
+    .await
+}
+
+async fn zscore(
+    leaderboards: &impl LeaderboardBackend,
+    client: &impl DictionaryBackend,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    req: &SortedSetScore,
+) -> ProxyResult {
+    update_method_metrics(&ZSCORE, &ZSCORE_EX, async move {
+        let ranked = rank_members(
+            leaderboards,
+            client,
+            cache_name,
+            req.key(),
+            &[req.member()],
+            Order::Ascending,
+        )
+        .await?;
+
+        match ranked.first().and_then(Option::as_ref) {
+            Some(element) => {
+                let score_str = element.score.to_string();
+                write!(response_buf, "${}\r\n{}\r\n", score_str.len(), score_str)?;
+                klog_1(&"zscore", &req.key(), Status::Hit, response_buf.len());
+            }
+            None => {
+                write!(response_buf, "_\r\n")?;
+                klog_1(&"zscore", &req.key(), Status::Miss, response_buf.len());
+            }
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+async fn zmscore(
+    leaderboards: &impl LeaderboardBackend,
+    client: &impl DictionaryBackend,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    req: &SortedSetMultiScore,
+) -> ProxyResult {
+    update_method_metrics(&ZMSCORE, &ZMSCORE_EX, async move {
+        let members: Vec<&[u8]> = req.members().iter().map(|m| &**m).collect();
+        let ranked = rank_members(
+            leaderboards,
+            client,
+            cache_name,
+            req.key(),
+            &members,
+            Order::Ascending,
+        )
+        .await?;
+
+        write!(response_buf, "*{}\r\n", ranked.len())?;
+        for element in ranked {
+            match element {
+                Some(element) => {
+                    let score_str = element.score.to_string();
+                    write!(response_buf, "${}\r\n{}\r\n", score_str.len(), score_str)?;
+                }
+                None => response_buf.extend_from_slice(b"_\r\n"),
+            }
+        }
+        klog_1(&"zmscore", &req.key(), Status::Hit, response_buf.len());
+
+        Ok(())
+    })
+    .await
+}
+
+async fn zrank(
+    leaderboards: &impl LeaderboardBackend,
+    client: &impl DictionaryBackend,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    req: &SortedSetRank,
+) -> ProxyResult {
+    update_method_metrics(&ZRANK, &ZRANK_EX, async move {
+        let ranked = rank_members(
+            leaderboards,
+            client,
+            cache_name,
+            req.key(),
+            &[req.member()],
+            Order::Ascending,
+        )
+        .await?;
+
+        write_rank(
+            response_buf,
+            ranked.first().and_then(Option::as_ref),
+            req.with_score(),
+        )?;
+        klog_1(&"zrank", &req.key(), Status::Hit, response_buf.len());
+
+        Ok(())
+    })
+    .await
+}
+
+async fn zrevrank(
+    leaderboards: &impl LeaderboardBackend,
+    client: &impl DictionaryBackend,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    req: &SortedSetReverseRank,
+) -> ProxyResult {
+    update_method_metrics(&ZREVRANK, &ZREVRANK_EX, async move {
+        let ranked = rank_members(
+            leaderboards,
+            client,
+            cache_name,
+            req.key(),
+            &[req.member()],
+            Order::Descending,
+        )
+        .await?;
+
+        write_rank(
+            response_buf,
+            ranked.first().and_then(Option::as_ref),
+            req.with_score(),
+        )?;
+        klog_1(&"zrevrank", &req.key(), Status::Hit, response_buf.len());
+
+        Ok(())
+    })
+    .await
+}
+
+// replies with the rank, and the score if requested, or nil for a miss
+fn write_rank(
+    response_buf: &mut Vec<u8>,
+    element: Option<&RankedElement>,
+    with_score: bool,
+) -> ProxyResult {
+    match element {
+        Some(element) if with_score => {
+            let score_str = element.score.to_string();
+            write!(
+                response_buf,
+                "*2\r\n:{}\r\n${}\r\n{}\r\n",
+                element.rank,
+                score_str.len(),
+                score_str
+            )?;
+        }
+        Some(element) => write!(response_buf, ":{}\r\n", element.rank)?,
+        None => response_buf.extend_from_slice(b"_\r\n"),
+    }
+    Ok(())
+}
+
+async fn zrange(
+    leaderboards: &impl LeaderboardBackend,
+    client: &impl DictionaryBackend,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    req: &SortedSetRange,
+) -> ProxyResult {
+    update_method_metrics(&ZRANGE, &ZRANGE_EX, async move {
+        if *req.range_type() != RangeType::ByIndex {
+            klog_1(&"zrange", &req.key(), Status::ServerError, 0);
+            return Err(ProxyError::custom(
+                "only ranges by index are supported for leaderboard sorted sets",
+            ));
+        }
+
+        let start = parse_score_boundary_as_integer(req.start())? as i64;
+        let stop = parse_score_boundary_as_integer(req.stop())? as i64;
+
+        let order = match req.optional_args().reversed {
+            Some(true) => Order::Descending,
+            _ => Order::Ascending,
+        };
+        let include_scores = matches!(req.optional_args().with_scores, Some(true));
+
+        let name = leaderboard_name(req.key());
+
+        // negative indexes count back from the end, as with redis, and a
+        // range past the end is clamped to it
+        let (start, stop) = if start < 0 || stop < 0 || stop - start >= MAX_FETCH as i64 {
+            let len = timeout(
+                backend_timeout(),
+                leaderboards.leaderboard_length(cache_name, &name),
+            )
+            .await?? as i64;
+            let start = if start < 0 {
+                (len + start).max(0)
+            } else {
+                start
+            };
+            let stop = if stop < 0 {
+                len + stop
+            } else {
+                stop.min(len - 1)
+            };
+            (start, stop)
+        } else {
+            (start, stop)
+        };
+
+        if start > stop {
+            response_buf.extend_from_slice(b"*0\r\n");
+            klog_1(&"zrange", &req.key(), Status::Miss, response_buf.len());
+            return Ok(());
+        }
+        if stop - start >= MAX_FETCH as i64 {
+            klog_1(&"zrange", &req.key(), Status::ServerError, 0);
+            return Err(ProxyError::custom(
+                "range is too large for a leaderboard sorted set",
+            ));
+        }
+
+        // a leaderboard ranks at most u32::MAX elements
+        let (Ok(start), Ok(stop)) = (u32::try_from(start), u32::try_from(stop)) else {
+            klog_1(&"zrange", &req.key(), Status::ServerError, 0);
+            return Err(ProxyError::custom(
+                "index is out of range for a leaderboard sorted set",
+            ));
+        };
+
+        let elements = timeout(
+            backend_timeout(),
+            leaderboards.leaderboard_fetch_by_rank(
+                cache_name,
+                &name,
+                start..stop.saturating_add(1),
+                order,
+            ),
+        )
+        .await??;
+
+        // an element whose member is no longer recorded can't be returned,
+        // and is left over from before the members expired
+        let members = members(client, cache_name, req.key(), elements.iter().map(|e| e.id)).await?;
+        let found: Vec<(Vec<u8>, f64)> = elements
+            .iter()
+            .zip(members)
+            .filter_map(|(element, member)| member.map(|member| (member, element.score)))
+            .collect();
+        if found.len() < elements.len() {
+            remove_orphans(leaderboards, client, cache_name, req.key()).await?;
+        }
+
+        let count = if include_scores {
+            found.len() * 2
+        } else {
+            found.len()
+        };
+        write!(response_buf, "*{count}\r\n")?;
+        for (member, score) in found {
+            write!(response_buf, "${}\r\n", member.len())?;
+            response_buf.extend_from_slice(&member);
+            response_buf.extend_from_slice(b"\r\n");
+
+            if include_scores {
+                let score_str = score.to_string();
+                write!(response_buf, "${}\r\n{}\r\n", score_str.len(), score_str)?;
+            }
+        }
+        klog_1(&"zrange", &req.key(), Status::Hit, response_buf.len());
+
+        Ok(())
+    })
+    .await
+}
+
+async fn zrem(
+    leaderboards: &impl LeaderboardBackend,
+    client: &impl DictionaryBackend,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    req: &SortedSetRemove,
+) -> ProxyResult {
+    update_method_metrics(&ZREM, &ZREM_EX, async move {
+        let members: Vec<&[u8]> = req.members().iter().map(|m| &**m).collect();
+        let ids: HashSet<u32> = ids(client, None, cache_name, req.key(), &members)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        let name = leaderboard_name(req.key());
+        let removed: Vec<u32> = ranks(
+            leaderboards,
+            cache_name,
+            &name,
+            ids.into_iter(),
+            Order::Ascending,
+        )
+        .await?
+        .iter()
+        .map(|element| element.id)
+        .collect();
+
+        if !removed.is_empty() {
+            timeout(
+                backend_timeout(),
+                leaderboards.leaderboard_remove_elements(cache_name, &name, removed.clone()),
+            )
+            .await??;
+        }
+
+        write!(response_buf, ":{}\r\n", removed.len())?;
+        klog_1(&"zrem", &req.key(), Status::Hit, response_buf.len());
+
+        Ok(())
+    })
+    .await
+}
+
+async fn zcard(
+    leaderboards: &impl LeaderboardBackend,
+    client: &impl DictionaryBackend,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    req: &SortedSetCardinality,
+) -> ProxyResult {
+    update_method_metrics(&ZCARD, &ZCARD_EX, async move {
+        let name = leaderboard_name(req.key());
+        let mut len = timeout(
+            backend_timeout(),
+            leaderboards.leaderboard_length(cache_name, &name),
+        )
+        .await??;
+
+        // what is left of a set whose members have expired isn't counted
+        if len > 0 && !recorded(client, cache_name, req.key()).await? {
+            let removed = remove_orphans(leaderboards, client, cache_name, req.key()).await?;
+            len = len.saturating_sub(removed as u32);
+        }
+
+        write!(response_buf, ":{len}\r\n")?;
+        klog_1(&"zcard", &req.key(), Status::Hit, response_buf.len());
+
+        Ok(())
+    })
+    .await
+}
+
+// leaderboard names are strings, so a key which isn't utf8 is named in hex
+fn leaderboard_name(key: &[u8]) -> String {
+    match std::str::from_utf8(key) {
+        Ok(key) => key.to_string(),
+        Err(_) => key.iter().fold("0x".to_string(), |mut name, byte| {
+            let _ = std::fmt::Write::write_fmt(&mut name, format_args!("{byte:02x}"));
+            name
+        }),
+    }
+}
+
+fn members_dictionary(key: &[u8]) -> Vec<u8> {
+    [key, MEMBERS_SUFFIX].concat()
+}
+
+// the id tried for `member` after `probe` collisions
+fn candidate(member: &[u8], probe: u32) -> u32 {
+    let digest = Sha256::digest(member);
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]).wrapping_add(probe)
+}
+
+/// The id of each member, `None` for a member which has never been added.
+/// With a client to claim them through, the members without an id are given
+/// one, and the ids of all of the members are written again to refresh the
+/// TTL of the dictionary.
+async fn ids(
+    client: &impl DictionaryBackend,
+    claims: Option<&CacheClient>,
+    cache_name: &str,
+    key: &[u8],
+    members: &[&[u8]],
+) -> ProxyResult<Vec<Option<u32>>> {
+    // each distinct member is looked up once
+    let mut unique: Vec<&[u8]> = Vec::new();
+    let mut slots: HashMap<&[u8], usize> = HashMap::new();
+    let members: Vec<usize> = members
+        .iter()
+        .map(|&member| {
+            *slots.entry(member).or_insert_with(|| {
+                unique.push(member);
+                unique.len() - 1
+            })
+        })
+        .collect();
+
+    let dictionary = members_dictionary(key);
+    let mut ids = vec![None; unique.len()];
+
+    // the members still looking for their id
+    let mut pending: Vec<usize> = (0..unique.len()).collect();
+
+    for probe in 0..MAX_PROBES {
+        if pending.is_empty() {
+            break;
+        }
+
+        let fields: Vec<String> = pending
+            .iter()
+            .map(|i| candidate(unique[*i], probe).to_string())
+            .collect();
+        let responses: Vec<Option<Vec<u8>>> = match timeout(
+            backend_timeout(),
+            client.dictionary_get_fields(
+                cache_name,
+                &dictionary,
+                fields.iter().map(|field| field.as_bytes()).collect(),
+            ),
+        )
+        .await??
+        {
+            DictionaryGetFieldsResponse::Hit { responses, .. } => responses
+                .into_iter()
+                .map(|response| match response {
+                    DictionaryGetFieldResponse::Hit { value } => Some(value.into()),
+                    DictionaryGetFieldResponse::Miss => None,
+                })
+                .collect(),
+            DictionaryGetFieldsResponse::Miss => vec![None; pending.len()],
+        };
+
+        let mut collided = Vec::new();
+        let mut free: Vec<(usize, String)> = Vec::new();
+        for ((i, field), member) in pending.into_iter().zip(fields).zip(responses) {
+            match member {
+                Some(member) if member == unique[i] => ids[i] = Some(candidate(unique[i], probe)),
+                Some(_) => collided.push(i),
+                // ids aren't freed while the members are kept, so the first
+                // free id ends the search
+                None if claims.is_none() => {}
+                // taken by another member of this request
+                None if free.iter().any(|(_, f)| *f == field) => collided.push(i),
+                None => free.push((i, field)),
+            }
+        }
+
+        if let Some(claims) = claims {
+            for (i, field) in free {
+                if claim(claims, cache_name, key, &field, unique[i]).await? {
+                    ids[i] = Some(candidate(unique[i], probe));
+                } else {
+                    collided.push(i);
+                }
+            }
+        }
+        pending = collided;
+    }
+
+    if !pending.is_empty() {
+        return Err(ProxyError::custom(
+            "could not find a free leaderboard id for a member",
+        ));
+    }
+
+    if claims.is_some() && !unique.is_empty() {
+        let recorded: Vec<(Vec<u8>, Vec<u8>)> = unique
+            .iter()
+            .zip(&ids)
+            .filter_map(|(member, id)| id.map(|id| (id.to_string().into_bytes(), member.to_vec())))
+            .collect();
+        timeout(
+            backend_timeout(),
+            client.dictionary_set_fields(cache_name, &dictionary, recorded, MEMBERS_TTL),
+        )
+        .await??;
+    }
+
+    Ok(members.into_iter().map(|slot| ids[slot]).collect())
+}
+
+/// Claims a free id for `member`. The claim is a conditional write, so of
+/// concurrent requests which find the same id free for different members,
+/// only one is given it. Returns whether the id belongs to the member, which
+/// it also does if it was claimed for the member by an earlier request which
+/// didn't get to record it.
+async fn claim(
+    client: &CacheClient,
+    cache_name: &str,
+    key: &[u8],
+    id: &str,
+    member: &[u8],
+) -> ProxyResult<bool> {
+    let claim_key = [key, CLAIM_SUFFIX, id.as_bytes()].concat();
+    if super::write_if_unchanged(client, cache_name, &claim_key, member.to_vec(), None, None)
+        .await?
+    {
+        return Ok(true);
+    }
+
+    let claimed = timeout(backend_timeout(), client.get(cache_name, claim_key)).await??;
+    Ok(match claimed {
+        GetResponse::Hit { value } => Vec::<u8>::from(value) == member,
+        GetResponse::Miss => false,
+    })
+}
+
+/// Whether the dictionary of members exists, which it doesn't once it has
+/// expired.
+async fn recorded(
+    client: &impl DictionaryBackend,
+    cache_name: &str,
+    key: &[u8],
+) -> ProxyResult<bool> {
+    let response = timeout(
+        backend_timeout(),
+        client.dictionary_length(cache_name, &members_dictionary(key)),
+    )
+    .await??;
+    Ok(matches!(response, DictionaryLengthResponse::Hit { .. }))
+}
+
+/// Removes the elements whose members are no longer recorded, which are left
+/// in the leaderboard once the dictionary has expired. The leaderboard is read
+/// before the dictionary, and an add records its members before ranking them,
+/// so an element added meanwhile is never removed. Returns how many elements
+/// were removed.
+async fn remove_orphans(
+    leaderboards: &impl LeaderboardBackend,
+    client: &impl DictionaryBackend,
+    cache_name: &str,
+    key: &[u8],
+) -> ProxyResult<usize> {
+    let name = leaderboard_name(key);
+    let len = timeout(
+        backend_timeout(),
+        leaderboards.leaderboard_length(cache_name, &name),
+    )
+    .await??;
+
+    let mut ids = Vec::new();
+    for start in (0..len).step_by(MAX_FETCH as usize) {
+        let elements = timeout(
+            backend_timeout(),
+            leaderboards.leaderboard_fetch_by_rank(
+                cache_name,
+                &name,
+                start..start.saturating_add(MAX_FETCH),
+                Order::Ascending,
+            ),
+        )
+        .await??;
+        ids.extend(elements.iter().map(|element| element.id));
+    }
+
+    let mut orphans = Vec::new();
+    for chunk in ids.chunks(MAX_FETCH as usize) {
+        let members = members(client, cache_name, key, chunk.iter().copied()).await?;
+        orphans.extend(
+            chunk
+                .iter()
+                .zip(members)
+                .filter(|(_, member)| member.is_none())
+                .map(|(id, _)| *id),
+        );
+    }
+
+    for chunk in orphans.chunks(MAX_FETCH as usize) {
+        timeout(
+            backend_timeout(),
+            leaderboards.leaderboard_remove_elements(cache_name, &name, chunk.to_vec()),
+        )
+        .await??;
+    }
+
+    Ok(orphans.len())
+}
+
+/// The recorded member of each id, `None` for an id without one.
+async fn members(
+    client: &impl DictionaryBackend,
+    cache_name: &str,
+    key: &[u8],
+    ids: impl Iterator<Item = u32>,
+) -> ProxyResult<Vec<Option<Vec<u8>>>> {
+    let fields: Vec<String> = ids.map(|id| id.to_string()).collect();
+    if fields.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let count = fields.len();
+    match timeout(
+        backend_timeout(),
+        client.dictionary_get_fields(
+            cache_name,
+            &members_dictionary(key),
+            fields.iter().map(|field| field.as_bytes()).collect(),
+        ),
+    )
+    .await??
+    {
+        DictionaryGetFieldsResponse::Hit { responses, .. } => Ok(responses
+            .into_iter()
+            .map(|response| match response {
+                DictionaryGetFieldResponse::Hit { value } => Some(value.into()),
+                DictionaryGetFieldResponse::Miss => None,
+            })
+            .collect()),
+        DictionaryGetFieldsResponse::Miss => Ok(vec![None; count]),
+    }
+}
+
+/// The ranked elements for the ids which are in the leaderboard.
+async fn ranks(
+    leaderboards: &impl LeaderboardBackend,
+    cache_name: &str,
+    name: &str,
+    ids: impl Iterator<Item = u32>,
+    order: Order,
+) -> ProxyResult<Vec<RankedElement>> {
+    let ids: Vec<u32> = ids.collect();
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    Ok(timeout(
+        backend_timeout(),
+        leaderboards.leaderboard_get_rank(cache_name, name, ids, order),
+    )
+    .await??)
+}
+
+/// The ranked element of each member, `None` for a member which is not in
+/// the leaderboard.
+async fn rank_members(
+    leaderboards: &impl LeaderboardBackend,
+    client: &impl DictionaryBackend,
+    cache_name: &str,
+    key: &[u8],
+    members: &[&[u8]],
+    order: Order,
+) -> ProxyResult<Vec<Option<RankedElement>>> {
+    let ids = ids(client, None, cache_name, key, members).await?;
+
+    let name = leaderboard_name(key);
+    let ranked = ranks(
+        leaderboards,
+        cache_name,
+        &name,
+        ids.iter().flatten().copied(),
+        order,
+    )
+    .await?;
+
+    Ok(ids
+        .into_iter()
+        .map(|id| id.and_then(|id| ranked.iter().find(|e| e.id == id).cloned()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{request, MockBackend};
+
+    // records the members and ranks them, as ZADD does for members which
+    // don't collide
+    async fn add(backend: &MockBackend, key: &str, elements: &[(&str, f64)]) {
+        let recorded = elements
+            .iter()
+            .map(|(member, _)| {
+                let id = candidate(member.as_bytes(), 0);
+                (id.to_string().into_bytes(), member.as_bytes().to_vec())
+            })
+            .collect();
+        backend
+            .dictionary_set_fields(
+                "cache",
+                &members_dictionary(key.as_bytes()),
+                recorded,
+                MEMBERS_TTL,
+            )
+            .await
+            .unwrap();
+
+        let elements = elements
+            .iter()
+            .map(|(member, score)| Element {
+                id: candidate(member.as_bytes(), 0),
+                score: *score,
+            })
+            .collect();
+        backend
+            .leaderboard_upsert("cache", key, elements)
+            .await
+            .unwrap();
+    }
+
+    async fn range(backend: &MockBackend, key: &str) -> Vec<u8> {
+        let Request::SortedSetRange(r) = request(&["ZRANGE", key, "0", "-1"]) else {
+            panic!("not a ZRANGE");
+        };
+        let mut response = Vec::new();
+        zrange(backend, backend, "cache", &mut response, &r)
+            .await
+            .unwrap();
+        response
+    }
+
+    async fn card(backend: &MockBackend, key: &str) -> Vec<u8> {
+        let Request::SortedSetCardinality(r) = request(&["ZCARD", key]) else {
+            panic!("not a ZCARD");
+        };
+        let mut response = Vec::new();
+        zcard(backend, backend, "cache", &mut response, &r)
+            .await
+            .unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn expired_members_are_not_returned() {
+        let backend = MockBackend::default();
+        add(&backend, "z", &[("a", 1.0), ("b", 2.0)]).await;
+        assert_eq!(range(&backend, "z").await, b"*2\r\n$1\r\na\r\n$1\r\nb\r\n");
+
+        backend.expire("cache", &members_dictionary(b"z"));
+
+        let Request::SortedSetScore(r) = request(&["ZSCORE", "z", "a"]) else {
+            panic!("not a ZSCORE");
+        };
+        let mut response = Vec::new();
+        zscore(&backend, &backend, "cache", &mut response, &r)
+            .await
+            .unwrap();
+        assert_eq!(response, b"_\r\n");
+
+        assert_eq!(range(&backend, "z").await, b"*0\r\n");
+        // and what was left of them has been removed from the leaderboard
+        assert_eq!(backend.leaderboard_length("cache", "z").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn expired_members_are_not_counted() {
+        let backend = MockBackend::default();
+        add(&backend, "z", &[("a", 1.0), ("b", 2.0)]).await;
+        assert_eq!(card(&backend, "z").await, b":2\r\n");
+
+        backend.expire("cache", &members_dictionary(b"z"));
+        assert_eq!(card(&backend, "z").await, b":0\r\n");
+        assert_eq!(backend.leaderboard_length("cache", "z").await.unwrap(), 0);
+
+        // the set starts over once it is added to again
+        add(&backend, "z", &[("c", 3.0)]).await;
+        assert_eq!(card(&backend, "z").await, b":1\r\n");
+        assert_eq!(range(&backend, "z").await, b"*1\r\n$1\r\nc\r\n");
+    }
+}
@@ -3,57 +3,48 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::io::Write;
-use std::time::Duration;
 
 use momento::cache::ListFetchResponse;
-use momento::CacheClient;
 use protocol_resp::{ListIndex, LINDEX, LINDEX_EX, LINDEX_HIT, LINDEX_MISS};
 
+use crate::backend::ListBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_2, Status};
 use crate::ProxyError;
 
-use super::update_method_metrics;
+use super::{normalize_index, update_method_metrics};
 
 pub async fn lindex(
-    client: &mut CacheClient,
+    client: &mut impl ListBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &ListIndex,
 ) -> ProxyResult {
     update_method_metrics(&LINDEX, &LINDEX_EX, async move {
-        let entry = match tokio::time::timeout(
-            Duration::from_millis(200),
-            client.list_fetch(cache_name, req.key()),
-        )
-        .await
-        {
-            Ok(Ok(r)) => r,
-            Ok(Err(e)) => {
-                let index = format!("{}", req.index());
-                klog_2(&"lindex", &req.key(), &index, Status::ServerError, 0);
+        let entry =
+            match tokio::time::timeout(backend_timeout(), client.list_fetch(cache_name, req.key()))
+                .await
+            {
+                Ok(Ok(r)) => r,
+                Ok(Err(e)) => {
+                    let index = format!("{}", req.index());
+                    klog_2(&"lindex", &req.key(), &index, Status::ServerError, 0);
 
-                return Err(ProxyError::from(e));
-            }
-            Err(e) => {
-                let index = format!("{}", req.index());
-                klog_2(&"lindex", &req.key(), &index, Status::Timeout, 0);
+                    return Err(ProxyError::from(e));
+                }
+                Err(e) => {
+                    let index = format!("{}", req.index());
+                    klog_2(&"lindex", &req.key(), &index, Status::Timeout, 0);
 
-                return Err(ProxyError::from(e));
-            }
-        };
+                    return Err(ProxyError::from(e));
+                }
+            };
 
         match entry {
             ListFetchResponse::Hit { values } => {
-                // TODO need to impl from here
                 let list: Vec<Vec<u8>> = values.into();
-                let index: Option<usize> = match req.index() {
-                    index @ 0.. => index.try_into().ok(),
-                    index => (-index)
-                        .try_into()
-                        .map(|index: usize| list.len() - index)
-                        .ok(),
-                };
+                let index = normalize_index(req.index(), list.len());
 
                 let status = match index.and_then(|index| list.get(index)).map(|x| &**x) {
                     Some(element) => {
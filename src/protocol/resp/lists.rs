@@ -0,0 +1,277 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! LPOS and LMPOP. Momento has no way to search a list, so LPOS fetches the
+//! whole list and searches it in the proxy. LMPOP pops one element at a time,
+//! as LPOP and RPOP with a count do, from the first of its keys which is not
+//! empty.
+
+use metriken::{metric, Counter};
+use momento::cache::{ListFetchResponse, ListPopBackResponse, ListPopFrontResponse};
+use tokio::time::timeout;
+
+use super::{update_method_metrics, Command};
+use crate::backend::ListBackend;
+use crate::deadline::backend_timeout;
+use crate::error::{ProxyError, ProxyResult};
+use crate::klog::{klog_1, Status};
+use crate::metrics::{with_rpc_call_guard, RespMetrics};
+use crate::shards::{Router, SHARD_CROSSSLOT};
+
+#[metric(name = "lpos")]
+pub static LPOS: Counter = Counter::new();
+
+#[metric(name = "lpos_ex")]
+pub static LPOS_EX: Counter = Counter::new();
+
+#[metric(name = "lmpop")]
+pub static LMPOP: Counter = Counter::new();
+
+#[metric(name = "lmpop_ex")]
+pub static LMPOP_EX: Counter = Counter::new();
+
+/// Serves LPOS and LMPOP. Returns the name of the command served, or `None`
+/// if the command is not one of them.
+pub(crate) async fn list_command(
+    client: &mut impl ListBackend,
+    router: &Router<'_>,
+    command: &Command,
+    proxy_metrics: &impl RespMetrics,
+    response_buf: &mut Vec<u8>,
+) -> Option<&'static str> {
+    let name = match command.name().as_str() {
+        "LPOS" => "lpos",
+        "LMPOP" => "lmpop",
+        _ => return None,
+    };
+
+    let result = match (name, command.args()) {
+        ("lpos", [key, element, options @ ..]) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_lpos(),
+                update_method_metrics(
+                    &LPOS,
+                    &LPOS_EX,
+                    lpos(
+                        client,
+                        router.cache_for(key),
+                        response_buf,
+                        key,
+                        element,
+                        options,
+                    ),
+                ),
+            )
+            .await
+        }
+        ("lmpop", [numkeys, rest @ ..]) => {
+            // LMPOP numkeys key [key ...] LEFT|RIGHT [COUNT count]
+            let keys = match super::parse_integer(numkeys) {
+                Ok(count @ 1..) if (count as usize) < rest.len() => &rest[..count as usize],
+                Ok(1..) => {
+                    response_buf.extend_from_slice(b"-ERR syntax error\r\n");
+                    return Some(name);
+                }
+                Ok(_) => {
+                    response_buf.extend_from_slice(b"-ERR numkeys should be greater than 0\r\n");
+                    return Some(name);
+                }
+                Err(e) => {
+                    super::proxy_error_to_resp_error(response_buf, name, e);
+                    return Some(name);
+                }
+            };
+
+            let Some(cache_name) = router.cache_for_all(keys.iter().map(|key| key.as_slice()))
+            else {
+                SHARD_CROSSSLOT.increment();
+                response_buf.extend_from_slice(
+                    b"-CROSSSLOT Keys in request don't hash to the same shard\r\n",
+                );
+                return Some(name);
+            };
+
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_lmpop(),
+                update_method_metrics(
+                    &LMPOP,
+                    &LMPOP_EX,
+                    lmpop(client, cache_name, response_buf, keys, &rest[keys.len()..]),
+                ),
+            )
+            .await
+        }
+        _ => {
+            response_buf.extend_from_slice(
+                format!("-ERR wrong number of arguments for '{name}' command\r\n").as_bytes(),
+            );
+            return Some(name);
+        }
+    };
+
+    if let Err(e) = result {
+        let status = match e {
+            ProxyError::Timeout(_) => Status::Timeout,
+            ProxyError::Custom(_) => Status::NotStored,
+            _ => Status::ServerError,
+        };
+        // the first argument of LMPOP is its number of keys
+        let key = command.keys().first().copied().unwrap_or_default();
+        klog_1(&name, &key, status, 0);
+
+        response_buf.clear();
+        super::proxy_error_to_resp_error(response_buf, name, e);
+    }
+
+    Some(name)
+}
+
+async fn lpos(
+    client: &impl ListBackend,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    key: &[u8],
+    element: &[u8],
+    options: &[Vec<u8>],
+) -> ProxyResult {
+    // LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]
+    let mut rank = 1;
+    let mut count = None;
+    let mut maxlen = 0;
+    for option in options.chunks(2) {
+        match option {
+            [option, value] if option.eq_ignore_ascii_case(b"RANK") => {
+                rank = super::parse_integer(value)?;
+                if rank == 0 {
+                    return Err(ProxyError::custom(
+                        "ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list",
+                    ));
+                }
+            }
+            [option, value] if option.eq_ignore_ascii_case(b"COUNT") => {
+                count = Some(
+                    usize::try_from(super::parse_integer(value)?)
+                        .map_err(|_| ProxyError::custom("ERR COUNT can't be negative"))?,
+                );
+            }
+            [option, value] if option.eq_ignore_ascii_case(b"MAXLEN") => {
+                maxlen = usize::try_from(super::parse_integer(value)?)
+                    .map_err(|_| ProxyError::custom("ERR MAXLEN can't be negative"))?;
+            }
+            _ => return Err(ProxyError::custom("ERR syntax error")),
+        }
+    }
+
+    let list: Vec<Vec<u8>> =
+        match timeout(backend_timeout(), client.list_fetch(cache_name, key)).await?? {
+            ListFetchResponse::Hit { values } => values.into(),
+            ListFetchResponse::Miss => Vec::new(),
+        };
+
+    // a negative rank searches from the end, but positions are always counted
+    // from the start. A count of 0 finds every match, and a maxlen of 0
+    // compares every element
+    let compared = if maxlen == 0 { list.len() } else { maxlen };
+    let skip = usize::try_from(rank.unsigned_abs() - 1).unwrap_or(usize::MAX);
+    let mut matches = (0..list.len())
+        .map(|i| if rank > 0 { i } else { list.len() - 1 - i })
+        .take(compared)
+        .filter(|position| list[*position] == element)
+        .skip(skip);
+
+    match count {
+        None => match matches.next() {
+            Some(position) => response_buf.extend_from_slice(format!(":{position}\r\n").as_bytes()),
+            None => response_buf.extend_from_slice(b"$-1\r\n"),
+        },
+        Some(count) => {
+            let found: Vec<usize> = match count {
+                0 => matches.collect(),
+                count => matches.take(count).collect(),
+            };
+
+            response_buf.extend_from_slice(format!("*{}\r\n", found.len()).as_bytes());
+            for position in found {
+                response_buf.extend_from_slice(format!(":{position}\r\n").as_bytes());
+            }
+        }
+    }
+
+    let status = if list.is_empty() {
+        Status::Miss
+    } else {
+        Status::Hit
+    };
+    klog_1(&"lpos", &key, status, response_buf.len());
+
+    Ok(())
+}
+
+async fn lmpop(
+    client: &impl ListBackend,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    keys: &[Vec<u8>],
+    options: &[Vec<u8>],
+) -> ProxyResult {
+    let (left, count) = match options {
+        [side, rest @ ..] if side.eq_ignore_ascii_case(b"LEFT") => (true, rest),
+        [side, rest @ ..] if side.eq_ignore_ascii_case(b"RIGHT") => (false, rest),
+        _ => return Err(ProxyError::custom("ERR syntax error")),
+    };
+    let count = match count {
+        [] => 1,
+        [option, count] if option.eq_ignore_ascii_case(b"COUNT") => {
+            match super::parse_integer(count)? {
+                count @ 1.. => count as usize,
+                _ => return Err(ProxyError::custom("ERR count should be greater than 0")),
+            }
+        }
+        _ => return Err(ProxyError::custom("ERR syntax error")),
+    };
+
+    // the elements are popped from the first key which has any. As with LPOP,
+    // each element is popped on its own, so that none are lost or duplicated
+    // by concurrent pops
+    for key in keys {
+        let mut popped: Vec<Vec<u8>> = Vec::with_capacity(count.min(64));
+        while popped.len() < count {
+            let value = if left {
+                match timeout(backend_timeout(), client.list_pop_front(cache_name, &**key))
+                    .await??
+                {
+                    ListPopFrontResponse::Hit { value } => value.into(),
+                    ListPopFrontResponse::Miss => break,
+                }
+            } else {
+                match timeout(backend_timeout(), client.list_pop_back(cache_name, &**key)).await?? {
+                    ListPopBackResponse::Hit { value } => value.into(),
+                    ListPopBackResponse::Miss => break,
+                }
+            };
+            popped.push(value);
+        }
+
+        if popped.is_empty() {
+            continue;
+        }
+
+        response_buf.extend_from_slice(format!("*2\r\n${}\r\n", key.len()).as_bytes());
+        response_buf.extend_from_slice(key);
+        response_buf.extend_from_slice(format!("\r\n*{}\r\n", popped.len()).as_bytes());
+        for element in &popped {
+            response_buf.extend_from_slice(format!("${}\r\n", element.len()).as_bytes());
+            response_buf.extend_from_slice(element);
+            response_buf.extend_from_slice(b"\r\n");
+        }
+
+        klog_1(&"lmpop", &key, Status::Hit, popped.len());
+        return Ok(());
+    }
+
+    response_buf.extend_from_slice(b"*-1\r\n");
+    klog_1(&"lmpop", &keys[0], Status::Miss, 0);
+
+    Ok(())
+}
@@ -3,12 +3,12 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::io::Write;
-use std::time::Duration;
 
 use momento::cache::ListLengthResponse;
-use momento::CacheClient;
 use protocol_resp::{ListLen, LLEN, LLEN_EX};
 
+use crate::backend::ListBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
@@ -16,14 +16,14 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn llen(
-    client: &mut CacheClient,
+    client: &mut impl ListBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &ListLen,
 ) -> ProxyResult {
     update_method_metrics(&LLEN, &LLEN_EX, async move {
         let length_response = match tokio::time::timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.list_length(cache_name, req.key()),
         )
         .await
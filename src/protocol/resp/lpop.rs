@@ -4,6 +4,7 @@
 
 use std::io::Write;
 
+use crate::backend::ListBackend;
 use crate::*;
 use momento::cache::{ListLengthResponse, ListPopFrontResponse};
 use protocol_resp::{ListPop, LPOP, LPOP_EX};
@@ -11,13 +12,13 @@ use protocol_resp::{ListPop, LPOP, LPOP_EX};
 use super::update_method_metrics;
 
 pub async fn lpop(
-    client: &mut CacheClient,
+    client: &mut impl ListBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &ListPop,
 ) -> ProxyResult {
     update_method_metrics(&LPOP, &LPOP_EX, async move {
-        let tout = Duration::from_millis(200);
+        let tout = backend_timeout();
 
         match req.count() {
             None => match timeout(tout, client.list_pop_front(cache_name, req.key())).await?? {
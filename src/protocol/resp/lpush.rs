@@ -2,24 +2,25 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
+use crate::backend::ListBackend;
 use crate::*;
 use protocol_resp::{ListPush, LPUSH, LPUSH_EX};
 
 use super::update_method_metrics;
 
 pub async fn lpush(
-    client: &mut CacheClient,
+    client: &mut impl ListBackend,
     cache_name: &str,
     _: &mut Vec<u8>,
     req: &ListPush,
 ) -> ProxyResult {
     update_method_metrics(&LPUSH, &LPUSH_EX, async move {
         timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.list_concatenate_front(
                 cache_name,
                 req.key(),
-                req.elements().iter().map(|e| &e[..]),
+                req.elements().iter().map(|e| &e[..]).collect(),
             ),
         )
         .await??;
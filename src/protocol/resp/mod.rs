@@ -4,7 +4,10 @@
 
 pub use protocol_resp::{Request, RequestParser};
 
+mod bits;
+mod command;
 mod del;
+mod dump;
 mod get;
 mod hdel;
 mod hexists;
@@ -13,10 +16,15 @@ mod hgetall;
 mod hincrby;
 mod hkeys;
 mod hlen;
+mod hll;
 mod hmget;
 mod hset;
 mod hvals;
+mod introspection;
+#[cfg(feature = "preview-leaderboards")]
+mod leaderboard;
 mod lindex;
+mod lists;
 mod llen;
 mod lpop;
 mod lpush;
@@ -30,7 +38,9 @@ mod sinter;
 mod sismember;
 mod smembers;
 mod srem;
+mod strings;
 mod sunion;
+mod ttl;
 mod utils;
 mod zadd;
 mod zcard;
@@ -44,6 +54,16 @@ mod zrevrank;
 mod zscore;
 mod zunionstore;
 
+pub(crate) use bits::bit_operation;
+pub(crate) use command::*;
+pub(crate) use dump::dump_restore;
+pub(crate) use hll::{hyperloglog, HLL_PRECISIONS};
+pub(crate) use introspection::introspection;
+#[cfg(feature = "preview-leaderboards")]
+pub(crate) use leaderboard::leaderboard_command;
+pub(crate) use lists::list_command;
+pub(crate) use strings::string_range;
+pub(crate) use ttl::ttl;
 pub(crate) use utils::*;
 
 pub use self::lindex::*;
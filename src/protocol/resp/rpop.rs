@@ -7,18 +7,19 @@ use std::io::Write;
 use momento::cache::{ListLengthResponse, ListPopBackResponse};
 use protocol_resp::{ListPopBack, RPOP, RPOP_EX};
 
+use crate::backend::ListBackend;
 use crate::*;
 
 use super::update_method_metrics;
 
 pub async fn rpop(
-    client: &mut CacheClient,
+    client: &mut impl ListBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &ListPopBack,
 ) -> ProxyResult {
     update_method_metrics(&RPOP, &RPOP_EX, async move {
-        let tout = Duration::from_millis(200);
+        let tout = backend_timeout();
 
         match req.count() {
             None => match timeout(tout, client.list_pop_back(cache_name, req.key())).await?? {
@@ -3,17 +3,17 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::io::Write;
-use std::time::Duration;
 
-use momento::CacheClient;
 use protocol_resp::{SetAdd, SADD, SADD_EX};
 
+use crate::backend::SetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 
 use super::update_method_metrics;
 
 pub async fn sadd(
-    client: &mut CacheClient,
+    client: &mut impl SetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SetAdd,
@@ -22,7 +22,7 @@ pub async fn sadd(
         let elements: Vec<&[u8]> = req.members().iter().map(|e| &**e).collect();
 
         tokio::time::timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.set_add_elements(cache_name, req.key(), elements),
         )
         .await??;
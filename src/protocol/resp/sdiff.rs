@@ -2,25 +2,28 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
-use std::time::Duration;
 use std::{collections::HashSet, io::Write};
 
-use momento::{cache::SetFetchResponse, CacheClient};
+use momento::cache::SetFetchResponse;
 use protocol_resp::{SetDiff, SDIFF, SDIFF_EX};
 use tokio::time;
 
+use crate::backend::SetBackend;
+use crate::deadline::backend_timeout;
+use crate::shards::Router;
 use crate::ProxyResult;
 
 use super::update_method_metrics;
 
+/// Subtracts the other sets from the first, each fetched from its own shard.
 pub async fn sdiff(
-    client: &mut CacheClient,
-    cache_name: &str,
+    client: &mut impl SetBackend,
+    router: &Router<'_>,
     response_buf: &mut Vec<u8>,
     req: &SetDiff,
 ) -> ProxyResult {
     update_method_metrics(&SDIFF, &SDIFF_EX, async move {
-        let timeout = Duration::from_millis(200);
+        let timeout = backend_timeout();
 
         // Note: the resp parser validates that SetDiff has at least one key.
         let (head, rest) = req
@@ -29,7 +32,8 @@ pub async fn sdiff(
             .expect("got an invalid set difference request");
         let head = &**head;
 
-        let response = time::timeout(timeout, client.set_fetch(cache_name, head)).await??;
+        let response =
+            time::timeout(timeout, client.set_fetch(router.cache_for(head), head)).await??;
         match response {
             SetFetchResponse::Hit { values } => {
                 let mut set: HashSet<Vec<u8>> = values.into();
@@ -41,7 +45,8 @@ pub async fn sdiff(
                     }
 
                     let response =
-                        time::timeout(timeout, client.set_fetch(cache_name, key)).await??;
+                        time::timeout(timeout, client.set_fetch(router.cache_for(key), key))
+                            .await??;
                     match response {
                         SetFetchResponse::Hit { values } => {
                             let other_set: HashSet<Vec<u8>> = values.into();
@@ -2,36 +2,96 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use momento::cache::SetRequest;
 use momento::CacheClient;
 use protocol_memcache::{SET, SET_EX, SET_STORED};
 use protocol_resp::Set;
 
+use crate::deadline::backend_timeout;
+use crate::enforce::{self, Policy};
 use crate::error::{ProxyError, ProxyResult};
 use crate::klog::{klog_set, Status};
+use crate::metrics::batch;
+use crate::protocol::memcache::SET_TOO_LARGE;
 
 use super::update_method_metrics;
 
+/// Sets the key. With `memcache_flags`, the value is stored in the memcache
+/// format, behind zero flags, so that memcache listeners can read it. A write
+/// without an expiry uses `default_ttl`, or else the client's default. A value
+/// larger than `max_item_size` is refused, unless it is 0.
 pub async fn set(
     client: &mut CacheClient,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &Set,
+    memcache_flags: bool,
+    default_ttl: Option<Duration>,
+    max_item_size: usize,
 ) -> ProxyResult {
     update_method_metrics(&SET, &SET_EX, async move {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
         let ttl = match req.expire_time() {
             Some(protocol_resp::ExpireTime::Seconds(v)) => Some(Duration::from_secs(v)),
-            Some(protocol_resp::ExpireTime::Milliseconds(v)) => {
-                Some(Duration::from_millis((v / 1000).max(1)))
+            Some(protocol_resp::ExpireTime::Milliseconds(v)) => Some(Duration::from_millis(v)),
+            Some(protocol_resp::ExpireTime::UnixSeconds(v)) => {
+                Some(Duration::from_secs(v).saturating_sub(now))
+            }
+            Some(protocol_resp::ExpireTime::UnixMilliseconds(v)) => {
+                Some(Duration::from_millis(v).saturating_sub(now))
             }
             Some(_) => return Err(ProxyError::custom("expire time")),
-            None => None,
+            None => default_ttl,
+        };
+
+        // an expiry in the past leaves the key absent, just like redis
+        if ttl.is_some_and(|ttl| ttl.is_zero()) {
+            tokio::time::timeout(backend_timeout(), client.delete(cache_name, req.key())).await??;
+
+            batch::increment(&SET_STORED);
+            response_buf.extend_from_slice(b"+OK\r\n");
+            return Ok(());
+        }
+
+        let value = if memcache_flags {
+            let mut value = 0u32.to_be_bytes().to_vec();
+            value.extend_from_slice(req.value());
+            value
+        } else {
+            req.value().to_vec()
         };
 
+        if max_item_size > 0
+            && value.len() > max_item_size
+            && enforce::enforce(Policy::ItemSize, || {
+                format!(
+                    "set of {} bytes to {}",
+                    value.len(),
+                    String::from_utf8_lossy(req.key())
+                )
+            })
+        {
+            SET_TOO_LARGE.increment();
+            klog_set(
+                &req.key(),
+                0,
+                ttl.map(|v| v.as_millis()).unwrap_or(0) as i32,
+                req.value().len(),
+                Status::NotStored,
+                0,
+            );
+            response_buf.extend_from_slice(b"-ERR string exceeds maximum allowed size\r\n");
+            return Ok(());
+        }
+
         let _response = match tokio::time::timeout(
-            Duration::from_millis(200),
-            client.set(cache_name, req.key(), req.value()),
+            backend_timeout(),
+            client.send_request(SetRequest::new(cache_name, req.key(), value).ttl(ttl)),
         )
         .await
         {
@@ -60,7 +120,7 @@ pub async fn set(
             }
         };
 
-        SET_STORED.increment();
+        batch::increment(&SET_STORED);
         klog_set(
             &req.key(),
             0,
@@ -3,25 +3,28 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::io::Write;
-use std::time::Duration;
 
-use momento::{cache::SetFetchResponse, CacheClient};
+use momento::cache::SetFetchResponse;
 use protocol_resp::{SetIntersect, SINTER, SINTER_EX};
 use std::collections::HashSet;
 use tokio::time;
 
+use crate::backend::SetBackend;
+use crate::deadline::backend_timeout;
+use crate::shards::Router;
 use crate::ProxyResult;
 
 use super::update_method_metrics;
 
+/// Intersects the sets, each fetched from its own shard.
 pub async fn sinter(
-    client: &mut CacheClient,
-    cache_name: &str,
+    client: &mut impl SetBackend,
+    router: &Router<'_>,
     response_buf: &mut Vec<u8>,
     req: &SetIntersect,
 ) -> ProxyResult {
     update_method_metrics(&SINTER, &SINTER_EX, async move {
-        let timeout = Duration::from_millis(200);
+        let timeout = backend_timeout();
 
         // Note: the resp parser validates that SetInter has at least one key.
         let (head, rest) = req
@@ -30,7 +33,8 @@ pub async fn sinter(
             .expect("got an invalid set difference request");
         let head = &**head;
 
-        let response = time::timeout(timeout, client.set_fetch(cache_name, head)).await??;
+        let response =
+            time::timeout(timeout, client.set_fetch(router.cache_for(head), head)).await??;
         match response {
             SetFetchResponse::Hit { values } => {
                 let mut set: HashSet<Vec<u8>> = values.into();
@@ -43,7 +47,8 @@ pub async fn sinter(
                     }
 
                     let response =
-                        time::timeout(timeout, client.set_fetch(cache_name, key)).await??;
+                        time::timeout(timeout, client.set_fetch(router.cache_for(key), key))
+                            .await??;
                     match response {
                         SetFetchResponse::Hit { values } => {
                             let other_set: Vec<Vec<u8>> = values.into();
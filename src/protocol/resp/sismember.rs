@@ -2,14 +2,13 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
-use std::time::Duration;
-
 use momento::cache::SetFetchResponse;
-use momento::CacheClient;
 use protocol_resp::{SetIsMember, SISMEMBER, SISMEMBER_EX, SISMEMBER_HIT, SISMEMBER_MISS};
 use std::collections::HashSet;
 use tokio::time;
 
+use crate::backend::SetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_2, Status};
 use crate::ProxyError;
@@ -17,40 +16,36 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn sismember(
-    client: &mut CacheClient,
+    client: &mut impl SetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SetIsMember,
 ) -> ProxyResult {
     update_method_metrics(&SISMEMBER, &SISMEMBER_EX, async move {
-        let response = match time::timeout(
-            Duration::from_millis(200),
-            client.set_fetch(cache_name, req.key()),
-        )
-        .await
-        {
-            Ok(Ok(r)) => r,
-            Ok(Err(e)) => {
-                klog_2(
-                    &"sismember",
-                    &req.key(),
-                    &req.field(),
-                    Status::ServerError,
-                    0,
-                );
-                return Err(ProxyError::from(e));
-            }
-            Err(e) => {
-                klog_2(
-                    &"sismember",
-                    &req.key(),
-                    &req.field(),
-                    Status::ServerError,
-                    0,
-                );
-                return Err(ProxyError::from(e));
-            }
-        };
+        let response =
+            match time::timeout(backend_timeout(), client.set_fetch(cache_name, req.key())).await {
+                Ok(Ok(r)) => r,
+                Ok(Err(e)) => {
+                    klog_2(
+                        &"sismember",
+                        &req.key(),
+                        &req.field(),
+                        Status::ServerError,
+                        0,
+                    );
+                    return Err(ProxyError::from(e));
+                }
+                Err(e) => {
+                    klog_2(
+                        &"sismember",
+                        &req.key(),
+                        &req.field(),
+                        Status::ServerError,
+                        0,
+                    );
+                    return Err(ProxyError::from(e));
+                }
+            };
 
         let status = match response {
             SetFetchResponse::Hit { values } => {
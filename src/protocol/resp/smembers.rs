@@ -4,13 +4,13 @@
 
 use std::collections::HashSet;
 use std::io::Write;
-use std::time::Duration;
 
 use momento::cache::SetFetchResponse;
-use momento::CacheClient;
 use protocol_resp::{SetMembers, SMEMBERS, SMEMBERS_EX};
 use tokio::time;
 
+use crate::backend::SetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
@@ -18,28 +18,24 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn smembers(
-    client: &mut CacheClient,
+    client: &mut impl SetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SetMembers,
 ) -> ProxyResult {
     update_method_metrics(&SMEMBERS, &SMEMBERS_EX, async move {
-        let response = match time::timeout(
-            Duration::from_millis(200),
-            client.set_fetch(cache_name, req.key()),
-        )
-        .await
-        {
-            Ok(Ok(r)) => r,
-            Ok(Err(e)) => {
-                klog_1(&"sismember", &req.key(), Status::ServerError, 0);
-                return Err(ProxyError::from(e));
-            }
-            Err(e) => {
-                klog_1(&"sismember", &req.key(), Status::Timeout, 0);
-                return Err(ProxyError::from(e));
-            }
-        };
+        let response =
+            match time::timeout(backend_timeout(), client.set_fetch(cache_name, req.key())).await {
+                Ok(Ok(r)) => r,
+                Ok(Err(e)) => {
+                    klog_1(&"sismember", &req.key(), Status::ServerError, 0);
+                    return Err(ProxyError::from(e));
+                }
+                Err(e) => {
+                    klog_1(&"sismember", &req.key(), Status::Timeout, 0);
+                    return Err(ProxyError::from(e));
+                }
+            };
 
         let (set, status) = match response {
             SetFetchResponse::Hit { values } => (values.into(), Status::Hit),
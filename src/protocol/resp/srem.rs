@@ -2,17 +2,17 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
-use momento::CacheClient;
 use protocol_resp::{SetRem, SREM, SREM_EX};
 use std::io::Write;
-use std::time::Duration;
 
+use crate::backend::SetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 
 use super::update_method_metrics;
 
 pub async fn srem(
-    client: &mut CacheClient,
+    client: &mut impl SetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SetRem,
@@ -21,7 +21,7 @@ pub async fn srem(
         let elements = req.members().iter().map(|e| &**e).collect();
 
         tokio::time::timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.set_remove_elements(cache_name, req.key(), elements),
         )
         .await??;
@@ -0,0 +1,267 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! STRLEN, GETRANGE, and SETRANGE. Momento can only read and write whole
+//! values, so the value is fetched and measured or sliced in the proxy, and
+//! SETRANGE writes back the whole value only if it is still the one which was
+//! read, with what remains of its TTL. With `resp_memcache_flags`, the memcache flags ahead of the value are
+//! skipped and kept, as they are by GET and SET.
+
+use metriken::{metric, Counter};
+use momento::CacheClient;
+use std::time::Duration;
+
+use super::{read_for_update, read_value, update_method_metrics, Command, FLAGS_LEN};
+use crate::error::{ProxyError, ProxyResult};
+use crate::klog::{klog_1, Status};
+use crate::metrics::{with_rpc_call_guard, RespMetrics};
+use crate::shards::Router;
+
+// how many times a conflicting write is retried before giving up
+const MAX_ATTEMPTS: usize = 8;
+
+#[metric(name = "strlen")]
+pub static STRLEN: Counter = Counter::new();
+
+#[metric(name = "strlen_ex")]
+pub static STRLEN_EX: Counter = Counter::new();
+
+#[metric(name = "getrange")]
+pub static GETRANGE: Counter = Counter::new();
+
+#[metric(name = "getrange_ex")]
+pub static GETRANGE_EX: Counter = Counter::new();
+
+#[metric(name = "setrange")]
+pub static SETRANGE: Counter = Counter::new();
+
+#[metric(name = "setrange_ex")]
+pub static SETRANGE_EX: Counter = Counter::new();
+
+#[metric(name = "setrange_too_large")]
+pub static SETRANGE_TOO_LARGE: Counter = Counter::new();
+
+#[metric(name = "setrange_write_conflict")]
+pub static SETRANGE_WRITE_CONFLICT: Counter = Counter::new();
+
+/// Serves STRLEN, GETRANGE, and SETRANGE, refusing to write values larger
+/// than `max_bytes`. Returns the name of the command served, or `None` if the
+/// command is not one of them.
+pub(crate) async fn string_range(
+    client: &mut CacheClient,
+    router: &Router<'_>,
+    command: &Command,
+    memcache_flags: bool,
+    max_bytes: usize,
+    proxy_metrics: &impl RespMetrics,
+    response_buf: &mut Vec<u8>,
+) -> Option<&'static str> {
+    let name = match command.name().as_str() {
+        "STRLEN" => "strlen",
+        "GETRANGE" => "getrange",
+        "SETRANGE" => "setrange",
+        _ => return None,
+    };
+
+    let header = if memcache_flags { FLAGS_LEN } else { 0 };
+
+    let result = match (name, command.args()) {
+        ("strlen", [key]) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_strlen(),
+                update_method_metrics(
+                    &STRLEN,
+                    &STRLEN_EX,
+                    strlen(client, router.cache_for(key), response_buf, key, header),
+                ),
+            )
+            .await
+        }
+        ("getrange", [key, start, end]) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_getrange(),
+                update_method_metrics(
+                    &GETRANGE,
+                    &GETRANGE_EX,
+                    getrange(
+                        client,
+                        router.cache_for(key),
+                        response_buf,
+                        key,
+                        start,
+                        end,
+                        header,
+                    ),
+                ),
+            )
+            .await
+        }
+        ("setrange", [key, offset, value]) => {
+            with_rpc_call_guard(
+                proxy_metrics.begin_resp_setrange(),
+                update_method_metrics(
+                    &SETRANGE,
+                    &SETRANGE_EX,
+                    setrange(
+                        client,
+                        router.cache_for(key),
+                        response_buf,
+                        key,
+                        offset,
+                        value,
+                        header,
+                        max_bytes,
+                        router.ttl_for(key),
+                    ),
+                ),
+            )
+            .await
+        }
+        _ => {
+            response_buf.extend_from_slice(
+                format!("-ERR wrong number of arguments for '{name}' command\r\n").as_bytes(),
+            );
+            return Some(name);
+        }
+    };
+
+    if let Err(e) = result {
+        let status = match e {
+            ProxyError::Timeout(_) => Status::Timeout,
+            ProxyError::Custom(_) => Status::NotStored,
+            _ => Status::ServerError,
+        };
+        klog_1(&name, &command.args()[0], status, 0);
+
+        response_buf.clear();
+        super::proxy_error_to_resp_error(response_buf, name, e);
+    }
+
+    Some(name)
+}
+
+async fn strlen(
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    key: &[u8],
+    header: usize,
+) -> ProxyResult {
+    let value = read_value(client, cache_name, key).await?;
+
+    // a value too short to hold the flags reads as a miss, as it does for GET
+    let len = value
+        .as_ref()
+        .and_then(|value| value.len().checked_sub(header))
+        .unwrap_or(0);
+
+    response_buf.extend_from_slice(format!(":{len}\r\n").as_bytes());
+    let status = if value.is_some() {
+        Status::Hit
+    } else {
+        Status::Miss
+    };
+    klog_1(&"strlen", &key, status, response_buf.len());
+
+    Ok(())
+}
+
+async fn getrange(
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    key: &[u8],
+    start: &[u8],
+    end: &[u8],
+    header: usize,
+) -> ProxyResult {
+    let start = super::parse_integer(start)?;
+    let end = super::parse_integer(end)?;
+
+    let value = read_value(client, cache_name, key).await?;
+    let payload = value
+        .as_ref()
+        .and_then(|value| value.get(header..))
+        .unwrap_or_default();
+
+    let range = match super::normalize_range(start, end, payload.len()) {
+        Some(range) => &payload[range],
+        None => &[],
+    };
+
+    response_buf.extend_from_slice(format!("${}\r\n", range.len()).as_bytes());
+    response_buf.extend_from_slice(range);
+    response_buf.extend_from_slice(b"\r\n");
+
+    let status = if value.is_some() {
+        Status::Hit
+    } else {
+        Status::Miss
+    };
+    klog_1(&"getrange", &key, status, range.len());
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn setrange(
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    key: &[u8],
+    offset: &[u8],
+    value: &[u8],
+    header: usize,
+    max_bytes: usize,
+    ttl: Option<Duration>,
+) -> ProxyResult {
+    let offset = std::str::from_utf8(offset)
+        .ok()
+        .and_then(|offset| offset.parse::<usize>().ok())
+        .ok_or_else(|| ProxyError::custom("ERR offset is out of range"))?;
+
+    let len = offset
+        .checked_add(value.len())
+        .filter(|len| *len <= max_bytes)
+        .ok_or_else(|| {
+            SETRANGE_TOO_LARGE.increment();
+            ProxyError::custom("ERR string exceeds maximum allowed size")
+        })?;
+
+    for _ in 0..MAX_ATTEMPTS {
+        let (previous, write_ttl) = read_for_update(client, cache_name, key, ttl).await?;
+
+        // a value too short to hold the flags is replaced
+        let mut updated = match previous.as_ref().filter(|p| p.len() >= header) {
+            Some(previous) => previous.clone(),
+            None => vec![0; header],
+        };
+
+        // as with redis, an empty write leaves the value as it is
+        if value.is_empty() {
+            let len = updated.len() - header;
+            response_buf.extend_from_slice(format!(":{len}\r\n").as_bytes());
+            klog_1(&"setrange", &key, Status::NotStored, 0);
+            return Ok(());
+        }
+
+        if updated.len() < header + len {
+            updated.resize(header + len, 0);
+        }
+        updated[header + offset..header + len].copy_from_slice(value);
+        let updated_len = updated.len() - header;
+
+        if super::write_if_unchanged(client, cache_name, key, updated, previous, write_ttl).await? {
+            response_buf.extend_from_slice(format!(":{updated_len}\r\n").as_bytes());
+            klog_1(&"setrange", &key, Status::Stored, value.len());
+            return Ok(());
+        }
+
+        SETRANGE_WRITE_CONFLICT.increment();
+    }
+
+    Err(ProxyError::custom(
+        "ERR too many concurrent writes to the key, try again",
+    ))
+}
@@ -4,32 +4,34 @@
 
 use std::collections::HashSet;
 use std::io::Write;
-use std::time::Duration;
 
 use momento::cache::SetFetchResponse;
-use momento::CacheClient;
 use protocol_resp::{SetUnion, SUNION, SUNION_EX};
 use tokio::time;
 
+use crate::backend::SetBackend;
+use crate::deadline::backend_timeout;
+use crate::shards::Router;
 use crate::ProxyResult;
 
 use super::update_method_metrics;
 
+/// Unions the sets, each fetched from its own shard.
 pub async fn sunion(
-    client: &mut CacheClient,
-    cache_name: &str,
+    client: &mut impl SetBackend,
+    router: &Router<'_>,
     response_buf: &mut Vec<u8>,
     req: &SetUnion,
 ) -> ProxyResult {
     update_method_metrics(&SUNION, &SUNION_EX, async move {
-        let timeout = Duration::from_millis(200);
+        let timeout = backend_timeout();
         let mut set: HashSet<Vec<u8>> = HashSet::new();
 
         for key in req.keys() {
             let key = &**key;
 
             let response: SetFetchResponse =
-                time::timeout(timeout, client.set_fetch(cache_name, key)).await??;
+                time::timeout(timeout, client.set_fetch(router.cache_for(key), key)).await??;
 
             match response {
                 SetFetchResponse::Hit { values } => {
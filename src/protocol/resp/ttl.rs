@@ -0,0 +1,102 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! TTL and PTTL, so that tooling such as cache warming jobs can read how long
+//! the items written through the proxy have left. Every item in Momento has a
+//! TTL, so neither ever replies `-1`.
+
+use metriken::{metric, Counter};
+use momento::CacheClient;
+
+use super::{update_method_metrics, Command};
+use crate::error::{ProxyError, ProxyResult};
+use crate::klog::{klog_1, Status};
+use crate::metrics::{with_rpc_call_guard, RespMetrics};
+use crate::protocol::item::{remaining_ttl, ttl_seconds};
+use crate::shards::Router;
+
+#[metric(name = "ttl")]
+pub static TTL: Counter = Counter::new();
+
+#[metric(name = "ttl_ex")]
+pub static TTL_EX: Counter = Counter::new();
+
+#[metric(name = "pttl")]
+pub static PTTL: Counter = Counter::new();
+
+#[metric(name = "pttl_ex")]
+pub static PTTL_EX: Counter = Counter::new();
+
+/// Serves TTL and PTTL. Returns the name of the command served, or `None` if
+/// the command is not one of them.
+pub(crate) async fn ttl(
+    client: &mut CacheClient,
+    router: &Router<'_>,
+    command: &Command,
+    proxy_metrics: &impl RespMetrics,
+    response_buf: &mut Vec<u8>,
+) -> Option<&'static str> {
+    let name = match command.name().as_str() {
+        "TTL" => "ttl",
+        "PTTL" => "pttl",
+        _ => return None,
+    };
+
+    let [key] = command.args() else {
+        response_buf.extend_from_slice(
+            format!("-ERR wrong number of arguments for '{name}' command\r\n").as_bytes(),
+        );
+        return Some(name);
+    };
+
+    let (guard, count, count_ex) = match name {
+        "pttl" => (proxy_metrics.begin_resp_pttl(), &PTTL, &PTTL_EX),
+        _ => (proxy_metrics.begin_resp_ttl(), &TTL, &TTL_EX),
+    };
+    let result = with_rpc_call_guard(
+        guard,
+        update_method_metrics(
+            count,
+            count_ex,
+            read_ttl(client, router.cache_for(key), response_buf, name, key),
+        ),
+    )
+    .await;
+
+    if let Err(e) = result {
+        let status = match e {
+            ProxyError::Timeout(_) => Status::Timeout,
+            _ => Status::ServerError,
+        };
+        klog_1(&name, &key, status, 0);
+
+        response_buf.clear();
+        super::proxy_error_to_resp_error(response_buf, name, e);
+    }
+
+    Some(name)
+}
+
+async fn read_ttl(
+    client: &CacheClient,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    name: &'static str,
+    key: &[u8],
+) -> ProxyResult {
+    let Some(ttl) = remaining_ttl(client, cache_name, key).await? else {
+        response_buf.extend_from_slice(b":-2\r\n");
+        klog_1(&name, &key, Status::Miss, 0);
+        return Ok(());
+    };
+
+    let ttl = match name {
+        "pttl" => ttl.as_millis() as u64,
+        _ => ttl_seconds(ttl),
+    };
+    response_buf.extend_from_slice(format!(":{ttl}\r\n").as_bytes());
+    klog_1(&name, &key, Status::Hit, response_buf.len());
+
+    Ok(())
+}
@@ -2,10 +2,18 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
-use momento::MomentoError;
+use momento::cache::{
+    GetResponse, SetIfAbsentRequest, SetIfAbsentResponse, SetIfEqualRequest, SetIfEqualResponse,
+};
+use momento::{CacheClient, MomentoError};
+use protocol_resp::Request;
 use std::future::Future;
+use std::ops::RangeInclusive;
+use std::time::Duration;
 
-use crate::error::ProxyError;
+use crate::deadline::backend_timeout;
+use crate::error::{ProxyError, ProxyResult};
+use crate::metrics::batch;
 
 pub(crate) fn momento_error_to_resp_error(buf: &mut Vec<u8>, command: &str, error: MomentoError) {
     use crate::BACKEND_EX;
@@ -16,12 +24,83 @@ pub(crate) fn momento_error_to_resp_error(buf: &mut Vec<u8>, command: &str, erro
     buf.extend_from_slice(format!("-ERR backend error: {error}\r\n").as_bytes());
 }
 
+/// Writes the error response for a command served by the proxy itself, where
+/// a custom error is the whole response line.
+pub(crate) fn proxy_error_to_resp_error(buf: &mut Vec<u8>, command: &str, error: ProxyError) {
+    match error {
+        ProxyError::Momento(error) => momento_error_to_resp_error(buf, command, error),
+        ProxyError::Timeout(_) => buf.extend_from_slice(b"-ERR backend timeout\r\n"),
+        ProxyError::Custom(message) => buf.extend_from_slice(format!("-{message}\r\n").as_bytes()),
+        e => buf.extend_from_slice(format!("-ERR {e}\r\n").as_bytes()),
+    }
+}
+
+/// The keys which must be on the same shard to serve the request. `DEL`,
+/// `SUNION`, `SINTER`, `SDIFF`, and `ZUNIONSTORE` read or write each key on its
+/// own shard, so their keys are not returned.
+pub(crate) fn request_keys(request: &Request) -> Vec<&[u8]> {
+    match request {
+        Request::Get(r) => vec![r.key()],
+        Request::HashDelete(r) => vec![r.key()],
+        Request::HashExists(r) => vec![r.key()],
+        Request::HashGet(r) => vec![r.key()],
+        Request::HashGetAll(r) => vec![r.key()],
+        Request::HashIncrBy(r) => vec![r.key()],
+        Request::HashKeys(r) => vec![r.key()],
+        Request::HashLength(r) => vec![r.key()],
+        Request::HashMultiGet(r) => vec![r.key()],
+        Request::HashSet(r) => vec![r.key()],
+        Request::HashValues(r) => vec![r.key()],
+        Request::ListIndex(r) => vec![r.key()],
+        Request::ListLen(r) => vec![r.key()],
+        Request::ListPop(r) => vec![r.key()],
+        Request::ListRange(r) => vec![r.key()],
+        Request::ListPush(r) => vec![r.key()],
+        Request::ListPushBack(r) => vec![r.key()],
+        Request::ListPopBack(r) => vec![r.key()],
+        Request::Set(r) => vec![r.key()],
+        Request::SetAdd(r) => vec![r.key()],
+        Request::SetRem(r) => vec![r.key()],
+        Request::SetMembers(r) => vec![r.key()],
+        Request::SetIsMember(r) => vec![r.key()],
+        Request::SortedSetCardinality(r) => vec![r.key()],
+        Request::SortedSetIncrement(r) => vec![r.key()],
+        Request::SortedSetScore(r) => vec![r.key()],
+        Request::SortedSetMultiScore(r) => vec![r.key()],
+        Request::SortedSetRemove(r) => vec![r.key()],
+        Request::SortedSetRank(r) => vec![r.key()],
+        Request::SortedSetRange(r) => vec![r.key()],
+        Request::SortedSetAdd(r) => vec![r.key()],
+        Request::SortedSetReverseRank(r) => vec![r.key()],
+        Request::SortedSetCount(r) => vec![r.key()],
+        _ => Vec::new(),
+    }
+}
+
+/// Whether the request is refused while the backend is under pressure. Like
+/// the commands redis flags `denyoom`, these are the writes which can grow the
+/// data set, while reads and removals are still served.
+pub(crate) fn is_denyoom(request: &Request) -> bool {
+    matches!(
+        request,
+        Request::HashIncrBy(_)
+            | Request::HashSet(_)
+            | Request::ListPush(_)
+            | Request::ListPushBack(_)
+            | Request::Set(_)
+            | Request::SetAdd(_)
+            | Request::SortedSetIncrement(_)
+            | Request::SortedSetAdd(_)
+            | Request::SortedSetUnionStore(_)
+    )
+}
+
 pub(crate) async fn update_method_metrics<T, E>(
-    count: &metriken::Counter,
+    count: &'static metriken::Counter,
     count_ex: &metriken::Counter,
     future: impl Future<Output = Result<T, E>>,
 ) -> Result<T, E> {
-    count.increment();
+    batch::increment(count);
     future.await.inspect_err(|_| {
         count_ex.increment();
     })
@@ -83,3 +162,172 @@ pub(crate) fn parse_score_boundary_as_float(value: &[u8]) -> Result<(f64, bool),
         Ok((score, false))
     }
 }
+
+/// The length of the memcache flags stored ahead of each value with
+/// `resp_memcache_flags`.
+pub(crate) const FLAGS_LEN: usize = 4;
+
+/// Reads the whole value of a key, for commands which work on the value in the
+/// proxy.
+pub(crate) async fn read_value(
+    client: &CacheClient,
+    cache_name: &str,
+    key: &[u8],
+) -> ProxyResult<Option<Vec<u8>>> {
+    match tokio::time::timeout(backend_timeout(), client.get(cache_name, key)).await?? {
+        GetResponse::Hit { value } => Ok(Some(value.into())),
+        GetResponse::Miss => Ok(None),
+    }
+}
+
+/// Reads the whole value of a key along with the TTL to write it back with,
+/// for commands which modify a value in place. As in redis, a key which exists
+/// keeps what remains of its TTL, and a new key is written with `ttl`, the TTL
+/// of its key rule, or the default if `None`.
+pub(crate) async fn read_for_update(
+    client: &CacheClient,
+    cache_name: &str,
+    key: &[u8],
+    ttl: Option<Duration>,
+) -> ProxyResult<(Option<Vec<u8>>, Option<Duration>)> {
+    let (value, remaining) = tokio::join!(
+        read_value(client, cache_name, key),
+        crate::protocol::item::remaining_ttl(client, cache_name, key)
+    );
+
+    let value = value?;
+    let ttl = match value {
+        // the key may have expired between the two reads
+        Some(_) => remaining?.or(ttl),
+        None => ttl,
+    };
+
+    Ok((value, ttl))
+}
+
+/// Writes `value` with `ttl` only if the key still holds `previous`, or is
+/// still missing if `previous` is `None`, for commands which read, modify, and
+/// write back a value. Returns whether the value was written.
+pub(crate) async fn write_if_unchanged(
+    client: &CacheClient,
+    cache_name: &str,
+    key: &[u8],
+    value: Vec<u8>,
+    previous: Option<Vec<u8>>,
+    ttl: Option<Duration>,
+) -> ProxyResult<bool> {
+    let timeout = backend_timeout();
+
+    let stored = match previous {
+        Some(previous) => matches!(
+            tokio::time::timeout(
+                timeout,
+                client.send_request(
+                    SetIfEqualRequest::new(cache_name, key, value, previous).ttl(ttl)
+                )
+            )
+            .await??,
+            SetIfEqualResponse::Stored
+        ),
+        None => matches!(
+            tokio::time::timeout(
+                timeout,
+                client.send_request(SetIfAbsentRequest::new(cache_name, key, value).ttl(ttl))
+            )
+            .await??,
+            SetIfAbsentResponse::Stored
+        ),
+    };
+
+    Ok(stored)
+}
+
+/// Parses an integer argument of a command served by the proxy.
+pub(crate) fn parse_integer(value: &[u8]) -> ProxyResult<i64> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .ok_or_else(|| ProxyError::custom("ERR value is not an integer or out of range"))
+}
+
+/// The position of an index into `len` items, where negative indexes count
+/// back from the end as they do in redis. Returns `None` if the index is out of
+/// range.
+pub(crate) fn normalize_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 {
+        len.checked_sub(usize::try_from(index.unsigned_abs()).ok()?)?
+    } else {
+        usize::try_from(index).ok()?
+    };
+
+    (index < len).then_some(index)
+}
+
+/// The positions of an inclusive range of `len` items, where negative indexes
+/// count back from the end and the range is clamped to the items, as they are
+/// in redis. Returns `None` if the range is empty.
+pub(crate) fn normalize_range(start: i64, end: i64, len: usize) -> Option<RangeInclusive<usize>> {
+    let len = i64::try_from(len).unwrap_or(i64::MAX);
+    let resolve = |index: i64| {
+        if index < 0 {
+            len.saturating_add(index)
+        } else {
+            index
+        }
+    };
+    let start = resolve(start).max(0);
+    let end = resolve(end).min(len - 1);
+
+    (start <= end).then(|| start as usize..=end as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_counts_back_from_the_end() {
+        assert_eq!(normalize_index(0, 5), Some(0));
+        assert_eq!(normalize_index(4, 5), Some(4));
+        assert_eq!(normalize_index(-1, 5), Some(4));
+        assert_eq!(normalize_index(-5, 5), Some(0));
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        assert_eq!(normalize_index(5, 5), None);
+        assert_eq!(normalize_index(-6, 5), None);
+        assert_eq!(normalize_index(i64::MAX, 5), None);
+        assert_eq!(normalize_index(i64::MIN, 5), None);
+        assert_eq!(normalize_index(0, 0), None);
+        assert_eq!(normalize_index(-1, 0), None);
+    }
+
+    #[test]
+    fn range_counts_back_from_the_end() {
+        assert_eq!(normalize_range(0, -1, 5), Some(0..=4));
+        assert_eq!(normalize_range(-3, -2, 5), Some(2..=3));
+        assert_eq!(normalize_range(1, 1, 5), Some(1..=1));
+    }
+
+    #[test]
+    fn range_is_clamped() {
+        assert_eq!(normalize_range(-10, 10, 5), Some(0..=4));
+        assert_eq!(normalize_range(3, i64::MAX, 5), Some(3..=4));
+        assert_eq!(normalize_range(i64::MIN, 0, 5), Some(0..=0));
+    }
+
+    #[test]
+    fn range_is_empty() {
+        // start after end
+        assert_eq!(normalize_range(3, 2, 5), None);
+        assert_eq!(normalize_range(-1, -2, 5), None);
+        // start past the last item
+        assert_eq!(normalize_range(5, 10, 5), None);
+        // end before the first item
+        assert_eq!(normalize_range(0, -6, 5), None);
+        // no items
+        assert_eq!(normalize_range(0, -1, 0), None);
+        assert_eq!(normalize_range(0, 0, 0), None);
+    }
+}
@@ -3,35 +3,50 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::io::Write;
-use std::time::Duration;
 
-use momento::cache::SortedSetElement;
-use momento::CacheClient;
+use metriken::{metric, Counter};
+use momento::cache::{SortedSetElement, SortedSetGetScoreResponse, SortedSetGetScoresResponse};
 use protocol_resp::{SortedSetAdd, SortedSetIncrement, ZADD, ZADD_EX};
 use tokio::time;
 
+use crate::backend::SortedSetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
 
 use super::{update_method_metrics, zincrby};
 
+#[metric(name = "zadd_emulated")]
+pub static ZADD_EMULATED: Counter = Counter::new();
+
+/// Adds the members to the sorted set. Momento has no conditional writes for
+/// sorted sets, so with `emulate_conditional` the CH, XX, NX, GT, and LT
+/// arguments are applied by reading the current scores first. This is not
+/// atomic, a concurrent write between the read and the write may be lost.
 pub async fn zadd(
-    client: &mut CacheClient,
+    client: &mut impl SortedSetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SortedSetAdd,
+    emulate_conditional: bool,
 ) -> ProxyResult {
     update_method_metrics(&ZADD, &ZADD_EX, async move {
         let number_of_elements_added = req.members().len();
 
-        // Momento does not yet support some of these optional arguments, return an error if any are set
-        if req.optional_args().ch
+        let conditional = req.optional_args().ch
             || req.optional_args().xx
             || req.optional_args().nx
             || req.optional_args().gt
-            || req.optional_args().lt
-        {
+            || req.optional_args().lt;
+
+        if conditional && emulate_conditional {
+            ZADD_EMULATED.increment();
+            return zadd_conditional(client, cache_name, response_buf, req).await;
+        }
+
+        // Momento does not yet support some of these optional arguments, return an error if any are set
+        if conditional {
             klog_1(&"zadd", &req.key(), Status::ServerError, 0);
             return Err(ProxyError::from(std::io::Error::new(
                 std::io::ErrorKind::Other,
@@ -52,18 +67,12 @@ pub async fn zadd(
         for element in req.members() {
             converted_members.push(SortedSetElement {
                 value: element.1.to_vec(),
-                score: if element.0 == f64::INFINITY {
-                    f64::MAX
-                } else if element.0 == f64::NEG_INFINITY {
-                    f64::MIN
-                } else {
-                    element.0
-                },
+                score: momento_score(element.0),
             })
         }
 
         match time::timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.sorted_set_put_elements(cache_name, req.key(), converted_members),
         )
         .await
@@ -87,3 +96,142 @@ pub async fn zadd(
     })
     .await
 }
+
+// applies the conditional arguments against the current scores, then writes
+// the members which pass them
+async fn zadd_conditional(
+    client: &mut impl SortedSetBackend,
+    cache_name: &str,
+    response_buf: &mut Vec<u8>,
+    req: &SortedSetAdd,
+) -> ProxyResult {
+    let args = req.optional_args();
+
+    let members: Vec<&[u8]> = req.members().iter().map(|m| &*m.1).collect();
+    let current: Vec<Option<f64>> = match time::timeout(
+        backend_timeout(),
+        client.sorted_set_get_scores(cache_name, req.key(), members),
+    )
+    .await
+    {
+        Ok(Ok(SortedSetGetScoresResponse::Hit { responses, .. })) => responses
+            .into_iter()
+            .map(|response| match response {
+                SortedSetGetScoreResponse::Hit { score } => Some(score),
+                SortedSetGetScoreResponse::Miss => None,
+            })
+            .collect(),
+        Ok(Ok(SortedSetGetScoresResponse::Miss)) => vec![None; req.members().len()],
+        Ok(Err(e)) => {
+            klog_1(&"zadd", &req.key(), Status::ServerError, 0);
+            return Err(ProxyError::from(e));
+        }
+        Err(e) => {
+            klog_1(&"zadd", &req.key(), Status::Timeout, 0);
+            return Err(ProxyError::from(e));
+        }
+    };
+
+    // whether a member with the `current` score should be set to `score`
+    let passes = |current: Option<f64>, score: f64| match current {
+        None => !args.xx,
+        Some(current) => !args.nx && (!args.gt || score > current) && (!args.lt || score < current),
+    };
+
+    // with INCR, ZADD behaves like ZINCRBY for a single member, replying with
+    // the new score or nil if the conditions were not met
+    if args.incr {
+        let (increment, member) = &req.members()[0];
+        let current = current.first().copied().flatten();
+        let score = momento_score(current.unwrap_or(0.0) + increment);
+
+        if !passes(current, score) {
+            response_buf.extend_from_slice(b"_\r\n");
+            klog_1(&"zadd", &req.key(), Status::Miss, response_buf.len());
+            return Ok(());
+        }
+
+        put(client, cache_name, req, vec![element(member, score)]).await?;
+
+        let score_str = score.to_string();
+        write!(response_buf, "${}\r\n{}\r\n", score_str.len(), score_str)?;
+        klog_1(&"zadd", &req.key(), Status::Hit, response_buf.len());
+        return Ok(());
+    }
+
+    let mut elements = Vec::new();
+    let mut added = 0;
+    let mut changed = 0;
+    for ((score, member), current) in req.members().iter().zip(current) {
+        let score = momento_score(*score);
+        if !passes(current, score) {
+            continue;
+        }
+
+        match current {
+            None => {
+                added += 1;
+                changed += 1;
+            }
+            Some(current) if current != score => changed += 1,
+            Some(_) => {}
+        }
+        elements.push(element(member, score));
+    }
+
+    if !elements.is_empty() {
+        put(client, cache_name, req, elements).await?;
+    }
+
+    write!(
+        response_buf,
+        ":{}\r\n",
+        if args.ch { changed } else { added }
+    )?;
+    klog_1(&"zadd", &req.key(), Status::Hit, response_buf.len());
+
+    Ok(())
+}
+
+async fn put(
+    client: &mut impl SortedSetBackend,
+    cache_name: &str,
+    req: &SortedSetAdd,
+    elements: Vec<SortedSetElement<Vec<u8>>>,
+) -> ProxyResult {
+    match time::timeout(
+        backend_timeout(),
+        client.sorted_set_put_elements(cache_name, req.key(), elements),
+    )
+    .await
+    {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => {
+            klog_1(&"zadd", &req.key(), Status::ServerError, 0);
+            Err(ProxyError::from(e))
+        }
+        Err(e) => {
+            klog_1(&"zadd", &req.key(), Status::Timeout, 0);
+            Err(ProxyError::from(e))
+        }
+    }
+}
+
+fn element(member: &[u8], score: f64) -> SortedSetElement<Vec<u8>> {
+    SortedSetElement {
+        value: member.to_vec(),
+        score,
+    }
+}
+
+// Momento calls cannot accept f64::INFINITY or f64::NEG_INFINITY, so use
+// f64::MAX and f64::MIN instead
+pub(super) fn momento_score(score: f64) -> f64 {
+    if score == f64::INFINITY {
+        f64::MAX
+    } else if score == f64::NEG_INFINITY {
+        f64::MIN
+    } else {
+        score
+    }
+}
@@ -3,13 +3,13 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::io::Write;
-use std::time::Duration;
 
 use momento::cache::SortedSetLengthResponse;
-use momento::CacheClient;
 use protocol_resp::{SortedSetCardinality, ZCARD, ZCARD_EX, ZCARD_HIT, ZCARD_MISS};
 use tokio::time;
 
+use crate::backend::SortedSetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
@@ -17,14 +17,14 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn zcard(
-    client: &mut CacheClient,
+    client: &mut impl SortedSetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SortedSetCardinality,
 ) -> ProxyResult {
     update_method_metrics(&ZCARD, &ZCARD_EX, async move {
         let response = match time::timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.sorted_set_length(cache_name, req.key()),
         )
         .await
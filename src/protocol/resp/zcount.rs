@@ -3,13 +3,13 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::io::Write;
-use std::time::Duration;
 
-use momento::cache::{ScoreBound, SortedSetLengthByScoreRequest, SortedSetLengthByScoreResponse};
-use momento::CacheClient;
+use momento::cache::{ScoreBound, SortedSetLengthByScoreResponse};
 use protocol_resp::{SortedSetCount, ZCOUNT, ZCOUNT_EX, ZCOUNT_HIT, ZCOUNT_MISS};
 use tokio::time;
 
+use crate::backend::SortedSetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
@@ -17,7 +17,7 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn zcount(
-    client: &mut CacheClient,
+    client: &mut impl SortedSetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SortedSetCount,
@@ -37,22 +37,22 @@ pub async fn zcount(
             (score, false) => Some(ScoreBound::Inclusive(score)),
         };
 
-        let request = SortedSetLengthByScoreRequest::new(cache_name, req.key())
-            .min_score(min_score)
-            .max_score(max_score);
-
-        let response =
-            match time::timeout(Duration::from_millis(200), client.send_request(request)).await {
-                Ok(Ok(r)) => r,
-                Ok(Err(e)) => {
-                    klog_1(&"zcount", &req.key(), Status::ServerError, 0);
-                    return Err(ProxyError::from(e));
-                }
-                Err(e) => {
-                    klog_1(&"zcount", &req.key(), Status::Timeout, 0);
-                    return Err(ProxyError::from(e));
-                }
-            };
+        let response = match time::timeout(
+            backend_timeout(),
+            client.sorted_set_length_by_score(cache_name, req.key(), min_score, max_score),
+        )
+        .await
+        {
+            Ok(Ok(r)) => r,
+            Ok(Err(e)) => {
+                klog_1(&"zcount", &req.key(), Status::ServerError, 0);
+                return Err(ProxyError::from(e));
+            }
+            Err(e) => {
+                klog_1(&"zcount", &req.key(), Status::Timeout, 0);
+                return Err(ProxyError::from(e));
+            }
+        };
 
         match response {
             SortedSetLengthByScoreResponse::Hit { length } => {
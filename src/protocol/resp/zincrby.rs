@@ -3,12 +3,12 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::io::Write;
-use std::time::Duration;
 
-use momento::CacheClient;
 use protocol_resp::{SortedSetIncrement, ZINCRBY, ZINCRBY_EX};
 use tokio::time;
 
+use crate::backend::SortedSetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
@@ -16,7 +16,7 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn zincrby(
-    client: &mut CacheClient,
+    client: &mut impl SortedSetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SortedSetIncrement,
@@ -33,7 +33,7 @@ pub async fn zincrby(
         };
 
         let response = match time::timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.sorted_set_increment_score(cache_name, req.key(), req.member(), increment),
         )
         .await
@@ -2,13 +2,12 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
-use std::time::Duration;
-
 use momento::cache::{SortedSetGetScoreResponse, SortedSetGetScoresResponse};
-use momento::CacheClient;
 use protocol_resp::{SortedSetMultiScore, ZMSCORE, ZMSCORE_EX, ZMSCORE_HIT, ZMSCORE_MISS};
 use tokio::time;
 
+use crate::backend::SortedSetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
@@ -16,7 +15,7 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn zmscore(
-    client: &mut CacheClient,
+    client: &mut impl SortedSetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SortedSetMultiScore,
@@ -25,7 +24,7 @@ pub async fn zmscore(
         let members: Vec<_> = req.members().iter().map(|x| &**x).collect();
         let num_members = members.len();
         let response: SortedSetGetScoresResponse<_> = match time::timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.sorted_set_get_scores(cache_name, req.key(), members),
         )
         .await
@@ -2,16 +2,14 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
-use std::time::Duration;
-
-use momento::cache::{
-    ScoreBound, SortedSetFetchByScoreRequest, SortedSetFetchResponse, SortedSetOrder,
-};
-use momento::CacheClient;
+use metriken::{metric, Counter};
+use momento::cache::{ScoreBound, SortedSetFetchResponse, SortedSetOrder};
 use protocol_resp::RangeType;
 use protocol_resp::{SortedSetRange, ZRANGE, ZRANGE_EX, ZRANGE_HIT, ZRANGE_MISS};
 use tokio::time;
 
+use crate::backend::SortedSetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
@@ -20,20 +18,26 @@ use super::{
     parse_score_boundary_as_float, parse_score_boundary_as_integer, update_method_metrics,
 };
 
+#[metric(name = "zrange_bylex_too_large")]
+pub static ZRANGE_BYLEX_TOO_LARGE: Counter = Counter::new();
+
+/// Fetches a range of the sorted set. Momento has no lexicographic ranges, so
+/// for BYLEX the whole set, of at most `bylex_max_set_size` members, is fetched
+/// and filtered in the proxy. As with Redis, BYLEX assumes that all members
+/// have the same score.
 pub async fn zrange(
-    client: &mut CacheClient,
+    client: &mut impl SortedSetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SortedSetRange,
+    bylex_max_set_size: usize,
 ) -> ProxyResult {
     update_method_metrics(&ZRANGE, &ZRANGE_EX, async move {
-        if *req.range_type() == RangeType::ByLex {
-            klog_1(&"zrange", &req.key(), Status::ServerError, 0);
-            return Err(ProxyError::from(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Momento proxy does not support BYLEX for ZRANGE",
-            )));
-        }
+        let lex_range = if *req.range_type() == RangeType::ByLex {
+            Some(LexRange::new(req)?)
+        } else {
+            None
+        };
 
         let response = match *req.range_type() {
             RangeType::ByIndex => {
@@ -47,7 +51,7 @@ pub async fn zrange(
                 };
 
                 match time::timeout(
-                    Duration::from_millis(200),
+                    backend_timeout(),
                     client.sorted_set_fetch_by_rank(
                         cache_name,
                         req.key(),
@@ -93,16 +97,43 @@ pub async fn zrange(
                     (score, false) => Some(ScoreBound::Inclusive(score)),
                 };
 
-                let fetch_request = SortedSetFetchByScoreRequest::new(cache_name, req.key())
-                    .order(order)
-                    .min_score(min_score)
-                    .max_score(max_score)
-                    .offset(req.optional_args().offset.map(|o| o as u32))
-                    .count(req.optional_args().count.map(|c| c as i32));
-
                 match time::timeout(
-                    Duration::from_millis(200),
-                    client.send_request(fetch_request),
+                    backend_timeout(),
+                    client.sorted_set_fetch_by_score(
+                        cache_name,
+                        req.key(),
+                        order,
+                        min_score,
+                        max_score,
+                        req.optional_args().offset.map(|o| o as u32),
+                        req.optional_args().count.map(|c| c as i32),
+                    ),
+                )
+                .await
+                {
+                    Ok(Ok(r)) => r,
+                    Ok(Err(e)) => {
+                        klog_1(&"zrange", &req.key(), Status::ServerError, 0);
+                        return Err(ProxyError::from(e));
+                    }
+                    Err(e) => {
+                        klog_1(&"zrange", &req.key(), Status::Timeout, 0);
+                        return Err(ProxyError::from(e));
+                    }
+                }
+            }
+            RangeType::ByLex => {
+                // one more member than the limit is fetched, to tell whether
+                // the set is over it
+                match time::timeout(
+                    backend_timeout(),
+                    client.sorted_set_fetch_by_rank(
+                        cache_name,
+                        req.key(),
+                        SortedSetOrder::Ascending,
+                        Some(0),
+                        Some(bylex_max_set_size.saturating_add(1).min(i32::MAX as usize) as i32),
+                    ),
                 )
                 .await
                 {
@@ -126,6 +157,23 @@ pub async fn zrange(
             }
         };
 
+        let response = match (response, lex_range) {
+            (SortedSetFetchResponse::Hit { mut value }, Some(range)) => {
+                if value.elements.len() > bylex_max_set_size {
+                    ZRANGE_BYLEX_TOO_LARGE.increment();
+                    klog_1(&"zrange", &req.key(), Status::ServerError, 0);
+                    return Err(ProxyError::from(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        "sorted set is too large for BYLEX",
+                    )));
+                }
+
+                value.elements = range.filter(value.elements);
+                SortedSetFetchResponse::Hit { value }
+            }
+            (response, _) => response,
+        };
+
         let include_scores = matches!(req.optional_args().with_scores, Some(true));
         match response {
             SortedSetFetchResponse::Hit { value } => {
@@ -168,3 +216,92 @@ pub async fn zrange(
     })
     .await
 }
+
+// one end of a lexicographic range
+enum LexBound {
+    NegInf,
+    PosInf,
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+}
+
+impl LexBound {
+    fn parse(value: &[u8]) -> Result<Self, ProxyError> {
+        match value {
+            b"-" => Ok(Self::NegInf),
+            b"+" => Ok(Self::PosInf),
+            [b'[', rest @ ..] => Ok(Self::Inclusive(rest.to_vec())),
+            [b'(', rest @ ..] => Ok(Self::Exclusive(rest.to_vec())),
+            _ => Err(ProxyError::from(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "min or max not valid string range item",
+            ))),
+        }
+    }
+
+    fn is_below(&self, member: &[u8]) -> bool {
+        match self {
+            Self::NegInf => true,
+            Self::PosInf => false,
+            Self::Inclusive(bound) => bound.as_slice() <= member,
+            Self::Exclusive(bound) => bound.as_slice() < member,
+        }
+    }
+
+    fn is_above(&self, member: &[u8]) -> bool {
+        match self {
+            Self::NegInf => false,
+            Self::PosInf => true,
+            Self::Inclusive(bound) => bound.as_slice() >= member,
+            Self::Exclusive(bound) => bound.as_slice() > member,
+        }
+    }
+}
+
+// a BYLEX range, with its order and limit
+struct LexRange {
+    min: LexBound,
+    max: LexBound,
+    reversed: bool,
+    offset: usize,
+    count: Option<usize>,
+}
+
+impl LexRange {
+    fn new(req: &SortedSetRange) -> Result<Self, ProxyError> {
+        let reversed = matches!(req.optional_args().reversed, Some(true));
+
+        // with REV the range is given from max to min
+        let (min, max) = if reversed {
+            (req.stop(), req.start())
+        } else {
+            (req.start(), req.stop())
+        };
+
+        Ok(Self {
+            min: LexBound::parse(min)?,
+            max: LexBound::parse(max)?,
+            reversed,
+            offset: req.optional_args().offset.unwrap_or(0).max(0) as usize,
+            count: req
+                .optional_args()
+                .count
+                .filter(|count| *count >= 0)
+                .map(|count| count as usize),
+        })
+    }
+
+    fn filter(&self, mut elements: Vec<(Vec<u8>, f64)>) -> Vec<(Vec<u8>, f64)> {
+        elements.sort_by(|a, b| a.0.cmp(&b.0));
+        if self.reversed {
+            elements.reverse();
+        }
+
+        elements
+            .into_iter()
+            .filter(|(member, _)| self.min.is_below(member) && self.max.is_above(member))
+            .skip(self.offset)
+            .take(self.count.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
@@ -3,13 +3,13 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::io::Write;
-use std::time::Duration;
 
-use momento::cache::SortedSetGetRankResponse;
-use momento::CacheClient;
+use momento::cache::{SortedSetGetRankResponse, SortedSetOrder};
 use protocol_resp::{SortedSetRank, ZRANK, ZRANK_EX, ZRANK_HIT, ZRANK_MISS};
 use tokio::time;
 
+use crate::backend::SortedSetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
@@ -17,16 +17,21 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn zrank(
-    client: &mut CacheClient,
+    client: &mut impl SortedSetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SortedSetRank,
 ) -> ProxyResult {
     update_method_metrics(&ZRANK, &ZRANK_EX, async move {
-        // sorted_set_get_rank uses ascending order (scores sorted from lowest to highest) by default
+        // ascending order ranks scores from lowest to highest
         let response = match time::timeout(
-            Duration::from_millis(200),
-            client.sorted_set_get_rank(cache_name, req.key(), req.member()),
+            backend_timeout(),
+            client.sorted_set_get_rank(
+                cache_name,
+                req.key(),
+                req.member(),
+                SortedSetOrder::Ascending,
+            ),
         )
         .await
         {
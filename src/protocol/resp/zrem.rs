@@ -3,12 +3,12 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::io::Write;
-use std::time::Duration;
 
-use momento::CacheClient;
 use protocol_resp::{SortedSetRemove, ZREM, ZREM_EX};
 use tokio::time;
 
+use crate::backend::SortedSetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
@@ -16,7 +16,7 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn zrem(
-    client: &mut CacheClient,
+    client: &mut impl SortedSetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SortedSetRemove,
@@ -25,7 +25,7 @@ pub async fn zrem(
         let members: Vec<_> = req.members().iter().map(|x| &**x).collect();
         let number_of_elements_removed = members.len();
         match time::timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.sorted_set_remove_elements(cache_name, req.key(), members),
         )
         .await
@@ -3,13 +3,13 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::io::Write;
-use std::time::Duration;
 
-use momento::cache::{SortedSetGetRankRequest, SortedSetGetRankResponse, SortedSetOrder};
-use momento::CacheClient;
+use momento::cache::{SortedSetGetRankResponse, SortedSetOrder};
 use protocol_resp::{SortedSetReverseRank, ZREVRANK, ZREVRANK_EX, ZREVRANK_HIT, ZREVRANK_MISS};
 use tokio::time;
 
+use crate::backend::SortedSetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
@@ -17,19 +17,22 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn zrevrank(
-    client: &mut CacheClient,
+    client: &mut impl SortedSetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SortedSetReverseRank,
 ) -> ProxyResult {
     update_method_metrics(&ZREVRANK, &ZREVRANK_EX, async move {
-        // sorted_set_get_rank uses ascending order (scores sorted from lowest to highest) by default,
-        // must specify descending order to get reverse rank
-        let get_rank_request = SortedSetGetRankRequest::new(cache_name, req.key(), req.member())
-            .order(SortedSetOrder::Descending);
+        // descending order ranks scores from highest to lowest, which is the
+        // reverse rank
         let response = match time::timeout(
-            Duration::from_millis(200),
-            client.send_request(get_rank_request),
+            backend_timeout(),
+            client.sorted_set_get_rank(
+                cache_name,
+                req.key(),
+                req.member(),
+                SortedSetOrder::Descending,
+            ),
         )
         .await
         {
@@ -3,13 +3,13 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::io::Write;
-use std::time::Duration;
 
 use momento::cache::SortedSetGetScoreResponse;
-use momento::CacheClient;
 use protocol_resp::{SortedSetScore, ZSCORE, ZSCORE_EX, ZSCORE_HIT, ZSCORE_MISS};
 use tokio::time;
 
+use crate::backend::SortedSetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
 use crate::ProxyError;
@@ -17,14 +17,14 @@ use crate::ProxyError;
 use super::update_method_metrics;
 
 pub async fn zscore(
-    client: &mut CacheClient,
+    client: &mut impl SortedSetBackend,
     cache_name: &str,
     response_buf: &mut Vec<u8>,
     req: &SortedSetScore,
 ) -> ProxyResult {
     update_method_metrics(&ZSCORE, &ZSCORE_EX, async move {
         let response = match time::timeout(
-            Duration::from_millis(200),
+            backend_timeout(),
             client.sorted_set_get_score(cache_name, req.key(), req.member()),
         )
         .await
@@ -2,24 +2,32 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
+use std::collections::HashMap;
 use std::io::Write;
-use std::time::Duration;
 
-use momento::cache::{SortedSetAggregateFunction, SortedSetUnionStoreRequest};
-use momento::CacheClient;
+use momento::cache::{
+    SortedSetAggregateFunction, SortedSetElement, SortedSetFetchResponse, SortedSetOrder,
+};
 use protocol_resp::AggregateFunction;
 use protocol_resp::{SortedSetUnionStore, ZUNIONSTORE, ZUNIONSTORE_EX};
 use tokio::time;
 
+use crate::backend::SortedSetBackend;
+use crate::deadline::backend_timeout;
 use crate::error::ProxyResult;
 use crate::klog::{klog_1, Status};
+use crate::shards::Router;
 use crate::ProxyError;
 
 use super::update_method_metrics;
 
+/// Stores the union of the source sets in the destination. When the keys are
+/// on different shards, the sources are fetched from their own shards and
+/// combined in the proxy, and the destination is replaced with the result.
+/// Unlike a union in one cache, that isn't atomic.
 pub async fn zunionstore(
-    client: &mut CacheClient,
-    cache_name: &str,
+    client: &mut impl SortedSetBackend,
+    router: &Router<'_>,
     response_buf: &mut Vec<u8>,
     req: &SortedSetUnionStore,
 ) -> ProxyResult {
@@ -56,26 +64,46 @@ pub async fn zunionstore(
                 .collect()
         };
 
-        let request = SortedSetUnionStoreRequest::new(cache_name, req.destination_key(), sources)
-            .aggregate(aggregate_function);
-
-        let response =
-            match time::timeout(Duration::from_millis(200), client.send_request(request)).await {
-                Ok(Ok(r)) => r,
-                Ok(Err(e)) => {
-                    klog_1(
-                        &"zunionstore",
-                        &req.destination_key(),
-                        Status::ServerError,
-                        0,
-                    );
-                    return Err(ProxyError::from(e));
-                }
-                Err(e) => {
-                    klog_1(&"zunionstore", &req.destination_key(), Status::Timeout, 0);
-                    return Err(ProxyError::from(e));
-                }
-            };
+        let keys = std::iter::once(req.destination_key())
+            .chain(req.source_keys().iter().map(|key| &**key));
+        let Some(cache_name) = router.cache_for_all(keys) else {
+            let length = union_across_shards(client, router, req, sources).await?;
+            write!(response_buf, ":{length}\r\n")?;
+            klog_1(
+                &"zunionstore",
+                &req.destination_key(),
+                Status::Stored,
+                response_buf.len(),
+            );
+            return Ok(());
+        };
+
+        let response = match time::timeout(
+            backend_timeout(),
+            client.sorted_set_union_store(
+                cache_name,
+                req.destination_key(),
+                sources,
+                aggregate_function,
+            ),
+        )
+        .await
+        {
+            Ok(Ok(r)) => r,
+            Ok(Err(e)) => {
+                klog_1(
+                    &"zunionstore",
+                    &req.destination_key(),
+                    Status::ServerError,
+                    0,
+                );
+                return Err(ProxyError::from(e));
+            }
+            Err(e) => {
+                klog_1(&"zunionstore", &req.destination_key(), Status::Timeout, 0);
+                return Err(ProxyError::from(e));
+            }
+        };
 
         // Return the number of elements in the destination sorted set
         write!(response_buf, ":{}\r\n", response.length)?;
@@ -90,3 +118,76 @@ pub async fn zunionstore(
     })
     .await
 }
+
+// unions the sources, each fetched from its own shard, into the destination on
+// its shard, returning the number of members stored
+async fn union_across_shards(
+    client: &mut impl SortedSetBackend,
+    router: &Router<'_>,
+    req: &SortedSetUnionStore,
+    sources: Vec<(Vec<u8>, f32)>,
+) -> Result<usize, ProxyError> {
+    let destination = req.destination_key();
+    let error = |e: ProxyError, status| {
+        klog_1(&"zunionstore", &destination, status, 0);
+        e
+    };
+
+    let mut union: HashMap<Vec<u8>, f64> = HashMap::new();
+    for (source, weight) in sources {
+        let response = time::timeout(
+            backend_timeout(),
+            client.sorted_set_fetch_by_rank(
+                router.cache_for(&source),
+                source.as_slice(),
+                SortedSetOrder::Ascending,
+                None,
+                None,
+            ),
+        )
+        .await
+        .map_err(|e| error(e.into(), Status::Timeout))?
+        .map_err(|e| error(e.into(), Status::ServerError))?;
+
+        let SortedSetFetchResponse::Hit { value } = response else {
+            continue;
+        };
+        for (member, score) in value.elements {
+            let score = score * weight as f64;
+            union
+                .entry(member)
+                .and_modify(|current| {
+                    *current = match req.aggregate_function() {
+                        Some(AggregateFunction::Min) => current.min(score),
+                        Some(AggregateFunction::Max) => current.max(score),
+                        _ => *current + score,
+                    }
+                })
+                .or_insert(score);
+        }
+    }
+
+    // the destination is replaced, as with a union in one cache
+    let cache_name = router.cache_for(destination);
+    time::timeout(backend_timeout(), client.delete(cache_name, destination))
+        .await
+        .map_err(|e| error(e.into(), Status::Timeout))?
+        .map_err(|e| error(e.into(), Status::ServerError))?;
+
+    let length = union.len();
+    if length > 0 {
+        let elements: Vec<SortedSetElement<Vec<u8>>> = union
+            .into_iter()
+            .map(|(value, score)| SortedSetElement { value, score })
+            .collect();
+        time::timeout(
+            backend_timeout(),
+            client.sorted_set_put_elements(cache_name, destination, elements),
+        )
+        .await
+        .map_err(|e| error(e.into(), Status::Timeout))?
+        .map_err(|e| error(e.into(), Status::ServerError))?;
+    }
+
+    Ok(length)
+}
@@ -0,0 +1,368 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Quotas for the keys under a prefix, so that teams which share a cache by
+//! prefixing their keys each get a fair share of it. Each key of a request is
+//! counted against the first quota whose prefix it starts with, for both the
+//! rate in requests a second and the bytes of keys and values sent in a UTC
+//! day. Requests over the rate are rejected or, with `action = "throttle"`,
+//! held back until the rate allows them. Requests over the bytes of the day
+//! are always rejected.
+//!
+//! The usage of each prefix is counted whether or not it has limits, so a
+//! quota with neither serves as chargeback data.
+//!
+//! ```toml
+//! [[cache.quotas]]
+//! prefix = "team-a:"
+//! requests_per_second = 1000
+//! bytes_per_day = 10_000_000_000
+//! action = "throttle"
+//! ```
+
+use crate::enforce::{self, Policy};
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[metric(name = "quota_rejected")]
+pub static QUOTA_REJECTED: Counter = Counter::new();
+
+#[metric(name = "quota_throttled")]
+pub static QUOTA_THROTTLED: Counter = Counter::new();
+
+// the longest a request is held back by a throttling quota. A request which
+// would wait longer is rejected, so that a connection over its quota doesn't
+// queue requests without bound
+const MAX_THROTTLE: Duration = Duration::from_secs(1);
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// What happens to requests over a quota's rate.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+    /// reply with an error
+    Reject,
+    /// hold the request back until the rate allows it
+    Throttle,
+}
+
+impl Default for QuotaAction {
+    fn default() -> Self {
+        Self::Reject
+    }
+}
+
+/// A quota as configured.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaConfig {
+    /// keys starting with it are counted against the quota
+    prefix: String,
+    /// requests a second, 0 for no limit
+    #[serde(default)]
+    requests_per_second: u64,
+    /// bytes of keys and values sent in a UTC day, 0 for no limit
+    #[serde(default)]
+    bytes_per_day: u64,
+    /// what happens to requests over the rate
+    #[serde(default)]
+    action: QuotaAction,
+}
+
+impl QuotaConfig {
+    /// The prefix of the keys counted against the quota
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+}
+
+/// Whether a request is served.
+pub(crate) enum Admission {
+    /// the request is served now
+    Admit,
+    /// the request is served after waiting
+    Throttle(Duration),
+    /// the request is answered with this error, which has no prefix
+    Reject(String),
+}
+
+/// The usage counters of a prefix of a cache, which are totals across the
+/// listeners of the cache. The cache name and prefix are part of the metric
+/// names, as the admin `stats` command does not show metadata.
+struct Usage {
+    requests: DynBoxedMetric<Counter>,
+    bytes: DynBoxedMetric<Counter>,
+    rejected: DynBoxedMetric<Counter>,
+    throttled: DynBoxedMetric<Counter>,
+}
+
+impl Usage {
+    fn new(cache_name: &str, prefix: &str) -> Self {
+        let counter = |metric: &str, description: &str| {
+            MetricBuilder::new(format!("cache/{cache_name}/quota/{prefix}/{metric}"))
+                .description(format!(
+                    "{description} for keys of cache {cache_name} starting with {prefix}"
+                ))
+                .metadata("cache_name", cache_name)
+                .metadata("prefix", prefix)
+                .build(Counter::new())
+        };
+
+        Self {
+            requests: counter("requests", "requests served"),
+            bytes: counter("bytes", "bytes of keys and values sent"),
+            rejected: counter("rejected", "requests rejected by the quota"),
+            throttled: counter("throttled", "requests held back by the quota"),
+        }
+    }
+}
+
+// the usage counters of a prefix of a cache, which are registered on first use
+fn usage(cache_name: &str, prefix: &str) -> Arc<Usage> {
+    static USAGE: OnceLock<Mutex<HashMap<(String, String), Arc<Usage>>>> = OnceLock::new();
+
+    USAGE
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry((cache_name.to_string(), prefix.to_string()))
+        .or_insert_with(|| Arc::new(Usage::new(cache_name, prefix)))
+        .clone()
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() / SECONDS_PER_DAY)
+        .unwrap_or_default()
+}
+
+struct Quota {
+    config: QuotaConfig,
+    // the requests available, which go negative while requests are held
+    // back, and when they were last refilled
+    bucket: Mutex<(f64, Instant)>,
+    // the day, and the bytes sent in it
+    day: Mutex<(u64, u64)>,
+    usage: Arc<Usage>,
+}
+
+impl Quota {
+    fn new(cache_name: &str, config: QuotaConfig) -> Self {
+        Self {
+            bucket: Mutex::new((config.requests_per_second as f64, Instant::now())),
+            day: Mutex::new((today(), 0)),
+            usage: usage(cache_name, &config.prefix),
+            config,
+        }
+    }
+
+    fn reject(&self, limit: &str) -> Admission {
+        self.usage.rejected.increment();
+        QUOTA_REJECTED.increment();
+        Admission::Reject(format!(
+            "quota exceeded for keys starting with '{}', over {limit}",
+            self.config.prefix
+        ))
+    }
+
+    fn admit(&self, bytes: usize) -> Admission {
+        let bytes = bytes as u64;
+
+        // the bytes of the day are checked first, as a request rejected for
+        // them doesn't take a request from the rate, but are only added once
+        // the rate admits the request
+        let mut day = (self.config.bytes_per_day > 0)
+            .then(|| self.day.lock().unwrap_or_else(|e| e.into_inner()));
+        if let Some(day) = &mut day {
+            let today = today();
+            if day.0 != today {
+                *day = (today, 0);
+            }
+            if day.1 + bytes > self.config.bytes_per_day
+                && enforce::enforce(Policy::Quota, || {
+                    format!(
+                        "{} bytes for prefix '{}' over {} a day",
+                        day.1 + bytes,
+                        self.config.prefix,
+                        self.config.bytes_per_day
+                    )
+                })
+            {
+                return self.reject("its bytes for the day");
+            }
+        }
+
+        let mut admission = Admission::Admit;
+        if self.config.requests_per_second > 0 {
+            let rate = self.config.requests_per_second as f64;
+            let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            let (tokens, refilled) = &mut *bucket;
+
+            let now = Instant::now();
+            *tokens = (*tokens + now.duration_since(*refilled).as_secs_f64() * rate).min(rate);
+            *refilled = now;
+
+            if *tokens >= 1.0 {
+                *tokens -= 1.0;
+            } else if enforce::enforce(Policy::Quota, || {
+                format!(
+                    "request for prefix '{}' over {} a second",
+                    self.config.prefix, self.config.requests_per_second
+                )
+            }) {
+                let wait = Duration::from_secs_f64((1.0 - *tokens) / rate);
+                if self.config.action != QuotaAction::Throttle || wait > MAX_THROTTLE {
+                    return self.reject("its requests a second");
+                }
+                self.usage.throttled.increment();
+                QUOTA_THROTTLED.increment();
+                admission = Admission::Throttle(wait);
+                *tokens -= 1.0;
+            }
+        }
+
+        if let Some(day) = &mut day {
+            day.1 += bytes;
+        }
+        self.usage.requests.increment();
+        self.usage.bytes.add(bytes);
+        admission
+    }
+}
+
+/// The quotas of a listener, in the order they were configured.
+pub(crate) struct Quotas {
+    quotas: Vec<Quota>,
+}
+
+impl Quotas {
+    pub fn new(cache: &momento_proxy::Cache) -> Self {
+        let cache_name = cache.cache_name();
+        Self {
+            quotas: cache
+                .quotas()
+                .iter()
+                .map(|config| Quota::new(&cache_name, config.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quotas.is_empty()
+    }
+
+    /// Counts a request with `keys`, which sends `bytes` of keys and values,
+    /// once against each quota its keys fall under, with the bytes of the
+    /// keys under that quota. The rest of the bytes, such as those of values,
+    /// are counted against the quota of the first key. The request is
+    /// rejected if any of its quotas rejects it, and otherwise waits for the
+    /// longest of their throttles.
+    pub fn admit<'a>(&self, keys: impl IntoIterator<Item = &'a [u8]>, bytes: usize) -> Admission {
+        if self.quotas.is_empty() {
+            return Admission::Admit;
+        }
+
+        // the bytes of the request under each quota, if it has any keys
+        // under that quota
+        let mut charges: Vec<Option<usize>> = vec![None; self.quotas.len()];
+        let mut first = None;
+        let mut rest = bytes;
+        for (i, key) in keys.into_iter().enumerate() {
+            rest = rest.saturating_sub(key.len());
+            let Some(index) = self.position(key) else {
+                continue;
+            };
+            if i == 0 {
+                first = Some(index);
+            }
+            *charges[index].get_or_insert(0) += key.len();
+        }
+        if let Some(index) = first {
+            *charges[index].get_or_insert(0) += rest;
+        }
+
+        let mut wait = Duration::ZERO;
+        for (quota, bytes) in self.quotas.iter().zip(charges) {
+            let Some(bytes) = bytes else {
+                continue;
+            };
+            match quota.admit(bytes) {
+                Admission::Admit => {}
+                Admission::Throttle(delay) => wait = wait.max(delay),
+                Admission::Reject(error) => return Admission::Reject(error),
+            }
+        }
+
+        if wait.is_zero() {
+            Admission::Admit
+        } else {
+            Admission::Throttle(wait)
+        }
+    }
+
+    // the index of the first quota whose prefix `key` starts with
+    fn position(&self, key: &[u8]) -> Option<usize> {
+        self.quotas
+            .iter()
+            .position(|quota| key.starts_with(quota.config.prefix.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quotas(cache_name: &str, requests_per_second: u64) -> Quotas {
+        let quota = |prefix: &str| {
+            Quota::new(
+                cache_name,
+                QuotaConfig {
+                    prefix: prefix.to_string(),
+                    requests_per_second,
+                    bytes_per_day: 0,
+                    action: QuotaAction::Reject,
+                },
+            )
+        };
+        Quotas {
+            quotas: vec![quota("a:"), quota("b:")],
+        }
+    }
+
+    #[test]
+    fn each_key_counts_against_its_own_quota() {
+        let quotas = quotas("each-key", 1);
+
+        // the second key of the get takes the one request b has a second
+        assert!(matches!(
+            quotas.admit([&b"a:1"[..], b"b:1"], 6),
+            Admission::Admit
+        ));
+        assert!(matches!(
+            quotas.admit([&b"b:2"[..]], 3),
+            Admission::Reject(_)
+        ));
+        assert!(matches!(quotas.admit([&b"c:1"[..]], 3), Admission::Admit));
+    }
+
+    #[test]
+    fn values_count_against_the_first_key() {
+        let quotas = quotas("values", 0);
+
+        // a set of a:1 and b:1 to values of 5 bytes each
+        assert!(matches!(
+            quotas.admit([&b"a:1"[..], b"b:1"], 16),
+            Admission::Admit
+        ));
+
+        let [a, b] = [0, 1].map(|i| &quotas.quotas[i].usage);
+        assert_eq!((a.requests.value(), a.bytes.value()), (1, 13));
+        assert_eq!((b.requests.value(), b.bytes.value()), (1, 3));
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Routes keys to pools of Momento caches by their prefix, as configured in a
+//! routing policy file. A route may name a failover pool, which is tried when
+//! the primary pool fails, and a shadow pool, which receives a copy of each
+//! write in the background. Keys without a matching route use the listener's
+//! own caches.
+//!
+//! ```toml
+//! [pools]
+//! users = ["users-a", "users-b"]
+//! users-backup = ["users-backup"]
+//!
+//! [[routes]]
+//! prefix = "user:"
+//! pool = "users"
+//! failover = "users-backup"
+//! ```
+
+use crate::shards::Shards;
+use crate::*;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+
+#[metric(name = "route_failover")]
+pub static ROUTE_FAILOVER: Counter = Counter::new();
+
+#[metric(name = "route_shadow")]
+pub static ROUTE_SHADOW: Counter = Counter::new();
+
+#[metric(name = "route_shadow_ex")]
+pub static ROUTE_SHADOW_EX: Counter = Counter::new();
+
+/// The contents of a routing policy file.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingPolicy {
+    /// the caches of each pool, keys are spread across them
+    pools: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    routes: Vec<RouteConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RouteConfig {
+    prefix: String,
+    pool: String,
+    #[serde(default)]
+    failover: Option<String>,
+    #[serde(default)]
+    shadow: Option<String>,
+}
+
+impl RoutingPolicy {
+    /// Loads and validates the policy at `path`.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read routing policy `{path}`: {e}"))?;
+        let policy: Self = toml::from_str(&content)
+            .map_err(|e| format!("could not parse routing policy `{path}`: {e}"))?;
+
+        for (name, caches) in &policy.pools {
+            if caches.is_empty() {
+                return Err(format!("pool `{name}` has no caches"));
+            }
+        }
+
+        for route in &policy.routes {
+            let pools = [
+                Some(&route.pool),
+                route.failover.as_ref(),
+                route.shadow.as_ref(),
+            ];
+            for pool in pools.into_iter().flatten() {
+                if !policy.pools.contains_key(pool) {
+                    return Err(format!(
+                        "route for prefix `{}` uses unknown pool `{pool}`",
+                        route.prefix
+                    ));
+                }
+            }
+        }
+
+        Ok(policy)
+    }
+
+    /// The names of every cache in the pools.
+    pub fn cache_names(&self) -> impl Iterator<Item = &String> {
+        self.pools.values().flatten()
+    }
+}
+
+/// The pools for keys with a prefix.
+pub(crate) struct Route {
+    prefix: Vec<u8>,
+    pool: Shards,
+    failover: Option<Shards>,
+    shadow: Option<Shards>,
+}
+
+/// The routes of a listener.
+#[derive(Default)]
+pub(crate) struct Routes {
+    // longest prefix first, so the first match is the most specific
+    routes: Vec<Route>,
+}
+
+impl Routes {
+    pub fn new(policy: &RoutingPolicy) -> Self {
+        let pool = |name: &String| Shards::new(policy.pools[name].clone());
+
+        let mut routes: Vec<Route> = policy
+            .routes
+            .iter()
+            .map(|route| Route {
+                prefix: route.prefix.as_bytes().to_vec(),
+                pool: pool(&route.pool),
+                failover: route.failover.as_ref().map(pool),
+                shadow: route.shadow.as_ref().map(pool),
+            })
+            .collect();
+        routes.sort_by(|a, b| b.prefix.len().cmp(&a.prefix.len()));
+
+        Self { routes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// The route for `key`, if any prefix matches.
+    pub fn route(&self, key: &[u8]) -> Option<&Route> {
+        self.routes
+            .iter()
+            .find(|route| key.starts_with(&route.prefix))
+    }
+}
+
+impl Route {
+    pub fn pool(&self) -> &Shards {
+        &self.pool
+    }
+
+    pub fn failover(&self) -> Option<&Shards> {
+        self.failover.as_ref()
+    }
+
+    pub fn shadow(&self) -> Option<&Shards> {
+        self.shadow.as_ref()
+    }
+}
+
+/// Where a request for a single key is sent.
+#[derive(Clone, Copy)]
+pub(crate) struct Target<'a> {
+    pub cache_name: &'a str,
+    pub failover: Option<&'a str>,
+    pub shadow: Option<&'a str>,
+    /// the ttl of writes which don't set one, if a key rule overrides it
+    pub ttl: Option<Duration>,
+}
+
+/// Sends a copy of a write to a shadow pool in the background. The outcome
+/// only shows up in the metrics.
+pub(crate) fn shadow<F, T, E>(write: F)
+where
+    F: Future<Output = Result<T, E>> + Send + 'static,
+    E: std::fmt::Display,
+{
+    ROUTE_SHADOW.increment();
+
+    let backend_timeout = backend_timeout();
+    tokio::spawn(async move {
+        match timeout(backend_timeout, write).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                ROUTE_SHADOW_EX.increment();
+                debug!("shadow write failed: {e}");
+            }
+            Err(_) => {
+                ROUTE_SHADOW_EX.increment();
+            }
+        }
+    });
+}
@@ -0,0 +1,349 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Spreads the keys of one logical cache across several Momento caches. Keys
+//! are placed with consistent hashing, so adding or removing a shard only
+//! moves the keys which hash to that shard.
+
+use std::time::Duration;
+
+use goodmetrics::{default_gauge_factory, GaugeDimensions, SumHandle};
+use metriken::{metric, Counter};
+
+use crate::policy::KeyPolicy;
+use crate::routing::{Routes, Target};
+
+// points on the ring for each shard, more points even out the key
+// distribution between shards
+const POINTS_PER_SHARD: usize = 160;
+
+// requests rejected as their keys are on different shards
+#[metric(name = "shard_crossslot")]
+pub static SHARD_CROSSSLOT: Counter = Counter::new();
+
+struct Shard {
+    cache_name: String,
+    requests: SumHandle,
+}
+
+/// The Momento caches behind a listener.
+pub(crate) struct Shards {
+    shards: Vec<Shard>,
+    // sorted by hash, each point maps to an index into `shards`
+    ring: Vec<(u64, usize)>,
+    // keys matching a route use its pool instead
+    routes: Routes,
+    // checked before the routes, the first matching rule applies
+    policy: KeyPolicy,
+    // the cache of each rule, if it overrides the cache
+    rule_caches: Vec<Option<Shards>>,
+    // the caches of resp databases 1 and up, these are database 0
+    databases: Vec<Shards>,
+}
+
+impl Shards {
+    /// Builds the ring for the given cache names. A single cache is not
+    /// hashed, every key maps to it.
+    pub fn new(cache_names: Vec<String>) -> Self {
+        let g = default_gauge_factory();
+
+        let shards: Vec<Shard> = cache_names
+            .into_iter()
+            .map(|cache_name| Shard {
+                requests: g.dimensioned_gauge_sum(
+                    "momento_proxy",
+                    "shard_requests",
+                    GaugeDimensions::new([("shard", cache_name.clone())]),
+                ),
+                cache_name,
+            })
+            .collect();
+
+        let mut ring = Vec::new();
+        if shards.len() > 1 {
+            for (index, shard) in shards.iter().enumerate() {
+                for point in 0..POINTS_PER_SHARD {
+                    let hash = hash(format!("{}-{point}", shard.cache_name).as_bytes());
+                    ring.push((hash, index));
+                }
+            }
+            ring.sort_unstable();
+        }
+
+        Self {
+            shards,
+            ring,
+            routes: Routes::default(),
+            policy: KeyPolicy::default(),
+            rule_caches: Vec::new(),
+            databases: Vec::new(),
+        }
+    }
+
+    /// Routes keys with the prefixes in `routes` to their own pools.
+    pub fn with_routes(mut self, routes: Routes) -> Self {
+        self.routes = routes;
+        self
+    }
+
+    /// Applies the key rules, which take precedence over the routes.
+    pub fn with_policy(mut self, policy: KeyPolicy) -> Self {
+        self.rule_caches = policy
+            .rules()
+            .iter()
+            .map(|rule| {
+                rule.cache_name()
+                    .map(|name| Shards::new(vec![name.to_string()]))
+            })
+            .collect();
+        self.policy = policy;
+        self
+    }
+
+    /// Gives resp databases 1 and up a cache each, with the same key rules.
+    pub fn with_databases(mut self, cache_names: Vec<String>, policy: KeyPolicy) -> Self {
+        self.databases = cache_names
+            .into_iter()
+            .map(|name| Shards::new(vec![name]).with_policy(policy.clone()))
+            .collect();
+        self
+    }
+
+    /// The number of resp databases, including these as database 0.
+    pub fn database_count(&self) -> usize {
+        self.databases.len() + 1
+    }
+
+    /// The caches of a resp database, `None` if there is no such database.
+    pub fn database(&self, index: usize) -> Option<&Shards> {
+        match index {
+            0 => Some(self),
+            _ => self.databases.get(index - 1),
+        }
+    }
+
+    /// Whether keys are spread across more than one cache.
+    pub fn is_sharded(&self) -> bool {
+        self.shards.len() > 1
+            || !self.routes.is_empty()
+            || self.rule_caches.iter().any(Option::is_some)
+    }
+
+    /// The TTL for writes to `key` which don't set one, if a key rule
+    /// overrides it.
+    pub fn ttl_for(&self, key: &[u8]) -> Option<Duration> {
+        self.policy.rule(key).and_then(|rule| rule.ttl())
+    }
+
+    /// The names of all the caches.
+    pub fn cache_names(&self) -> impl Iterator<Item = &str> {
+        self.shards.iter().map(|shard| shard.cache_name.as_str())
+    }
+
+    /// Routes a request for `key` to its cache, returning the cache name.
+    pub fn cache_for(&self, key: &[u8]) -> &str {
+        let (shards, index) = self.locate(key);
+        shards.count(index)
+    }
+
+    /// Routes a request for `key`, along with the failover and shadow caches
+    /// of its route.
+    pub fn target_for(&self, key: &[u8]) -> Target<'_> {
+        let ttl = self.ttl_for(key);
+        if let Some(pool) = self.rule_cache(key) {
+            return Target {
+                cache_name: pool.cache_for(key),
+                failover: None,
+                shadow: None,
+                ttl,
+            };
+        }
+
+        match self.routes.route(key) {
+            Some(route) => Target {
+                cache_name: route.pool().cache_for(key),
+                failover: route.failover().map(|pool| pool.cache_for(key)),
+                shadow: route.shadow().map(|pool| pool.cache_for(key)),
+                ttl,
+            },
+            None => Target {
+                cache_name: self.cache_for(key),
+                failover: None,
+                shadow: None,
+                ttl,
+            },
+        }
+    }
+
+    /// Routes a request which touches all of `keys` to their cache. Returns
+    /// `None` if the keys are on different shards, which a caller that then
+    /// rejects the request counts with [`SHARD_CROSSSLOT`]. A request without
+    /// keys goes to the first cache and is not counted.
+    pub fn cache_for_all<'a>(&self, keys: impl IntoIterator<Item = &'a [u8]>) -> Option<&str> {
+        let mut keys = keys.into_iter();
+
+        let (shards, index) = match keys.next() {
+            Some(key) => self.locate(key),
+            None => return Some(&self.shards[0].cache_name),
+        };
+
+        if keys.any(|key| {
+            let (other, other_index) = self.locate(key);
+            !std::ptr::eq(shards, other) || other_index != index
+        }) {
+            return None;
+        }
+
+        Some(shards.count(index))
+    }
+
+    // the shards for `key`, either these or those of a key rule or route, and
+    // the index of its shard
+    fn locate(&self, key: &[u8]) -> (&Shards, usize) {
+        if let Some(pool) = self.rule_cache(key) {
+            return (pool, 0);
+        }
+
+        match self.routes.route(key) {
+            Some(route) => (route.pool(), route.pool().index(key)),
+            None => (self, self.index(key)),
+        }
+    }
+
+    // the cache of the first key rule matching `key`, if that rule overrides
+    // the cache
+    fn rule_cache(&self, key: &[u8]) -> Option<&Shards> {
+        let (index, _) = self.policy.position(key)?;
+        self.rule_caches[index].as_ref()
+    }
+
+    fn count(&self, index: usize) -> &str {
+        let shard = &self.shards[index];
+        shard.requests.observe(1);
+        &shard.cache_name
+    }
+
+    fn index(&self, key: &[u8]) -> usize {
+        if self.ring.is_empty() {
+            return 0;
+        }
+
+        // the first point at or after the key's hash, wrapping around
+        let hash = hash(key);
+        let point = self.ring.partition_point(|(h, _)| *h < hash);
+        self.ring[point % self.ring.len()].1
+    }
+}
+
+/// Routes the keys of a request by the keys the client sent, for requests
+/// whose keys were rewritten for the backend. A rewritten key is routed, and
+/// matched against the key rules, as the key it was rewritten from.
+pub(crate) struct Router<'a> {
+    shards: &'a Shards,
+    // each rewritten key, with the key the client sent
+    originals: &'a [(Vec<u8>, Vec<u8>)],
+}
+
+impl<'a> Router<'a> {
+    pub fn new(shards: &'a Shards, originals: &'a [(Vec<u8>, Vec<u8>)]) -> Self {
+        Self { shards, originals }
+    }
+
+    /// See [`Shards::ttl_for`].
+    pub fn ttl_for(&self, key: &[u8]) -> Option<Duration> {
+        self.shards.ttl_for(self.original(key))
+    }
+
+    /// See [`Shards::cache_for`].
+    pub fn cache_for(&self, key: &[u8]) -> &'a str {
+        self.shards.cache_for(self.original(key))
+    }
+
+    /// See [`Shards::cache_for_all`].
+    pub fn cache_for_all<'k>(&self, keys: impl IntoIterator<Item = &'k [u8]>) -> Option<&'a str> {
+        self.shards
+            .cache_for_all(keys.into_iter().map(|key| self.original(key)))
+    }
+
+    // the key the client sent for `key`
+    fn original<'k>(&self, key: &'k [u8]) -> &'k [u8]
+    where
+        'a: 'k,
+    {
+        self.originals
+            .iter()
+            .find(|(transformed, _)| transformed == key)
+            .map_or(key, |(_, original)| original.as_slice())
+    }
+}
+
+// FNV-1a with a final mix. The mapping must not change between releases, so
+// the std hasher can't be used.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shards(names: &[&str]) -> Shards {
+        Shards::new(names.iter().map(|name| name.to_string()).collect())
+    }
+
+    #[test]
+    fn mapping_is_stable() {
+        // keys must stay on the caches they were written to across releases
+        let shards = shards(&["cache-a", "cache-b", "cache-c"]);
+        for (key, cache_name) in [
+            ("foo", "cache-b"),
+            ("bar", "cache-a"),
+            ("baz", "cache-a"),
+            ("user:1", "cache-a"),
+            ("user:2", "cache-c"),
+            ("session:42", "cache-b"),
+        ] {
+            assert_eq!(shards.cache_for(key.as_bytes()), cache_name, "{key}");
+        }
+    }
+
+    #[test]
+    fn removing_a_shard_only_moves_its_keys() {
+        let before = shards(&["cache-a", "cache-b", "cache-c", "cache-d"]);
+        let after = shards(&["cache-a", "cache-b", "cache-d"]);
+
+        let mut moved = 0;
+        for i in 0..10_000 {
+            let key = format!("key-{i}");
+            let from = before.cache_for(key.as_bytes());
+            let to = after.cache_for(key.as_bytes());
+            if from == "cache-c" {
+                moved += 1;
+            } else {
+                assert_eq!(from, to, "{key}");
+            }
+        }
+
+        // the removed shard held about a quarter of the keys
+        assert!((2_000..3_000).contains(&moved), "{moved}");
+    }
+
+    #[test]
+    fn keys_on_different_shards_are_not_routed_together() {
+        let shards = shards(&["cache-a", "cache-b", "cache-c"]);
+        assert_eq!(shards.cache_for_all([&b"bar"[..], b"baz"]), Some("cache-a"));
+        assert_eq!(shards.cache_for_all([&b"foo"[..], b"bar"]), None);
+        assert_eq!(shards.cache_for_all([]), Some("cache-a"));
+    }
+}
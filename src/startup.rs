@@ -0,0 +1,258 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Startup diagnostics. Every cache block is validated and its listener bound
+//! before any of them starts serving, so that all of the problems with a
+//! config are reported together instead of one per restart. With
+//! `partial_start`, the healthy caches are served while the broken ones are
+//! reported as degraded.
+
+use crate::*;
+use serde::Serialize;
+use std::net::SocketAddr;
+
+#[metric(name = "caches_degraded")]
+pub static CACHES_DEGRADED: Gauge = Gauge::new();
+
+/// The outcome of the startup checks.
+#[derive(Serialize)]
+pub(crate) struct StartupReport {
+    admin: Check,
+    credentials: Check,
+    caches: Vec<CacheCheck>,
+}
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn new(error: Option<&String>) -> Self {
+        Self {
+            ok: error.is_none(),
+            error: error.cloned(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CacheCheck {
+    name: String,
+    listen_address: String,
+    degraded: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<String>,
+}
+
+/// The report and the bound listeners of each healthy cache, in the order of
+/// the caches in the config.
+pub(crate) struct Startup {
+    pub report: StartupReport,
+    pub listeners: Vec<Option<Vec<std::net::TcpListener>>>,
+}
+
+/// Checks every cache in the config, binding the listeners of those which are
+/// valid. Problems with the admin listener and the credentials are found by
+/// the caller and included in the report.
+pub(crate) fn diagnose(
+    config: &MomentoProxyConfig,
+    admin_error: Option<&String>,
+    credentials_error: Option<&String>,
+) -> Startup {
+    let mut caches = Vec::new();
+    let mut listeners = Vec::new();
+
+    for cache in config.caches() {
+        let (listener, errors) = match check_cache(cache, config.pinned_workers()) {
+            Ok(listener) => (Some(listener), Vec::new()),
+            Err(errors) => (None, errors),
+        };
+
+        caches.push(CacheCheck {
+            name: cache.cache_name(),
+            listen_address: cache.listen_address(),
+            degraded: listener.is_none(),
+            errors,
+        });
+        listeners.push(listener);
+    }
+
+    Startup {
+        report: StartupReport {
+            admin: Check::new(admin_error),
+            credentials: Check::new(credentials_error),
+            caches,
+        },
+        listeners,
+    }
+}
+
+// all of the problems with a cache, or its bound listeners if there are none.
+// With pinned workers, each listener shares its address with the workers' own
+fn check_cache(
+    cache: &momento_proxy::Cache,
+    reuse_port: bool,
+) -> Result<Vec<std::net::TcpListener>, Vec<String>> {
+    let mut errors = Vec::new();
+
+    let ttl: u64 = cache
+        .default_ttl()
+        .as_micros()
+        .try_into()
+        .unwrap_or(u64::MAX);
+    let limit = u64::MAX / 1000;
+    if ttl > limit {
+        errors.push(format!("default ttl of {ttl} is greater than {limit}"));
+    }
+
+    if let Err(e) = cache.key_transform() {
+        errors.push(format!("key transform is not valid: {e}"));
+    }
+
+    if let Err(e) = cache.routing_policy() {
+        errors.push(format!("routing policy is not valid: {e}"));
+    }
+
+    if let Err(e) = cache.validate_key_rules() {
+        errors.push(format!("key rules are not valid: {e}"));
+    }
+
+    let precision = cache.hyperloglog_precision();
+    if !protocol::resp::HLL_PRECISIONS.contains(&precision) {
+        errors.push(format!(
+            "hyperloglog precision of {precision} is not between {} and {}",
+            protocol::resp::HLL_PRECISIONS.start(),
+            protocol::resp::HLL_PRECISIONS.end()
+        ));
+    }
+
+    let port = cache.advertised_port();
+    if port.parse::<u16>().is_err() {
+        errors.push(format!("advertised port `{port}` is not a valid port"));
+    }
+
+    // a hostname listens on every address it resolves to. A listener which
+    // waits for its client to warm up is only bound, and listens later
+    let listen = !cache.listen_after_client_warmup();
+    let mut listeners = Vec::new();
+    match cache.socket_addrs() {
+        Ok(addrs) => {
+            for addr in addrs {
+                let listener = if reuse_port {
+                    pinned::bind_reuse_port(addr, cache.ipv6_only(), cache.listen_backlog(), listen)
+                } else {
+                    bind(addr, cache.ipv6_only(), cache.listen_backlog(), listen)
+                };
+                match listener {
+                    Ok(listener) => listeners.push(listener),
+                    Err(e) => errors.push(format!(
+                        "could not bind tcp listener on address `{addr}`: {e}"
+                    )),
+                }
+            }
+        }
+        Err(e) => errors.push(format!("listen address is not valid: {e}")),
+    }
+
+    if errors.is_empty() {
+        Ok(listeners)
+    } else {
+        Err(errors)
+    }
+}
+
+// binds a non-blocking listener, which only listens with `listen`. An ipv6
+// listener on `::` also accepts ipv4 connections unless it is ipv6 only, which
+// is left to the system by default
+fn bind(
+    addr: SocketAddr,
+    ipv6_only: Option<bool>,
+    backlog: i32,
+    listen: bool,
+) -> std::io::Result<std::net::TcpListener> {
+    use socket2::{Domain, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if let (true, Some(ipv6_only)) = (addr.is_ipv6(), ipv6_only) {
+        socket.set_only_v6(ipv6_only)?;
+    }
+    // as with std, so that a restart can bind while old connections linger
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+
+    socket.bind(&addr.into())?;
+    if listen {
+        socket.listen(backlog)?;
+    }
+    socket.set_nonblocking(true)?;
+
+    Ok(socket.into())
+}
+
+impl StartupReport {
+    /// Whether the proxy can start. Without `partial_start`, every cache must
+    /// be healthy, otherwise at least one must be.
+    pub fn can_start(&self, partial_start: bool) -> bool {
+        let healthy = self.caches.iter().filter(|cache| !cache.degraded).count();
+
+        self.admin.ok
+            && self.credentials.ok
+            && if partial_start {
+                healthy > 0
+            } else {
+                healthy == self.caches.len()
+            }
+    }
+
+    /// Logs each line of the report, and sets the degraded cache gauge.
+    pub fn log(&self) {
+        let degraded = self.caches.iter().filter(|cache| cache.degraded).count();
+        CACHES_DEGRADED.set(degraded as _);
+
+        for line in self.to_string().lines() {
+            if line.ends_with(": ok") {
+                info!("{line}");
+            } else {
+                error!("{line}");
+            }
+        }
+    }
+
+    /// Writes the report as JSON to `path`.
+    pub fn write_json(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| e.to_string())
+    }
+}
+
+impl std::fmt::Display for StartupReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = |error: &Option<String>| match error {
+            Some(error) => error.clone(),
+            None => "ok".to_string(),
+        };
+
+        writeln!(f, "admin listener: {}", status(&self.admin.error))?;
+        writeln!(f, "credentials: {}", status(&self.credentials.error))?;
+
+        for cache in &self.caches {
+            if cache.errors.is_empty() {
+                writeln!(f, "cache `{}` on {}: ok", cache.name, cache.listen_address)?;
+            } else {
+                writeln!(
+                    f,
+                    "cache `{}` on {}: degraded: {}",
+                    cache.name,
+                    cache.listen_address,
+                    cache.errors.join("; ")
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
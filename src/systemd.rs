@@ -0,0 +1,108 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Service state notifications for systemd, sent over the datagram socket
+//! named by `NOTIFY_SOCKET`. This follows the `sd_notify(3)` protocol so that
+//! a `Type=notify` unit only becomes active once the proxy is serving.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+// set by the `--systemd` flag, notifications are not sent otherwise
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub(crate) fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Sends `state` (eg: `READY=1`) to the service manager. Does nothing unless
+/// systemd mode is enabled and the service manager provided a socket.
+pub(crate) fn notify(state: &str) {
+    if !is_enabled() {
+        return;
+    }
+
+    let Ok(path) = std::env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    if let Err(e) = send(&path, state) {
+        warn!("could not notify systemd of `{state}`: {e}");
+    }
+}
+
+#[cfg(unix)]
+fn send(path: &str, state: &str) -> std::io::Result<()> {
+    let socket = std::os::unix::net::UnixDatagram::unbound()?;
+
+    // a leading `@` names a socket in the abstract namespace
+    if let Some(name) = path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+            return Ok(());
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = name;
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "abstract sockets are only supported on linux",
+            ));
+        }
+    }
+
+    socket.send_to(state.as_bytes(), path)?;
+    Ok(())
+}
+
+// the notification socket is a unix socket, so there is no service manager to
+// notify on other platforms
+#[cfg(not(unix))]
+fn send(_path: &str, _state: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "systemd notifications are only supported on unix",
+    ))
+}
+
+/// The interval for watchdog keep-alives, half of the timeout the service
+/// manager set in `WATCHDOG_USEC`. Returns `None` if the watchdog is off.
+pub(crate) fn watchdog_interval() -> Option<Duration> {
+    if !is_enabled() {
+        return None;
+    }
+
+    // the watchdog may be meant for a different process
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    if usec == 0 {
+        return None;
+    }
+
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Sends a watchdog keep-alive every `interval`. Runs on the admin runtime,
+/// so a wedged worker runtime does not stop the keep-alives, but a wedged
+/// process does.
+pub(crate) async fn watchdog(interval: Duration) {
+    loop {
+        notify("WATCHDOG=1");
+        tokio::time::sleep(interval).await;
+    }
+}
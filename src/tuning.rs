@@ -0,0 +1,133 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Runtime tuning of a memcache listener from its own data port. With
+//! `proxy_config_commands` enabled, trusted clients can read and change a small
+//! whitelist of settings with `proxy_config get <param>` and
+//! `proxy_config set <param> <value>`, for networks where the admin port can't
+//! be reached. Changes last until the proxy is restarted.
+
+use crate::momento_proxy::CommandClass;
+use crate::*;
+use core::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+#[metric(name = "proxy_config_set")]
+pub static PROXY_CONFIG_SET: Counter = Counter::new();
+
+/// The settings of a listener which can be changed at runtime.
+pub(crate) struct Tuning {
+    config: Arc<momento_proxy::Cache>,
+    // in milliseconds, zero for the configured timeout
+    backend_timeout_ms: AtomicU64,
+    memory_cache: Option<MCache>,
+    klog_sample: Arc<klog::ListenerSample>,
+}
+
+impl Tuning {
+    pub fn new(config: Arc<momento_proxy::Cache>, memory_cache: Option<MCache>) -> Self {
+        Self {
+            config,
+            backend_timeout_ms: AtomicU64::new(0),
+            memory_cache,
+            klog_sample: Default::default(),
+        }
+    }
+
+    /// The klog sampling of the listener, which its requests are logged with.
+    pub fn klog_sample(&self) -> Arc<klog::ListenerSample> {
+        self.klog_sample.clone()
+    }
+
+    /// The timeout for calls to momento made by a class of command. A timeout
+    /// set at runtime applies to every class.
+    pub fn backend_timeout(&self, class: CommandClass) -> Duration {
+        match self.backend_timeout_ms.load(Ordering::Relaxed) {
+            0 => self.config.command_timeout(class, None),
+            ms => Duration::from_millis(ms),
+        }
+    }
+
+    // the current value of a parameter, `None` if there is no such parameter
+    fn get(&self, param: &str) -> Option<String> {
+        match param {
+            "backend_timeout_ms" => Some(
+                match self.backend_timeout_ms.load(Ordering::Relaxed) {
+                    0 => self.config.backend_timeout(None),
+                    ms => Duration::from_millis(ms),
+                }
+                .as_millis()
+                .to_string(),
+            ),
+            "memory_cache_ttl_seconds" => Some(match &self.memory_cache {
+                Some(memory_cache) if memory_cache.ttl() >= cache::MAX_TTL => "0".to_string(),
+                Some(memory_cache) => memory_cache.ttl().as_secs().to_string(),
+                None => "disabled".to_string(),
+            }),
+            "klog_sample" => Some(self.klog_sample.get().to_string()),
+            _ => None,
+        }
+    }
+
+    // changes a parameter, returning the error response if it can't be
+    fn set(&self, param: &str, value: &str) -> Result<(), &'static str> {
+        let value: u64 = value.parse().map_err(|_| "CLIENT_ERROR bad value\r\n")?;
+
+        match param {
+            "backend_timeout_ms" => {
+                if value == 0 {
+                    return Err("CLIENT_ERROR bad value\r\n");
+                }
+                self.backend_timeout_ms.store(value, Ordering::Relaxed);
+            }
+            "memory_cache_ttl_seconds" => {
+                let Some(memory_cache) = &self.memory_cache else {
+                    return Err("SERVER_ERROR memory cache is disabled\r\n");
+                };
+                // as in the config, zero means items don't expire
+                memory_cache.set_ttl(if value == 0 {
+                    Duration::MAX
+                } else {
+                    Duration::from_secs(value)
+                });
+            }
+            "klog_sample" => self.klog_sample.set(value),
+            _ => return Err("CLIENT_ERROR unknown parameter\r\n"),
+        }
+
+        PROXY_CONFIG_SET.increment();
+        info!(
+            "{param} changed to {value} for cache `{}` by a proxy_config command",
+            self.config.cache_name()
+        );
+        Ok(())
+    }
+
+    /// Parses and serves a complete `proxy_config` text command. Returns the
+    /// number of bytes consumed and the response, or `None` if the buffer does
+    /// not start with such a command.
+    pub fn command(&self, buf: &[u8]) -> Option<(usize, Vec<u8>)> {
+        let end = buf.iter().position(|b| *b == b'\n')?;
+        let line = std::str::from_utf8(&buf[..end]).ok()?;
+        let mut tokens = line.trim_end_matches('\r').split_whitespace();
+
+        if tokens.next()? != "proxy_config" {
+            return None;
+        }
+
+        let response = match (tokens.next(), tokens.next(), tokens.next(), tokens.next()) {
+            (Some("get"), Some(param), None, _) => match self.get(param) {
+                Some(value) => format!("CONFIG {param} {value}\r\n"),
+                None => "CLIENT_ERROR unknown parameter\r\n".to_string(),
+            },
+            (Some("set"), Some(param), Some(value), None) => match self.set(param, value) {
+                Ok(()) => "OK\r\n".to_string(),
+                Err(response) => response.to_string(),
+            },
+            _ => "CLIENT_ERROR bad command line format\r\n".to_string(),
+        };
+
+        Some((end + 1, response.into_bytes()))
+    }
+}
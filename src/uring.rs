@@ -0,0 +1,386 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! io_uring workers. With `io_uring = true`, the listeners still accept
+//! connections as usual, but then hand each one to an io_uring worker, which
+//! reads and writes its socket through an io_uring instead of waiting for
+//! readiness with epoll. Each worker is a single threaded runtime, and serves a
+//! connection on its own thread for the whole life of the connection.
+//!
+//! The frontends read and write through `AsyncRead` and `AsyncWrite`, while
+//! io_uring completes reads and writes into buffers it owns, so each
+//! connection's socket holds the buffer of the read or write it has in flight,
+//! and hands the frontend what a read completed with. The syscalls are only
+//! made through the ring.
+//!
+//! io_uring is only available on linux, and only in builds with the `io-uring`
+//! Cargo feature. Elsewhere, connections are served as though `io_uring` was
+//! not set.
+
+use crate::*;
+use std::sync::Arc;
+
+/// Starts the io_uring workers if the config asks for them, with one worker
+/// per thread in `threads`, or one per CPU.
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub(crate) fn start(config: &MomentoProxyConfig) -> Result<Option<Arc<Workers>>, String> {
+    if !config.io_uring() {
+        return Ok(None);
+    }
+
+    Workers::start(pinned::workers(config)).map(|workers| Some(Arc::new(workers)))
+}
+
+/// Warns that the config asks for io_uring workers which this build doesn't
+/// include.
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+pub(crate) fn start(config: &MomentoProxyConfig) -> Result<Option<Arc<Workers>>, String> {
+    if config.io_uring() {
+        warn!(
+            "`io_uring` is set, but this build does not include io_uring support. Rebuild on linux with `--features io-uring` to enable it"
+        );
+    }
+
+    Ok(None)
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub(crate) use imp::Workers;
+
+/// Stands in for the io_uring workers in builds without them, and can't be
+/// created.
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+pub(crate) enum Workers {}
+
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+impl Workers {
+    pub(crate) fn dispatch<F, Fut>(&self, _socket: tokio::net::TcpStream, _serve: F)
+    where
+        F: FnOnce(tokio::net::TcpStream) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        match *self {}
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod imp {
+    use crate::frontend::ClientSocket;
+    use crate::*;
+    use pelikan_net::TCP_CLOSE;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::rc::Rc;
+    use std::task::{ready, Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio::sync::mpsc;
+
+    #[metric(name = "uring_dispatch_ex")]
+    pub static URING_DISPATCH_EX: Counter = Counter::new();
+
+    // the most a connection reads from its socket at once
+    const READ_BUFFER: usize = 64 * KB;
+
+    // the runtime doesn't flush the counts a worker holds for its batched
+    // requests when it parks, so they are flushed this often instead
+    const METRICS_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+    type Serve = Box<dyn FnOnce(Stream) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+    /// The io_uring workers, which the listeners hand their connections to.
+    pub(crate) struct Workers {
+        senders: Vec<mpsc::UnboundedSender<(std::net::TcpStream, Serve)>>,
+        next: AtomicUsize,
+    }
+
+    impl Workers {
+        /// Starts `workers` workers, each on its own thread. Fails if the
+        /// kernel doesn't allow an io_uring to be created.
+        pub(crate) fn start(workers: usize) -> Result<Self, String> {
+            let mut senders = Vec::with_capacity(workers);
+
+            for id in 0..workers {
+                let (sender, receiver) = mpsc::unbounded_channel();
+                let (started, result) = std::sync::mpsc::channel();
+
+                std::thread::Builder::new()
+                    .name(format!("pelikan_uring_{id}"))
+                    .spawn(move || {
+                        let runtime = match tokio_uring::Runtime::new(&tokio_uring::builder()) {
+                            Ok(runtime) => runtime,
+                            Err(e) => {
+                                let _ = started.send(Err(e));
+                                return;
+                            }
+                        };
+                        let _ = started.send(Ok(()));
+                        runtime.block_on(worker(receiver));
+                    })
+                    .map_err(|e| format!("could not start an io_uring worker: {e}"))?;
+
+                result
+                    .recv()
+                    .map_err(|_| "an io_uring worker stopped while starting".to_string())?
+                    .map_err(|e| format!("could not create an io_uring: {e}"))?;
+
+                senders.push(sender);
+            }
+
+            Ok(Self {
+                senders,
+                next: AtomicUsize::new(0),
+            })
+        }
+
+        /// Hands a connection to the next worker, which serves it with
+        /// `serve`. The connection is closed if it can't be handed over.
+        pub(crate) fn dispatch<F, Fut>(&self, socket: tokio::net::TcpStream, serve: F)
+        where
+            F: FnOnce(Stream) -> Fut + Send + 'static,
+            Fut: Future<Output = ()> + Send + 'static,
+        {
+            // a ring completes operations on a nonblocking socket with
+            // EAGAIN instead of waiting for them
+            let socket = match socket.into_std().and_then(|socket| {
+                socket.set_nonblocking(false)?;
+                Ok(socket)
+            }) {
+                Ok(socket) => socket,
+                Err(e) => {
+                    URING_DISPATCH_EX.increment();
+                    TCP_CLOSE.increment();
+                    debug!("could not hand a connection to an io_uring worker: {e}");
+                    return;
+                }
+            };
+
+            let serve: Serve = Box::new(move |stream| Box::pin(serve(stream)));
+            let worker = self.next.fetch_add(1, Ordering::Relaxed) % self.senders.len();
+            if self.senders[worker].send((socket, serve)).is_err() {
+                URING_DISPATCH_EX.increment();
+                TCP_CLOSE.increment();
+                debug!("could not hand a connection to io_uring worker {worker}, it has stopped");
+            }
+        }
+    }
+
+    // serves the connections handed to this worker, until the workers are
+    // dropped
+    async fn worker(mut connections: mpsc::UnboundedReceiver<(std::net::TcpStream, Serve)>) {
+        tokio::spawn(async {
+            let mut flush = tokio::time::interval(METRICS_FLUSH_INTERVAL);
+            loop {
+                flush.tick().await;
+                metrics::batch::flush();
+            }
+        });
+
+        while let Some((socket, serve)) = connections.recv().await {
+            let socket = tokio_uring::net::TcpStream::from_std(socket);
+            tokio::spawn(serve(Stream::new(socket)));
+        }
+    }
+
+    // a read or write submitted to the ring, which returns the buffer it owned
+    type Op<T> = Pin<Box<dyn Future<Output = (std::io::Result<T>, Vec<u8>)>>>;
+
+    /// A connection served by an io_uring worker.
+    pub(crate) struct Stream {
+        reader: Reader,
+        writer: Writer,
+    }
+
+    // SAFETY: the socket and the operations in flight on it are `!Send`, as the
+    // socket is shared between the halves through an `Rc` and has to be used
+    // on the thread of its ring. A stream is created by a worker and only
+    // used by the tasks serving its connection, which the worker's
+    // current-thread runtime never moves to another thread.
+    unsafe impl Send for Stream {}
+    unsafe impl Send for Reader {}
+    unsafe impl Send for Writer {}
+
+    impl Stream {
+        fn new(socket: tokio_uring::net::TcpStream) -> Self {
+            let socket = Rc::new(socket);
+
+            Self {
+                reader: Reader {
+                    socket: socket.clone(),
+                    buf: Vec::with_capacity(READ_BUFFER),
+                    pos: 0,
+                    read: None,
+                },
+                writer: Writer {
+                    socket,
+                    buf: Vec::new(),
+                    write: None,
+                },
+            }
+        }
+    }
+
+    /// The read half of a connection served by an io_uring worker.
+    pub(crate) struct Reader {
+        socket: Rc<tokio_uring::net::TcpStream>,
+        // what the last read completed with, of which `pos` bytes have been
+        // read by the frontend
+        buf: Vec<u8>,
+        pos: usize,
+        read: Option<Op<usize>>,
+    }
+
+    impl Reader {
+        // completes once there are bytes to read, or the client has closed the
+        // connection if there are none
+        fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            if self.pos == self.buf.len() {
+                let read = self.read.get_or_insert_with(|| {
+                    let socket = self.socket.clone();
+                    let mut buf = std::mem::take(&mut self.buf);
+                    buf.clear();
+                    self.pos = 0;
+                    Box::pin(async move { socket.read(buf).await })
+                });
+
+                let (result, buf) = ready!(read.as_mut().poll(cx));
+                self.read = None;
+                self.buf = buf;
+                self.pos = 0;
+                result?;
+            }
+
+            Poll::Ready(Ok(()))
+        }
+
+        // the bytes received which haven't been read
+        fn received(&self) -> &[u8] {
+            &self.buf[self.pos..]
+        }
+    }
+
+    impl AsyncRead for Reader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            ready!(self.poll_fill(cx))?;
+            let n = self.received().len().min(buf.remaining());
+            buf.put_slice(&self.received()[..n]);
+            self.pos += n;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// The write half of a connection served by an io_uring worker. A write
+    /// is accepted once it is submitted, and its completion is waited for by
+    /// the next write, or a flush.
+    pub(crate) struct Writer {
+        socket: Rc<tokio_uring::net::TcpStream>,
+        // the buffer of the last completed write, reused by the next one
+        buf: Vec<u8>,
+        write: Option<Op<()>>,
+    }
+
+    impl Writer {
+        // completes once the write in flight, if any, has completed
+        fn poll_complete(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            if let Some(write) = &mut self.write {
+                let (result, buf) = ready!(write.as_mut().poll(cx));
+                self.write = None;
+                self.buf = buf;
+                result?;
+            }
+
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for Writer {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            ready!(self.poll_complete(cx))?;
+
+            let socket = self.socket.clone();
+            let mut owned = std::mem::take(&mut self.buf);
+            owned.clear();
+            owned.extend_from_slice(buf);
+            let mut write: Op<()> = Box::pin(async move { socket.write_all(owned).await });
+
+            // polled once so that it is submitted to the ring
+            match write.as_mut().poll(cx) {
+                Poll::Ready((result, owned)) => {
+                    self.buf = owned;
+                    result?;
+                }
+                Poll::Pending => self.write = Some(write),
+            }
+
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            self.poll_complete(cx)
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            ready!(self.poll_complete(cx))?;
+            Poll::Ready(self.socket.shutdown(std::net::Shutdown::Write))
+        }
+    }
+
+    impl AsyncRead for Stream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.reader).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Stream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Pin::new(&mut self.writer).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.writer).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.writer).poll_shutdown(cx)
+        }
+    }
+
+    impl ClientSocket for Stream {
+        type ReadHalf = Reader;
+        type WriteHalf = Writer;
+
+        async fn peek(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            std::future::poll_fn(|cx| self.reader.poll_fill(cx)).await?;
+            let received = self.reader.received();
+            let n = received.len().min(buf.len());
+            buf[..n].copy_from_slice(&received[..n]);
+            Ok(n)
+        }
+
+        fn into_split(self) -> (Self::ReadHalf, Self::WriteHalf) {
+            (self.reader, self.writer)
+        }
+    }
+}
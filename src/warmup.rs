@@ -0,0 +1,146 @@
+// Copyright 2025 Pelikan Foundation LLC.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+use crate::cache::CacheValue;
+use crate::keys::KeyTransform;
+use crate::protocol::memcache::ValueCodec;
+use crate::shards::Shards;
+use crate::*;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use momento::cache::GetResponse;
+use std::sync::Arc;
+
+// limits the number of outstanding warmup requests for a single cache
+const WARMUP_CONCURRENCY: usize = 16;
+
+#[metric(name = "warmup_keys_total")]
+pub static WARMUP_KEYS_TOTAL: Gauge = Gauge::new();
+
+#[metric(name = "warmup_keys_done")]
+pub static WARMUP_KEYS_DONE: Gauge = Gauge::new();
+
+#[metric(name = "warmup_keys_hit")]
+pub static WARMUP_KEYS_HIT: Counter = Counter::new();
+
+#[metric(name = "warmup_keys_ex")]
+pub static WARMUP_KEYS_EX: Counter = Counter::new();
+
+/// Reads the key list at `path` and pre-fetches each key from Momento into the
+/// memory cache, issuing at most `rate` requests per second.
+pub(crate) async fn warmup(
+    client: CacheClient,
+    cache_name: String,
+    shards: Arc<Shards>,
+    key_transform: KeyTransform,
+    path: String,
+    codec: ValueCodec,
+    rate: u64,
+    memory_cache: MCache,
+) {
+    let keys = match tokio::fs::read_to_string(&path).await {
+        Ok(content) => content,
+        Err(e) => {
+            error!("could not read warmup key file `{path}` for cache `{cache_name}`: {e}");
+            return;
+        }
+    };
+
+    let keys: Vec<&str> = keys
+        .lines()
+        .map(|key| key.trim())
+        .filter(|key| !key.is_empty())
+        .collect();
+
+    info!(
+        "warming memory cache for cache `{cache_name}` with {} keys",
+        keys.len()
+    );
+    WARMUP_KEYS_TOTAL.add(keys.len() as _);
+
+    let mut interval = if rate > 0 {
+        let mut interval =
+            tokio::time::interval(Duration::from_nanos((1_000_000_000 / rate).max(1)));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        Some(interval)
+    } else {
+        None
+    };
+
+    let mut tasks = FuturesUnordered::new();
+    let mut loaded = 0;
+
+    for key in keys.iter() {
+        if let Some(interval) = interval.as_mut() {
+            interval.tick().await;
+        }
+
+        if tasks.len() >= WARMUP_CONCURRENCY {
+            if let Some(true) = tasks.next().await {
+                loaded += 1;
+            }
+        }
+
+        tasks.push(warmup_key(
+            &client,
+            shards.cache_for(key.as_bytes()),
+            key.as_bytes(),
+            &key_transform,
+            codec,
+            &memory_cache,
+        ));
+    }
+
+    while let Some(hit) = tasks.next().await {
+        if hit {
+            loaded += 1;
+        }
+    }
+
+    info!(
+        "finished warming memory cache for cache `{cache_name}`: loaded {loaded} of {} keys",
+        keys.len()
+    );
+}
+
+async fn warmup_key(
+    client: &CacheClient,
+    cache_name: &str,
+    key: &[u8],
+    key_transform: &KeyTransform,
+    codec: ValueCodec,
+    memory_cache: &MCache,
+) -> bool {
+    let result = timeout(
+        Duration::from_millis(200),
+        client.get(cache_name, key_transform.apply(key).into_owned()),
+    )
+    .await;
+
+    WARMUP_KEYS_DONE.increment();
+
+    match result {
+        Ok(Ok(GetResponse::Hit { value })) => {
+            let value: Vec<u8> = value.into();
+            match codec.decode(key, &value) {
+                Some(value) => {
+                    WARMUP_KEYS_HIT.increment();
+                    memory_cache.set(key.to_vec(), CacheValue::Memcached { value });
+                    true
+                }
+                None => false,
+            }
+        }
+        Ok(Ok(GetResponse::Miss)) => false,
+        Ok(Err(e)) => {
+            debug!("backend error during warmup: {e}");
+            WARMUP_KEYS_EX.increment();
+            false
+        }
+        Err(_) => {
+            WARMUP_KEYS_EX.increment();
+            false
+        }
+    }
+}